@@ -0,0 +1,27 @@
+//! Passes on what the `libbzip3-sys` build script found of the libbz3 API, for the code to only
+//! reference the symbols the library has; see `probe` there.
+
+use std::env;
+
+/// The cfgs `libbzip3-sys` may set, one per part of the API that isn't in all libbz3 versions.
+const LIBBZ3_CFGS: &[&str] = &[
+    "libbz3_has_version",
+    "libbz3_has_bound",
+    "libbz3_has_min_memory_needed",
+    "libbz3_has_orig_size_sufficient_for_decode",
+    "libbz3_decode_buffer_size",
+];
+
+fn main() {
+    for cfg in LIBBZ3_CFGS {
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+    }
+    if let Ok(cfgs) = env::var("DEP_BZIP3_CFGS") {
+        for cfg in cfgs.split(',').filter(|x| !x.is_empty()) {
+            println!("cargo:rustc-cfg={}", cfg);
+        }
+    }
+    // for `version()`, with a libbz3 lacking `bz3_version`
+    let version = env::var("DEP_BZIP3_VERSION").unwrap_or_else(|_| "unknown".into());
+    println!("cargo:rustc-env=LIBBZ3_VERSION={}", version);
+}
@@ -0,0 +1,27 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use bzip3::frame::{BlockHeader, FrameHeader, BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE};
+use bzip3::seek::Bz3Index;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(bytes) = data.first_chunk::<FRAME_HEADER_SIZE>() {
+        if let Ok(header) = FrameHeader::parse(bytes) {
+            assert_eq!(&header.to_bytes(), bytes);
+        }
+    }
+    if let Some(bytes) = data.first_chunk::<BLOCK_HEADER_SIZE>() {
+        let header = BlockHeader::parse(bytes);
+        assert_eq!(&header.to_bytes(), bytes);
+        let _ = header.validate(bzip3::BLOCK_SIZE_MIN);
+    }
+
+    if let Ok(index) = Bz3Index::load(data) {
+        let mut saved = Vec::new();
+        index.save(&mut saved).unwrap();
+        assert_eq!(Bz3Index::load(saved.as_slice()).unwrap(), index);
+    }
+    let _ = Bz3Index::read_trailer(Cursor::new(data));
+});
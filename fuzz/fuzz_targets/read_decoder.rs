@@ -0,0 +1,26 @@
+#![no_main]
+
+use std::io::Read;
+
+use bzip3::read::Bz3Decoder;
+use bzip3::CrcMode;
+use libfuzzer_sys::fuzz_target;
+
+/// Keeps the memory of a decoder well below libFuzzer's RSS limit.
+const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the options, so both modes get covered
+    let Some((&options, data)) = data.split_first() else {
+        return;
+    };
+    let Ok(mut decoder) = Bz3Decoder::with_max_block_size(data, MAX_BLOCK_SIZE) else {
+        return;
+    };
+    if options & 1 != 0 {
+        decoder.set_crc_mode(CrcMode::Permissive);
+    }
+    decoder.set_paranoid(options & 2 != 0);
+    decoder.set_ignore_trailing_data(options & 4 != 0);
+    let _ = decoder.read_to_end(&mut Vec::new());
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use std::io::Write;
+
+use bzip3::write::Bz3Decoder;
+use libfuzzer_sys::fuzz_target;
+
+/// Keeps the memory of a decoder well below libFuzzer's RSS limit.
+const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte splits the input into two writes, so partial headers and blocks are
+    // covered
+    let Some((&split, data)) = data.split_first() else {
+        return;
+    };
+    let (first, second) = data.split_at((split as usize).min(data.len()));
+    let mut decoder = Bz3Decoder::new(Vec::new());
+    decoder.set_max_block_size(MAX_BLOCK_SIZE);
+    let _ = decoder
+        .write_all(first)
+        .and_then(|_| decoder.write_all(second));
+});
@@ -0,0 +1,59 @@
+/*
+ * C API of the bzip3 crate's streaming codecs, built with the `capi` feature.
+ *
+ * See the documentation of the `capi` module for details.
+ */
+
+#ifndef BZIP3_RS_H
+#define BZIP3_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BZ3RS_OK 0
+#define BZ3RS_MORE_OUTPUT 1
+#define BZ3RS_ERR_IO (-1)
+#define BZ3RS_ERR_BLOCK_SIZE (-2)
+#define BZ3RS_ERR_BLOCK_SIZE_LIMIT (-3)
+#define BZ3RS_ERR_PROCESS_BLOCK (-4)
+#define BZ3RS_ERR_INVALID_SIGNATURE (-5)
+#define BZ3RS_ERR_BAD_CRC (-6)
+#define BZ3RS_ERR_VERIFY_FAILED (-7)
+#define BZ3RS_ERR_TRUNCATED_BLOCK (-8)
+#define BZ3RS_ERR_TRAILING_DATA (-9)
+#define BZ3RS_ERR_MISPLACED_FRAME_HEADER (-10)
+#define BZ3RS_ERR_CHECKSUM_MISMATCH (-11)
+#define BZ3RS_ERR_TIMEOUT (-12)
+#define BZ3RS_ERR_INVALID_ARGUMENT (-13)
+#define BZ3RS_ERR_UNSUPPORTED_VERSION (-14)
+
+typedef struct Bz3rsEncoder Bz3rsEncoder;
+typedef struct Bz3rsDecoder Bz3rsDecoder;
+
+Bz3rsEncoder *bz3rs_encoder_new(size_t block_size);
+int bz3rs_encoder_compress(Bz3rsEncoder *encoder, const uint8_t *input, size_t input_len,
+                           size_t *input_consumed, uint8_t *output, size_t output_len,
+                           size_t *output_written);
+int bz3rs_encoder_finish(Bz3rsEncoder *encoder, uint8_t *output, size_t output_len,
+                         size_t *output_written);
+const char *bz3rs_encoder_error(const Bz3rsEncoder *encoder);
+void bz3rs_encoder_free(Bz3rsEncoder *encoder);
+
+Bz3rsDecoder *bz3rs_decoder_new(void);
+void bz3rs_decoder_set_max_block_size(Bz3rsDecoder *decoder, size_t limit);
+int bz3rs_decoder_decompress(Bz3rsDecoder *decoder, const uint8_t *input, size_t input_len,
+                             size_t *input_consumed, uint8_t *output, size_t output_len,
+                             size_t *output_written);
+int bz3rs_decoder_finish(Bz3rsDecoder *decoder);
+const char *bz3rs_decoder_error(const Bz3rsDecoder *decoder);
+void bz3rs_decoder_free(Bz3rsDecoder *decoder);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BZIP3_RS_H */
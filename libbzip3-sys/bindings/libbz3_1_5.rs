@@ -0,0 +1,91 @@
+/* automatically generated by rust-bindgen 0.63.0 */
+
+pub const BZ3_OK: u32 = 0;
+pub const BZ3_ERR_OUT_OF_BOUNDS: i32 = -1;
+pub const BZ3_ERR_BWT: i32 = -2;
+pub const BZ3_ERR_CRC: i32 = -3;
+pub const BZ3_ERR_MALFORMED_HEADER: i32 = -4;
+pub const BZ3_ERR_TRUNCATED_DATA: i32 = -5;
+pub const BZ3_ERR_DATA_TOO_BIG: i32 = -6;
+pub const BZ3_ERR_INIT: i32 = -7;
+pub const BZ3_ERR_DATA_SIZE_TOO_SMALL: i32 = -8;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct bz3_state {
+    _unused: [u8; 0],
+}
+extern "C" {
+    pub fn bz3_version() -> *const ::core::ffi::c_char;
+}
+extern "C" {
+    pub fn bz3_last_error(state: *mut bz3_state) -> i8;
+}
+extern "C" {
+    pub fn bz3_strerror(state: *mut bz3_state) -> *const ::core::ffi::c_char;
+}
+extern "C" {
+    pub fn bz3_new(block_size: i32) -> *mut bz3_state;
+}
+extern "C" {
+    pub fn bz3_free(state: *mut bz3_state);
+}
+extern "C" {
+    pub fn bz3_bound(input_size: usize) -> usize;
+}
+extern "C" {
+    pub fn bz3_compress(
+        block_size: u32,
+        in_: *const u8,
+        out: *mut u8,
+        in_size: usize,
+        out_size: *mut usize,
+    ) -> ::core::ffi::c_int;
+}
+extern "C" {
+    pub fn bz3_decompress(
+        in_: *const u8,
+        out: *mut u8,
+        in_size: usize,
+        out_size: *mut usize,
+    ) -> ::core::ffi::c_int;
+}
+extern "C" {
+    pub fn bz3_min_memory_needed(block_size: i32) -> usize;
+}
+extern "C" {
+    pub fn bz3_encode_block(state: *mut bz3_state, buffer: *mut u8, size: i32) -> i32;
+}
+extern "C" {
+    pub fn bz3_decode_block(
+        state: *mut bz3_state,
+        buffer: *mut u8,
+        buffer_size: usize,
+        compressed_size: i32,
+        orig_size: i32,
+    ) -> i32;
+}
+extern "C" {
+    pub fn bz3_encode_blocks(
+        states: *mut *mut bz3_state,
+        buffers: *mut *mut u8,
+        sizes: *mut i32,
+        n: i32,
+    );
+}
+extern "C" {
+    pub fn bz3_decode_blocks(
+        states: *mut *mut bz3_state,
+        buffers: *mut *mut u8,
+        buffer_sizes: *mut usize,
+        sizes: *mut i32,
+        orig_sizes: *mut i32,
+        n: i32,
+    );
+}
+extern "C" {
+    pub fn bz3_orig_size_sufficient_for_decode(
+        block: *const u8,
+        block_size: usize,
+        orig_size: i32,
+    ) -> ::core::ffi::c_int;
+}
@@ -1,40 +1,104 @@
+#[cfg(all(feature = "bindgen", not(feature = "pregenerated-bindings")))]
 extern crate bindgen;
 
 use cfg_if::cfg_if;
 use std::env;
 use std::path::{Path, PathBuf};
 
+#[cfg(not(feature = "bundled"))]
 const DEFAULT_LIB_DIR: &str = "/usr/lib";
+#[cfg(not(feature = "bundled"))]
 const DEFAULT_INCLUDE_DIR: &str = "/usr/include";
 #[cfg(feature = "bundled")]
 const BZIP3_REPO_DIR: &str = "./bzip3";
+/// Bindings of the libbz3 version bundled, used instead of running bindgen with the
+/// `pregenerated-bindings` feature, or without the `bindgen` one.
+#[cfg(any(feature = "pregenerated-bindings", not(feature = "bindgen")))]
+const PREGENERATED_BINDINGS: &str = "./bindings/libbz3_1_5.rs";
+const USE_PREGENERATED_BINDINGS: bool = cfg!(any(
+    feature = "pregenerated-bindings",
+    not(feature = "bindgen")
+));
+/// Stand-ins for the libc headers on wasm32-unknown-unknown, which has no libc.
+#[cfg_attr(not(feature = "bundled"), allow(dead_code))]
+const WASM_SHIM_DIR: &str = "./wasm-shim";
+
+/// Whether this builds for wasm32-unknown-unknown, as opposed to wasi or emscripten, which have
+/// a libc.
+fn is_wasm_unknown() -> bool {
+    env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "wasm32"
+        && env::var("CARGO_CFG_TARGET_OS").unwrap() == "unknown"
+}
+
+/// The libbz3 to build against.
+struct Library {
+    header_file: PathBuf,
+    /// Include directories to pass to bindgen.
+    #[cfg_attr(
+        any(feature = "pregenerated-bindings", not(feature = "bindgen")),
+        allow(dead_code)
+    )]
+    include_dirs: Vec<PathBuf>,
+    version: Option<String>,
+}
 
 #[allow(unused)]
 fn main() {
-    let mut bzip3_include_dir = String::from(DEFAULT_INCLUDE_DIR);
-
     let bindings_output = PathBuf::from(env::var("OUT_DIR").unwrap()).join("bindings.rs");
 
-    let mut bzip3_lib_dir = String::from(DEFAULT_LIB_DIR);
-    if let Ok(d) = env::var("BZIP3_LIB_DIR") {
-        bzip3_lib_dir = d;
-    }
-    if let Ok(d) = env::var("BZIP3_INCLUDE_DIR") {
-        bzip3_include_dir = d;
-    }
-
-    let mut header_file = Path::new(&bzip3_include_dir).join("libbz3.h");
-
     cfg_if! {
         if #[cfg(feature = "bundled")] {
-            header_file = bundled::get_bzip3_header();
+            let library = bundled::library();
+        } else {
+            if is_wasm_unknown() {
+                panic!("There's no system libbzip3 on wasm32-unknown-unknown; enable the `bundled` feature");
+            }
+            let library = system::find();
         }
     }
 
+    write_bindings(&library, &bindings_output);
+
+    let api = if library.header_file.exists() {
+        probe::api(&library.header_file)
+    } else {
+        // only with the pregenerated bindings, which are of the latest API
+        probe::API_CFGS.to_vec()
+    };
+    if USE_PREGENERATED_BINDINGS && api.len() != probe::API_CFGS.len() {
+        panic!(
+            "The pregenerated bindings are of libbz3 1.5, but {:?} is older
+Note: Enable the `bindgen` feature, and disable `pregenerated-bindings`, to generate the bindings of this version",
+            library.header_file
+        );
+    }
+    probe::emit(&api, library.version.as_deref());
+
+    #[cfg(feature = "bundled")]
+    bundled::compile();
+}
+
+/// Copies the pregenerated bindings, instead of running bindgen.
+#[cfg(any(feature = "pregenerated-bindings", not(feature = "bindgen")))]
+fn write_bindings(_library: &Library, bindings_output: &Path) {
+    println!("cargo:rerun-if-changed={}", PREGENERATED_BINDINGS);
+    std::fs::copy(PREGENERATED_BINDINGS, bindings_output)
+        .expect("Couldn't copy the pregenerated bindings!");
+}
+
+/// Runs bindgen on the header of `library`, which needs libclang.
+#[cfg(all(feature = "bindgen", not(feature = "pregenerated-bindings")))]
+fn write_bindings(library: &Library, bindings_output: &Path) {
+    let Library {
+        header_file,
+        include_dirs,
+        ..
+    } = library;
     if !header_file.exists() {
         panic!(
             "Header file doesn't exist: {:?}
-Note: You can specify BZIP3_LIB_DIR and BZIP3_INCLUDE_DIR environment variables",
+Note: libbzip3 is looked up with pkg-config (and vcpkg on MSVC); you can also specify
+BZIP3_LIB_DIR and BZIP3_INCLUDE_DIR environment variables",
             header_file
         );
     }
@@ -42,29 +106,158 @@ Note: You can specify BZIP3_LIB_DIR and BZIP3_INCLUDE_DIR environment variables"
     // TODO: handle OsStr (e.g. arbitrary bytes path on filesystems like ext4)
     println!("cargo:rerun-if-changed={}", header_file.to_string_lossy());
 
-    let bindings = bindgen::Builder::default()
+    let mut bindings = bindgen::Builder::default();
+    if is_wasm_unknown() {
+        bindings = bindings.clang_args(["-ffreestanding", "-I", WASM_SHIM_DIR]);
+    }
+    for dir in include_dirs {
+        bindings = bindings.clang_arg(format!("-I{}", dir.to_string_lossy()));
+    }
+    let bindings = bindings
         .header(header_file.to_string_lossy())
+        .use_core()
+        .ctypes_prefix("::core::ffi")
         .generate()
         .expect("Unable to generate bindings");
 
     bindings
         .write_to_file(bindings_output)
         .expect("Couldn't write bindings!");
+}
 
-    cfg_if! {
-        if #[cfg(feature = "bundled")] {
-            bundled::compile();
-        } else {
-            println!("cargo:rustc-link-search={}", bzip3_lib_dir);
-            println!("cargo:rustc-link-lib=bzip3");
+#[cfg(not(feature = "bundled"))]
+mod system {
+    use crate::{Library, DEFAULT_INCLUDE_DIR, DEFAULT_LIB_DIR};
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    /// Finds the system libbzip3 and emits how to link it.
+    ///
+    /// `BZIP3_LIB_DIR` and `BZIP3_INCLUDE_DIR` are used when set. Otherwise, the library is
+    /// looked up with pkg-config, then with vcpkg on MSVC, and at last in the default
+    /// directories.
+    pub fn find() -> Library {
+        println!("cargo:rerun-if-env-changed=BZIP3_LIB_DIR");
+        println!("cargo:rerun-if-env-changed=BZIP3_INCLUDE_DIR");
+        let lib_dir = env::var("BZIP3_LIB_DIR").ok();
+        let include_dir = env::var("BZIP3_INCLUDE_DIR").ok();
+
+        if lib_dir.is_none() && include_dir.is_none() {
+            if let Some(library) = probe_pkg_config().or_else(probe_vcpkg) {
+                return library;
+            }
+        }
+
+        let lib_dir = lib_dir.unwrap_or_else(|| DEFAULT_LIB_DIR.into());
+        let include_dir = include_dir.unwrap_or_else(|| DEFAULT_INCLUDE_DIR.into());
+        println!("cargo:rustc-link-search={}", lib_dir);
+        println!("cargo:rustc-link-lib=bzip3");
+        Library {
+            header_file: Path::new(&include_dir).join("libbz3.h"),
+            include_dirs: Vec::new(),
+            version: None,
+        }
+    }
+
+    fn with_include_dirs(include_dirs: Vec<PathBuf>, version: Option<String>) -> Library {
+        let header_file = include_dirs
+            .iter()
+            .map(|x| x.join("libbz3.h"))
+            .find(|x| x.exists())
+            // pkg-config leaves out the system include directories
+            .unwrap_or_else(|| Path::new(DEFAULT_INCLUDE_DIR).join("libbz3.h"));
+        Library {
+            header_file,
+            include_dirs,
+            version,
+        }
+    }
+
+    /// Links the library as `bzip3.pc` tells, statically if `BZIP3_STATIC` or
+    /// `PKG_CONFIG_ALL_STATIC` is set; `BZIP3_NO_PKG_CONFIG` skips this.
+    fn probe_pkg_config() -> Option<Library> {
+        let library = pkg_config::Config::new().probe("bzip3").ok()?;
+        Some(with_include_dirs(
+            library.include_paths,
+            Some(library.version),
+        ))
+    }
+
+    /// Links the library of the `bzip3` vcpkg port, statically unless `VCPKGRS_DYNAMIC` is set,
+    /// as the vcpkg crate does.
+    #[cfg(target_env = "msvc")]
+    fn probe_vcpkg() -> Option<Library> {
+        let library = vcpkg::Config::new().find_package("bzip3").ok()?;
+        Some(with_include_dirs(library.include_paths, None))
+    }
+
+    #[cfg(not(target_env = "msvc"))]
+    fn probe_vcpkg() -> Option<Library> {
+        None
+    }
+}
+
+/// Tells the dependents what of the libbz3 API the library has, beyond that of the oldest
+/// versions, for them to only reference the symbols that are there; linking an older system
+/// libbz3 fails otherwise, with undefined symbols.
+///
+/// Each part of the API present is a cfg name, listed in the `cfgs` metadata, which dependents
+/// read from `DEP_BZIP3_CFGS` in their build script and pass on with `cargo:rustc-cfg`.
+mod probe {
+    use regex::Regex;
+    use std::fs;
+    use std::path::Path;
+
+    /// All the cfgs, of the functions that aren't in all versions, in order.
+    pub const API_CFGS: &[&str] = &[
+        "libbz3_has_version",
+        "libbz3_has_bound",
+        "libbz3_has_min_memory_needed",
+        "libbz3_has_orig_size_sufficient_for_decode",
+        // `bz3_decode_block` and `bz3_decode_blocks` taking the buffer sizes, and
+        // `BZ3_ERR_DATA_SIZE_TOO_SMALL`, as of 1.5.0
+        "libbz3_decode_buffer_size",
+    ];
+
+    /// The cfgs of what `header_file` declares.
+    pub fn api(header_file: &Path) -> Vec<&'static str> {
+        let header = fs::read_to_string(header_file).expect("Couldn't read the header file");
+        let comments = Regex::new(r"(?s)/\*.*?\*/|//[^\n]*").unwrap();
+        let header = comments.replace_all(&header, "");
+        let declares = |function: &str| {
+            Regex::new(&format!(r"\b{}\s*\(", function))
+                .unwrap()
+                .is_match(&header)
+        };
+        // the five parameters of `bz3_decode_block` since 1.5.0, instead of four
+        let decode_buffer_size = Regex::new(r"\bbz3_decode_block\s*\(([^)]*)\)")
+            .unwrap()
+            .captures(&header)
+            .is_some_and(|x| x[1].matches(',').count() == 4);
+
+        API_CFGS
+            .iter()
+            .copied()
+            .filter(|&cfg| match cfg {
+                "libbz3_decode_buffer_size" => decode_buffer_size,
+                _ => declares(&cfg.replace("libbz3_has_", "bz3_")),
+            })
+            .collect()
+    }
+
+    pub fn emit(cfgs: &[&str], version: Option<&str>) {
+        println!("cargo:cfgs={}", cfgs.join(","));
+        if let Some(version) = version {
+            println!("cargo:version={}", version);
         }
     }
 }
 
 #[cfg(feature = "bundled")]
 mod bundled {
-    use crate::BZIP3_REPO_DIR;
+    use crate::{is_wasm_unknown, Library, BZIP3_REPO_DIR, WASM_SHIM_DIR};
     use regex::Regex;
+    use std::env;
     use std::fs::File;
     use std::io::Read;
     use std::path::PathBuf;
@@ -103,12 +296,40 @@ mod bundled {
         if !include_dir.exists() {
             panic!("Missing include dir: {:?}", include_dir);
         }
-        cc::Build::new()
+        let mut build = cc::Build::new();
+        build
             .file(src_file)
             .include(include_dir)
             .define("VERSION", Some(format!(r#""{}""#, version).as_str()))
-            .warnings(false)
-            .compile("bzip3");
+            .warnings(false);
+        if is_wasm_unknown() {
+            if cfg!(feature = "threads") {
+                panic!("The `threads` feature needs pthreads, which wasm32-unknown-unknown doesn't have");
+            }
+            // cc picks clang, the compiler with a wasm32 backend, and passes it the target. There's
+            // no libc to link, so the libc headers are replaced with `wasm-shim/`, forwarding to
+            // Rust's allocator, and libsais is kept single-threaded, without OpenMP.
+            build
+                .include(WASM_SHIM_DIR)
+                .flag("-ffreestanding")
+                .flag_if_supported("-fno-openmp");
+        }
+        if cfg!(feature = "threads") {
+            // enables `bz3_encode_blocks` and `bz3_decode_blocks`
+            build.define("PTHREAD", None);
+        }
+        build.compile("bzip3");
+        if cfg!(feature = "threads") && env::var("CARGO_CFG_UNIX").is_ok() {
+            println!("cargo:rustc-link-lib=pthread");
+        }
+    }
+
+    pub fn library() -> Library {
+        Library {
+            header_file: get_bzip3_header(),
+            include_dirs: Vec::new(),
+            version: Some(parse_version()),
+        }
     }
 
     pub fn get_bzip3_header() -> PathBuf {
@@ -1,5 +1,12 @@
+#![no_std]
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+extern crate alloc;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod wasm_shim;
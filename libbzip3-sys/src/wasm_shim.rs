@@ -0,0 +1,99 @@
+//! The libc functions libbz3 needs on wasm32-unknown-unknown, which has no libc; the headers in
+//! `wasm-shim/` forward to these.
+
+use alloc::alloc::{alloc, alloc_zeroed, dealloc, Layout};
+use core::ffi::{c_int, c_void};
+use core::ptr;
+
+/// Alignment of the allocations, that of `max_align_t`, and the room in front of them, where
+/// their size is kept for `free`.
+const HEADER: usize = 16;
+
+unsafe fn allocate(size: usize, zeroed: bool) -> *mut c_void {
+    let Some(layout) = size
+        .checked_add(HEADER)
+        .and_then(|x| Layout::from_size_align(x, HEADER).ok())
+    else {
+        return ptr::null_mut();
+    };
+    let base = if zeroed {
+        alloc_zeroed(layout)
+    } else {
+        alloc(layout)
+    };
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+    base.cast::<usize>().write(size);
+    base.add(HEADER).cast()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_malloc(size: usize) -> *mut c_void {
+    allocate(size, false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_calloc(nmemb: usize, size: usize) -> *mut c_void {
+    match nmemb.checked_mul(size) {
+        Some(size) => allocate(size, true),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_free(p: *mut c_void) {
+    if p.is_null() {
+        return;
+    }
+    let base = p.cast::<u8>().sub(HEADER);
+    let size = base.cast::<usize>().read();
+    dealloc(
+        base,
+        Layout::from_size_align_unchecked(size + HEADER, HEADER),
+    );
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_memcpy(
+    dest: *mut c_void,
+    src: *const c_void,
+    n: usize,
+) -> *mut c_void {
+    ptr::copy_nonoverlapping(src.cast::<u8>(), dest.cast::<u8>(), n);
+    dest
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_memmove(
+    dest: *mut c_void,
+    src: *const c_void,
+    n: usize,
+) -> *mut c_void {
+    ptr::copy(src.cast::<u8>(), dest.cast::<u8>(), n);
+    dest
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_memset(
+    dest: *mut c_void,
+    c: c_int,
+    n: usize,
+) -> *mut c_void {
+    ptr::write_bytes(dest.cast::<u8>(), c as u8, n);
+    dest
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rust_bzip3_wasm_shim_memcmp(
+    a: *const c_void,
+    b: *const c_void,
+    n: usize,
+) -> c_int {
+    let a = core::slice::from_raw_parts(a.cast::<u8>(), n);
+    let b = core::slice::from_raw_parts(b.cast::<u8>(), n);
+    a.iter()
+        .zip(b)
+        .find(|(x, y)| x != y)
+        .map_or(0, |(&x, &y)| c_int::from(x) - c_int::from(y))
+}
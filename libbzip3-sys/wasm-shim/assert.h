@@ -0,0 +1,12 @@
+/* Freestanding stand-in for <assert.h> on wasm32-unknown-unknown, which has no libc. */
+
+#ifndef BZIP3_WASM_SHIM_ASSERT_H
+#define BZIP3_WASM_SHIM_ASSERT_H
+
+#ifdef NDEBUG
+#define assert(x) ((void)0)
+#else
+#define assert(x) ((x) ? (void)0 : __builtin_trap())
+#endif
+
+#endif
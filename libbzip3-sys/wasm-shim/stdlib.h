@@ -0,0 +1,29 @@
+/* Freestanding stand-in for <stdlib.h> on wasm32-unknown-unknown, which has no libc.
+   The allocator is Rust's, exported by `libbzip3-sys`. */
+
+#ifndef BZIP3_WASM_SHIM_STDLIB_H
+#define BZIP3_WASM_SHIM_STDLIB_H
+
+#include <stddef.h>
+
+void *rust_bzip3_wasm_shim_malloc(size_t size);
+void *rust_bzip3_wasm_shim_calloc(size_t nmemb, size_t size);
+void rust_bzip3_wasm_shim_free(void *ptr);
+
+static inline void *malloc(size_t size) {
+    return rust_bzip3_wasm_shim_malloc(size);
+}
+
+static inline void *calloc(size_t nmemb, size_t size) {
+    return rust_bzip3_wasm_shim_calloc(nmemb, size);
+}
+
+static inline void free(void *ptr) {
+    rust_bzip3_wasm_shim_free(ptr);
+}
+
+static inline void abort(void) {
+    __builtin_trap();
+}
+
+#endif
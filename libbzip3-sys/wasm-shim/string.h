@@ -0,0 +1,30 @@
+/* Freestanding stand-in for <string.h> on wasm32-unknown-unknown, which has no libc.
+   The functions are Rust's, exported by `libbzip3-sys`. */
+
+#ifndef BZIP3_WASM_SHIM_STRING_H
+#define BZIP3_WASM_SHIM_STRING_H
+
+#include <stddef.h>
+
+void *rust_bzip3_wasm_shim_memcpy(void *dest, const void *src, size_t n);
+void *rust_bzip3_wasm_shim_memmove(void *dest, const void *src, size_t n);
+void *rust_bzip3_wasm_shim_memset(void *dest, int c, size_t n);
+int rust_bzip3_wasm_shim_memcmp(const void *a, const void *b, size_t n);
+
+static inline void *memcpy(void *dest, const void *src, size_t n) {
+    return rust_bzip3_wasm_shim_memcpy(dest, src, n);
+}
+
+static inline void *memmove(void *dest, const void *src, size_t n) {
+    return rust_bzip3_wasm_shim_memmove(dest, src, n);
+}
+
+static inline void *memset(void *dest, int c, size_t n) {
+    return rust_bzip3_wasm_shim_memset(dest, c, n);
+}
+
+static inline int memcmp(const void *a, const void *b, size_t n) {
+    return rust_bzip3_wasm_shim_memcmp(a, b, n);
+}
+
+#endif
@@ -0,0 +1,177 @@
+//! Python bindings of the bzip3 crate, as the `bzip3_rs` module.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use bzip3::{read, write};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+/// Default block size, as the `bzip3` command.
+const DEFAULT_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+create_exception!(
+    bzip3_rs,
+    Bz3Error,
+    PyValueError,
+    "Invalid or corrupt bzip3 data, or invalid arguments."
+);
+
+fn to_py_err(e: bzip3::Error) -> PyErr {
+    match e {
+        bzip3::Error::Io(e) if e.get_ref().is_some_and(|x| x.is::<bzip3::Error>()) => {
+            to_py_err(*e.into_inner().unwrap().downcast().unwrap())
+        }
+        bzip3::Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            Bz3Error::new_err(e.to_string())
+        }
+        bzip3::Error::Io(e) => PyIOError::new_err(e),
+        e => Bz3Error::new_err(e.to_string()),
+    }
+}
+
+fn io_to_py_err(e: std::io::Error) -> PyErr {
+    to_py_err(e.into())
+}
+
+/// Compresses `data` into a complete bzip3 file.
+#[pyfunction]
+#[pyo3(signature = (data, block_size = DEFAULT_BLOCK_SIZE))]
+fn compress<'py>(py: Python<'py>, data: &[u8], block_size: usize) -> PyResult<Bound<'py, PyBytes>> {
+    let compressed = py
+        .allow_threads(|| bzip3::mem::compress(data, block_size))
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &compressed))
+}
+
+/// Decompresses a complete bzip3 file.
+#[pyfunction]
+fn decompress<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let decompressed = py
+        .allow_threads(|| bzip3::mem::decompress(data))
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &decompressed))
+}
+
+/// Compresses the file at `src` into a bzip3 file at `dst`.
+#[pyfunction]
+#[pyo3(signature = (src, dst, block_size = DEFAULT_BLOCK_SIZE))]
+fn compress_file(py: Python<'_>, src: PathBuf, dst: PathBuf, block_size: usize) -> PyResult<()> {
+    py.allow_threads(|| {
+        let mut input = File::open(src)?;
+        let output = BufWriter::new(File::create(dst)?);
+        let mut encoder = write::Bz3Encoder::new(output, block_size)?;
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    })
+    .map_err(to_py_err)
+}
+
+/// Decompresses the bzip3 file at `src` into a file at `dst`.
+#[pyfunction]
+fn decompress_file(py: Python<'_>, src: PathBuf, dst: PathBuf) -> PyResult<()> {
+    py.allow_threads(|| {
+        let mut decoder = read::Bz3Decoder::new(BufReader::new(File::open(src)?))?;
+        let mut output = BufWriter::new(File::create(dst)?);
+        std::io::copy(&mut decoder, &mut output)?;
+        output.flush()?;
+        Ok(())
+    })
+    .map_err(to_py_err)
+}
+
+/// Streaming compressor, like `bz2.BZ2Compressor`.
+///
+/// `compress` returns the compressed data of the blocks completed so far, and `flush` ends
+/// the file and returns the rest.
+#[pyclass]
+struct Compressor {
+    /// `None` once flushed.
+    ///
+    /// The codecs are boxed, since they need more alignment than Python objects have.
+    encoder: Option<Box<write::Bz3Encoder<Vec<u8>>>>,
+}
+
+#[pymethods]
+impl Compressor {
+    #[new]
+    #[pyo3(signature = (block_size = DEFAULT_BLOCK_SIZE))]
+    fn new(block_size: usize) -> PyResult<Self> {
+        let encoder = write::Bz3Encoder::new(Vec::new(), block_size).map_err(to_py_err)?;
+        Ok(Self {
+            encoder: Some(Box::new(encoder)),
+        })
+    }
+
+    fn compress<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| Bz3Error::new_err("Compressor already flushed"))?;
+        py.allow_threads(|| encoder.write_all(data))
+            .map_err(io_to_py_err)?;
+        Ok(PyBytes::new(py, &mem::take(encoder.get_mut())))
+    }
+
+    fn flush<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let encoder = self
+            .encoder
+            .take()
+            .ok_or_else(|| Bz3Error::new_err("Compressor already flushed"))?;
+        let output = py
+            .allow_threads(|| encoder.finish())
+            .map_err(io_to_py_err)?;
+        Ok(PyBytes::new(py, &output))
+    }
+}
+
+/// Streaming decompressor, like `bz2.BZ2Decompressor`.
+///
+/// `decompress` takes any piece of the file and returns the data of the blocks completed so
+/// far.
+#[pyclass]
+struct Decompressor {
+    decoder: Box<write::Bz3Decoder<Vec<u8>>>,
+}
+
+#[pymethods]
+impl Decompressor {
+    #[new]
+    fn new() -> Self {
+        Self {
+            decoder: Box::new(write::Bz3Decoder::new(Vec::new())),
+        }
+    }
+
+    fn decompress<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let decoder = &mut self.decoder;
+        py.allow_threads(|| decoder.write_all(data))
+            .map_err(io_to_py_err)?;
+        Ok(PyBytes::new(py, &mem::take(decoder.get_mut())))
+    }
+}
+
+/// Returns the version of the libbz3 library in use.
+#[pyfunction]
+fn libbz3_version() -> &'static str {
+    bzip3::version()
+}
+
+#[pymodule]
+fn bzip3_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("Bz3Error", m.py().get_type::<Bz3Error>())?;
+    m.add("DEFAULT_BLOCK_SIZE", DEFAULT_BLOCK_SIZE)?;
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(compress_file, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress_file, m)?)?;
+    m.add_function(wrap_pyfunction!(libbz3_version, m)?)?;
+    m.add_class::<Compressor>()?;
+    m.add_class::<Decompressor>()?;
+    Ok(())
+}
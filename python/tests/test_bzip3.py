@@ -0,0 +1,53 @@
+import os
+import tempfile
+
+import pytest
+
+import bzip3_rs
+
+BLOCK_SIZE = 100 * 1024
+DATA = b"".join(str(i).encode() for i in range(100_000))
+
+
+def test_round_trip():
+    compressed = bzip3_rs.compress(DATA, BLOCK_SIZE)
+    assert compressed.startswith(b"BZ3v1")
+    assert bzip3_rs.decompress(compressed) == DATA
+    assert bzip3_rs.decompress(bzip3_rs.compress(b"")) == b""
+
+
+def test_errors():
+    with pytest.raises(bzip3_rs.Bz3Error):
+        bzip3_rs.decompress(b"not bzip3")
+    with pytest.raises(bzip3_rs.Bz3Error):
+        bzip3_rs.decompress(bzip3_rs.compress(DATA, BLOCK_SIZE)[:-1])
+    with pytest.raises(bzip3_rs.Bz3Error):
+        bzip3_rs.compress(DATA, 10)
+
+
+def test_streaming():
+    compressor = bzip3_rs.Compressor(BLOCK_SIZE)
+    chunks = [compressor.compress(DATA[i : i + 10_000]) for i in range(0, len(DATA), 10_000)]
+    chunks.append(compressor.flush())
+    with pytest.raises(bzip3_rs.Bz3Error):
+        compressor.flush()
+    compressed = b"".join(chunks)
+
+    decompressor = bzip3_rs.Decompressor()
+    output = b"".join(
+        decompressor.decompress(compressed[i : i + 3000]) for i in range(0, len(compressed), 3000)
+    )
+    assert output == DATA
+
+
+def test_files():
+    with tempfile.TemporaryDirectory() as dir:
+        src, compressed, dst = (os.path.join(dir, x) for x in ("src", "src.bz3", "dst"))
+        with open(src, "wb") as f:
+            f.write(DATA)
+        bzip3_rs.compress_file(src, compressed, block_size=BLOCK_SIZE)
+        bzip3_rs.decompress_file(compressed, dst)
+        with open(dst, "rb") as f:
+            assert f.read() == DATA
+        with pytest.raises(OSError):
+            bzip3_rs.decompress_file(os.path.join(dir, "missing"), dst)
@@ -0,0 +1,83 @@
+//! `.tar.bz3` archives of whole directories.
+//!
+//! This pipes the [`tar`] crate's builder and unpacker through the bzip3 codecs, for the
+//! common case of compressing a directory in one call.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::errors::*;
+use crate::{read, write, DEFAULT_BLOCK_SIZE};
+
+/// Options of [`compress_dir`].
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    /// Block size of the bzip3 file; [`DEFAULT_BLOCK_SIZE`] by default, like the `bzip3` command.
+    pub block_size: usize,
+    /// Whether to archive the files symlinks point to instead of the symlinks; off by default.
+    pub follow_symlinks: bool,
+    /// Whether to end the file with a [checksum](crate::write::Bz3Encoder::set_checksum); off
+    /// by default, for other bzip3 implementations to read the file.
+    pub checksum: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            follow_symlinks: false,
+            checksum: false,
+        }
+    }
+}
+
+/// Archives the contents of the directory at `path` into a `.tar.bz3` file written to
+/// `writer`, and returns the writer.
+///
+/// Paths in the archive are relative to `path`, as with `tar -C path .`.
+///
+/// # Errors
+///
+/// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use bzip3::archive;
+///
+/// let file = File::create("photos.tar.bz3").unwrap();
+/// archive::compress_dir("photos", file, &archive::ArchiveOptions::default()).unwrap();
+/// archive::extract(File::open("photos.tar.bz3").unwrap(), "restored").unwrap();
+/// ```
+pub fn compress_dir<P, W>(path: P, writer: W, options: &ArchiveOptions) -> Result<W>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut encoder = write::Bz3Encoder::new(writer, options.block_size)?;
+    encoder.set_checksum(options.checksum);
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(options.follow_symlinks);
+    builder.append_dir_all(".", path)?;
+    Ok(builder.into_inner()?.finish()?)
+}
+
+/// Extracts a `.tar.bz3` file read from `reader` into the directory `dest`, creating it if
+/// needed.
+///
+/// Entries which would land outside `dest`, like ones with `..` in their path, are skipped.
+///
+/// # Errors
+///
+/// [`Error::InvalidSignature`] if it's not a bzip3 file, and [`Error::Io`] on all IO errors,
+/// including invalid input.
+pub fn extract<R, P>(reader: R, dest: P) -> Result<()>
+where
+    R: Read,
+    P: AsRef<Path>,
+{
+    let decoder = read::Bz3Decoder::new(reader)?;
+    tar::Archive::new(decoder).unpack(dest)?;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Compressed in-memory byte containers.
+//!
+//! [`CompressedBlob`] keeps bytes bzip3-compressed in memory and decompresses them on access,
+//! trading CPU for RAM, e.g. for the entries of a cache. Along with the compressed data, it
+//! keeps the [seek index](crate::seek::Bz3Index) of the blocks, so a range of the data is read
+//! by decoding only the blocks it overlaps.
+
+use std::fmt;
+use std::io::Cursor;
+use std::ops::Range;
+
+use crate::errors::*;
+use crate::mem;
+use crate::seek::{read_range, scan_index, Bz3Index, Bz3SeekableDecoder};
+
+/// Bytes stored bzip3-compressed in memory.
+///
+/// The compressed data is an ordinary bzip3 stream, available with
+/// [`CompressedBlob::as_compressed`]. Smaller block sizes make range reads cheaper, as less
+/// data is decoded around the range, at the cost of the compression ratio.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::blob::CompressedBlob;
+///
+/// let data = (0..250 * 1024).map(|x| (x / 1024) as u8).collect::<Vec<_>>();
+/// let blob = CompressedBlob::new(&data, 100 * 1024).unwrap();
+/// assert_eq!(blob.len(), data.len() as u64);
+///
+/// // decodes the second block only
+/// assert_eq!(blob.get(150_000..160_000).unwrap(), &data[150_000..160_000]);
+/// assert_eq!(blob.to_vec().unwrap(), data);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct CompressedBlob {
+    compressed: Vec<u8>,
+    index: Bz3Index,
+}
+
+impl CompressedBlob {
+    /// Compresses `data` with the given block size.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(data: &[u8], block_size: usize) -> Result<Self> {
+        Self::from_compressed(mem::compress(data, block_size)?)
+    }
+
+    /// Creates a blob from an existing bzip3 stream, indexing its blocks.
+    ///
+    /// Only the block headers are checked here; corrupt block data shows up on access.
+    pub fn from_compressed(compressed: Vec<u8>) -> Result<Self> {
+        let index = scan_index(Cursor::new(&compressed))?;
+        Ok(Self { compressed, index })
+    }
+
+    /// Returns the size of the uncompressed data.
+    pub fn len(&self) -> u64 {
+        self.index.uncompressed_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the size of the compressed data.
+    pub fn compressed_len(&self) -> usize {
+        self.compressed.len()
+    }
+
+    /// Returns the compressed data, a complete bzip3 stream.
+    pub fn as_compressed(&self) -> &[u8] {
+        &self.compressed
+    }
+
+    pub fn into_compressed(self) -> Vec<u8> {
+        self.compressed
+    }
+
+    /// Returns the index of the blocks.
+    pub fn index(&self) -> &Bz3Index {
+        &self.index
+    }
+
+    /// Decompresses all the data.
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        mem::decompress(&self.compressed)
+    }
+
+    /// Decompresses a range of the data, decoding only the blocks it overlaps.
+    ///
+    /// The range is clipped to the size of the data.
+    pub fn get(&self, range: Range<u64>) -> Result<Vec<u8>> {
+        read_range(Cursor::new(&self.compressed), &self.index, range)
+    }
+
+    /// Returns a reader of the decompressed data, with [`Seek`](std::io::Seek).
+    pub fn reader(&self) -> Result<Bz3SeekableDecoder<Cursor<&[u8]>>> {
+        Bz3SeekableDecoder::with_index(Cursor::new(self.compressed.as_slice()), self.index.clone())
+    }
+}
+
+impl fmt::Debug for CompressedBlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedBlob")
+            .field("len", &self.len())
+            .field("compressed_len", &self.compressed_len())
+            .finish()
+    }
+}
@@ -0,0 +1,306 @@
+//! Compressing and decompressing [`Bytes`], for network servers using them throughout.
+//!
+//! [`BytesEncoder`] and [`BytesDecoder`] take their input as any [`Buf`], and hand out each
+//! compressed or decompressed block as [`Bytes`] backed by the very buffer the block was
+//! processed in, rather than copying it out through `std::io` buffers. Given a
+//! [`BufferPool`], the buffers are taken from it, and go back to it once the last [`Bytes`]
+//! referencing them is dropped.
+//!
+//! # Examples
+//!
+//! ```
+//! use bytes::Bytes;
+//! use bzip3::bytes::{BytesDecoder, BytesEncoder};
+//! use bzip3::pool::BufferPool;
+//!
+//! let pool = BufferPool::new(4);
+//! let mut encoder = BytesEncoder::new(100 * 1024).unwrap();
+//! encoder.set_buffer_pool(pool.clone());
+//! encoder.push_buf(Bytes::from_static(b"hello, world")).unwrap();
+//! let compressed = encoder.finish().unwrap();
+//!
+//! let mut decoder = BytesDecoder::new();
+//! decoder.set_buffer_pool(pool.clone());
+//! for chunk in compressed {
+//!     decoder.push_buf(chunk).unwrap();
+//! }
+//! let decompressed = decoder.finish().unwrap();
+//! assert_eq!(decompressed.concat(), b"hello, world");
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+
+use bytes::{Buf, Bytes};
+
+use crate::errors::*;
+use crate::frame::BlockHeader;
+use crate::pool::{self, BlockBuffer, BufferPool};
+use crate::{bound, push};
+
+/// A block buffer owned by [`Bytes`], put back to the pool when dropped.
+struct PooledBuffer {
+    buffer: BlockBuffer,
+    range: Range<usize>,
+    pool: Option<BufferPool>,
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer[self.range.clone()]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.put(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+fn pooled_bytes(buffer: BlockBuffer, range: Range<usize>, pool: Option<&BufferPool>) -> Bytes {
+    Bytes::from_owner(PooledBuffer {
+        buffer,
+        range,
+        pool: pool.cloned(),
+    })
+}
+
+/// Encoder taking [`Buf`] input, and handing out [`Bytes`].
+///
+/// The output is taken with [`BytesEncoder::next_output`]; it's kept until then. Each item is
+/// the file header, or a compressed block.
+pub struct BytesEncoder {
+    encoder: push::Encoder,
+    pool: Option<BufferPool>,
+    outputs: VecDeque<Bytes>,
+}
+
+impl fmt::Debug for BytesEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("BytesEncoder");
+        self.encoder.debug_fields(&mut s);
+        s.field("pending_outputs", &self.outputs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BytesEncoder {
+    /// Creates an encoder with the given block size.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(block_size: usize) -> Result<Self> {
+        let mut encoder = push::Encoder::new(block_size)?;
+        // the file header
+        let header = Bytes::copy_from_slice(encoder.output());
+        encoder.consume(header.len());
+        Ok(Self {
+            encoder,
+            pool: None,
+            outputs: VecDeque::from([header]),
+        })
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// Sets a pool to take the block buffers and the state from; see the
+    /// [module documentation](self).
+    ///
+    /// Call this before pushing anything.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.encoder.set_buffer_pool(pool.clone());
+        self.pool = Some(pool);
+    }
+
+    /// Sets whether [`BytesEncoder::finish`] appends a checksum; see
+    /// [`write::Bz3Encoder::set_checksum`](crate::write::Bz3Encoder::set_checksum).
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.encoder.set_checksum(enabled);
+    }
+
+    /// Compresses all of `input`; full blocks come out right away.
+    pub fn push_buf<B: Buf>(&mut self, mut input: B) -> Result<()> {
+        while input.has_remaining() {
+            let size = self.encoder.feed(input.chunk())?;
+            input.advance(size);
+            self.take_output();
+        }
+        Ok(())
+    }
+
+    /// Compresses the partial block, at the cost of a smaller block.
+    pub fn flush(&mut self) -> Result<()> {
+        self.encoder.flush()?;
+        self.take_output();
+        Ok(())
+    }
+
+    /// Takes the next output, if any.
+    pub fn next_output(&mut self) -> Option<Bytes> {
+        self.outputs.pop_front()
+    }
+
+    /// Compresses the partial block, and returns the output not taken yet, the checksum
+    /// included if enabled.
+    pub fn finish(mut self) -> Result<Vec<Bytes>> {
+        loop {
+            self.take_output();
+            if self.encoder.is_finished() {
+                break;
+            }
+            self.encoder.finish()?;
+        }
+        Ok(self.outputs.into())
+    }
+
+    fn take_output(&mut self) {
+        if self.encoder.output().is_empty() {
+            return;
+        }
+        let (buffer, range) = self.encoder.take_output();
+        self.outputs
+            .push_back(pooled_bytes(buffer, range, self.pool.as_ref()));
+    }
+}
+
+/// A block whose data is being gathered.
+struct PendingBlock {
+    header: BlockHeader,
+    buffer: BlockBuffer,
+    filled: usize,
+}
+
+/// Decoder taking [`Buf`] input, and handing out [`Bytes`].
+///
+/// The output is taken with [`BytesDecoder::next_output`]; it's kept until then. Each item is
+/// the data of a block.
+pub struct BytesDecoder {
+    decoder: push::Decoder,
+    pool: Option<BufferPool>,
+    block: Option<PendingBlock>,
+    outputs: VecDeque<Bytes>,
+}
+
+impl fmt::Debug for BytesDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("BytesDecoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("pending_block", &self.block.is_some())
+            .field("pending_outputs", &self.outputs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BytesDecoder {
+    pub fn new() -> Self {
+        Self {
+            decoder: push::Decoder::new(),
+            pool: None,
+            block: None,
+            outputs: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Sets a pool to take the block buffers and the state from; see the
+    /// [module documentation](self).
+    ///
+    /// Call this before pushing anything.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.decoder.set_buffer_pool(pool.clone());
+        self.pool = Some(pool);
+    }
+
+    /// Sets the largest block size accepted; see
+    /// [`read::Bz3Decoder::with_max_block_size`](crate::read::Bz3Decoder::with_max_block_size).
+    pub fn set_max_block_size(&mut self, limit: usize) {
+        self.decoder.set_max_block_size(limit);
+    }
+
+    /// Decompresses all of `input`; complete blocks come out right away.
+    pub fn push_buf<B: Buf>(&mut self, mut input: B) -> Result<()> {
+        while input.has_remaining() {
+            let size = self.feed(input.chunk())?;
+            input.advance(size);
+        }
+        Ok(())
+    }
+
+    /// Takes the next output, if any.
+    pub fn next_output(&mut self) -> Option<Bytes> {
+        self.outputs.pop_front()
+    }
+
+    /// Checks that the input is complete, and returns the output not taken yet.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::TruncatedBlock`] if the input ends within a block, and
+    /// [`Error::InvalidSignature`] if it ends within the file header.
+    pub fn finish(mut self) -> Result<Vec<Bytes>> {
+        if let Some(block) = self.block.take() {
+            // let the decoder tell about the truncated block
+            self.decoder.feed(&block.buffer[..block.filled])?;
+        }
+        self.decoder.finish()?;
+        Ok(self.outputs.into())
+    }
+
+    /// Processes as much of `input` as currently needed.
+    ///
+    /// Returns the number of bytes consumed.
+    fn feed(&mut self, input: &[u8]) -> Result<usize> {
+        if self.block.is_none() {
+            if let Some(header) = self.decoder.pending_block() {
+                // the same size for all blocks, for the buffers to be reused
+                let size = bound(self.decoder.block_size().unwrap());
+                self.block = Some(PendingBlock {
+                    header,
+                    buffer: pool::allocate(self.pool.as_ref(), size, 0),
+                    filled: 0,
+                });
+            }
+        }
+        let Some(block) = &mut self.block else {
+            let size = self.decoder.feed(input)?;
+            // empty blocks and the like, not worth a buffer of their own
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                self.outputs.push_back(Bytes::copy_from_slice(output));
+                self.decoder.consume(output.len());
+            }
+            return Ok(size);
+        };
+        let data_size = block.header.data_size();
+        let size = (data_size - block.filled).min(input.len());
+        block.buffer[block.filled..(block.filled + size)].copy_from_slice(&input[..size]);
+        block.filled += size;
+        if block.filled == data_size {
+            let PendingBlock {
+                header, mut buffer, ..
+            } = self.block.take().unwrap();
+            self.decoder.decode_block_into(header, &mut buffer)?;
+            let range = 0..(header.read_size as usize);
+            self.outputs
+                .push_back(pooled_bytes(buffer, range, self.pool.as_ref()));
+        }
+        Ok(size)
+    }
+}
+
+impl Default for BytesDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
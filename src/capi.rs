@@ -0,0 +1,392 @@
+//! C API of the streaming codecs, for using this crate's file handling from other languages.
+//!
+//! Unlike the raw libbz3 API, which works on single blocks, these functions read and write
+//! whole bzip3 files: the file header, any number of blocks, extension blocks and skippable
+//! frames, with the same checks as the Rust decoders.
+//!
+//! Build a shared library with:
+//!
+//! ```sh
+//! cargo rustc --release --features capi,bundled --crate-type cdylib
+//! ```
+//!
+//! The declarations are in `include/bzip3_rs.h`.
+//!
+//! # Usage
+//!
+//! Both codecs work like zlib's `deflate`/`inflate`: each call consumes as much input and
+//! produces as much output as fits, and tells how much of each it did. The functions return
+//! [`BZ3RS_OK`], [`BZ3RS_MORE_OUTPUT`] where noted, or a negative error code; the message of
+//! the last error is available from the handle.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+use std::slice;
+
+use crate::errors::*;
+use crate::push;
+
+/// Success.
+pub const BZ3RS_OK: c_int = 0;
+/// Success, but there's more output to take; call the function again.
+pub const BZ3RS_MORE_OUTPUT: c_int = 1;
+pub const BZ3RS_ERR_IO: c_int = -1;
+pub const BZ3RS_ERR_BLOCK_SIZE: c_int = -2;
+pub const BZ3RS_ERR_BLOCK_SIZE_LIMIT: c_int = -3;
+pub const BZ3RS_ERR_PROCESS_BLOCK: c_int = -4;
+pub const BZ3RS_ERR_INVALID_SIGNATURE: c_int = -5;
+pub const BZ3RS_ERR_BAD_CRC: c_int = -6;
+pub const BZ3RS_ERR_VERIFY_FAILED: c_int = -7;
+pub const BZ3RS_ERR_TRUNCATED_BLOCK: c_int = -8;
+pub const BZ3RS_ERR_TRAILING_DATA: c_int = -9;
+pub const BZ3RS_ERR_MISPLACED_FRAME_HEADER: c_int = -10;
+pub const BZ3RS_ERR_CHECKSUM_MISMATCH: c_int = -11;
+pub const BZ3RS_ERR_TIMEOUT: c_int = -12;
+/// A null handle or buffer was passed, or the codec is being used after an error, or the
+/// encoder after finishing; also [`Error::InvalidArgument`].
+pub const BZ3RS_ERR_INVALID_ARGUMENT: c_int = -13;
+pub const BZ3RS_ERR_UNSUPPORTED_VERSION: c_int = -14;
+
+fn error_code(e: &Error) -> c_int {
+    match e {
+        Error::Io(_) => BZ3RS_ERR_IO,
+        Error::BlockSize => BZ3RS_ERR_BLOCK_SIZE,
+        Error::BlockSizeLimit { .. } => BZ3RS_ERR_BLOCK_SIZE_LIMIT,
+        Error::ProcessBlock(_) => BZ3RS_ERR_PROCESS_BLOCK,
+        Error::InvalidSignature => BZ3RS_ERR_INVALID_SIGNATURE,
+        Error::BadCrc { .. } => BZ3RS_ERR_BAD_CRC,
+        Error::VerifyFailed { .. } => BZ3RS_ERR_VERIFY_FAILED,
+        Error::TruncatedBlock { .. } => BZ3RS_ERR_TRUNCATED_BLOCK,
+        Error::TrailingData { .. } => BZ3RS_ERR_TRAILING_DATA,
+        Error::MisplacedFrameHeader { .. } => BZ3RS_ERR_MISPLACED_FRAME_HEADER,
+        Error::ChecksumMismatch { .. } => BZ3RS_ERR_CHECKSUM_MISMATCH,
+        Error::Timeout(_) => BZ3RS_ERR_TIMEOUT,
+        Error::UnsupportedVersion(_) => BZ3RS_ERR_UNSUPPORTED_VERSION,
+        Error::InvalidArgument(_) => BZ3RS_ERR_INVALID_ARGUMENT,
+    }
+}
+
+/// Error state shared by both handles.
+#[derive(Default)]
+struct LastError {
+    message: Option<CString>,
+    /// A codec stays failed after an error, since its state is undefined.
+    failed: bool,
+}
+
+impl LastError {
+    fn set(&mut self, e: Error) -> c_int {
+        self.message = CString::new(e.to_string().replace('\0', " ")).ok();
+        self.failed = true;
+        error_code(&e)
+    }
+
+    fn as_ptr(&self) -> *const c_char {
+        self.message.as_deref().map_or(ptr::null(), CStr::as_ptr)
+    }
+}
+
+/// Moves pending output of a codec into `output`.
+fn take_output(pending: &[u8], output: &mut [u8], written: &mut usize) -> usize {
+    let size = pending.len().min(output.len() - *written);
+    output[*written..(*written + size)].copy_from_slice(&pending[..size]);
+    *written += size;
+    size
+}
+
+/// # Safety
+///
+/// The pointer must be null or valid for `len` bytes; null is only allowed with `len == 0`.
+unsafe fn input_slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// # Safety
+///
+/// As with [`input_slice`].
+unsafe fn output_slice<'a>(data: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&mut []),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts_mut(data, len)),
+    }
+}
+
+/// Streaming encoder handle.
+pub struct Bz3rsEncoder {
+    encoder: push::Encoder,
+    error: LastError,
+    /// Whether [`bz3rs_encoder_finish`] has been called.
+    finishing: bool,
+}
+
+/// Streaming decoder handle.
+pub struct Bz3rsDecoder {
+    decoder: push::Decoder,
+    error: LastError,
+}
+
+/// Creates an encoder, or returns null if the block size is invalid.
+///
+/// Free it with [`bz3rs_encoder_free`].
+#[no_mangle]
+pub extern "C" fn bz3rs_encoder_new(block_size: usize) -> *mut Bz3rsEncoder {
+    match push::Encoder::new(block_size) {
+        Ok(encoder) => Box::into_raw(Box::new(Bz3rsEncoder {
+            encoder,
+            error: LastError::default(),
+            finishing: false,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Compresses up to `input_len` bytes of `input` into up to `output_len` bytes of `output`.
+///
+/// `*input_consumed` and `*output_written` are set to the number of bytes consumed and
+/// written. Blocks are only output once full; call [`bz3rs_encoder_finish`] at the end.
+///
+/// # Safety
+///
+/// `encoder` must come from [`bz3rs_encoder_new`], the buffers must be valid for their
+/// lengths, and the size pointers must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_encoder_compress(
+    encoder: *mut Bz3rsEncoder,
+    input: *const u8,
+    input_len: usize,
+    input_consumed: *mut usize,
+    output: *mut u8,
+    output_len: usize,
+    output_written: *mut usize,
+) -> c_int {
+    let (Some(handle), Some(input), Some(output)) = (
+        encoder.as_mut(),
+        input_slice(input, input_len),
+        output_slice(output, output_len),
+    ) else {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    };
+    if handle.error.failed
+        || handle.finishing
+        || input_consumed.is_null()
+        || output_written.is_null()
+    {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    }
+    let encoder = &mut handle.encoder;
+    let (mut consumed, mut written) = (0, 0);
+    let result = loop {
+        let taken = take_output(encoder.output(), output, &mut written);
+        encoder.consume(taken);
+        if !encoder.output().is_empty() || consumed == input.len() {
+            break Ok(());
+        }
+        match encoder.feed(&input[consumed..]) {
+            Ok(size) => consumed += size,
+            Err(e) => break Err(e),
+        }
+    };
+    *input_consumed = consumed;
+    *output_written = written;
+    match result {
+        Ok(()) => BZ3RS_OK,
+        Err(e) => handle.error.set(e),
+    }
+}
+
+/// Compresses the last partial block, and writes up to `output_len` bytes of the remaining
+/// output to `output`, setting `*output_written` to the number of bytes written.
+///
+/// Returns [`BZ3RS_MORE_OUTPUT`] until all the output has been taken; nothing may be
+/// compressed afterwards.
+///
+/// # Safety
+///
+/// As with [`bz3rs_encoder_compress`].
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_encoder_finish(
+    encoder: *mut Bz3rsEncoder,
+    output: *mut u8,
+    output_len: usize,
+    output_written: *mut usize,
+) -> c_int {
+    let (Some(handle), Some(output)) = (encoder.as_mut(), output_slice(output, output_len)) else {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    };
+    if handle.error.failed || output_written.is_null() {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    }
+    handle.finishing = true;
+    let encoder = &mut handle.encoder;
+    let mut written = 0;
+    let result = loop {
+        let taken = take_output(encoder.output(), output, &mut written);
+        encoder.consume(taken);
+        if encoder.is_finished() {
+            break Ok(BZ3RS_OK);
+        }
+        if !encoder.output().is_empty() {
+            break Ok(BZ3RS_MORE_OUTPUT);
+        }
+        if let Err(e) = encoder.finish() {
+            break Err(e);
+        }
+    };
+    *output_written = written;
+    result.unwrap_or_else(|e| handle.error.set(e))
+}
+
+/// Returns the message of the last error of the encoder, or null if there's none.
+///
+/// The message lives as long as the encoder.
+///
+/// # Safety
+///
+/// `encoder` must come from [`bz3rs_encoder_new`].
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_encoder_error(encoder: *const Bz3rsEncoder) -> *const c_char {
+    encoder.as_ref().map_or(ptr::null(), |x| x.error.as_ptr())
+}
+
+/// Frees an encoder; null is ignored.
+///
+/// # Safety
+///
+/// `encoder` must be null or come from [`bz3rs_encoder_new`], and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_encoder_free(encoder: *mut Bz3rsEncoder) {
+    if !encoder.is_null() {
+        drop(Box::from_raw(encoder));
+    }
+}
+
+/// Creates a decoder.
+///
+/// Free it with [`bz3rs_decoder_free`].
+#[no_mangle]
+pub extern "C" fn bz3rs_decoder_new() -> *mut Bz3rsDecoder {
+    Box::into_raw(Box::new(Bz3rsDecoder {
+        decoder: push::Decoder::new(),
+        error: LastError::default(),
+    }))
+}
+
+/// Sets the largest block size accepted in a file header, which bounds the memory used;
+/// larger fails with [`BZ3RS_ERR_BLOCK_SIZE_LIMIT`]. Call it before decompressing anything.
+///
+/// # Safety
+///
+/// `decoder` must come from [`bz3rs_decoder_new`].
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_decoder_set_max_block_size(
+    decoder: *mut Bz3rsDecoder,
+    limit: usize,
+) {
+    if let Some(handle) = decoder.as_mut() {
+        handle.decoder.set_max_block_size(limit);
+    }
+}
+
+/// Decompresses up to `input_len` bytes of `input` into up to `output_len` bytes of `output`.
+///
+/// `*input_consumed` and `*output_written` are set to the number of bytes consumed and
+/// written. Returns [`BZ3RS_MORE_OUTPUT`] if decompressed data is left over, which the next
+/// call outputs first, with or without more input.
+///
+/// # Safety
+///
+/// `decoder` must come from [`bz3rs_decoder_new`], the buffers must be valid for their
+/// lengths, and the size pointers must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_decoder_decompress(
+    decoder: *mut Bz3rsDecoder,
+    input: *const u8,
+    input_len: usize,
+    input_consumed: *mut usize,
+    output: *mut u8,
+    output_len: usize,
+    output_written: *mut usize,
+) -> c_int {
+    let (Some(handle), Some(input), Some(output)) = (
+        decoder.as_mut(),
+        input_slice(input, input_len),
+        output_slice(output, output_len),
+    ) else {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    };
+    if handle.error.failed || input_consumed.is_null() || output_written.is_null() {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    }
+    let decoder = &mut handle.decoder;
+    let (mut consumed, mut written) = (0, 0);
+    let result = loop {
+        let taken = take_output(decoder.output(), output, &mut written);
+        decoder.consume(taken);
+        if !decoder.output().is_empty() {
+            break Ok(BZ3RS_MORE_OUTPUT);
+        }
+        if consumed == input.len() {
+            break Ok(BZ3RS_OK);
+        }
+        match decoder.feed(&input[consumed..]) {
+            // nothing more is accepted, e.g. once a block has been fully received
+            Ok(0) if decoder.output().is_empty() => break Ok(BZ3RS_OK),
+            Ok(size) => consumed += size,
+            Err(e) => break Err(e),
+        }
+    };
+    *input_consumed = consumed;
+    *output_written = written;
+    result.unwrap_or_else(|e| handle.error.set(e))
+}
+
+/// Checks whether the input may end here: returns [`BZ3RS_OK`] if the file is complete, and
+/// an error such as [`BZ3RS_ERR_TRUNCATED_BLOCK`] otherwise.
+///
+/// Take all the output with [`bz3rs_decoder_decompress`] first.
+///
+/// # Safety
+///
+/// `decoder` must come from [`bz3rs_decoder_new`].
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_decoder_finish(decoder: *mut Bz3rsDecoder) -> c_int {
+    let Some(handle) = decoder.as_mut() else {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    };
+    if handle.error.failed {
+        return BZ3RS_ERR_INVALID_ARGUMENT;
+    }
+    match handle.decoder.finish() {
+        Ok(()) => BZ3RS_OK,
+        Err(e) => handle.error.set(e),
+    }
+}
+
+/// Returns the message of the last error of the decoder, or null if there's none.
+///
+/// The message lives as long as the decoder.
+///
+/// # Safety
+///
+/// `decoder` must come from [`bz3rs_decoder_new`].
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_decoder_error(decoder: *const Bz3rsDecoder) -> *const c_char {
+    decoder.as_ref().map_or(ptr::null(), |x| x.error.as_ptr())
+}
+
+/// Frees a decoder; null is ignored.
+///
+/// # Safety
+///
+/// `decoder` must be null or come from [`bz3rs_decoder_new`], and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bz3rs_decoder_free(decoder: *mut Bz3rsDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
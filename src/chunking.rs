@@ -0,0 +1,133 @@
+//! Content-defined chunking: ending blocks where the data says so, rather than every
+//! block size bytes.
+//!
+//! With fixed-size blocks, inserting or removing a single byte shifts every block after it, so
+//! the whole rest of the compressed file changes. With content-defined boundaries, found by
+//! rolling a gear hash over the data as in FastCDC, the boundaries move along with the data and
+//! only the blocks around an edit change. This is what deduplicating backup storage needs.
+//!
+//! Enable it with [`write::Bz3Encoder::set_chunking`]. The output is an ordinary bzip3 file,
+//! whose blocks just have varying sizes.
+//!
+//! [`write::Bz3Encoder::set_chunking`]: crate::write::Bz3Encoder::set_chunking
+
+use alloc::format;
+
+use crate::errors::*;
+
+/// Random values of the gear hash, one per byte value.
+const GEAR: [u64; 256] = {
+    // splitmix64
+    let mut table = [0_u64; 256];
+    let mut state = 0x3779_2B3C_8A5C_E3F1_u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Block sizes of content-defined chunking.
+///
+/// Blocks are at least `min_size` bytes, except the last one, and at most `max_size` bytes,
+/// and `avg_size` bytes long on average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+    pub min_size: usize,
+    pub avg_size: usize,
+    /// Must not exceed the block size of the file.
+    pub max_size: usize,
+}
+
+impl Chunking {
+    /// Creates chunking options with the given average size, a quarter of it as minimum size,
+    /// and four times it as maximum size, as FastCDC suggests.
+    pub fn new(avg_size: usize) -> Self {
+        Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size.saturating_mul(4),
+        }
+    }
+
+    /// Checks that `0 < min_size <= avg_size <= max_size <= block_size`, and that the
+    /// average size is at least 64 bytes.
+    pub(crate) fn validate(&self, block_size: usize) -> Result<()> {
+        if self.min_size == 0
+            || self.avg_size < 64
+            || self.min_size > self.avg_size
+            || self.avg_size > self.max_size
+            || self.max_size > block_size
+        {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid chunk sizes {}/{}/{} for block size {block_size}",
+                self.min_size, self.avg_size, self.max_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Finds content-defined block boundaries in data fed piece by piece.
+pub(crate) struct Chunker {
+    chunking: Chunking,
+    /// Mask of the hash before reaching the average size; harder to match.
+    mask_small: u64,
+    /// Mask of the hash after reaching the average size; easier to match.
+    mask_large: u64,
+    hash: u64,
+}
+
+impl Chunker {
+    /// The options are expected to be [validated](Chunking::validate).
+    pub(crate) fn new(chunking: Chunking) -> Self {
+        // the high bits of the hash depend on the last 64 bytes; the low ones on fewer
+        let bits = chunking.avg_size.ilog2();
+        let mask = |bits: u32| !0_u64 << (64 - bits);
+        Self {
+            chunking,
+            mask_small: mask(bits + 1),
+            mask_large: mask(bits - 1),
+            hash: 0,
+        }
+    }
+
+    pub(crate) fn max_size(&self) -> usize {
+        self.chunking.max_size
+    }
+
+    pub(crate) fn chunking(&self) -> &Chunking {
+        &self.chunking
+    }
+
+    /// Scans `data`, which follows `block_len` bytes of the current block, and returns where
+    /// in `data` the block ends, if it does. This includes reaching the maximum size.
+    pub(crate) fn scan(&mut self, block_len: usize, data: &[u8]) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            let len = block_len + i + 1;
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            if len < self.chunking.min_size {
+                continue;
+            }
+            let mask = if len < self.chunking.avg_size {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if self.hash & mask == 0 || len >= self.chunking.max_size {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    /// Starts over for a new block.
+    pub(crate) fn reset(&mut self) {
+        self.hash = 0;
+    }
+}
@@ -0,0 +1,121 @@
+//! Format-agnostic codec trait, for applications supporting several compression formats.
+//!
+//! [`StreamCodec`] is object-safe, so an application can keep its formats as
+//! `Box<dyn StreamCodec>`, pick one by the file extension or the magic number, and wrap its
+//! readers and writers without knowing which format it is. bzip3 implements it through
+//! [`Bz3Options`]; the other formats take a small adapter over their own crates.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use bzip3::codec::StreamCodec;
+//! use bzip3::options::Bz3Options;
+//!
+//! let codecs: Vec<Box<dyn StreamCodec>> = vec![Box::new(Bz3Options::default())];
+//! let codec = codecs.iter().find(|x| x.extension() == "bz3").unwrap();
+//!
+//! let mut compressed = Vec::new();
+//! let mut writer = codec.wrap_writer(Box::new(&mut compressed)).unwrap();
+//! writer.write_all(b"hello, world").unwrap();
+//! writer.finish().unwrap();
+//!
+//! let codec = codecs.iter().find(|x| x.matches(&compressed)).unwrap();
+//! let mut reader = codec.wrap_reader(Box::new(compressed.as_slice())).unwrap();
+//! let mut contents = String::new();
+//! reader.read_to_string(&mut contents).unwrap();
+//! assert_eq!(contents, "hello, world");
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+
+use crate::errors::*;
+use crate::frame::{MAGIC_PREFIX, SKIPPABLE_MAGIC};
+use crate::options::Bz3Options;
+use crate::{write, MAGIC_NUMBER};
+
+/// A compression format, wrapping readers to decompress and writers to compress.
+pub trait StreamCodec: Send + Sync {
+    /// Name of the format, e.g. `"bzip3"`.
+    fn name(&self) -> &'static str;
+
+    /// File extension of the format, without the dot, e.g. `"bz3"`.
+    fn extension(&self) -> &'static str;
+
+    /// Bytes every stream of the format starts with.
+    fn magic(&self) -> &'static [u8];
+
+    /// Whether `header`, the first bytes of a stream, are of this format.
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(self.magic())
+    }
+
+    /// Wraps `reader`, reading the decompressed data of the stream it reads.
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> io::Result<Box<dyn Read + Send + 'a>>;
+
+    /// Wraps `writer`, compressing the data written into it.
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> io::Result<Box<dyn CodecWriter + Send + 'a>>;
+}
+
+/// A writer compressing into another, returned by [`StreamCodec::wrap_writer`].
+pub trait CodecWriter: Write {
+    /// Ends the stream, and flushes the inner writer.
+    ///
+    /// Dropping the writer instead may end the stream as well, but loses the errors.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W> CodecWriter for write::Bz3Encoder<W>
+where
+    W: Write,
+{
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        write::Bz3Encoder::finish(*self)?.flush()
+    }
+}
+
+/// bzip3 with the settings of the options; see [`Bz3Options::read_decoder`] and
+/// [`Bz3Options::write_encoder`].
+impl StreamCodec for Bz3Options {
+    fn name(&self) -> &'static str {
+        "bzip3"
+    }
+
+    fn extension(&self) -> &'static str {
+        "bz3"
+    }
+
+    fn magic(&self) -> &'static [u8] {
+        MAGIC_NUMBER
+    }
+
+    /// Also true of the other format versions, and of a leading
+    /// [skippable frame](crate::frame::SKIPPABLE_MAGIC), as with
+    /// [`MaybeBz3Decoder`](crate::read::MaybeBz3Decoder).
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(MAGIC_PREFIX) || header.starts_with(SKIPPABLE_MAGIC)
+    }
+
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> io::Result<Box<dyn Read + Send + 'a>> {
+        let decoder = self.read_decoder(reader).map_err(Error::into_io_error)?;
+        Ok(Box::new(decoder))
+    }
+
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> io::Result<Box<dyn CodecWriter + Send + 'a>> {
+        let encoder = self.write_encoder(writer).map_err(Error::into_io_error)?;
+        Ok(Box::new(encoder))
+    }
+}
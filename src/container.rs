@@ -0,0 +1,285 @@
+//! A simple container of many named bzip3 files.
+//!
+//! Each member is an independent bzip3 file, so any one of them can be read without touching
+//! the others, through the directory at the end of the container. [`ContainerWriter`] builds a
+//! container, and [`ContainerReader`] lists and opens its members.
+//!
+//! # Layout
+//!
+//! All integers are little-endian, offsets are relative to the start of the container, and
+//! names are UTF-8.
+//!
+//! \[ [`CONTAINER_MAGIC`] | version (u8) | member1 | member2 | memberN... | directory |
+//! directory offset (u64) | [`CONTAINER_MAGIC`] \]
+//!
+//! Each member is \[ name size (u16) | name | bzip3 file \], and the directory is
+//! \[ member count (u32) | entry1 | entry2 | entryN... \], where each entry is
+//! \[ name size (u16) | name | member offset (u64) | compressed size (u64) |
+//! uncompressed size (u64) \]. The compressed size is the size of the bzip3 file.
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::errors::*;
+use crate::{read, write};
+
+/// Signature at the start and at the end of a container.
+pub const CONTAINER_MAGIC: &[u8; 4] = b"BZ3c";
+
+/// Version of the container format.
+pub const CONTAINER_VERSION: u8 = 1;
+
+const HEADER_SIZE: u64 = CONTAINER_MAGIC.len() as u64 + 1 /* u8 */;
+const FOOTER_SIZE: u64 = 8 /* u64 */ + CONTAINER_MAGIC.len() as u64;
+
+/// A member of a container, as listed in its directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the member from the start of the container.
+    pub offset: u64,
+    /// Size of the member's bzip3 file.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Writer that counts the bytes written through it.
+struct CountingWriter<W> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.writer.write(buf)?;
+        self.count += size as u64;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn write_name<W: Write>(writer: &mut W, name: &str) -> Result<()> {
+    let size = u16::try_from(name.len())
+        .map_err(|_| Error::InvalidArgument(format!("Member name too long: {name}")))?;
+    writer.write_u16::<LE>(size)?;
+    writer.write_all(name.as_bytes())?;
+    Ok(())
+}
+
+fn read_name<R: Read>(reader: &mut R) -> Result<String> {
+    let mut name = vec![0_u8; reader.read_u16::<LE>()? as usize];
+    reader.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| corrupt())
+}
+
+fn corrupt() -> Error {
+    Error::ProcessBlock("Corrupt container".into())
+}
+
+/// Builds a container, one member after another.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use bzip3::container::{ContainerReader, ContainerWriter};
+///
+/// let mut writer = ContainerWriter::new(Vec::new()).unwrap();
+/// writer.add("a.txt", &b"hello"[..], 100 * 1024).unwrap();
+/// writer.add("b.txt", &b"world"[..], 100 * 1024).unwrap();
+/// let container = writer.finish().unwrap();
+///
+/// let mut reader = ContainerReader::new(Cursor::new(container)).unwrap();
+/// let mut contents = String::new();
+/// reader.open_by_name("b.txt").unwrap().read_to_string(&mut contents).unwrap();
+/// assert_eq!(contents, "world");
+/// ```
+pub struct ContainerWriter<W>
+where
+    W: Write,
+{
+    writer: CountingWriter<W>,
+    members: Vec<Member>,
+}
+
+impl<W> ContainerWriter<W>
+where
+    W: Write,
+{
+    /// Creates a container writer, writing the container header.
+    pub fn new(writer: W) -> Result<Self> {
+        let mut writer = CountingWriter { writer, count: 0 };
+        writer.write_all(CONTAINER_MAGIC)?;
+        writer.write_u8(CONTAINER_VERSION)?;
+        Ok(Self {
+            writer,
+            members: Vec::new(),
+        })
+    }
+
+    /// Returns the members added so far.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Compresses all the data from `reader` into a new member.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the name is taken or longer than 65535 bytes,
+    /// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors.
+    pub fn add<R: Read>(&mut self, name: &str, mut reader: R, block_size: usize) -> Result<()> {
+        if self.members.iter().any(|x| x.name == name) {
+            return Err(Error::InvalidArgument(format!(
+                "Duplicate member name: {name}"
+            )));
+        }
+        let offset = self.writer.count;
+        write_name(&mut self.writer, name)?;
+
+        let start = self.writer.count;
+        let mut encoder = write::Bz3Encoder::new(&mut self.writer, block_size)?;
+        let uncompressed_size = io::copy(&mut reader, &mut encoder)?;
+        encoder.finish()?;
+        self.members.push(Member {
+            name: name.into(),
+            offset,
+            compressed_size: self.writer.count - start,
+            uncompressed_size,
+        });
+        Ok(())
+    }
+
+    /// Writes the directory, and returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let directory_offset = self.writer.count;
+        let writer = &mut self.writer;
+        writer.write_u32::<LE>(self.members.len() as u32)?;
+        for member in &self.members {
+            write_name(writer, &member.name)?;
+            writer.write_u64::<LE>(member.offset)?;
+            writer.write_u64::<LE>(member.compressed_size)?;
+            writer.write_u64::<LE>(member.uncompressed_size)?;
+        }
+        writer.write_u64::<LE>(directory_offset)?;
+        writer.write_all(CONTAINER_MAGIC)?;
+        writer.flush()?;
+        Ok(self.writer.writer)
+    }
+}
+
+/// Reads the members of a container, in any order.
+pub struct ContainerReader<R>
+where
+    R: Read + Seek,
+{
+    reader: R,
+    members: Vec<Member>,
+}
+
+impl<R> ContainerReader<R>
+where
+    R: Read + Seek,
+{
+    /// Opens a container, reading its directory.
+    ///
+    /// The container is expected to start at position zero of `reader`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] if it's not a container, [`Error::ProcessBlock`] if the
+    /// version is unsupported or the directory is corrupt, and [`Error::Io`] on all IO errors.
+    pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut magic = [0_u8; CONTAINER_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != CONTAINER_MAGIC {
+            return Err(Error::InvalidSignature);
+        }
+        let version = reader.read_u8()?;
+        if version != CONTAINER_VERSION {
+            return Err(Error::ProcessBlock(format!(
+                "Unsupported container version: {version}"
+            )));
+        }
+
+        let size = reader.seek(SeekFrom::End(0))?;
+        if size < HEADER_SIZE + FOOTER_SIZE {
+            return Err(corrupt());
+        }
+        reader.seek(SeekFrom::Start(size - FOOTER_SIZE))?;
+        let directory_offset = reader.read_u64::<LE>()?;
+        reader.read_exact(&mut magic)?;
+        if &magic != CONTAINER_MAGIC || directory_offset > size - FOOTER_SIZE {
+            return Err(corrupt());
+        }
+
+        reader.seek(SeekFrom::Start(directory_offset))?;
+        let count = reader.read_u32::<LE>()?;
+        // don't trust the count for allocation
+        let mut members = Vec::with_capacity(count.min(1 << 16) as usize);
+        for _ in 0..count {
+            let member = Member {
+                name: read_name(&mut reader)?,
+                offset: reader.read_u64::<LE>()?,
+                compressed_size: reader.read_u64::<LE>()?,
+                uncompressed_size: reader.read_u64::<LE>()?,
+            };
+            let end = member.offset.checked_add(member.compressed_size);
+            if member.offset < HEADER_SIZE || end.is_none_or(|x| x > directory_offset) {
+                return Err(corrupt());
+            }
+            members.push(member);
+        }
+        Ok(Self { reader, members })
+    }
+
+    /// Returns the members, in the order they were added.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Returns the position of the member with the given name in
+    /// [`ContainerReader::members`].
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.members.iter().position(|x| x.name == name)
+    }
+
+    /// Opens the member at the given position of [`ContainerReader::members`], returning a
+    /// decoder of its data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn open(&mut self, index: usize) -> Result<read::Bz3Decoder<io::Take<&mut R>>> {
+        let member = &self.members[index];
+        self.reader.seek(SeekFrom::Start(member.offset))?;
+        if read_name(&mut self.reader)? != member.name {
+            return Err(corrupt());
+        }
+        read::Bz3Decoder::new((&mut self.reader).take(member.compressed_size))
+    }
+
+    /// Opens the member with the given name, returning a decoder of its data.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if there's no such member, and the errors of
+    /// [`ContainerReader::open`] otherwise.
+    pub fn open_by_name(&mut self, name: &str) -> Result<read::Bz3Decoder<io::Take<&mut R>>> {
+        let index = self
+            .find(name)
+            .ok_or_else(|| Error::InvalidArgument(format!("No such member: {name}")))?;
+        self.open(index)
+    }
+
+    /// Consumes the container reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -0,0 +1,390 @@
+//! `Content-Encoding: bzip3` for HTTP services, as tower middleware.
+//!
+//! [`Bz3Layer`] wraps a service so it decompresses request bodies sent with
+//! `Content-Encoding: bzip3`, and compresses response bodies for clients sending
+//! `Accept-Encoding: bzip3`. [`Bz3Body`] does the work on the bodies, and also serves clients
+//! directly, as it wraps any [`http_body::Body`], like hyper's `Incoming`.
+//!
+//! # Examples
+//!
+//! ```
+//! # tokio_test::block_on(async {
+//! use bytes::Bytes;
+//! use http::{header, Request, Response};
+//! use http_body_util::{BodyExt, Full};
+//! use tower::{service_fn, Layer, ServiceExt};
+//! use bzip3::content_encoding::{Bz3Body, Bz3Layer};
+//!
+//! let service = Bz3Layer::new().layer(service_fn(|_request: Request<_>| async {
+//!     Ok::<_, std::convert::Infallible>(Response::new(Full::new(Bytes::from("hello, world"))))
+//! }));
+//!
+//! let request = Request::builder()
+//!     .header(header::ACCEPT_ENCODING, "gzip, bzip3")
+//!     .body(Full::new(Bytes::new()))
+//!     .unwrap();
+//! let response = service.oneshot(request).await.unwrap();
+//! assert_eq!(response.headers()[header::CONTENT_ENCODING], "bzip3");
+//!
+//! let body = Bz3Body::decompress(response.into_body());
+//! let data = body.collect().await.unwrap().to_bytes();
+//! assert_eq!(data, "hello, world");
+//! # })
+//! ```
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, Bytes};
+use http::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY,
+};
+use http::{Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::errors::*;
+use crate::push;
+
+/// The `Content-Encoding` token of bzip3.
+pub const ENCODING: &str = "bzip3";
+
+/// Error type of [`Bz3Body`].
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Default block size of compressed responses, small enough for many responses at a time.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Default largest block size accepted in compressed requests, as the `bzip3` command's
+/// default block size.
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+enum Codec {
+    Identity,
+    Compress(Box<push::Encoder>),
+    Decompress(Box<push::Decoder>),
+}
+
+impl Codec {
+    fn output(&self) -> &[u8] {
+        match self {
+            Codec::Identity => &[],
+            Codec::Compress(encoder) => encoder.output(),
+            Codec::Decompress(decoder) => decoder.output(),
+        }
+    }
+
+    fn consume(&mut self, n: usize) {
+        match self {
+            Codec::Identity => {}
+            Codec::Compress(encoder) => encoder.consume(n),
+            Codec::Decompress(decoder) => decoder.consume(n),
+        }
+    }
+
+    fn feed(&mut self, input: &[u8]) -> Result<usize> {
+        match self {
+            Codec::Identity => unreachable!(),
+            Codec::Compress(encoder) => encoder.feed(input),
+            Codec::Decompress(decoder) => decoder.feed(input),
+        }
+    }
+
+    /// Ends the input; returns whether all the output has been produced.
+    fn finish(&mut self) -> Result<bool> {
+        match self {
+            Codec::Identity => Ok(true),
+            Codec::Compress(encoder) => {
+                encoder.finish()?;
+                Ok(encoder.is_finished())
+            }
+            Codec::Decompress(decoder) => decoder.finish().map(|_| true),
+        }
+    }
+}
+
+pin_project! {
+    /// HTTP body compressing or decompressing another body, or passing it through.
+    ///
+    /// Trailers of the inner body are passed on after the data.
+    pub struct Bz3Body<B> {
+        #[pin]
+        inner: B,
+        codec: Codec,
+        // the unconsumed part of the last data frame
+        chunk: Bytes,
+        trailers: Option<HeaderMap>,
+        input_end: bool,
+        done: bool,
+    }
+}
+
+impl<B> Bz3Body<B> {
+    fn with_codec(inner: B, codec: Codec) -> Self {
+        Self {
+            inner,
+            codec,
+            chunk: Bytes::new(),
+            trailers: None,
+            input_end: false,
+            done: false,
+        }
+    }
+
+    /// Passes `inner` through unchanged.
+    pub fn identity(inner: B) -> Self {
+        Self::with_codec(inner, Codec::Identity)
+    }
+
+    /// Compresses `inner`.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn compress(inner: B, block_size: usize) -> Result<Self> {
+        let encoder = push::Encoder::new(block_size)?;
+        Ok(Self::with_codec(inner, Codec::Compress(Box::new(encoder))))
+    }
+
+    /// Decompresses `inner`.
+    pub fn decompress(inner: B) -> Self {
+        Self::decompress_with_max_block_size(inner, crate::BLOCK_SIZE_MAX)
+    }
+
+    /// Decompresses `inner`, failing with [`Error::BlockSizeLimit`] if its block size is
+    /// above `limit`, which bounds the memory used.
+    pub fn decompress_with_max_block_size(inner: B, limit: usize) -> Self {
+        let mut decoder = push::Decoder::new();
+        decoder.set_max_block_size(limit);
+        Self::with_codec(inner, Codec::Decompress(Box::new(decoder)))
+    }
+}
+
+impl<B> Body for Bz3Body<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, BoxError>>> {
+        let mut this = self.project();
+        if let Codec::Identity = this.codec {
+            let frame = ready!(this.inner.poll_frame(cx));
+            return Poll::Ready(frame.map(|x| {
+                x.map(|x| x.map_data(|mut data| data.copy_to_bytes(data.remaining())))
+                    .map_err(Into::into)
+            }));
+        }
+        loop {
+            let output = this.codec.output();
+            if !output.is_empty() {
+                let data = Bytes::copy_from_slice(output);
+                this.codec.consume(data.len());
+                return Poll::Ready(Some(Ok(Frame::data(data))));
+            }
+            if *this.done {
+                return Poll::Ready(this.trailers.take().map(|x| Ok(Frame::trailers(x))));
+            }
+
+            if !this.chunk.is_empty() {
+                let size = this.codec.feed(this.chunk)?;
+                if size == 0 && this.codec.output().is_empty() {
+                    return Poll::Ready(Some(Err("bzip3 codec stalled".into())));
+                }
+                this.chunk.advance(size);
+            } else if *this.input_end {
+                *this.done = this.codec.finish()?;
+            } else {
+                match ready!(this.inner.as_mut().poll_frame(cx)) {
+                    Some(frame) => match frame.map_err(Into::<BoxError>::into)?.into_data() {
+                        Ok(mut data) => *this.chunk = data.copy_to_bytes(data.remaining()),
+                        Err(frame) => {
+                            if let Ok(trailers) = frame.into_trailers() {
+                                *this.trailers = Some(trailers);
+                            }
+                        }
+                    },
+                    None => *this.input_end = true,
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.codec {
+            Codec::Identity => self.inner.is_end_stream(),
+            _ => self.done && self.codec.output().is_empty() && self.trailers.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.codec {
+            Codec::Identity => self.inner.size_hint(),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+/// Whether an `Accept-Encoding` header value accepts bzip3.
+fn accepts_bzip3(value: &HeaderValue) -> bool {
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    value.split(',').any(|x| {
+        let mut params = x.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        // `q=0` means not acceptable
+        let refused = params.any(|x| {
+            x.strip_prefix("q=")
+                .and_then(|x| x.parse::<f32>().ok())
+                .is_some_and(|x| x == 0.0)
+        });
+        name.eq_ignore_ascii_case(ENCODING) && !refused
+    })
+}
+
+/// Layer applying [`Bz3Service`].
+#[derive(Debug, Clone, Copy)]
+pub struct Bz3Layer {
+    block_size: usize,
+    max_block_size: usize,
+}
+
+impl Default for Bz3Layer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bz3Layer {
+    /// Creates a layer with the block size [`DEFAULT_BLOCK_SIZE`] and the block size limit
+    /// [`DEFAULT_MAX_BLOCK_SIZE`].
+    pub fn new() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_block_size: DEFAULT_MAX_BLOCK_SIZE,
+        }
+    }
+
+    /// Sets the block size of compressed responses.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn block_size(mut self, block_size: usize) -> Result<Self> {
+        if !crate::Bz3State::check_block_size(block_size) {
+            return Err(Error::BlockSize);
+        }
+        self.block_size = block_size;
+        Ok(self)
+    }
+
+    /// Sets the largest block size accepted in compressed requests, which bounds the memory
+    /// used by each request.
+    pub fn max_block_size(mut self, limit: usize) -> Self {
+        self.max_block_size = limit;
+        self
+    }
+}
+
+impl<S> Layer<S> for Bz3Layer {
+    type Service = Bz3Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Bz3Service {
+            inner,
+            layer: *self,
+        }
+    }
+}
+
+/// Service decompressing requests and compressing responses; see the
+/// [module documentation](self).
+///
+/// Responses which already have a `Content-Encoding` are left alone.
+#[derive(Debug, Clone)]
+pub struct Bz3Service<S> {
+    inner: S,
+    layer: Bz3Layer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Bz3Service<S>
+where
+    S: Service<Request<Bz3Body<ReqBody>>, Response = Response<ResBody>>,
+{
+    type Response = Response<Bz3Body<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let compress = request
+            .headers()
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .any(accepts_bzip3);
+        let (mut parts, body) = request.into_parts();
+        let body = if parts
+            .headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|x| x.as_bytes().eq_ignore_ascii_case(ENCODING.as_bytes()))
+        {
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            Bz3Body::decompress_with_max_block_size(body, self.layer.max_block_size)
+        } else {
+            Bz3Body::identity(body)
+        };
+        ResponseFuture {
+            inner: self.inner.call(Request::from_parts(parts, body)),
+            block_size: compress.then_some(self.layer.block_size),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future of [`Bz3Service`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        // set if the response is to be compressed
+        block_size: Option<usize>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = std::result::Result<Response<B>, E>>,
+{
+    type Output = std::result::Result<Response<Bz3Body<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let (mut parts, body) = response.into_parts();
+        let body = match *this.block_size {
+            Some(block_size) if !parts.headers.contains_key(CONTENT_ENCODING) => {
+                let headers = &mut parts.headers;
+                headers.insert(CONTENT_ENCODING, HeaderValue::from_static(ENCODING));
+                headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+                headers.remove(CONTENT_LENGTH);
+                // the block size has been validated by the layer
+                Bz3Body::compress(body, block_size).unwrap()
+            }
+            _ => Bz3Body::identity(body),
+        };
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
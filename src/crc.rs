@@ -0,0 +1,36 @@
+//! The CRC32C checksum libbz3 stores in every block, computed independently of libbz3.
+
+use byteorder::{ByteOrder, LE};
+
+/// Lookup table of the reflected Castagnoli polynomial.
+const TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the CRC of a block's uncompressed data, the way libbz3 does.
+pub(crate) fn block_crc(data: &[u8]) -> u32 {
+    data.iter().fold(1, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Returns the CRC stored at the start of a block's compressed data.
+pub(crate) fn stored_crc(compressed: &[u8]) -> Option<u32> {
+    compressed.get(..4).map(LE::read_u32)
+}
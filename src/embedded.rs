@@ -0,0 +1,303 @@
+//! Codecs over [`embedded_io`] readers and writers, for firmware without std.
+//!
+//! These are the counterparts of [`read::Bz3Decoder`] and [`write::Bz3Encoder`], built on the
+//! [`push`] codecs, and need no more than `alloc`: the block buffer and the libbz3 state are
+//! allocated on the heap, which takes about six times the block size, so pick the smallest
+//! block size that compresses well enough, down to [`BLOCK_SIZE_MIN`], and bound the block size
+//! of incoming files with [`Bz3Decoder::with_max_block_size`].
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_io::{Read, Write};
+//! use bzip3::embedded::{Bz3Decoder, Bz3Encoder};
+//!
+//! let mut compressed = [0_u8; 1024];
+//! let mut writer = &mut compressed[..];
+//! let mut encoder = Bz3Encoder::new(&mut writer, 65 * 1024).unwrap();
+//! encoder.write_all(b"hello, world").unwrap();
+//! encoder.finish().unwrap();
+//! let size = 1024 - writer.len();
+//!
+//! let mut decoder = Bz3Decoder::with_max_block_size(&compressed[..size], 65 * 1024).unwrap();
+//! let mut decompressed = [0_u8; 12];
+//! decoder.read_exact(&mut decompressed).unwrap();
+//! assert_eq!(&decompressed, b"hello, world");
+//! ```
+//!
+//! [`read::Bz3Decoder`]: crate::read::Bz3Decoder
+//! [`write::Bz3Encoder`]: crate::write::Bz3Encoder
+//! [`BLOCK_SIZE_MIN`]: crate::BLOCK_SIZE_MIN
+
+use core::fmt;
+
+use embedded_io::{ErrorKind, ErrorType, Read, Write};
+
+use crate::push;
+
+/// Error of the embedded codecs: of the underlying reader or writer, or of the bzip3 stream.
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E> {
+    #[error("IO error: {0:?}")]
+    Io(E),
+    #[error(transparent)]
+    Bz3(#[from] crate::Error),
+}
+
+impl<E> embedded_io::Error for Error<E>
+where
+    E: embedded_io::Error,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(e) => e.kind(),
+            Error::Bz3(crate::Error::Timeout(_)) => ErrorKind::TimedOut,
+            Error::Bz3(crate::Error::UnsupportedVersion(_)) => ErrorKind::Unsupported,
+            Error::Bz3(crate::Error::InvalidArgument(_)) => ErrorKind::InvalidInput,
+            Error::Bz3(
+                crate::Error::ChecksumMismatch { .. }
+                | crate::Error::BadCrc { .. }
+                | crate::Error::VerifyFailed { .. }
+                | crate::Error::TruncatedBlock { .. }
+                | crate::Error::TrailingData { .. }
+                | crate::Error::MisplacedFrameHeader { .. },
+            ) => ErrorKind::InvalidData,
+            Error::Bz3(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// Compressor writing to an [`embedded_io::Write`].
+///
+/// Call [`Bz3Encoder::finish`] at the end; unlike [`write::Bz3Encoder`], this doesn't finish
+/// the stream when dropped, as there's no way to tell about errors then.
+///
+/// [`write::Bz3Encoder`]: crate::write::Bz3Encoder
+pub struct Bz3Encoder<W>
+where
+    W: Write,
+{
+    writer: W,
+    encoder: push::Encoder,
+}
+
+impl<W> fmt::Debug for Bz3Encoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Encoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3Encoder<W>
+where
+    W: Write,
+{
+    /// Creates a new bzip3 stream encoder.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`](crate::Error::BlockSize) if the block size is invalid.
+    pub fn new(writer: W, block_size: usize) -> crate::Result<Self> {
+        Ok(Self {
+            writer,
+            encoder: push::Encoder::new(block_size)?,
+        })
+    }
+
+    /// The [`push::Encoder`] within, e.g. to [set a checksum](push::Encoder::set_checksum)
+    /// before writing anything.
+    pub fn encoder_mut(&mut self) -> &mut push::Encoder {
+        &mut self.encoder
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Compresses the partial block, writes the rest of the stream, flushes the writer, and
+    /// returns it.
+    pub fn finish(mut self) -> Result<W, Error<W::Error>> {
+        loop {
+            self.write_output()?;
+            if self.encoder.is_finished() {
+                break;
+            }
+            self.encoder.finish()?;
+        }
+        self.writer.flush().map_err(Error::Io)?;
+        Ok(self.writer)
+    }
+
+    /// Writes the pending output; what's written is consumed right away, for a failed call
+    /// not to have it written twice by the next one.
+    fn write_output(&mut self) -> Result<(), Error<W::Error>> {
+        while !self.encoder.output().is_empty() {
+            let output = self.encoder.output();
+            let size = self.writer.write(output).map_err(Error::Io)?;
+            // as `write_all` does
+            assert!(size != 0, "write() returned Ok(0)");
+            self.encoder.consume(size);
+        }
+        Ok(())
+    }
+}
+
+impl<W> ErrorType for Bz3Encoder<W>
+where
+    W: Write,
+{
+    type Error = Error<W::Error>;
+}
+
+impl<W> Write for Bz3Encoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // output left over from a failed write goes first
+        self.write_output()?;
+        let write_size = self.encoder.feed(buf)?;
+        self.write_output()?;
+        Ok(write_size)
+    }
+
+    /// Compresses the partial block and writes it; this makes the data so far decodable, at
+    /// the cost of a smaller block.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        loop {
+            self.encoder.flush()?;
+            if self.encoder.output().is_empty() {
+                break;
+            }
+            self.write_output()?;
+        }
+        self.writer.flush().map_err(Error::Io)
+    }
+}
+
+/// Decompressor reading from an [`embedded_io::Read`].
+pub struct Bz3Decoder<R>
+where
+    R: Read,
+{
+    reader: R,
+    decoder: push::Decoder,
+    /// Underlying `reader` EOF indicator.
+    eof: bool,
+}
+
+impl<R> fmt::Debug for Bz3Decoder<R>
+where
+    R: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("eof", &self.eof).finish_non_exhaustive()
+    }
+}
+
+impl<R> Bz3Decoder<R>
+where
+    R: Read,
+{
+    /// Creates a bzip3 decoder, reading the file header.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`](crate::Error::InvalidSignature) for invalid file header
+    /// signature, and [`Error::Io`] on all IO errors.
+    pub fn new(reader: R) -> Result<Self, Error<R::Error>> {
+        Self::with_max_block_size(reader, crate::BLOCK_SIZE_MAX)
+    }
+
+    /// Creates a bzip3 decoder, rejecting files with a block size above `limit`, which bounds
+    /// the memory allocated.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSizeLimit`](crate::Error::BlockSizeLimit) if the block size is above
+    /// `limit`, and the same as [`Bz3Decoder::new`] otherwise.
+    pub fn with_max_block_size(reader: R, limit: usize) -> Result<Self, Error<R::Error>> {
+        let mut decoder = Self {
+            reader,
+            decoder: push::Decoder::new(),
+            eof: false,
+        };
+        decoder.decoder.set_max_block_size(limit);
+        while decoder.decoder.block_size().is_none() {
+            if !decoder.fill()? {
+                decoder.decoder.finish()?;
+            }
+        }
+        Ok(decoder)
+    }
+
+    /// The [`push::Decoder`] within, e.g. for the [metadata](push::Decoder::metadata).
+    pub fn decoder(&self) -> &push::Decoder {
+        &self.decoder
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads more input into the decoder; false at the end of the input.
+    fn fill(&mut self) -> Result<bool, Error<R::Error>> {
+        let buffer = self.decoder.input_buffer();
+        let read_size = self.reader.read(buffer).map_err(Error::Io)?;
+        if read_size == 0 {
+            return Ok(false);
+        }
+        self.decoder.advance(read_size)?;
+        Ok(true)
+    }
+}
+
+impl<R> ErrorType for Bz3Decoder<R>
+where
+    R: Read,
+{
+    type Error = Error<R::Error>;
+}
+
+impl<R> Read for Bz3Decoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            // empty blocks produce no output; keep going until there's some, or EOF
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.len());
+                buf[..size].copy_from_slice(&output[..size]);
+                self.decoder.consume(size);
+                return Ok(size);
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            if !self.fill()? {
+                self.decoder.finish()?;
+                self.eof = true;
+            }
+        }
+    }
+}
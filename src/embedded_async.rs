@@ -0,0 +1,270 @@
+//! Codecs over [`embedded_io_async`] readers and writers, for async executors without std,
+//! like embassy, streaming bzip3 data over a UART or a socket.
+//!
+//! These are the async counterparts of the [`embedded`](crate::embedded) codecs, with the same
+//! [`Error`] and the same memory use; only the IO is awaited, compressing and decompressing a
+//! block runs to completion within a poll.
+//!
+//! # Examples
+//!
+//! ```
+//! use embedded_io_async::{Read, Write};
+//! use bzip3::embedded_async::{Bz3Decoder, Bz3Encoder};
+//!
+//! # futures::executor::block_on(async {
+//! let mut compressed = [0_u8; 1024];
+//! let mut writer = &mut compressed[..];
+//! let mut encoder = Bz3Encoder::new(&mut writer, 65 * 1024).unwrap();
+//! encoder.write_all(b"hello, world").await.unwrap();
+//! encoder.finish().await.unwrap();
+//! let size = 1024 - writer.len();
+//!
+//! let mut decoder = Bz3Decoder::with_max_block_size(&compressed[..size], 65 * 1024)
+//!     .await
+//!     .unwrap();
+//! let mut decompressed = [0_u8; 12];
+//! decoder.read_exact(&mut decompressed).await.unwrap();
+//! assert_eq!(&decompressed, b"hello, world");
+//! # });
+//! ```
+
+use core::fmt;
+
+use embedded_io_async::{ErrorType, Read, Write};
+
+pub use crate::embedded::Error;
+use crate::push;
+
+/// Async compressor writing to an [`embedded_io_async::Write`].
+///
+/// Call [`Bz3Encoder::finish`] at the end; the stream isn't finished when dropped.
+pub struct Bz3Encoder<W>
+where
+    W: Write,
+{
+    writer: W,
+    encoder: push::Encoder,
+}
+
+impl<W> fmt::Debug for Bz3Encoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Encoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3Encoder<W>
+where
+    W: Write,
+{
+    /// Creates a new bzip3 stream encoder.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`](crate::Error::BlockSize) if the block size is invalid.
+    pub fn new(writer: W, block_size: usize) -> crate::Result<Self> {
+        Ok(Self {
+            writer,
+            encoder: push::Encoder::new(block_size)?,
+        })
+    }
+
+    /// The [`push::Encoder`] within, e.g. to [set a checksum](push::Encoder::set_checksum)
+    /// before writing anything.
+    pub fn encoder_mut(&mut self) -> &mut push::Encoder {
+        &mut self.encoder
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Compresses the partial block, writes the rest of the stream, flushes the writer, and
+    /// returns it.
+    pub async fn finish(mut self) -> Result<W, Error<W::Error>> {
+        loop {
+            self.write_output().await?;
+            if self.encoder.is_finished() {
+                break;
+            }
+            self.encoder.finish()?;
+        }
+        self.writer.flush().await.map_err(Error::Io)?;
+        Ok(self.writer)
+    }
+
+    /// Writes the pending output; what's written is consumed right away, for a cancelled call
+    /// not to write it twice.
+    async fn write_output(&mut self) -> Result<(), Error<W::Error>> {
+        while !self.encoder.output().is_empty() {
+            let output = self.encoder.output();
+            let size = self.writer.write(output).await.map_err(Error::Io)?;
+            // as `write_all` does
+            assert!(size != 0, "write() returned Ok(0)");
+            self.encoder.consume(size);
+        }
+        Ok(())
+    }
+}
+
+impl<W> ErrorType for Bz3Encoder<W>
+where
+    W: Write,
+{
+    type Error = Error<W::Error>;
+}
+
+impl<W> Write for Bz3Encoder<W>
+where
+    W: Write,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // output left over from a failed or cancelled write goes first
+        self.write_output().await?;
+        let write_size = self.encoder.feed(buf)?;
+        self.write_output().await?;
+        Ok(write_size)
+    }
+
+    /// Compresses the partial block and writes it; this makes the data so far decodable, at
+    /// the cost of a smaller block.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        loop {
+            self.encoder.flush()?;
+            if self.encoder.output().is_empty() {
+                break;
+            }
+            self.write_output().await?;
+        }
+        self.writer.flush().await.map_err(Error::Io)
+    }
+}
+
+/// Async decompressor reading from an [`embedded_io_async::Read`].
+pub struct Bz3Decoder<R>
+where
+    R: Read,
+{
+    reader: R,
+    decoder: push::Decoder,
+    /// Underlying `reader` EOF indicator.
+    eof: bool,
+}
+
+impl<R> fmt::Debug for Bz3Decoder<R>
+where
+    R: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("eof", &self.eof).finish_non_exhaustive()
+    }
+}
+
+impl<R> Bz3Decoder<R>
+where
+    R: Read,
+{
+    /// Creates a bzip3 decoder, reading the file header.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`](crate::Error::InvalidSignature) for invalid file header
+    /// signature, and [`Error::Io`] on all IO errors.
+    pub async fn new(reader: R) -> Result<Self, Error<R::Error>> {
+        Self::with_max_block_size(reader, crate::BLOCK_SIZE_MAX).await
+    }
+
+    /// Creates a bzip3 decoder, rejecting files with a block size above `limit`, which bounds
+    /// the memory allocated.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSizeLimit`](crate::Error::BlockSizeLimit) if the block size is above
+    /// `limit`, and the same as [`Bz3Decoder::new`] otherwise.
+    pub async fn with_max_block_size(reader: R, limit: usize) -> Result<Self, Error<R::Error>> {
+        let mut decoder = Self {
+            reader,
+            decoder: push::Decoder::new(),
+            eof: false,
+        };
+        decoder.decoder.set_max_block_size(limit);
+        while decoder.decoder.block_size().is_none() {
+            if !decoder.fill().await? {
+                decoder.decoder.finish()?;
+            }
+        }
+        Ok(decoder)
+    }
+
+    /// The [`push::Decoder`] within, e.g. for the [metadata](push::Decoder::metadata).
+    pub fn decoder(&self) -> &push::Decoder {
+        &self.decoder
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads more input into the decoder; false at the end of the input.
+    ///
+    /// Cancelling this loses nothing: the input only counts once read.
+    async fn fill(&mut self) -> Result<bool, Error<R::Error>> {
+        let buffer = self.decoder.input_buffer();
+        let read_size = self.reader.read(buffer).await.map_err(Error::Io)?;
+        if read_size == 0 {
+            return Ok(false);
+        }
+        self.decoder.advance(read_size)?;
+        Ok(true)
+    }
+}
+
+impl<R> ErrorType for Bz3Decoder<R>
+where
+    R: Read,
+{
+    type Error = Error<R::Error>;
+}
+
+impl<R> Read for Bz3Decoder<R>
+where
+    R: Read,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            // empty blocks produce no output; keep going until there's some, or EOF
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.len());
+                buf[..size].copy_from_slice(&output[..size]);
+                self.decoder.consume(size);
+                return Ok(size);
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            if !self.fill().await? {
+                self.decoder.finish()?;
+                self.eof = true;
+            }
+        }
+    }
+}
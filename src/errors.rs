@@ -1,26 +1,113 @@
+use alloc::string::String;
+use core::time::Duration;
+#[cfg(feature = "std")]
 use std::io;
-use std::io::ErrorKind;
 use thiserror::Error;
 
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[cfg(feature = "std")]
     #[error("{0}")]
     Io(#[from] io::Error),
     #[error("Invalid block size: must be between 65kiB and 511MiB")]
     BlockSize,
+    /// The block size in the file header is above the limit set on the decoder.
+    #[error("Block size {block_size} exceeds the limit of {limit}")]
+    BlockSizeLimit { block_size: usize, limit: usize },
     #[error("{0}")]
     ProcessBlock(String),
+    /// An argument is invalid, e.g. a compression level out of range, or a seek index whose
+    /// parts don't fit together.
+    #[error("{0}")]
+    InvalidArgument(String),
     #[error("Invalid file signature")]
     InvalidSignature,
+    /// The file is of a [format version](crate::frame::FormatVersion) this crate doesn't
+    /// support, e.g. 2 for `BZ3v2`, or a seek index file of an unknown version.
+    #[error("Unsupported file format version {0}")]
+    UnsupportedVersion(u8),
+    /// A block failed its CRC check; `block_index` counts the blocks of the stream from zero,
+    /// leaving out extension blocks.
+    #[error("CRC check failed in block {block_index}")]
+    BadCrc { block_index: usize },
+    /// A block's decompressed data no longer matches its CRC after being decoded, which means
+    /// it got corrupted in memory. Only checked in paranoid mode.
+    ///
+    /// `block_index` is numbered as in [`Error::BadCrc`].
+    #[error("Verification after decoding failed in block {block_index}")]
+    VerifyFailed { block_index: usize },
+    /// The input ends inside a block, with `have` of the `need` bytes of the block, header
+    /// included.
+    ///
+    /// `block_index` is numbered as in [`Error::BadCrc`]; for an extension block, it's the
+    /// number of the next block.
+    #[error("Truncated block {block_index}: {have} of {need} bytes")]
+    TruncatedBlock {
+        block_index: usize,
+        have: usize,
+        need: usize,
+    },
+    /// Something other than a block follows the last valid block, at the given offset of the
+    /// input.
+    ///
+    /// This is either garbage appended to the file, or a damaged block header.
+    #[error("Trailing data at offset {offset}")]
+    TrailingData { offset: u64 },
+    /// A file header appears where a block or its data was expected, at the given offset of the
+    /// input.
+    ///
+    /// This is the usual result of files concatenated or spliced together. Decoding the rest
+    /// can start over at the offset, or [`Bz3RecoveringDecoder`](crate::recover::Bz3RecoveringDecoder)
+    /// can take it all.
+    #[error("Misplaced file header at offset {offset}")]
+    MisplacedFrameHeader { offset: u64 },
+    /// The uncompressed data doesn't match the [checksum](crate::frame::CHECKSUM_TAG) stored in
+    /// the file.
+    #[error("Checksum mismatch: expected {expected:#018x}, got {actual:#018x}")]
+    ChecksumMismatch { expected: u64, actual: u64 },
+    /// The input made no progress for the given duration.
+    #[error("No progress within {0:?}")]
+    Timeout(Duration),
+}
+
+impl Error {
+    /// The input ending where more is expected: an [`Error::Io`] of kind
+    /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) with std, and an [`Error::ProcessBlock`]
+    /// without.
+    pub(crate) fn unexpected_eof(message: &str) -> Self {
+        #[cfg(feature = "std")]
+        return Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, message));
+        #[cfg(not(feature = "std"))]
+        return Error::ProcessBlock(message.into());
+    }
 }
 
+#[cfg(feature = "std")]
 impl Error {
     pub(crate) fn into_io_error(self) -> io::Error {
         match self {
             Error::Io(e) => e,
-            e => io::Error::new(ErrorKind::Other, e),
+            e @ Error::Timeout(_) => io::Error::new(io::ErrorKind::TimedOut, e),
+            e @ Error::TruncatedBlock { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            e @ Error::UnsupportedVersion(_) => io::Error::new(io::ErrorKind::Unsupported, e),
+            e @ Error::InvalidArgument(_) => io::Error::new(io::ErrorKind::InvalidInput, e),
+            e @ (Error::ChecksumMismatch { .. }
+            | Error::BadCrc { .. }
+            | Error::VerifyFailed { .. }
+            | Error::TrailingData { .. }
+            | Error::MisplacedFrameHeader { .. }) => io::Error::new(io::ErrorKind::InvalidData, e),
+            e => io::Error::other(e),
+        }
+    }
+
+    /// Recovers the error wrapped by [`Error::into_io_error`], and wraps other IO errors in
+    /// [`Error::Io`].
+    pub(crate) fn from_io_error(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|x| x.is::<Error>()) {
+            return *e.into_inner().unwrap().downcast::<Error>().unwrap();
         }
+        Error::Io(e)
     }
 }
@@ -0,0 +1,678 @@
+//! Structure of a bzip3 frame: the file header and the block headers.
+//!
+//! See the [crate-level documentation](crate) for the layout.
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::ops::Range;
+
+use byteorder::{ByteOrder, LE};
+
+use crate::errors::*;
+#[cfg(feature = "std")]
+use crate::TryReadExact;
+use crate::{bound, Bz3State, MAGIC_NUMBER};
+
+/// Start of the magic number, which is followed by the [version](FormatVersion) digit.
+pub const MAGIC_PREFIX: &[u8; 4] = b"BZ3v";
+
+/// Size of the file header: magic number and block size.
+pub const FRAME_HEADER_SIZE: usize = MAGIC_NUMBER.len() + 4 /* i32 */;
+
+/// Size of a block header: new size and read size.
+pub const BLOCK_HEADER_SIZE: usize = 2 * 4 /* i32 */;
+
+/// `new_size` marking an extension block, which carries metadata instead of compressed data.
+///
+/// The `read_size` of an extension block is the size of the extension data that follows,
+/// which starts with a tag of [`EXTENSION_TAG_SIZE`] bytes telling the kind of the extension.
+/// Decoders skip extension blocks they don't know.
+pub const EXTENSION_BLOCK: i32 = -1;
+
+/// `new_size` marking a stored block, which holds its `read_size` bytes of data as they are,
+/// uncompressed.
+///
+/// Encoders write stored blocks only when asked to, for blocks that would grow by compressing
+/// them. Unlike extension blocks, they can't be skipped: decoders not knowing them fail on them
+/// as on an invalid block header, rather than losing the data.
+pub const STORED_BLOCK: i32 = -2;
+
+/// Size of the tag at the start of extension data.
+pub const EXTENSION_TAG_SIZE: usize = 4;
+
+/// Tag of the checksum extension, which holds the xxh3 64-bit hash (u64) of all the
+/// uncompressed data of the frame before it.
+///
+/// Encoders write it as the last block when asked to, and decoders reading the frame
+/// sequentially verify it.
+pub const CHECKSUM_TAG: &[u8; EXTENSION_TAG_SIZE] = b"CSUM";
+
+/// Size of the checksum extension data, tag included.
+pub const CHECKSUM_EXTENSION_SIZE: usize = EXTENSION_TAG_SIZE + 8 /* u64 */;
+
+/// Signature of a skippable frame, which holds data of an application instead of compressed
+/// data.
+///
+/// A skippable frame is \[ `BZ3s` | tag (u8) | payload size (u32) | payload \]; the tag is free
+/// for the application to use. It may stand wherever a file header may: before the file header,
+/// or after the last block of a file. The stream decoders of this crate, and
+/// [`mem::decompress_parallel`](crate::mem::decompress_parallel), skip it; the tools working on
+/// the blocks of a single file, like the [seek](crate::seek) module, expect the file to start
+/// with its file header though.
+pub const SKIPPABLE_MAGIC: &[u8; 4] = b"BZ3s";
+
+/// Size of the header of a skippable frame, the same as [`FRAME_HEADER_SIZE`].
+pub const SKIPPABLE_HEADER_SIZE: usize = SKIPPABLE_MAGIC.len() + 1 /* u8 */ + 4 /* u32 */;
+
+/// A skippable frame; see [`SKIPPABLE_MAGIC`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use bzip3::frame::SkippableFrame;
+///
+/// let mut output = Vec::new();
+/// let frame = SkippableFrame {
+///     tag: 1,
+///     payload: b"application data".to_vec(),
+/// };
+/// frame.write_to(&mut output).unwrap();
+/// let mut encoder = bzip3::write::Bz3Encoder::new(&mut output, 100 * 1024).unwrap();
+/// encoder.write_all(b"hello, world").unwrap();
+/// encoder.finish().unwrap();
+///
+/// assert_eq!(bzip3::mem::decompress(&output).unwrap(), b"hello, world");
+/// assert_eq!(SkippableFrame::read_from(&mut output.as_slice()).unwrap(), frame);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippableFrame {
+    pub tag: u8,
+    pub payload: Vec<u8>,
+}
+
+impl SkippableFrame {
+    /// Parses the header of a skippable frame, returning the tag and the payload size.
+    pub fn parse_header(bytes: &[u8; SKIPPABLE_HEADER_SIZE]) -> Option<(u8, usize)> {
+        if !bytes.starts_with(SKIPPABLE_MAGIC) {
+            return None;
+        }
+        let tag = bytes[SKIPPABLE_MAGIC.len()];
+        let size = LE::read_u32(&bytes[(SKIPPABLE_MAGIC.len() + 1)..]);
+        Some((tag, size as usize))
+    }
+
+    /// Reads a whole skippable frame.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] if it's not a skippable frame, and [`Error::Io`] on all IO
+    /// errors.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut header = [0_u8; SKIPPABLE_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let (tag, size) = Self::parse_header(&header).ok_or(Error::InvalidSignature)?;
+        let mut payload = Vec::new();
+        reader.take(size as u64).read_to_end(&mut payload)?;
+        if payload.len() != size {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(Self { tag, payload })
+    }
+
+    /// Writes the frame; the payload must be smaller than 4 GiB.
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let size = u32::try_from(self.payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Payload too large"))?;
+        writer.write_all(SKIPPABLE_MAGIC)?;
+        writer.write_all(&[self.tag])?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(&self.payload)
+    }
+}
+
+/// Version of the file format: the digit ending the magic number, as in `BZ3v1`.
+///
+/// Files of a version this crate doesn't know are rejected with
+/// [`Error::UnsupportedVersion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum FormatVersion {
+    /// `BZ3v1`, the only version so far.
+    #[default]
+    V1,
+}
+
+impl FormatVersion {
+    /// Returns the version with the given number.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::UnsupportedVersion`] if it's unknown.
+    pub fn from_number(number: u8) -> Result<Self> {
+        match number {
+            1 => Ok(Self::V1),
+            _ => Err(Error::UnsupportedVersion(number)),
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        match self {
+            Self::V1 => 1,
+        }
+    }
+}
+
+/// Header at the start of every bzip3 file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameHeader {
+    pub version: FormatVersion,
+    pub block_size: usize,
+}
+
+impl FrameHeader {
+    /// Creates a header of the current version, validating the block size.
+    pub fn new(block_size: usize) -> Result<Self> {
+        if !Bz3State::check_block_size(block_size) {
+            return Err(Error::BlockSize);
+        }
+        Ok(Self {
+            version: FormatVersion::default(),
+            block_size,
+        })
+    }
+
+    /// Parses a header from its serialized form.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] if the magic number doesn't match,
+    /// [`Error::UnsupportedVersion`] if it does but with an unknown version, and
+    /// [`Error::BlockSize`] if the block size is out of range.
+    pub fn parse(bytes: &[u8; FRAME_HEADER_SIZE]) -> Result<Self> {
+        let digit = bytes[MAGIC_PREFIX.len()];
+        if !bytes.starts_with(MAGIC_PREFIX) || !digit.is_ascii_digit() {
+            return Err(Error::InvalidSignature);
+        }
+        let version = FormatVersion::from_number(digit - b'0')?;
+        let block_size = LE::read_i32(&bytes[MAGIC_NUMBER.len()..]);
+        if block_size < 0 {
+            return Err(Error::BlockSize);
+        }
+        Ok(Self {
+            version,
+            ..Self::new(block_size as usize)?
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut bytes = [0_u8; FRAME_HEADER_SIZE];
+        bytes[..MAGIC_PREFIX.len()].copy_from_slice(MAGIC_PREFIX);
+        bytes[MAGIC_PREFIX.len()] = b'0' + self.version.number();
+        LE::write_i32(&mut bytes[MAGIC_NUMBER.len()..], self.block_size as i32);
+        bytes
+    }
+
+    /// Reads and parses a header.
+    ///
+    /// A stream shorter than the header is reported as [`Error::InvalidSignature`].
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = [0_u8; FRAME_HEADER_SIZE];
+        if let Err(e) = reader.read_exact(&mut bytes) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(Error::InvalidSignature);
+            }
+            return Err(e.into());
+        }
+        Self::parse(&bytes)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+/// Concatenates the blocks of bzip3 files into a single file, without recompressing them.
+///
+/// All the inputs must have the same block size. Only the file headers are dropped, so this
+/// costs no more than copying the data, e.g. to combine the outputs of jobs compressing chunks
+/// of a file in parallel. Extension blocks are dropped too, as a [seek index](crate::seek) or a
+/// [checksum](CHECKSUM_TAG) doesn't hold for the merged file.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if there's no input or the block sizes differ, errors about invalid
+/// inputs as from [`read::Bz3Decoder`](crate::read::Bz3Decoder), and [`Error::Io`] on all IO
+/// errors.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::{frame, mem};
+///
+/// let first = mem::compress(b"hello, ", 100 * 1024).unwrap();
+/// let second = mem::compress(b"world", 100 * 1024).unwrap();
+///
+/// let mut merged = Vec::new();
+/// frame::merge([first.as_slice(), second.as_slice()], &mut merged).unwrap();
+/// assert_eq!(mem::decompress(&merged).unwrap(), b"hello, world");
+/// ```
+#[cfg(feature = "std")]
+pub fn merge<I, W>(inputs: I, mut output: W) -> Result<()>
+where
+    I: IntoIterator,
+    I::Item: Read,
+    W: Write,
+{
+    let mut block_size = None;
+    let mut blocks = 0;
+    for mut input in inputs {
+        let header = FrameHeader::read_from(&mut input)?;
+        match block_size {
+            None => header.write_to(&mut output)?,
+            Some(x) if x != header.block_size => {
+                return Err(Error::InvalidArgument(format!(
+                    "Block size mismatch: {} and {x}",
+                    header.block_size
+                )));
+            }
+            Some(_) => {}
+        }
+        block_size = Some(header.block_size);
+
+        let mut offset = FRAME_HEADER_SIZE as u64;
+        loop {
+            let mut header_bytes = [0_u8; BLOCK_HEADER_SIZE];
+            let read_size = input.try_read_exact(&mut header_bytes)?;
+            if read_size == 0 {
+                break;
+            }
+            let truncated = |have, need| Error::TruncatedBlock {
+                block_index: blocks,
+                have,
+                need,
+            };
+            if read_size < BLOCK_HEADER_SIZE {
+                return Err(truncated(read_size, BLOCK_HEADER_SIZE));
+            }
+
+            let block_header = BlockHeader::parse(&header_bytes);
+            let size = match block_header.extension_size() {
+                Some(size) => size,
+                None => {
+                    if block_header.validate(header.block_size).is_err() {
+                        return Err(Error::TrailingData { offset });
+                    }
+                    output.write_all(&header_bytes)?;
+                    block_header.data_size()
+                }
+            };
+            let mut data = (&mut input).take(size as u64);
+            let copied = if block_header.is_extension() {
+                io::copy(&mut data, &mut io::sink())?
+            } else {
+                io::copy(&mut data, &mut output)?
+            };
+            if copied < size as u64 {
+                return Err(truncated(
+                    BLOCK_HEADER_SIZE + copied as usize,
+                    BLOCK_HEADER_SIZE + size,
+                ));
+            }
+            if !block_header.is_extension() {
+                blocks += 1;
+            }
+            offset += (BLOCK_HEADER_SIZE + size) as u64;
+        }
+    }
+    if block_size.is_none() {
+        return Err(Error::InvalidArgument("No input to merge".into()));
+    }
+    Ok(())
+}
+
+/// Copies ranges of the blocks of a bzip3 file into standalone files, without recompressing
+/// them.
+///
+/// Blocks are numbered from zero as in the [seek index](crate::seek::Bz3Index), which is read
+/// from the file or built by scanning it. Each range gives a complete file with the block size
+/// of the input, e.g. for processing parts of a large archive on different machines. Extension
+/// blocks aren't copied. The file is expected to start at position zero of `input`.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if a range is out of bounds, errors about invalid input as from
+/// [`seek::scan_index`](crate::seek::scan_index), and [`Error::Io`] on all IO errors.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use bzip3::{frame, mem};
+///
+/// let data = vec![b'x'; 250 * 1024];
+/// let compressed = mem::compress(&data, 100 * 1024).unwrap();
+///
+/// let parts = frame::split(Cursor::new(&compressed), &[0..1, 1..3]).unwrap();
+/// assert_eq!(mem::decompress(&parts[0]).unwrap(), &data[..(100 * 1024)]);
+/// assert_eq!(mem::decompress(&parts[1]).unwrap(), &data[(100 * 1024)..]);
+/// ```
+#[cfg(feature = "std")]
+pub fn split<R: Read + Seek>(mut input: R, ranges: &[Range<usize>]) -> Result<Vec<Vec<u8>>> {
+    input.seek(SeekFrom::Start(0))?;
+    let header = FrameHeader::read_from(&mut input)?;
+    let index = crate::seek::find_index(&mut input)?;
+
+    let mut parts = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if range.start > range.end || range.end > index.len() {
+            return Err(Error::InvalidArgument(format!(
+                "Block range {range:?} out of bounds for {} blocks",
+                index.len()
+            )));
+        }
+        let mut part = header.to_bytes().to_vec();
+        let Some(first) = index.entries().get(range.start) else {
+            parts.push(part);
+            continue;
+        };
+        input.seek(SeekFrom::Start(first.compressed_offset))?;
+        let mut blocks = range.len();
+        while blocks != 0 {
+            let block_header = BlockHeader::read_from(&mut input)?;
+            let size = match block_header.extension_size() {
+                Some(size) => size,
+                None => {
+                    block_header.validate(header.block_size)?;
+                    block_header.data_size()
+                }
+            };
+            if block_header.is_extension() {
+                input.seek(SeekFrom::Current(size as i64))?;
+                continue;
+            }
+            block_header.write_to(&mut part)?;
+            let start = part.len();
+            part.resize(start + size, 0);
+            input.read_exact(&mut part[start..])?;
+            blocks -= 1;
+        }
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// Returns the position of the first file signature in `data`.
+pub(crate) fn find_magic(data: &[u8]) -> Option<usize> {
+    data.windows(MAGIC_NUMBER.len())
+        .position(|x| x == MAGIC_NUMBER)
+}
+
+/// Serializes a checksum extension block, header included.
+pub(crate) fn checksum_block(hash: u64) -> [u8; BLOCK_HEADER_SIZE + CHECKSUM_EXTENSION_SIZE] {
+    let mut bytes = [0_u8; BLOCK_HEADER_SIZE + CHECKSUM_EXTENSION_SIZE];
+    let (header, data) = bytes.split_at_mut(BLOCK_HEADER_SIZE);
+    header.copy_from_slice(&BlockHeader::extension(CHECKSUM_EXTENSION_SIZE).to_bytes());
+    data[..EXTENSION_TAG_SIZE].copy_from_slice(CHECKSUM_TAG);
+    LE::write_u64(&mut data[EXTENSION_TAG_SIZE..], hash);
+    bytes
+}
+
+/// Returns the hash in the given extension data, if it's a checksum extension.
+pub(crate) fn parse_checksum(data: &[u8]) -> Option<u64> {
+    if data.len() == CHECKSUM_EXTENSION_SIZE && data.starts_with(CHECKSUM_TAG) {
+        Some(LE::read_u64(&data[EXTENSION_TAG_SIZE..]))
+    } else {
+        None
+    }
+}
+
+/// Header in front of every block.
+///
+/// Due to the naming from the original bzip3 library, `new_size` is the size of the compressed
+/// data, and `read_size` is the size of the original data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub new_size: i32,
+    pub read_size: i32,
+}
+
+impl BlockHeader {
+    /// Creates the header of an extension block holding `data_size` bytes, tag included.
+    pub fn extension(data_size: usize) -> Self {
+        Self {
+            new_size: EXTENSION_BLOCK,
+            read_size: data_size as i32,
+        }
+    }
+
+    pub fn is_extension(&self) -> bool {
+        self.new_size == EXTENSION_BLOCK
+    }
+
+    /// Creates the header of a stored block holding `data_size` bytes.
+    pub fn stored(data_size: usize) -> Self {
+        Self {
+            new_size: STORED_BLOCK,
+            read_size: data_size as i32,
+        }
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.new_size == STORED_BLOCK
+    }
+
+    /// Returns the size of the data following the header of a compressed or stored block.
+    ///
+    /// This is meaningful only for headers that pass [`BlockHeader::validate`].
+    pub fn data_size(&self) -> usize {
+        if self.is_stored() {
+            self.read_size as usize
+        } else {
+            self.new_size as usize
+        }
+    }
+
+    /// Returns the size of the extension data, if this is a valid extension block header.
+    pub fn extension_size(&self) -> Option<usize> {
+        if self.is_extension() && self.read_size >= EXTENSION_TAG_SIZE as i32 {
+            Some(self.read_size as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(bytes: &[u8; BLOCK_HEADER_SIZE]) -> Self {
+        Self {
+            new_size: LE::read_i32(bytes),
+            read_size: LE::read_i32(&bytes[4..]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; BLOCK_HEADER_SIZE] {
+        let mut bytes = [0_u8; BLOCK_HEADER_SIZE];
+        LE::write_i32(&mut bytes, self.new_size);
+        LE::write_i32(&mut bytes[4..], self.read_size);
+        bytes
+    }
+
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut bytes = [0_u8; BLOCK_HEADER_SIZE];
+        reader.read_exact(&mut bytes)?;
+        Ok(Self::parse(&bytes))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Checks whether the sizes are possible for a compressed or stored block in a frame with
+    /// the given block size.
+    ///
+    /// Decoders must do this before trusting the sizes for buffer operations, and take the size
+    /// of the data from [`BlockHeader::data_size`]. Extension blocks don't pass this; check for
+    /// them with [`BlockHeader::extension_size`] first.
+    pub fn validate(&self, block_size: usize) -> Result<()> {
+        let valid_new_size =
+            self.is_stored() || (self.new_size >= 0 && self.new_size as usize <= bound(block_size));
+        let valid_read_size = self.read_size >= 0 && self.read_size as usize <= block_size;
+        if !valid_new_size || !valid_read_size {
+            return Err(Error::ProcessBlock(
+                "Corrupt file; invalid block header".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Any supported version.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FormatVersion {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(*u.choose(&[FormatVersion::V1])?)
+    }
+}
+
+/// A valid header, of any supported version and block size.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FrameHeader {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            version: u.arbitrary()?,
+            block_size: u.int_in_range(crate::BLOCK_SIZE_MIN..=crate::BLOCK_SIZE_MAX)?,
+        })
+    }
+}
+
+/// The header of a compressed, stored or extension block, with sizes that aren't negative.
+///
+/// Whether the sizes fit a block size is left to chance; check with [`BlockHeader::validate`].
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for BlockHeader {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let read_size = u.int_in_range(0..=i32::MAX)?;
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Self {
+                new_size: u.int_in_range(0..=i32::MAX)?,
+                read_size,
+            },
+            1 => Self::stored(read_size as usize),
+            _ => Self::extension(read_size as usize),
+        })
+    }
+}
+
+/// Largest block size of an [`ArbitraryFrame`], keeping the memory of decoding it small.
+#[cfg(feature = "arbitrary")]
+const ARBITRARY_FRAME_BLOCK_SIZE_MAX: usize = 4 * crate::BLOCK_SIZE_MIN;
+
+/// A bzip3 file built from arbitrary input, for property-based testing of decoders.
+///
+/// The file is written by [`write::Bz3Encoder`](crate::write::Bz3Encoder), with a small block
+/// size, up to a few blocks, and maybe a checksum and stored blocks, the latter always with
+/// the checksum. It's valid unless [`corruption`](ArbitraryFrame::corruption) is set: then a
+/// decoder must either fail, or give a prefix of the original data, as it does when the damage
+/// is in an extension block it skips, or a truncation falls between blocks.
+///
+/// # Examples
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use bzip3::frame::ArbitraryFrame;
+///
+/// let input = [7_u8; 1024];
+/// let frame = ArbitraryFrame::arbitrary(&mut Unstructured::new(&input)).unwrap();
+/// match bzip3::mem::decompress(&frame.bytes) {
+///     Ok(data) if frame.corruption.is_none() => assert_eq!(data, frame.data),
+///     Ok(data) => assert!(frame.data.starts_with(&data)),
+///     Err(_) => assert!(frame.corruption.is_some()),
+/// }
+/// ```
+#[cfg(feature = "arbitrary")]
+#[derive(Debug, Clone)]
+pub struct ArbitraryFrame {
+    /// The original data.
+    pub data: Vec<u8>,
+    pub block_size: usize,
+    /// The file, with the corruption applied.
+    pub bytes: Vec<u8>,
+    pub corruption: Option<Corruption>,
+}
+
+/// Damage done to an [`ArbitraryFrame`], past its file header.
+#[cfg(feature = "arbitrary")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// Flips the given bit of the byte at the offset.
+    FlipBit { offset: usize, bit: u8 },
+    /// Cuts the file to the given size.
+    Truncate { size: usize },
+}
+
+#[cfg(feature = "arbitrary")]
+impl Corruption {
+    pub fn apply(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            Corruption::FlipBit { offset, bit } => bytes[offset] ^= 1 << bit,
+            Corruption::Truncate { size } => bytes.truncate(size),
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ArbitraryFrame {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let block_size = u.int_in_range(crate::BLOCK_SIZE_MIN..=ARBITRARY_FRAME_BLOCK_SIZE_MAX)?;
+        let size = u.int_in_range(0..=3 * block_size)?;
+        let pattern: Vec<u8> = u.arbitrary()?;
+        let data = if pattern.is_empty() {
+            vec![0_u8; size]
+        } else {
+            pattern.into_iter().cycle().take(size).collect()
+        };
+
+        let mut encoder =
+            crate::write::Bz3Encoder::new(Vec::new(), block_size).expect("the block size is valid");
+        let checksum = u.arbitrary()?;
+        let stored_blocks = u.arbitrary()?;
+        // damage to stored blocks goes unnoticed without the checksum, as they have no CRC
+        encoder.set_checksum(checksum || stored_blocks);
+        encoder.set_stored_blocks(stored_blocks);
+        encoder.write_all(&data).expect("writing to a Vec");
+        let mut bytes = encoder.finish().expect("writing to a Vec");
+
+        let corruption = if bytes.len() > FRAME_HEADER_SIZE && u.arbitrary()? {
+            let offset = u.int_in_range(FRAME_HEADER_SIZE..=bytes.len() - 1)?;
+            Some(if u.arbitrary()? {
+                Corruption::FlipBit {
+                    offset,
+                    bit: u.int_in_range(0..=7)?,
+                }
+            } else {
+                Corruption::Truncate { size: offset }
+            })
+        } else {
+            None
+        };
+        if let Some(corruption) = corruption {
+            corruption.apply(&mut bytes);
+        }
+        Ok(Self {
+            data,
+            block_size,
+            bytes,
+            corruption,
+        })
+    }
+}
@@ -0,0 +1,10 @@
+//! Async BZip3 codecs for the [futures-io](https://docs.rs/futures-io) traits.
+//!
+//! These serve runtimes other than tokio, such as smol and async-std; the `tokio` module has
+//! the tokio counterparts. [`stream`] and [`sink`] work on `Bytes` streams and sinks,
+//! regardless of the runtime.
+
+pub mod read;
+pub mod sink;
+pub mod stream;
+pub mod write;
@@ -0,0 +1,141 @@
+//! AsyncRead-based BZip3 decompressor.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_io::AsyncRead;
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::{push, Bz3State, CrcMode};
+
+pin_project! {
+    /// Async bzip3 decoder, reading compressed data from an [`AsyncRead`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// use futures::io::AsyncReadExt;
+    ///
+    /// let compressed = bzip3::mem::compress(b"hello, world", 100 * 1024).unwrap();
+    /// let mut decoder = bzip3::futures::read::Bz3Decoder::new(compressed.as_slice());
+    /// let mut contents = String::new();
+    /// decoder.read_to_string(&mut contents).await.unwrap();
+    /// assert_eq!(contents, "hello, world");
+    /// # })
+    /// ```
+    pub struct Bz3Decoder<R> {
+        #[pin]
+        reader: R,
+        decoder: push::Decoder,
+        eof: bool,
+    }
+}
+
+impl<R> fmt::Debug for Bz3Decoder<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("eof", &self.eof).finish_non_exhaustive()
+    }
+}
+
+impl<R> Bz3Decoder<R>
+where
+    R: AsyncRead,
+{
+    /// Creates an async bzip3 decoder.
+    ///
+    /// The file header is only read on the first poll, so an invalid header is reported by the
+    /// first read.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: push::Decoder::new(),
+            eof: false,
+        }
+    }
+
+    /// Returns the bzip3 block size, once the file header has been read.
+    pub fn block_size(&self) -> Option<usize> {
+        self.decoder.block_size()
+    }
+
+    /// Sets how a block failing its CRC check is handled.
+    ///
+    /// See [`read::Bz3Decoder::set_crc_mode`](crate::read::Bz3Decoder::set_crc_mode).
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.decoder.set_crc_mode(mode);
+    }
+
+    /// Returns the blocks which failed the CRC check so far in permissive mode, numbered as in
+    /// [`Error::BadCrc`].
+    pub fn crc_failures(&self) -> &[usize] {
+        self.decoder.crc_failures()
+    }
+
+    /// Sets whether the output of each block is verified after decoding.
+    ///
+    /// See [`read::Bz3Decoder::set_paranoid`](crate::read::Bz3Decoder::set_paranoid).
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.decoder.set_paranoid(enabled);
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the file header has been read.
+    ///
+    /// See [`read::Bz3Decoder::state`](crate::read::Bz3Decoder::state).
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.decoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the file header has been read.
+    ///
+    /// See [`read::Bz3Decoder::state_mut`](crate::read::Bz3Decoder::state_mut).
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.decoder.state_mut()
+    }
+}
+
+impl<R> AsyncRead for Bz3Decoder<R>
+where
+    R: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut this = self.project();
+        loop {
+            let output = this.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.len());
+                buf[..size].copy_from_slice(&output[..size]);
+                this.decoder.consume(size);
+                return Poll::Ready(Ok(size));
+            }
+            if *this.eof || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let input = this.decoder.input_buffer();
+            let read_size = ready!(this.reader.as_mut().poll_read(cx, input))?;
+            if read_size == 0 {
+                this.decoder.finish().map_err(Error::into_io_error)?;
+                *this.eof = true;
+            } else {
+                this.decoder
+                    .advance(read_size)
+                    .map_err(Error::into_io_error)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Sink-based BZip3 compressor.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_sink::Sink;
+use pin_project_lite::pin_project;
+
+use crate::errors::Error;
+use crate::push;
+
+pin_project! {
+    /// Sink compressing the chunks pushed into it, and forwarding the compressed data to an inner
+    /// sink.
+    ///
+    /// The inner sink receives the file header and then each compressed block as an item.
+    /// Flushing compresses the partial block like [`write::Bz3Encoder`](crate::write::Bz3Encoder)
+    /// does, and closing finishes the stream and closes the inner sink.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// use bytes::Bytes;
+    /// use futures::{channel::mpsc, SinkExt, StreamExt};
+    ///
+    /// let (sender, receiver) = mpsc::unbounded::<Bytes>();
+    /// let sender = sender.sink_map_err(std::io::Error::other);
+    /// let mut sink = bzip3::futures::sink::Bz3Sink::new(sender, 100 * 1024).unwrap();
+    /// sink.send(Bytes::from("hello, world")).await.unwrap();
+    /// sink.close().await.unwrap();
+    ///
+    /// let compressed = receiver.collect::<Vec<_>>().await.concat();
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), b"hello, world");
+    /// # })
+    /// ```
+    pub struct Bz3Sink<S> {
+        #[pin]
+        sink: S,
+        encoder: push::Encoder,
+        // the part of the last item not taken by the encoder yet
+        item: Bytes,
+    }
+}
+
+impl<S> fmt::Debug for Bz3Sink<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Sink");
+        self.encoder.debug_fields(&mut s);
+        s.field("pending_item", &self.item.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Bz3Sink<S>
+where
+    S: Sink<Bytes>,
+    S::Error: From<io::Error>,
+{
+    /// Creates a compressing sink.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(sink: S, block_size: usize) -> crate::Result<Self> {
+        Ok(Self {
+            sink,
+            encoder: push::Encoder::new(block_size)?,
+            item: Bytes::new(),
+        })
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// Feeds the pending item to the encoder, and sends out all the compressed data.
+    fn poll_drain(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        let mut this = self.project();
+        loop {
+            let output = this.encoder.output();
+            if !output.is_empty() {
+                ready!(this.sink.as_mut().poll_ready(cx))?;
+                let output = Bytes::copy_from_slice(output);
+                this.encoder.consume(output.len());
+                this.sink.as_mut().start_send(output)?;
+            } else if !this.item.is_empty() {
+                let size = this.encoder.feed(this.item).map_err(Error::into_io_error)?;
+                this.item.advance(size);
+            } else {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    /// Compresses the partial block, and sends out all the compressed data.
+    fn poll_finish_block(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), S::Error>> {
+        ready!(self.as_mut().poll_drain(cx))?;
+        self.as_mut()
+            .project()
+            .encoder
+            .flush()
+            .map_err(Error::into_io_error)?;
+        self.poll_drain(cx)
+    }
+}
+
+impl<S> Sink<Bytes> for Bz3Sink<S>
+where
+    S: Sink<Bytes>,
+    S::Error: From<io::Error>,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_drain(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.project();
+        debug_assert!(this.item.is_empty());
+        *this.item = item;
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_finish_block(cx))?;
+        self.project().sink.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_finish_block(cx))?;
+        self.project().sink.poll_close(cx)
+    }
+}
@@ -0,0 +1,206 @@
+//! Adapters (de)compressing streams of [`Bytes`] chunks.
+//!
+//! Body streams of HTTP clients and object stores usually come as
+//! `Stream<Item = io::Result<Bytes>>`; these adapters work on them directly without turning
+//! them into an `AsyncRead` first.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::push;
+
+/// Compresses a stream of chunks.
+///
+/// Each item of the returned stream holds the file header or a compressed block.
+///
+/// # Errors
+///
+/// This returns [`Error::BlockSize`] if the block size is invalid.
+///
+/// # Examples
+///
+/// ```
+/// # futures::executor::block_on(async {
+/// use bytes::Bytes;
+/// use futures::{stream, TryStreamExt};
+///
+/// let chunks = stream::iter([Bytes::from("hello, "), Bytes::from("world")].map(Ok));
+/// let compressed = bzip3::futures::stream::compress_stream(chunks, 100 * 1024).unwrap();
+/// let decompressed = bzip3::futures::stream::decompress_stream(compressed);
+/// let chunks = decompressed.try_collect::<Vec<_>>().await.unwrap();
+/// assert_eq!(chunks.concat(), b"hello, world");
+/// # })
+/// ```
+pub fn compress_stream<S>(stream: S, block_size: usize) -> Result<CompressStream<S>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    Ok(CompressStream {
+        stream,
+        encoder: push::Encoder::new(block_size)?,
+        chunk: Bytes::new(),
+        input_end: false,
+        done: false,
+    })
+}
+
+/// Decompresses a stream of chunks.
+///
+/// Each item of the returned stream holds the data of a block.
+pub fn decompress_stream<S>(stream: S) -> DecompressStream<S>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    DecompressStream {
+        stream,
+        decoder: push::Decoder::new(),
+        chunk: Bytes::new(),
+        input_end: false,
+        done: false,
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`compress_stream`].
+    pub struct CompressStream<S> {
+        #[pin]
+        stream: S,
+        encoder: push::Encoder,
+        // the unconsumed part of the last chunk
+        chunk: Bytes,
+        input_end: bool,
+        done: bool,
+    }
+}
+
+impl<S> fmt::Debug for CompressStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("CompressStream");
+        self.encoder.debug_fields(&mut s);
+        s.field("pending_chunk", &self.chunk.len())
+            .field("input_end", &self.input_end)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> CompressStream<S> {
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+}
+
+impl<S> Stream for CompressStream<S>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let output = this.encoder.output();
+            if !output.is_empty() {
+                let item = Bytes::copy_from_slice(output);
+                this.encoder.consume(item.len());
+                return Poll::Ready(Some(Ok(item)));
+            }
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            if !this.chunk.is_empty() {
+                let size = this
+                    .encoder
+                    .feed(this.chunk)
+                    .map_err(Error::into_io_error)?;
+                this.chunk.advance(size);
+            } else if *this.input_end {
+                this.encoder.flush().map_err(Error::into_io_error)?;
+                *this.done = this.encoder.output().is_empty();
+            } else {
+                match ready!(this.stream.as_mut().poll_next(cx)) {
+                    Some(chunk) => *this.chunk = chunk?,
+                    None => *this.input_end = true,
+                }
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Stream returned by [`decompress_stream`].
+    pub struct DecompressStream<S> {
+        #[pin]
+        stream: S,
+        decoder: push::Decoder,
+        // the unconsumed part of the last chunk
+        chunk: Bytes,
+        input_end: bool,
+        done: bool,
+    }
+}
+
+impl<S> fmt::Debug for DecompressStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("DecompressStream");
+        self.decoder.debug_fields(&mut s);
+        s.field("pending_chunk", &self.chunk.len())
+            .field("input_end", &self.input_end)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> DecompressStream<S> {
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+}
+
+impl<S> Stream for DecompressStream<S>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let output = this.decoder.output();
+            if !output.is_empty() {
+                let item = Bytes::copy_from_slice(output);
+                this.decoder.consume(item.len());
+                return Poll::Ready(Some(Ok(item)));
+            }
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            if !this.chunk.is_empty() {
+                let size = this
+                    .decoder
+                    .feed(this.chunk)
+                    .map_err(Error::into_io_error)?;
+                this.chunk.advance(size);
+            } else if *this.input_end {
+                this.decoder.finish().map_err(Error::into_io_error)?;
+                *this.done = true;
+            } else {
+                match ready!(this.stream.as_mut().poll_next(cx)) {
+                    Some(chunk) => *this.chunk = chunk?,
+                    None => *this.input_end = true,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! AsyncWrite-based BZip3 compressor.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_io::AsyncWrite;
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::{push, Bz3State};
+
+pin_project! {
+    /// Async bzip3 encoder, writing compressed data to an [`AsyncWrite`].
+    ///
+    /// Close the encoder when done: that compresses the final partial block and closes the inner
+    /// writer. Dropping the encoder without closing it loses the buffered data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # futures::executor::block_on(async {
+    /// use futures::io::AsyncWriteExt;
+    ///
+    /// let mut encoder = bzip3::futures::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.write_all(b"hello, world").await.unwrap();
+    /// encoder.close().await.unwrap();
+    /// # })
+    /// ```
+    pub struct Bz3Encoder<W> {
+        #[pin]
+        writer: W,
+        encoder: push::Encoder,
+    }
+}
+
+impl<W> fmt::Debug for Bz3Encoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Encoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3Encoder<W>
+where
+    W: AsyncWrite,
+{
+    /// Creates an async bzip3 encoder.
+    ///
+    /// The file header is written along with the first block, or on flush.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        Ok(Self {
+            writer,
+            encoder: push::Encoder::new(block_size)?,
+        })
+    }
+
+    /// Creates an async bzip3 encoder with the [default block size](crate::DEFAULT_BLOCK_SIZE).
+    pub fn with_defaults(writer: W) -> Self {
+        Self::new(writer, crate::DEFAULT_BLOCK_SIZE).expect("the default block size is valid")
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the first block has been compressed.
+    ///
+    /// See [`write::Bz3Encoder::state`](crate::write::Bz3Encoder::state).
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.encoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the first block has been compressed.
+    ///
+    /// See [`write::Bz3Encoder::state_mut`](crate::write::Bz3Encoder::state_mut).
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.encoder.state_mut()
+    }
+
+    /// Writes all pending compressed data to the inner writer.
+    fn poll_write_output(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            let output = this.encoder.output();
+            if output.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let size = ready!(this.writer.as_mut().poll_write(cx, output))?;
+            if size == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.encoder.consume(size);
+        }
+    }
+
+    /// Compresses the partial block, and writes all pending compressed data.
+    fn poll_finish_block(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_write_output(cx))?;
+        self.as_mut()
+            .project()
+            .encoder
+            .flush()
+            .map_err(Error::into_io_error)?;
+        self.poll_write_output(cx)
+    }
+}
+
+impl<W> AsyncWrite for Bz3Encoder<W>
+where
+    W: AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.as_mut().poll_write_output(cx))?;
+        let size = self
+            .project()
+            .encoder
+            .feed(buf)
+            .map_err(Error::into_io_error)?;
+        Poll::Ready(Ok(size))
+    }
+
+    /// Compresses the partial block like [`write::Bz3Encoder`](crate::write::Bz3Encoder) does on
+    /// flush, and then flushes the inner writer.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_finish_block(cx))?;
+        self.project().writer.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_finish_block(cx))?;
+        self.project().writer.poll_close(cx)
+    }
+}
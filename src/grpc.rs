@@ -0,0 +1,188 @@
+//! A gRPC message codec for [tonic] that compresses protobuf messages with bzip3.
+//!
+//! tonic's message compression, negotiated through the `grpc-encoding` header, is limited to a
+//! fixed set of encodings, and there is no hook to add others. [`Bz3Codec`] instead takes the
+//! place of tonic's prost codec: each message is encoded with prost and compressed into a
+//! complete bzip3 file, and decoded the other way round. Both the client and the server have
+//! to use it. Generated services pick it up with the `codec_path` option of `tonic-build`:
+//!
+//! ```ignore
+//! tonic_build::configure()
+//!     .codec_path("bzip3::grpc::Bz3Codec")
+//!     .compile_protos(&["proto/jobs.proto"], &["proto"])?;
+//! ```
+//!
+//! tonic's own compression is better left off for these services, since the messages are
+//! already compressed.
+//!
+//! Every message is compressed on its own, with a block size fitted to it, so this pays off for
+//! large messages rather than for many small ones.
+
+use std::io::Read;
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut};
+use prost::Message;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+use crate::errors::*;
+use crate::{mem, read, BLOCK_SIZE_MIN};
+
+/// Default maximum block size for compressing messages.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Default limit of the decompressed size of messages, the same as tonic's default limit of
+/// received messages.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// A [`Codec`] for prost messages, compressing each of them into a bzip3 file.
+///
+/// `T` is the type of the messages sent, and `U` the type of the messages received.
+#[derive(Debug)]
+pub struct Bz3Codec<T, U> {
+    block_size: usize,
+    max_message_size: usize,
+    _marker: PhantomData<(T, U)>,
+}
+
+impl<T, U> Bz3Codec<T, U> {
+    /// Creates a codec compressing messages with blocks of at most `block_size` bytes.
+    ///
+    /// Messages smaller than the block size are compressed with a single block just large
+    /// enough for them.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(block_size: usize) -> Result<Self> {
+        if !crate::Bz3State::check_block_size(block_size) {
+            return Err(Error::BlockSize);
+        }
+        Ok(Self {
+            block_size,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Limits the decompressed size of received messages, which is
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`] by default.
+    ///
+    /// tonic's own limit applies to the compressed messages only; this one keeps small
+    /// messages from decompressing to huge ones. Messages above it are rejected with a
+    /// [`ResourceExhausted`](tonic::Code::ResourceExhausted) status.
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+}
+
+impl<T, U> Default for Bz3Codec<T, U> {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U> Clone for Bz3Codec<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            block_size: self.block_size,
+            max_message_size: self.max_message_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U> Codec for Bz3Codec<T, U>
+where
+    T: Message + Send + 'static,
+    U: Message + Default + Send + 'static,
+{
+    type Encode = T;
+    type Decode = U;
+    type Encoder = Bz3MessageEncoder<T>;
+    type Decoder = Bz3MessageDecoder<U>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        Bz3MessageEncoder {
+            block_size: self.block_size,
+            _marker: PhantomData,
+        }
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        Bz3MessageDecoder {
+            max_message_size: self.max_message_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// The [`Encoder`] of [`Bz3Codec`].
+#[derive(Debug)]
+pub struct Bz3MessageEncoder<T> {
+    block_size: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Message> Encoder for Bz3MessageEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: T, dst: &mut EncodeBuf<'_>) -> std::result::Result<(), Status> {
+        let data = item.encode_to_vec();
+        let block_size = data.len().clamp(BLOCK_SIZE_MIN, self.block_size);
+        let compressed = mem::compress(&data, block_size).map_err(internal)?;
+        dst.put_slice(&compressed);
+        Ok(())
+    }
+}
+
+/// The [`Decoder`] of [`Bz3Codec`].
+#[derive(Debug)]
+pub struct Bz3MessageDecoder<U> {
+    max_message_size: usize,
+    _marker: PhantomData<U>,
+}
+
+impl<U: Message + Default> Decoder for Bz3MessageDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> std::result::Result<Option<U>, Status> {
+        let compressed = src.copy_to_bytes(src.remaining());
+        // blocks can't hold more than the message, except for the rounding to the minimum
+        let block_limit = self.max_message_size.max(BLOCK_SIZE_MIN);
+        let decoder = read::Bz3Decoder::with_max_block_size(compressed.as_ref(), block_limit)
+            .map_err(|e| match e {
+                Error::BlockSizeLimit { .. } => too_large(self.max_message_size),
+                e => internal(e),
+            })?;
+        let mut data = Vec::new();
+        decoder
+            .take(self.max_message_size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| internal(Error::from(e)))?;
+        if data.len() > self.max_message_size {
+            return Err(too_large(self.max_message_size));
+        }
+        let message = U::decode(data.as_slice()).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Some(message))
+    }
+}
+
+/// Failing to encode or decode a message is an internal error, as in tonic's prost codec.
+fn internal(e: Error) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn too_large(limit: usize) -> Status {
+    Status::resource_exhausted(format!(
+        "Decompressed message larger than the limit ({limit} bytes)"
+    ))
+}
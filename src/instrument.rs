@@ -0,0 +1,189 @@
+//! Hooks of the `metrics` and `tracing` features into the codecs; without them, these do
+//! nothing.
+//!
+//! With `tracing`, every block processed by libbz3 gets a `bzip3_block` span, and an event
+//! telling its sizes, compression ratio and duration. The blocks of the stream codecs run
+//! within a `bzip3_stream` span, which ends with an event telling the totals of the stream
+//! when the codec is dropped. All of these are at the debug level.
+
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operation {
+    Compress,
+    Decompress,
+}
+
+impl Operation {
+    #[cfg_attr(not(any(feature = "metrics", feature = "tracing")), allow(dead_code))]
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Operation::Compress => "compress",
+            Operation::Decompress => "decompress",
+        }
+    }
+}
+
+/// Compressed size over original size.
+#[cfg(feature = "tracing")]
+fn ratio(operation: Operation, input_size: u64, output_size: u64) -> f64 {
+    let (compressed, original) = match operation {
+        Operation::Compress => (output_size, input_size),
+        Operation::Decompress => (input_size, output_size),
+    };
+    compressed as f64 / original.max(1) as f64
+}
+
+/// Times a block processed by libbz3, within a span of its own.
+pub(crate) struct BlockTimer {
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    operation: Operation,
+    #[cfg(any(feature = "metrics", feature = "tracing"))]
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+impl BlockTimer {
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "tracing")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn start(operation: Operation) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            _span: tracing::debug_span!("bzip3_block", operation = operation.as_str()).entered(),
+            #[cfg(any(feature = "metrics", feature = "tracing"))]
+            operation,
+            #[cfg(any(feature = "metrics", feature = "tracing"))]
+            start: Instant::now(),
+        }
+    }
+
+    /// Records a block processed successfully. Blocks processed together are each recorded
+    /// with the time taken by all of them.
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "tracing")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn record(&self, input_size: usize, output_size: usize) {
+        #[cfg(any(feature = "metrics", feature = "tracing"))]
+        let duration = self.start.elapsed();
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_block(self.operation, input_size, output_size, duration);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            operation = self.operation.as_str(),
+            input_size,
+            output_size,
+            ratio = ratio(self.operation, input_size as u64, output_size as u64),
+            ?duration,
+            "block processed"
+        );
+    }
+}
+
+/// Span of a stream codec, shared with the threads processing its blocks.
+#[derive(Clone)]
+pub(crate) struct StreamSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl StreamSpan {
+    /// Enters the span, for the blocks processed meanwhile to be within it.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(&self) -> SpanGuard {
+        self.span.clone().entered()
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn enter(&self) -> SpanGuard {
+        SpanGuard
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) type SpanGuard = tracing::span::EnteredSpan;
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct SpanGuard;
+
+/// Span of a stream codec, with the totals of the stream reported when dropped.
+pub(crate) struct StreamTrace {
+    span: StreamSpan,
+    #[cfg(feature = "tracing")]
+    operation: Operation,
+    #[cfg(feature = "tracing")]
+    input_size: u64,
+    #[cfg(feature = "tracing")]
+    output_size: u64,
+    #[cfg(feature = "tracing")]
+    blocks: u64,
+}
+
+impl StreamTrace {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn new(operation: Operation) -> Self {
+        Self {
+            span: StreamSpan {
+                #[cfg(feature = "tracing")]
+                span: tracing::debug_span!(
+                    "bzip3_stream",
+                    operation = operation.as_str(),
+                    block_size = tracing::field::Empty
+                ),
+            },
+            #[cfg(feature = "tracing")]
+            operation,
+            #[cfg(feature = "tracing")]
+            input_size: 0,
+            #[cfg(feature = "tracing")]
+            output_size: 0,
+            #[cfg(feature = "tracing")]
+            blocks: 0,
+        }
+    }
+
+    /// Records the block size of the stream, once known.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn set_block_size(&self, block_size: usize) {
+        #[cfg(feature = "tracing")]
+        self.span.span.record("block_size", block_size);
+    }
+
+    pub(crate) fn span(&self) -> &StreamSpan {
+        &self.span
+    }
+
+    /// Enters the span, for the blocks processed meanwhile to be within it.
+    pub(crate) fn enter(&self) -> SpanGuard {
+        self.span.enter()
+    }
+
+    /// Adds a block to the totals, with the sizes of its data going in and out of the codec.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn add_block(&mut self, input_size: usize, output_size: usize) {
+        #[cfg(feature = "tracing")]
+        {
+            self.input_size += input_size as u64;
+            self.output_size += output_size as u64;
+            self.blocks += 1;
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for StreamTrace {
+    fn drop(&mut self) {
+        tracing::debug!(
+            parent: &self.span.span,
+            operation = self.operation.as_str(),
+            input_size = self.input_size,
+            output_size = self.output_size,
+            blocks = self.blocks,
+            ratio = ratio(self.operation, self.input_size, self.output_size),
+            "stream done"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Integrity checks of bzip3 files, block by block.
+//!
+//! Unlike a decoder, which stops at the first problem, [`verify`] decodes every block and
+//! reports on each one, for tooling that tests archives and tells which parts are damaged.
+
+use std::io;
+use std::io::Read;
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::errors::*;
+use crate::frame::{
+    parse_checksum, BlockHeader, FrameHeader, SkippableFrame, BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE,
+    SKIPPABLE_HEADER_SIZE, SKIPPABLE_MAGIC,
+};
+use crate::{bound, Bz3State, TryReadExact, MAGIC_NUMBER};
+
+/// Outcome of decoding a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block decodes and passes its CRC check.
+    Valid,
+    /// The block decodes, but fails its CRC check.
+    BadCrc,
+    /// libbz3 rejects the compressed data of the block.
+    Undecodable,
+}
+
+/// Report on a single block, by [`verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReport {
+    /// Offset of the block header from the start of the file.
+    pub compressed_offset: u64,
+    /// Offset of the block's data in the uncompressed data.
+    pub uncompressed_offset: u64,
+    /// Size of the compressed data, leaving out the block header.
+    pub compressed_size: usize,
+    pub uncompressed_size: usize,
+    pub status: BlockStatus,
+}
+
+/// Report on a whole file, by [`verify`].
+#[derive(Debug)]
+pub struct Report {
+    pub block_size: usize,
+    /// All the blocks, leaving out extension blocks.
+    pub blocks: Vec<BlockReport>,
+    /// Whether the data matches the [checksum](crate::frame::CHECKSUM_TAG) of the file, if it
+    /// has one.
+    pub checksum_valid: Option<bool>,
+    /// What ended the check before the end of the input: [`Error::TruncatedBlock`],
+    /// [`Error::TrailingData`], [`Error::MisplacedFrameHeader`], or an
+    /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) IO error for a truncated
+    /// [skippable frame](crate::frame::SKIPPABLE_MAGIC).
+    pub error: Option<Error>,
+}
+
+impl Report {
+    /// Whether the whole file is fine: every block is valid, the checksum matches if there's
+    /// one, and nothing but blocks follows the file header.
+    pub fn is_intact(&self) -> bool {
+        self.damaged_blocks().next().is_none()
+            && self.checksum_valid != Some(false)
+            && self.error.is_none()
+    }
+
+    /// Returns the blocks which aren't [`BlockStatus::Valid`].
+    pub fn damaged_blocks(&self) -> impl Iterator<Item = &BlockReport> {
+        self.blocks
+            .iter()
+            .filter(|x| x.status != BlockStatus::Valid)
+    }
+}
+
+/// Decodes every block of a bzip3 file and reports on each one.
+///
+/// Damaged blocks don't stop the check; they are recorded in the report, together with the
+/// outcome of the checksum, and a truncated file or trailing garbage.
+///
+/// # Errors
+///
+/// [`Error::InvalidSignature`] or [`Error::BlockSize`] for an invalid file header, and
+/// [`Error::Io`] on all IO errors.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::integrity;
+///
+/// let compressed = bzip3::mem::compress(&[b'x'; 300 * 1024], 100 * 1024).unwrap();
+/// let report = integrity::verify(compressed.as_slice()).unwrap();
+/// assert!(report.is_intact());
+/// assert_eq!(report.blocks.len(), 3);
+/// ```
+pub fn verify<R: Read>(mut reader: R) -> Result<Report> {
+    let header = FrameHeader::read_from(&mut reader)?;
+    let mut state = Bz3State::new(header.block_size)?;
+    let mut buffer = vec![0_u8; bound(header.block_size)];
+    let mut hasher = Xxh3::new();
+
+    let mut report = Report {
+        block_size: header.block_size,
+        blocks: Vec::new(),
+        checksum_valid: None,
+        error: None,
+    };
+    let mut compressed_offset = FRAME_HEADER_SIZE as u64;
+    let mut uncompressed_offset = 0_u64;
+    // whether a skippable frame has followed the last block
+    let mut after_frame = false;
+    loop {
+        let mut header_bytes = [0_u8; BLOCK_HEADER_SIZE];
+        let read_size = reader.try_read_exact(&mut header_bytes)?;
+        if read_size == 0 {
+            break;
+        }
+        let block_index = report.blocks.len();
+        let truncated = |have, need| Error::TruncatedBlock {
+            block_index,
+            have,
+            need,
+        };
+        if read_size < BLOCK_HEADER_SIZE {
+            report.error = Some(truncated(read_size, BLOCK_HEADER_SIZE));
+            break;
+        }
+
+        if header_bytes.starts_with(SKIPPABLE_MAGIC) {
+            // only skippable frames may follow the last block
+            let mut frame_header = [0_u8; SKIPPABLE_HEADER_SIZE];
+            frame_header[..BLOCK_HEADER_SIZE].copy_from_slice(&header_bytes);
+            let read_size = reader.try_read_exact(&mut frame_header[BLOCK_HEADER_SIZE..])?;
+            let size = SkippableFrame::parse_header(&frame_header).unwrap().1 as u64;
+            let skipped = io::copy(&mut (&mut reader).take(size), &mut io::sink())?;
+            if read_size + BLOCK_HEADER_SIZE < SKIPPABLE_HEADER_SIZE || skipped < size {
+                report.error = Some(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+                break;
+            }
+            compressed_offset += SKIPPABLE_HEADER_SIZE as u64 + size;
+            after_frame = true;
+            continue;
+        }
+        if after_frame {
+            report.error = Some(Error::TrailingData {
+                offset: compressed_offset,
+            });
+            break;
+        }
+
+        let block_header = BlockHeader::parse(&header_bytes);
+        if let Some(size) = block_header.extension_size() {
+            // small extension data is kept to be inspected; larger is thrown away
+            let read_size = if size <= buffer.len() {
+                reader.try_read_exact(&mut buffer[..size])?
+            } else {
+                io::copy(&mut (&mut reader).take(size as u64), &mut io::sink())? as usize
+            };
+            if read_size < size {
+                report.error = Some(truncated(
+                    BLOCK_HEADER_SIZE + read_size,
+                    BLOCK_HEADER_SIZE + size,
+                ));
+                break;
+            }
+            if let Some(expected) = buffer.get(..size).and_then(parse_checksum) {
+                report.checksum_valid = Some(hasher.digest() == expected);
+            }
+            compressed_offset += (BLOCK_HEADER_SIZE + size) as u64;
+            continue;
+        }
+        if block_header.validate(header.block_size).is_err() {
+            report.error = Some(if header_bytes.starts_with(MAGIC_NUMBER) {
+                Error::MisplacedFrameHeader {
+                    offset: compressed_offset,
+                }
+            } else {
+                Error::TrailingData {
+                    offset: compressed_offset,
+                }
+            });
+            break;
+        }
+
+        let new_size = block_header.data_size();
+        let original_size = block_header.read_size as usize;
+        let read_size = reader.try_read_exact(&mut buffer[..new_size])?;
+        if read_size < new_size {
+            report.error = Some(truncated(
+                BLOCK_HEADER_SIZE + read_size,
+                BLOCK_HEADER_SIZE + new_size,
+            ));
+            break;
+        }
+        let status = if block_header.is_stored() {
+            // nothing to check in stored data
+            BlockStatus::Valid
+        } else {
+            match state.decode_block_at(&mut buffer, new_size, original_size, block_index) {
+                Ok(()) => BlockStatus::Valid,
+                Err(Error::BadCrc { .. }) => BlockStatus::BadCrc,
+                Err(_) => BlockStatus::Undecodable,
+            }
+        };
+        // a damaged block makes the checksum fail anyway, whatever is hashed for it
+        hasher.update(&buffer[..original_size]);
+
+        report.blocks.push(BlockReport {
+            compressed_offset,
+            uncompressed_offset,
+            compressed_size: new_size,
+            uncompressed_size: original_size,
+            status,
+        });
+        compressed_offset += (BLOCK_HEADER_SIZE + new_size) as u64;
+        uncompressed_offset += original_size as u64;
+    }
+    Ok(report)
+}
@@ -13,6 +13,10 @@
 //! `new size` indicates the data size after compression, and `read size` indicates the original
 //! data size.
 //!
+//! A block with a `new size` of [`frame::EXTENSION_BLOCK`] is an extension block carrying
+//! metadata, such as the [seek index](seek), a [checksum](frame::CHECKSUM_TAG) or
+//! [file metadata](metadata), in place of compressed data. Decoders skip the ones they don't know.
+//!
 //! # Examples
 //!
 //! ```
@@ -29,44 +33,245 @@
 //! decompressor.read_to_string(&mut contents).unwrap();
 //! assert_eq!(contents, "hello, world");
 //! ```
-extern crate core;
+//!
+//! # `no_std`
+//!
+//! Without the default `std` feature, the crate only needs `alloc`: what's left is the
+//! [`push`] codecs, the block functions of [`Bz3State`] and the [`frame`] types, and with the
+//! `embedded-io` and `embedded-io-async` features, the [`embedded`] and [`embedded_async`]
+//! codecs over the readers and writers of these crates.
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::{ffi::CStr, io::Read};
+extern crate alloc;
 
-use bytesize::{KIB, MIB};
+use core::ffi::CStr;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
 
 use libbzip3_sys::{
-    bz3_bound, bz3_decode_block, bz3_encode_block, bz3_free, bz3_new, bz3_state, bz3_strerror,
+    bz3_decode_block, bz3_encode_block, bz3_free, bz3_new, bz3_state, bz3_strerror,
 };
 
+use crate::crc::stored_crc;
+use crate::instrument::{BlockTimer, Operation};
+
+#[cfg(feature = "tar")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod blob;
+#[cfg(feature = "bytes")]
+pub mod bytes;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod chunking;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod container;
+#[cfg(feature = "http")]
+pub mod content_encoding;
+mod crc;
+#[cfg(feature = "embedded-io")]
+pub mod embedded;
+#[cfg(feature = "embedded-io-async")]
+pub mod embedded_async;
 pub mod errors;
+pub mod frame;
+#[cfg(feature = "futures")]
+pub mod futures;
+#[cfg(feature = "tonic")]
+pub mod grpc;
+mod instrument;
+#[cfg(feature = "std")]
+pub mod integrity;
+#[cfg(feature = "std")]
+pub mod log;
+#[cfg(feature = "std")]
+pub mod mem;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod multipart;
+#[cfg(feature = "std")]
+pub mod mux;
+#[cfg(feature = "std")]
+pub mod options;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "serde")]
+pub mod payload;
+pub mod pool;
+pub mod push;
+#[cfg(feature = "std")]
 pub mod read;
+#[cfg(feature = "std")]
+pub mod recover;
+#[cfg(feature = "std")]
+pub mod seek;
+#[cfg(feature = "std")]
 pub mod stream;
+#[cfg(feature = "std")]
+pub mod temp;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "std")]
+pub mod transcode;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+#[cfg(feature = "std")]
 pub mod write;
 pub use errors::{Error, Result};
 
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+
 /// Signature of a bzip3 file.
 pub const MAGIC_NUMBER: &[u8; 5] = b"BZ3v1";
 
 /// Minimum block size.
-pub const BLOCK_SIZE_MIN: usize = 65 * KIB as usize;
+pub const BLOCK_SIZE_MIN: usize = 65 * KIB;
 
 /// Maximum block size.
-pub const BLOCK_SIZE_MAX: usize = 511 * MIB as usize;
+pub const BLOCK_SIZE_MAX: usize = 511 * MIB;
+
+/// Default block size, 16 MiB, that of the default [`Level`] and of the reference `bzip3` tool.
+///
+/// This is a good trade-off between the compression ratio and the memory used, about six times
+/// the block size for either compression or decompression. Larger blocks compress better, and
+/// smaller ones are preferable for small inputs, or to flush data more often.
+pub const DEFAULT_BLOCK_SIZE: usize = 16 * MIB;
+
+/// Compression level, from 1 to 9, as the `-1` to `-9` flags of bzip2-style tools.
+///
+/// bzip3 has no tuning knobs besides the block size, so a level picks a block size: level `n`
+/// means blocks of 2<sup>n - 1</sup> MiB, from 1 MiB for level 1 up to 256 MiB for level 9.
+/// Larger blocks compress better, but take more memory, about six times the block size for
+/// either compression or decompression. The default, level 5, is 16 MiB, the default block size
+/// of the reference `bzip3` tool.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::Level;
+///
+/// let level = Level::new(3).unwrap();
+/// assert_eq!(level.block_size(), 4 * 1024 * 1024);
+/// assert_eq!(Level::default().block_size(), bzip3::DEFAULT_BLOCK_SIZE);
+/// assert!(Level::new(10).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Level(u8);
+
+impl Level {
+    /// Level 1, the smallest blocks.
+    pub const FASTEST: Self = Self(1);
+
+    /// Level 9, the largest blocks.
+    pub const BEST: Self = Self(9);
+
+    /// Creates a compression level.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::InvalidArgument`] if the level isn't between 1 and 9.
+    pub fn new(level: u32) -> Result<Self> {
+        match level {
+            1..=9 => Ok(Self(level as u8)),
+            _ => Err(Error::InvalidArgument("Invalid compression level".into())),
+        }
+    }
+
+    /// Returns the level number.
+    pub fn get(self) -> u32 {
+        self.0 as u32
+    }
 
-pub(crate) trait TryReadExact {
+    /// Returns the block size of the level.
+    pub fn block_size(self) -> usize {
+        MIB << (self.0 - 1)
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self(5)
+    }
+}
+
+/// Parses a human-readable block size, e.g. `"16MiB"` or `"512k"`, as found in command-line
+/// arguments and config files.
+///
+/// The units are those of [bytesize](https://docs.rs/bytesize), case-insensitive: `k`/`kb`,
+/// `m`/`mb` and so on are powers of 1000, and `ki`/`kib`, `mi`/`mib` and so on powers of 1024.
+/// A plain number is in bytes.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if `s` isn't a size, and [`Error::BlockSize`] if the size isn't a
+/// valid block size.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(bzip3::parse_block_size("16MiB").unwrap(), 16 * 1024 * 1024);
+/// assert_eq!(bzip3::parse_block_size("512k").unwrap(), 512 * 1000);
+/// assert!(bzip3::parse_block_size("1k").is_err());
+/// ```
+#[cfg(feature = "std")]
+pub fn parse_block_size(s: &str) -> Result<usize> {
+    let size = s
+        .trim()
+        .parse::<bytesize::ByteSize>()
+        .map_err(|_| Error::InvalidArgument(format!("Invalid block size: {s:?}")))?;
+    let size = usize::try_from(size.as_u64()).map_err(|_| Error::BlockSize)?;
+    if !Bz3State::check_block_size(size) {
+        return Err(Error::BlockSize);
+    }
+    Ok(size)
+}
+
+/// How stream decoders handle a block failing its CRC check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrcMode {
+    /// Fail with [`Error::BadCrc`].
+    #[default]
+    Strict,
+    /// Output the data of the block anyway, record the block, and go on.
+    Permissive,
+}
+
+/// Reading as much as possible into a buffer, for parsers of bzip3 frames telling a complete
+/// read from the end of the input.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::TryReadExact;
+///
+/// let mut reader: &[u8] = b"BZ3v1";
+/// let mut header = [0_u8; 9];
+/// assert_eq!(reader.try_read_exact(&mut header).unwrap(), 5);
+/// ```
+#[cfg(feature = "std")]
+pub trait TryReadExact {
     /// Read exact data
     ///
     /// This function blocks. It reads exact data, and returns bytes it reads. The return value
     /// will always be the buffer size until it reaches EOF.
     ///
     /// When reaching EOF, the return value will be less than the size of the given buffer,
-    /// or just zero.
+    /// or just zero. [`Interrupted`](std::io::ErrorKind::Interrupted) errors are retried.
     ///
     /// This simulates C function `fread`.
     fn try_read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
 }
 
+#[cfg(feature = "std")]
 impl<R> TryReadExact for R
 where
     R: Read,
@@ -85,6 +290,7 @@ where
                         return Ok(read);
                     }
                 }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
                 Err(e) => {
                     return Err(e);
                 }
@@ -93,7 +299,55 @@ where
     }
 }
 
+/// Writing as much as possible of a buffer, the counterpart of [`TryReadExact`].
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::TryWriteAll;
+///
+/// let mut buffer = [0_u8; 5];
+/// let mut writer = &mut buffer[..];
+/// assert_eq!(writer.try_write_all(b"BZ3v1\x00\x00\x01\x00").unwrap(), 5);
+/// assert_eq!(&buffer, b"BZ3v1");
+/// ```
+#[cfg(feature = "std")]
+pub trait TryWriteAll {
+    /// Write all data
+    ///
+    /// This function blocks. It writes all the data, and returns bytes it writes. The return
+    /// value will always be the buffer size until the writer accepts no more data, by writing
+    /// zero bytes; it's then less than the size of the given buffer, or just zero.
+    /// [`Interrupted`](std::io::ErrorKind::Interrupted) errors are retried.
+    ///
+    /// Unlike [`Write::write_all`], this tells how much of the data was written when the writer
+    /// fills up, for the caller to keep the rest.
+    ///
+    /// This simulates C function `fwrite`.
+    fn try_write_all(&mut self, buf: &[u8]) -> std::io::Result<usize>;
+}
+
+#[cfg(feature = "std")]
+impl<W> TryWriteAll for W
+where
+    W: Write,
+{
+    fn try_write_all(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut written = 0_usize;
+        while written < buf.len() {
+            match self.write(&buf[written..]) {
+                Ok(0) => break,
+                Ok(w) => written += w,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(written)
+    }
+}
+
 /// Version of the underlying bzip3 library.
+#[cfg(libbz3_has_version)]
 pub fn version() -> &'static str {
     // SAFETY: `bz3_version` from the C lib is supposed to return a static string.
     unsafe { CStr::from_ptr(libbzip3_sys::bz3_version()) }
@@ -101,19 +355,57 @@ pub fn version() -> &'static str {
         .expect("Invalid UTF-8")
 }
 
+/// Version of the underlying bzip3 library, as found at build time; "unknown" if it wasn't.
+///
+/// This libbz3 predates `bz3_version`.
+#[cfg(not(libbz3_has_version))]
+pub fn version() -> &'static str {
+    env!("LIBBZ3_VERSION")
+}
+
 // TODO: It may be a const function?
 /// Returns the recommended output buffer size for the compression function.
+#[cfg(libbz3_has_bound)]
 pub fn bound(input: usize) -> usize {
     unsafe {
         // SAFETY: only performs an arithmetic calculation
-        bz3_bound(input)
+        libbzip3_sys::bz3_bound(input)
     }
 }
 
+/// Returns the recommended output buffer size for the compression function.
+#[cfg(not(libbz3_has_bound))]
+pub fn bound(input: usize) -> usize {
+    // as `bz3_bound` of later libbz3 versions computes it
+    input + input / 50 + 32
+}
+
+/// Compresses `data` into a standalone block, block header included.
+///
+/// The returned buffer has the layout `[ new size (i32) | read size (i32) | data ]`.
+#[cfg_attr(not(any(feature = "parallel", feature = "tokio")), allow(dead_code))]
+#[cfg(feature = "std")]
+pub(crate) fn compress_block_to_vec(state: &mut Bz3State, data: &[u8]) -> Result<Vec<u8>> {
+    use byteorder::{ByteOrder, LE};
+
+    if data.len() > state.block_size {
+        return Err(Error::ProcessBlock("Data exceeds the block size".into()));
+    }
+    let mut buffer = alloc::vec![0_u8; 8 + bound(data.len())];
+    buffer[8..(8 + data.len())].copy_from_slice(data);
+    let new_size = state.encode_block(&mut buffer[8..], data.len())?;
+    LE::write_i32(&mut buffer, new_size as i32);
+    LE::write_i32(&mut buffer[4..], data.len() as i32);
+    buffer.truncate(8 + new_size);
+    Ok(buffer)
+}
+
 /// Wrapper for the raw Bz3State.
 pub struct Bz3State {
     block_size: usize,
     raw: *mut bz3_state,
+    /// CRC stored in the last block processed.
+    last_crc: Option<u32>,
 }
 
 impl Bz3State {
@@ -137,6 +429,7 @@ impl Bz3State {
             Ok(Bz3State {
                 raw: state,
                 block_size,
+                last_crc: None,
             })
         }
     }
@@ -146,6 +439,26 @@ impl Bz3State {
         self.raw
     }
 
+    /// Returns the block size of the state.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the code of the last error of libbz3, `BZ3_OK` (zero) or one of the negative
+    /// `BZ3_ERR_*` codes of [`libbzip3_sys`]; see [`Bz3State::error`] for its description.
+    pub fn last_error(&self) -> i32 {
+        // SAFETY: the state is valid
+        unsafe { libbzip3_sys::bz3_last_error(self.raw) as i32 }
+    }
+
+    /// Returns the CRC32C of the original data of the last block processed, as stored in the
+    /// block; `None` before any block.
+    ///
+    /// For a block failing to decompress, this is the CRC it was expected to have.
+    pub fn last_crc(&self) -> Option<u32> {
+        self.last_crc
+    }
+
     pub fn error(&mut self) -> &'static str {
         unsafe {
             // SAFETY: in bzip3 source code, this returns static string literals
@@ -155,10 +468,37 @@ impl Bz3State {
         }
     }
 
+    /// Like [`Bz3State::decode_block`], but reports a failed CRC check as [`Error::BadCrc`] for
+    /// the given block.
+    ///
+    /// The decompressed data is in `buf` even if the CRC check fails.
+    pub(crate) fn decode_block_at(
+        &mut self,
+        buf: &mut [u8],
+        compressed_size: usize,
+        original_size: usize,
+        block_index: usize,
+    ) -> Result<()> {
+        self.decode_block(buf, compressed_size, original_size)
+            .map_err(|e| {
+                if self.crc_failed(-1) {
+                    Error::BadCrc { block_index }
+                } else {
+                    e
+                }
+            })
+    }
+
+    /// Whether a block operation returning `code` failed because of the CRC check.
+    pub(crate) fn crc_failed(&mut self, code: i32) -> bool {
+        code == -1 && self.last_error() == libbzip3_sys::BZ3_ERR_CRC
+    }
+
     fn check_block_process_code(&mut self, code: i32) -> Result<()> {
         if code == -1 {
             return Err(Error::ProcessBlock(self.error().into()));
         }
+        #[cfg(libbz3_decode_buffer_size)]
         if code == libbzip3_sys::BZ3_ERR_DATA_SIZE_TOO_SMALL {
             return Err(Error::BlockSize);
         }
@@ -168,17 +508,20 @@ impl Bz3State {
     /// Compresses a block in-place.
     ///
     /// - `input_size` is the original data size before compression. It must not exceed the block
-    ///    size associated with the state.
+    ///   size associated with the state.
     /// - `buf` must be able to hold the data after compression. That's,
-    ///    `buf.len() >= bound(input_size)` must be required, in some cases where the compressed
-    ///    data is larger than the original one.
+    ///   `buf.len() >= bound(input_size)` must be required, in some cases where the compressed
+    ///   data is larger than the original one.
     ///
     /// Returns the size of data written to `buf`.
     pub fn encode_block(&mut self, buf: &mut [u8], input_size: usize) -> Result<usize> {
         debug_assert!(input_size <= self.block_size);
         debug_assert!(buf.len() >= bound(input_size));
+        let timer = BlockTimer::start(Operation::Compress);
         let result = unsafe { bz3_encode_block(self.raw, buf.as_mut_ptr(), input_size as _) };
         self.check_block_process_code(result)?;
+        timer.record(input_size, result as usize);
+        self.last_crc = stored_crc(&buf[..(result as usize)]);
 
         Ok(result as usize)
     }
@@ -205,6 +548,9 @@ impl Bz3State {
     ) -> Result<()> {
         debug_assert!(buf.len() >= original_size && buf.len() >= compressed_size);
         debug_assert!(compressed_size <= i32::MAX as usize);
+        self.last_crc = stored_crc(&buf[..compressed_size]);
+        let timer = BlockTimer::start(Operation::Decompress);
+        #[cfg(libbz3_decode_buffer_size)]
         let result = unsafe {
             bz3_decode_block(
                 self.raw,
@@ -214,12 +560,189 @@ impl Bz3State {
                 original_size as _,
             )
         };
+        #[cfg(not(libbz3_decode_buffer_size))]
+        let result = self.decode_block_unsized(buf, compressed_size, original_size);
         self.check_block_process_code(result)?;
         if result as usize != original_size {
             return Err(Error::ProcessBlock(
                 "Data not match the origin size after decompression".into(),
             ));
         }
+        timer.record(compressed_size, original_size);
+        Ok(())
+    }
+
+    /// `bz3_decode_block` of libbz3 before 1.5, which can't be told the buffer size, and may
+    /// write up to `bound(original_size)` bytes; a smaller `buf` goes through a temporary one.
+    #[cfg(not(libbz3_decode_buffer_size))]
+    fn decode_block_unsized(
+        &mut self,
+        buf: &mut [u8],
+        compressed_size: usize,
+        original_size: usize,
+    ) -> i32 {
+        let needed = bound(original_size).max(compressed_size);
+        if buf.len() >= needed {
+            return unsafe {
+                // SAFETY: `buf` holds all that's written
+                bz3_decode_block(
+                    self.raw,
+                    buf.as_mut_ptr(),
+                    compressed_size as _,
+                    original_size as _,
+                )
+            };
+        }
+        let mut temp = alloc::vec![0_u8; needed];
+        temp[..compressed_size].copy_from_slice(&buf[..compressed_size]);
+        let result = unsafe {
+            // SAFETY: `temp` holds all that's written
+            bz3_decode_block(
+                self.raw,
+                temp.as_mut_ptr(),
+                compressed_size as _,
+                original_size as _,
+            )
+        };
+        let size = buf.len();
+        buf.copy_from_slice(&temp[..size]);
+        result
+    }
+}
+
+#[cfg(feature = "libbz3-threads")]
+impl Bz3State {
+    /// Compresses multiple blocks in-place concurrently, using libbz3's built-in threads.
+    ///
+    /// `states`, `buffers` and `sizes` pair up by index, and each block is compressed by its own
+    /// thread with its own state. On input, `sizes` holds the original data sizes; on success,
+    /// it holds the compressed sizes. The requirements on each buffer are the same as
+    /// [`Bz3State::encode_block`].
+    pub fn encode_blocks(
+        states: &mut [Bz3State],
+        buffers: &mut [&mut [u8]],
+        sizes: &mut [usize],
+    ) -> Result<()> {
+        assert!(states.len() == buffers.len() && states.len() == sizes.len());
+        for ((state, buffer), &size) in states.iter().zip(buffers.iter()).zip(sizes.iter()) {
+            assert!(size <= state.block_size && buffer.len() >= bound(size));
+        }
+
+        let mut raw_states = states.iter_mut().map(|x| x.raw).collect::<Vec<_>>();
+        let mut raw_buffers = buffers
+            .iter_mut()
+            .map(|x| x.as_mut_ptr())
+            .collect::<Vec<_>>();
+        let mut raw_sizes = sizes.iter().map(|&x| x as i32).collect::<Vec<_>>();
+        let timer = BlockTimer::start(Operation::Compress);
+        unsafe {
+            // SAFETY: the states are distinct, and the buffers are large enough as checked above
+            libbzip3_sys::bz3_encode_blocks(
+                raw_states.as_mut_ptr(),
+                raw_buffers.as_mut_ptr(),
+                raw_sizes.as_mut_ptr(),
+                states.len() as i32,
+            );
+        }
+
+        for (((state, result), size), buffer) in states
+            .iter_mut()
+            .zip(raw_sizes)
+            .zip(sizes.iter_mut())
+            .zip(buffers.iter())
+        {
+            state.check_block_process_code(result)?;
+            timer.record(*size, result as usize);
+            *size = result as usize;
+            state.last_crc = stored_crc(&buffer[..*size]);
+        }
+        Ok(())
+    }
+
+    /// Decompresses multiple blocks in-place concurrently, using libbz3's built-in threads.
+    ///
+    /// `states`, `buffers`, `compressed_sizes` and `original_sizes` pair up by index. The
+    /// requirements on each buffer are the same as [`Bz3State::decode_block`]. A failed CRC check
+    /// is reported as [`Error::BadCrc`] with the index of the block in the slices.
+    pub fn decode_blocks(
+        states: &mut [Bz3State],
+        buffers: &mut [&mut [u8]],
+        compressed_sizes: &[usize],
+        original_sizes: &[usize],
+    ) -> Result<()> {
+        let n = states.len();
+        assert!(buffers.len() == n && compressed_sizes.len() == n && original_sizes.len() == n);
+        for (i, buffer) in buffers.iter().enumerate() {
+            assert!(buffer.len() >= compressed_sizes[i] && buffer.len() >= original_sizes[i]);
+        }
+
+        // libbz3 before 1.5 can't be told the buffer sizes; blocks are decoded one by one then
+        // if any needs a temporary buffer
+        #[cfg(not(libbz3_decode_buffer_size))]
+        if (0..n).any(|i| buffers[i].len() < bound(original_sizes[i]).max(compressed_sizes[i])) {
+            for (i, (state, buffer)) in states.iter_mut().zip(buffers.iter_mut()).enumerate() {
+                state.decode_block_at(buffer, compressed_sizes[i], original_sizes[i], i)?;
+            }
+            return Ok(());
+        }
+
+        for ((state, buffer), &size) in states.iter_mut().zip(buffers.iter()).zip(compressed_sizes)
+        {
+            state.last_crc = stored_crc(&buffer[..size]);
+        }
+        let mut raw_states = states.iter_mut().map(|x| x.raw).collect::<Vec<_>>();
+        let mut raw_buffers = buffers
+            .iter_mut()
+            .map(|x| x.as_mut_ptr())
+            .collect::<Vec<_>>();
+        #[cfg(libbz3_decode_buffer_size)]
+        let mut buffer_sizes = buffers.iter().map(|x| x.len()).collect::<Vec<_>>();
+        let mut raw_sizes = compressed_sizes
+            .iter()
+            .map(|&x| x as i32)
+            .collect::<Vec<_>>();
+        let mut raw_original_sizes = original_sizes.iter().map(|&x| x as i32).collect::<Vec<_>>();
+        let timer = BlockTimer::start(Operation::Decompress);
+        unsafe {
+            // SAFETY: the states are distinct, and the buffers are large enough as checked above
+            #[cfg(libbz3_decode_buffer_size)]
+            libbzip3_sys::bz3_decode_blocks(
+                raw_states.as_mut_ptr(),
+                raw_buffers.as_mut_ptr(),
+                buffer_sizes.as_mut_ptr(),
+                raw_sizes.as_mut_ptr(),
+                raw_original_sizes.as_mut_ptr(),
+                n as i32,
+            );
+            #[cfg(not(libbz3_decode_buffer_size))]
+            libbzip3_sys::bz3_decode_blocks(
+                raw_states.as_mut_ptr(),
+                raw_buffers.as_mut_ptr(),
+                raw_sizes.as_mut_ptr(),
+                raw_original_sizes.as_mut_ptr(),
+                n as i32,
+            );
+        }
+
+        for (i, ((state, result), &original_size)) in states
+            .iter_mut()
+            .zip(raw_sizes)
+            .zip(original_sizes)
+            .enumerate()
+        {
+            if state.crc_failed(result) {
+                return Err(Error::BadCrc { block_index: i });
+            }
+            state.check_block_process_code(result)?;
+            if result as usize != original_size {
+                return Err(Error::ProcessBlock(
+                    "Data not match the origin size after decompression".into(),
+                ));
+            }
+        }
+        for (&compressed_size, &original_size) in compressed_sizes.iter().zip(original_sizes) {
+            timer.record(compressed_size, original_size);
+        }
         Ok(())
     }
 }
@@ -263,4 +786,18 @@ mod test {
         let decompressed = &buf[..data.len()];
         assert_eq!(decompressed, &data[..]);
     }
+
+    #[test]
+    fn block_crc() {
+        let data = b"hello, world";
+        let mut buf = vec![0_u8; bound(data.len())];
+        buf[..data.len()].copy_from_slice(data);
+        let mut bs = Bz3State::new(MIB as _).unwrap();
+        let compressed_size = bs.encode_block(&mut buf, data.len()).unwrap();
+
+        assert_eq!(
+            crate::crc::stored_crc(&buf[..compressed_size]),
+            Some(crate::crc::block_crc(data))
+        );
+    }
 }
@@ -0,0 +1,198 @@
+//! Compressed log files with rotation.
+//!
+//! [`Bz3LogWriter`] compresses everything written to it into a bzip3 file, and rotates the file
+//! by size or age, as logging stacks expect: the current file is finished, so each rotated file
+//! is a complete bzip3 file, renamed to `<path>.1`, the older ones shifted to `<path>.2`,
+//! `<path>.3` and so on, and a new file started at the path. Only a bounded number of old files
+//! is kept.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::{write, Level};
+
+/// Options of [`Bz3LogWriter`].
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// Block size of the bzip3 files; 1 MiB, that of [`Level::FASTEST`], by default.
+    pub block_size: usize,
+    /// Rotate once this many bytes of uncompressed data have been written to the current
+    /// file; no limit by default.
+    pub max_size: Option<u64>,
+    /// Rotate once the current file has been open this long; no limit by default.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files kept; 5 by default. Older ones are deleted.
+    pub max_files: usize,
+    /// Whether to end each file with a [checksum](crate::write::Bz3Encoder::set_checksum); off
+    /// by default, for other bzip3 implementations to read the files.
+    pub checksum: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            block_size: Level::FASTEST.block_size(),
+            max_size: None,
+            max_age: None,
+            max_files: 5,
+            checksum: false,
+        }
+    }
+}
+
+/// Writer of compressed log files, rotating them by size or age.
+///
+/// The limits are checked when data is written, so a file is rotated on the first write after
+/// reaching one of them, and never while empty; there's no background timer. The data of one
+/// write call always goes to the same file.
+///
+/// Data is compressed a block at a time, so it reaches the file once a block is full;
+/// [`Write::flush`] compresses the partial block, at the cost of a smaller block.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Write;
+/// use std::time::Duration;
+/// use bzip3::log::{Bz3LogWriter, LogOptions};
+///
+/// let options = LogOptions {
+///     max_size: Some(100 * 1024 * 1024),
+///     max_age: Some(Duration::from_secs(24 * 60 * 60)),
+///     ..Default::default()
+/// };
+/// let mut writer = Bz3LogWriter::new("app.log.bz3", options).unwrap();
+/// writeln!(writer, "service started").unwrap();
+/// ```
+pub struct Bz3LogWriter {
+    path: PathBuf,
+    options: LogOptions,
+    /// `None` after a failed rotation.
+    encoder: Option<write::Bz3Encoder<File>>,
+    /// Uncompressed data written to the current file.
+    written: u64,
+    opened: Instant,
+}
+
+impl Bz3LogWriter {
+    /// Creates a log writer, starting a new file at `path`.
+    ///
+    /// A file already at `path`, e.g. from a previous run, is rotated first, as a finished file
+    /// can't be appended to.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors.
+    pub fn new<P: AsRef<Path>>(path: P, options: LogOptions) -> Result<Self> {
+        let mut writer = Self {
+            path: path.as_ref().to_path_buf(),
+            options,
+            encoder: None,
+            written: 0,
+            opened: Instant::now(),
+        };
+        if writer.path.exists() {
+            writer.shift_files()?;
+        }
+        writer.open()?;
+        Ok(writer)
+    }
+
+    /// Returns the path of the current file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the rotated file with the given number, from 1 for the newest.
+    pub fn rotated_path(&self, number: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{number}"));
+        path.into()
+    }
+
+    /// Rotates the current file right away, even if it's empty.
+    pub fn rotate(&mut self) -> Result<()> {
+        self.finish_file()?;
+        self.shift_files()?;
+        self.open()
+    }
+
+    /// Finishes the current file without rotating it.
+    pub fn finish(mut self) -> Result<()> {
+        self.finish_file()
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let file = File::create(&self.path)?;
+        let mut encoder = write::Bz3Encoder::new(file, self.options.block_size)?;
+        encoder.set_checksum(self.options.checksum);
+        self.encoder = Some(encoder);
+        self.written = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the file at the path to `<path>.1`, shifting the rotated files and deleting the
+    /// oldest.
+    fn shift_files(&self) -> Result<()> {
+        if self.options.max_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        match fs::remove_file(self.rotated_path(self.options.max_files)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        for number in (1..self.options.max_files).rev() {
+            match fs::rename(self.rotated_path(number), self.rotated_path(number + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        Ok(())
+    }
+
+    /// Whether the current file has data and reached a limit.
+    fn needs_rotation(&self) -> bool {
+        let options = &self.options;
+        self.written != 0
+            && (options.max_size.is_some_and(|x| self.written >= x)
+                || options.max_age.is_some_and(|x| self.opened.elapsed() >= x))
+    }
+}
+
+impl Write for Bz3LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation() {
+            self.rotate().map_err(Error::into_io_error)?;
+        }
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| io::Error::other("No log file open after a failed rotation"))?;
+        // all at once, so that what's written in one call doesn't get split across files
+        encoder.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Some(encoder) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,576 @@
+//! One-shot BZip3 compression and decompression of in-memory buffers.
+//!
+//! [`compress`] and [`decompress`] keep the libbz3 state and the block buffer they used in a
+//! per-thread scratch, for the next call with the same block size, so that calling them over
+//! and over, e.g. once per request in a server, doesn't allocate and free several times the
+//! block size each time. The scratch is only kept for block sizes up to a
+//! [limit](set_scratch_limit), and is freed with [`purge_scratch`].
+
+use std::cell::RefCell;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::errors::*;
+use crate::frame::{
+    parse_checksum, BlockHeader, FrameHeader, SkippableFrame, BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE,
+    SKIPPABLE_HEADER_SIZE, SKIPPABLE_MAGIC,
+};
+use crate::pool::BufferPool;
+use crate::{bound, push, DEFAULT_BLOCK_SIZE, MAGIC_NUMBER};
+
+/// Default of the largest block size the scratch is kept for, 64 MiB, four times the
+/// [default block size](DEFAULT_BLOCK_SIZE).
+pub const DEFAULT_SCRATCH_LIMIT: usize = 4 * DEFAULT_BLOCK_SIZE;
+
+static SCRATCH_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_SCRATCH_LIMIT);
+
+thread_local! {
+    /// The scratch of the thread, for a single block size.
+    static SCRATCH: RefCell<Option<(usize, BufferPool)>> = const { RefCell::new(None) };
+}
+
+/// Sets the largest block size the scratch of [`compress`] and [`decompress`] is kept for, in
+/// all threads; zero disables the scratch.
+///
+/// The scratch takes about six times the block size. One of a larger block size already kept
+/// stays until [purged](purge_scratch).
+pub fn set_scratch_limit(block_size: usize) {
+    SCRATCH_LIMIT.store(block_size, Ordering::Relaxed);
+}
+
+/// Frees the scratch of the calling thread.
+pub fn purge_scratch() {
+    SCRATCH.with(|x| x.borrow_mut().take());
+}
+
+/// Returns the scratch of the calling thread for the block size, replacing the one of another
+/// block size, or `None` if the block size is above the limit.
+fn scratch(block_size: usize) -> Option<BufferPool> {
+    if block_size > SCRATCH_LIMIT.load(Ordering::Relaxed) {
+        return None;
+    }
+    SCRATCH.with(|scratch| {
+        let mut scratch = scratch.borrow_mut();
+        match &*scratch {
+            Some((size, pool)) if *size == block_size => Some(pool.clone()),
+            _ => {
+                let pool = BufferPool::new(1);
+                *scratch = Some((block_size, pool.clone()));
+                Some(pool)
+            }
+        }
+    })
+}
+
+/// Compresses `data` into a complete bzip3 stream.
+pub fn compress(data: &[u8], block_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut encoder = crate::write::Bz3Encoder::new(&mut output, block_size)?;
+    if let Some(pool) = scratch(block_size) {
+        encoder.set_buffer_pool(pool);
+    }
+    encoder.write_all(data)?;
+    encoder.flush()?;
+    drop(encoder);
+    Ok(output)
+}
+
+/// Decompresses a complete bzip3 stream.
+///
+/// The output is allocated once, at its exact size, which the block headers tell.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let offset = skip_skippable_frames(data, 0);
+    let pool = data
+        .get(offset..)
+        .and_then(|x| x.first_chunk())
+        .and_then(|x| FrameHeader::parse(x).ok())
+        .and_then(|x| scratch(x.block_size));
+    SliceDecoder::with_options(data, pool)?.decode_all()
+}
+
+/// Decoder of a bzip3 stream in memory.
+///
+/// This is [`read::Bz3Decoder`](crate::read::Bz3Decoder) without the [`Read`] calls on the
+/// input: block data is copied right from the slice to where it's decoded, into the buffer
+/// given to [`Read::read`] when it's large enough, or the output of
+/// [`SliceDecoder::decode_all`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Read;
+/// use bzip3::mem::SliceDecoder;
+///
+/// let data = vec![b'x'; 250 * 1024];
+/// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+///
+/// let mut decoder = SliceDecoder::new(&compressed).unwrap();
+/// let mut head = [0_u8; 1024];
+/// decoder.read_exact(&mut head).unwrap();
+///
+/// assert_eq!(SliceDecoder::new(&compressed).unwrap().decode_all().unwrap(), data);
+/// ```
+pub struct SliceDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    decoder: push::Decoder,
+    eof: bool,
+}
+
+impl<'a> SliceDecoder<'a> {
+    /// Creates a decoder of the bzip3 stream in `data`.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`read::Bz3Decoder::new`](crate::read::Bz3Decoder::new).
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        Self::with_options(data, None)
+    }
+
+    fn with_options(data: &'a [u8], pool: Option<BufferPool>) -> Result<Self> {
+        let mut decoder = Self {
+            data,
+            pos: 0,
+            decoder: push::Decoder::new(),
+            eof: false,
+        };
+        if let Some(pool) = pool {
+            decoder.decoder.set_buffer_pool(pool);
+        }
+        // parse the file header
+        while decoder.decoder.block_size().is_none() {
+            if !decoder.fill()? {
+                decoder.decoder.finish()?;
+            }
+        }
+        Ok(decoder)
+    }
+
+    /// Returns the block size of the stream.
+    pub fn block_size(&self) -> usize {
+        self.decoder.block_size().unwrap()
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Decodes all the rest of the data.
+    ///
+    /// Before any data has been read, the output is allocated once, at its exact size, which
+    /// the block headers tell.
+    pub fn decode_all(mut self) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        // the sizes are only checked while decoding, so the reservation may fail, or be far too
+        // large for a corrupt stream, which then fails anyway
+        if self.decoder.before_blocks() {
+            if let Ok(scanned) = scan_blocks(self.data) {
+                let size = scanned
+                    .blocks
+                    .iter()
+                    .try_fold(0_usize, |size, x| size.checked_add(x.read_size));
+                if let Some(size) = size {
+                    let _ = output.try_reserve_exact(size);
+                }
+            }
+        }
+        self.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// Feeds more of the data to the decoder.
+    ///
+    /// Returns false at the end of the data.
+    fn fill(&mut self) -> Result<bool> {
+        if self.pos == self.data.len() {
+            return Ok(false);
+        }
+        self.pos += self.decoder.feed(&self.data[self.pos..])?;
+        Ok(true)
+    }
+
+    /// Decodes the next block straight into `buf` if it's large enough, saving a copy.
+    ///
+    /// Returns the size of the block's data, or `None` if the block has to go through the
+    /// decoder.
+    fn read_block_into(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let Some(header) = self.decoder.pending_block() else {
+            return Ok(None);
+        };
+        let data_size = header.data_size();
+        let read_size = header.read_size as usize;
+        // a truncated block goes through the decoder, which tells about it
+        if buf.len() < data_size.max(bound(read_size)) || self.data.len() - self.pos < data_size {
+            return Ok(None);
+        }
+        buf[..data_size].copy_from_slice(&self.data[self.pos..(self.pos + data_size)]);
+        self.pos += data_size;
+        self.decoder.decode_block_into(header, buf)?;
+        Ok(Some(read_size))
+    }
+
+    /// Makes progress when there's no output: decodes the next block into `buf`, returning
+    /// its size, or feeds the decoder.
+    fn advance(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        if let Some(size) = self.read_block_into(buf)? {
+            return Ok(Some(size));
+        }
+        if !self.fill()? {
+            self.decoder.finish()?;
+            self.eof = true;
+        }
+        Ok(None)
+    }
+}
+
+impl Read for SliceDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // empty blocks produce no output; keep going until there's some, or EOF
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.len());
+                buf[..size].copy_from_slice(&output[..size]);
+                self.decoder.consume(size);
+                return Ok(size);
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            match self.advance(buf).map_err(Error::into_io_error)? {
+                Some(size) if size != 0 => return Ok(size),
+                _ => {}
+            }
+        }
+    }
+
+    /// Decodes the blocks straight into the spare capacity of `buf`.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        loop {
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                buf.extend_from_slice(output);
+                let size = output.len();
+                self.decoder.consume(size);
+                continue;
+            }
+            if self.eof {
+                return Ok(buf.len() - start);
+            }
+            let len = buf.len();
+            let mut size = 0;
+            if let Some(header) = self.decoder.pending_block() {
+                let spare = buf.capacity() - len;
+                size = header.data_size().max(bound(header.read_size as usize));
+                // a block fitting only in the data size, e.g. in an exact reservation, goes
+                // through the decoder rather than growing `buf`
+                if spare < size && spare >= header.read_size as usize {
+                    size = 0;
+                }
+            }
+            buf.resize(len + size, 0);
+            let result = self.advance(&mut buf[len..]);
+            buf.truncate(len + result.as_ref().map_or(0, |x| x.unwrap_or(0)));
+            result.map_err(Error::into_io_error)?;
+        }
+    }
+}
+
+/// Compresses `data` into a complete bzip3 stream, compressing the blocks concurrently.
+///
+/// `threads` is the number of worker threads to use; zero means using rayon's global thread
+/// pool. The output is identical to what [`compress`] produces.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::mem;
+///
+/// let data = vec![b'x'; 1024 * 1024];
+/// let compressed = mem::compress_parallel(&data, 100 * 1024, 4).unwrap();
+/// assert_eq!(mem::decompress(&compressed).unwrap(), data);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn compress_parallel(data: &[u8], block_size: usize, threads: usize) -> Result<Vec<u8>> {
+    let chunks = data.chunks(block_size).collect::<Vec<_>>();
+    let compress = || crate::parallel::compress_chunks(&chunks, block_size, None);
+    let blocks = match crate::parallel::build_pool(threads)? {
+        Some(pool) => pool.install(compress)?,
+        None => compress()?,
+    };
+
+    let mut output =
+        Vec::with_capacity(FRAME_HEADER_SIZE + blocks.iter().map(Vec::len).sum::<usize>());
+    FrameHeader::new(block_size)?.write_to(&mut output)?;
+    for block in blocks {
+        output.write_all(&block)?;
+    }
+    Ok(output)
+}
+
+/// Location of a block inside an in-memory bzip3 stream.
+#[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+struct BlockSpan {
+    /// Number of the block, leaving out extension blocks.
+    index: usize,
+    /// Offset of the compressed data, right after the block header.
+    data_offset: usize,
+    /// Size of the data after the block header.
+    new_size: usize,
+    read_size: usize,
+    /// Whether the data is stored uncompressed.
+    stored: bool,
+}
+
+/// Block headers of an in-memory bzip3 stream.
+#[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+struct ScannedBlocks {
+    header: FrameHeader,
+    blocks: Vec<BlockSpan>,
+    /// The last checksum in the stream, and the size of the uncompressed data it covers.
+    checksum: Option<(u64, usize)>,
+}
+
+/// Returns the offset after the skippable frames starting at `offset`, if any.
+///
+/// The offset is past the end of `data` if the last skippable frame is truncated.
+fn skip_skippable_frames(data: &[u8], mut offset: usize) -> usize {
+    while let Some((_, size)) = data
+        .get(offset..(offset + SKIPPABLE_HEADER_SIZE))
+        .and_then(|x| SkippableFrame::parse_header(x.try_into().unwrap()))
+    {
+        offset += SKIPPABLE_HEADER_SIZE + size;
+    }
+    offset
+}
+
+/// Walks through all the block headers without decompressing anything.
+fn scan_blocks(data: &[u8]) -> Result<ScannedBlocks> {
+    let mut offset = skip_skippable_frames(data, 0);
+    let mut cursor = data.get(offset..).unwrap_or_default();
+    let header = FrameHeader::read_from(&mut cursor)?;
+
+    let mut blocks = Vec::new();
+    let mut checksum = None;
+    let mut uncompressed_size = 0;
+    offset += FRAME_HEADER_SIZE;
+    while offset < data.len() {
+        if data[offset..].starts_with(SKIPPABLE_MAGIC) {
+            // only skippable frames may follow the last block
+            offset = skip_skippable_frames(data, offset);
+            if offset > data.len() {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            let rest = &data[offset..];
+            if rest.starts_with(MAGIC_NUMBER) {
+                return Err(Error::MisplacedFrameHeader {
+                    offset: offset as u64,
+                });
+            }
+            if !rest.is_empty() {
+                return Err(Error::TrailingData {
+                    offset: offset as u64,
+                });
+            }
+            break;
+        }
+        let Some(header_bytes) = data.get(offset..(offset + BLOCK_HEADER_SIZE)) else {
+            return Err(Error::TruncatedBlock {
+                block_index: blocks.len(),
+                have: data.len() - offset,
+                need: BLOCK_HEADER_SIZE,
+            });
+        };
+        let block_header = BlockHeader::parse(header_bytes.try_into().unwrap());
+        if let Some(size) = block_header.extension_size() {
+            let extension = (offset + BLOCK_HEADER_SIZE)..(offset + BLOCK_HEADER_SIZE + size);
+            let Some(extension) = data.get(extension) else {
+                return Err(Error::TruncatedBlock {
+                    block_index: blocks.len(),
+                    have: data.len() - offset,
+                    need: BLOCK_HEADER_SIZE + size,
+                });
+            };
+            if let Some(hash) = parse_checksum(extension) {
+                checksum = Some((hash, uncompressed_size));
+            }
+            offset += BLOCK_HEADER_SIZE + size;
+            continue;
+        }
+        if block_header.validate(header.block_size).is_err() {
+            let offset = offset as u64;
+            if header_bytes.starts_with(MAGIC_NUMBER) {
+                return Err(Error::MisplacedFrameHeader { offset });
+            }
+            return Err(Error::TrailingData { offset });
+        }
+
+        let span = BlockSpan {
+            index: blocks.len(),
+            data_offset: offset + BLOCK_HEADER_SIZE,
+            new_size: block_header.data_size(),
+            read_size: block_header.read_size as usize,
+            stored: block_header.is_stored(),
+        };
+        if span.data_offset + span.new_size > data.len() {
+            return Err(Error::TruncatedBlock {
+                block_index: span.index,
+                have: data.len() - offset,
+                need: BLOCK_HEADER_SIZE + span.new_size,
+            });
+        }
+        offset = span.data_offset + span.new_size;
+        uncompressed_size += span.read_size;
+        blocks.push(span);
+    }
+    Ok(ScannedBlocks {
+        header,
+        blocks,
+        checksum,
+    })
+}
+
+/// Decompresses a complete bzip3 stream, decompressing the blocks concurrently.
+///
+/// All the block headers are scanned first, then each block is decoded on rayon's global
+/// thread pool straight to its final position in the output.
+///
+/// # Examples
+///
+/// ```
+/// use bzip3::mem;
+///
+/// let data = vec![b'x'; 1024 * 1024];
+/// let compressed = mem::compress(&data, 100 * 1024).unwrap();
+/// assert_eq!(mem::decompress_parallel(&compressed).unwrap(), data);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn decompress_parallel(data: &[u8]) -> Result<Vec<u8>> {
+    #[cfg(not(feature = "libbz3-threads"))]
+    use {
+        crate::{bound, pool::BlockBuffer, Bz3State},
+        rayon::prelude::*,
+    };
+
+    let ScannedBlocks {
+        header,
+        blocks,
+        checksum,
+    } = scan_blocks(data)?;
+    let block_size = header.block_size;
+
+    // the sizes are only checked while decoding, so a few bytes of block headers can declare
+    // any amount of output; fail rather than abort if it can't be allocated
+    let output_size = blocks
+        .iter()
+        .try_fold(0_usize, |size, x| size.checked_add(x.read_size))
+        .ok_or_else(out_of_memory)?;
+    let mut output = Vec::new();
+    output
+        .try_reserve_exact(output_size)
+        .map_err(|_| out_of_memory())?;
+    output.resize(output_size, 0);
+    let mut outputs = Vec::with_capacity(blocks.len());
+    let mut remaining = output.as_mut_slice();
+    let mut compressed_blocks = Vec::with_capacity(blocks.len());
+    for block in &blocks {
+        let (head, tail) = remaining.split_at_mut(block.read_size);
+        remaining = tail;
+        if block.stored {
+            head.copy_from_slice(&data[block.data_offset..(block.data_offset + block.new_size)]);
+        } else {
+            compressed_blocks.push(block);
+            outputs.push(head);
+        }
+    }
+    let blocks = compressed_blocks;
+
+    #[cfg(feature = "libbz3-threads")]
+    decode_blocks_native(data, block_size, &blocks, outputs)?;
+    #[cfg(not(feature = "libbz3-threads"))]
+    blocks.par_iter().zip(outputs).try_for_each_init(
+        || {
+            // the block size has been validated by `scan_blocks`
+            let state = Bz3State::new(block_size).unwrap();
+            (state, BlockBuffer::new(bound(block_size), 0))
+        },
+        |(state, buffer), (block, output)| {
+            let compressed = &data[block.data_offset..(block.data_offset + block.new_size)];
+            buffer[..block.new_size].copy_from_slice(compressed);
+            state.decode_block_at(buffer, block.new_size, block.read_size, block.index)?;
+            output.copy_from_slice(&buffer[..block.read_size]);
+            Ok::<_, Error>(())
+        },
+    )?;
+
+    if let Some((expected, size)) = checksum {
+        let actual = xxhash_rust::xxh3::xxh3_64(&output[..size]);
+        if actual != expected {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok(output)
+}
+
+/// The error of a declared decompressed size too large to allocate.
+#[cfg(feature = "parallel")]
+fn out_of_memory() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        "Decompressed size too large to allocate",
+    ))
+}
+
+/// Decodes `blocks` into `outputs` with libbz3's built-in threads, in batches of as many blocks
+/// as rayon's current pool has threads.
+#[cfg(feature = "libbz3-threads")]
+fn decode_blocks_native(
+    data: &[u8],
+    block_size: usize,
+    blocks: &[&BlockSpan],
+    mut outputs: Vec<&mut [u8]>,
+) -> Result<()> {
+    use crate::{bound, pool::BlockBuffer, Bz3State};
+
+    let threads = rayon::current_num_threads().clamp(1, blocks.len().max(1));
+    let mut states = (0..threads)
+        .map(|_| Bz3State::new(block_size))
+        .collect::<Result<Vec<_>>>()?;
+    // not `vec![buffer; threads]`, which would copy the buffer, touching all of its pages
+    let mut buffers = (0..threads)
+        .map(|_| BlockBuffer::new(bound(block_size), 0))
+        .collect::<Vec<_>>();
+
+    for (batch, batch_outputs) in blocks.chunks(threads).zip(outputs.chunks_mut(threads)) {
+        for (block, buffer) in batch.iter().zip(buffers.iter_mut()) {
+            let compressed = &data[block.data_offset..(block.data_offset + block.new_size)];
+            buffer[..block.new_size].copy_from_slice(compressed);
+        }
+        let compressed_sizes = batch.iter().map(|x| x.new_size).collect::<Vec<_>>();
+        let original_sizes = batch.iter().map(|x| x.read_size).collect::<Vec<_>>();
+        let mut batch_buffers = buffers
+            .iter_mut()
+            .take(batch.len())
+            .map(|x| &mut **x)
+            .collect::<Vec<_>>();
+        Bz3State::decode_blocks(
+            &mut states[..batch.len()],
+            &mut batch_buffers,
+            &compressed_sizes,
+            &original_sizes,
+        )
+        .map_err(|e| match e {
+            Error::BadCrc { block_index } => Error::BadCrc {
+                block_index: batch[block_index].index,
+            },
+            e => e,
+        })?;
+        for ((output, buffer), block) in batch_outputs.iter_mut().zip(&batch_buffers).zip(batch) {
+            output.copy_from_slice(&buffer[..block.read_size]);
+        }
+    }
+    Ok(())
+}
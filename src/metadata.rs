@@ -0,0 +1,130 @@
+//! Metadata about the compressed file, like gzip's file name and comment fields.
+//!
+//! The metadata is an [extension block](crate::frame::EXTENSION_BLOCK) tagged `META`, written
+//! right after the file header by [`write::Bz3Encoder::with_metadata`]. Decoders of this crate
+//! skip it unless asked for it, e.g. with [`read::Bz3Decoder::read_metadata`]. Other bzip3
+//! implementations don't know about extension blocks though, and may reject the file.
+//!
+//! # Layout
+//!
+//! All integers are little-endian, and strings are UTF-8.
+//!
+//! \[ [`EXTENSION_BLOCK`] (i32) | extension data size (i32) | `META` | entry count (u32) |
+//! entry1 | entry2 | entryN... \]
+//!
+//! Each entry is \[ key size (u16) | key | value size (u32) | value \]. The keys `filename`,
+//! `mtime` (in decimal) and `comment` are the fields of [`Metadata`]; other keys are kept in
+//! [`Metadata::extra`].
+//!
+//! [`EXTENSION_BLOCK`]: crate::frame::EXTENSION_BLOCK
+//! [`write::Bz3Encoder::with_metadata`]: crate::write::Bz3Encoder::with_metadata
+//! [`read::Bz3Decoder::read_metadata`]: crate::read::Bz3Decoder::read_metadata
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use byteorder::{ByteOrder, LE};
+
+use crate::errors::*;
+use crate::frame::{BlockHeader, BLOCK_HEADER_SIZE, EXTENSION_TAG_SIZE};
+use crate::BLOCK_SIZE_MIN;
+
+/// Extension tag of the metadata.
+pub const METADATA_TAG: &[u8; EXTENSION_TAG_SIZE] = b"META";
+
+/// Largest size of the metadata extension data, tag included.
+///
+/// Decoders only keep extension data up to the size of a compressed block.
+pub const METADATA_MAX_SIZE: usize = BLOCK_SIZE_MIN;
+
+const FILENAME_KEY: &str = "filename";
+const MTIME_KEY: &str = "mtime";
+const COMMENT_KEY: &str = "comment";
+
+/// Metadata of a bzip3 file; all the fields are optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Name of the original file.
+    pub filename: Option<String>,
+    /// Modification time of the original file, in seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    pub comment: Option<String>,
+    /// Other key/value pairs, in order.
+    pub extra: Vec<(String, String)>,
+}
+
+impl Metadata {
+    fn entries(&self) -> impl Iterator<Item = (&str, String)> {
+        let fields = [
+            (FILENAME_KEY, self.filename.clone()),
+            (MTIME_KEY, self.mtime.map(|x| x.to_string())),
+            (COMMENT_KEY, self.comment.clone()),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|x| (key, x)))
+            .chain(self.extra.iter().map(|(k, v)| (k.as_str(), v.clone())))
+    }
+
+    /// Serializes the metadata into an extension block, header included.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if a key is longer than 65535 bytes, or the whole metadata is
+    /// larger than [`METADATA_MAX_SIZE`].
+    pub fn to_block(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(METADATA_TAG);
+        data.extend_from_slice(&(self.entries().count() as u32).to_le_bytes());
+        for (key, value) in self.entries() {
+            let key_size = u16::try_from(key.len())
+                .map_err(|_| Error::InvalidArgument(format!("Metadata key too long: {key}")))?;
+            data.extend_from_slice(&key_size.to_le_bytes());
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            data.extend_from_slice(value.as_bytes());
+            if data.len() > METADATA_MAX_SIZE {
+                return Err(Error::InvalidArgument("Metadata too large".into()));
+            }
+        }
+
+        let mut bytes = BlockHeader::extension(data.len()).to_bytes().to_vec();
+        bytes.extend_from_slice(&data);
+        debug_assert_eq!(bytes.len(), BLOCK_HEADER_SIZE + data.len());
+        Ok(bytes)
+    }
+
+    /// Parses the data of a metadata extension block, tag included.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::ProcessBlock`] if it's not metadata, or it's malformed.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let corrupt = || Error::ProcessBlock("Corrupt metadata".into());
+        let Some(mut data) = data.strip_prefix(METADATA_TAG) else {
+            return Err(corrupt());
+        };
+        let mut take = |size: usize| {
+            let (head, tail) = data.split_at_checked(size).ok_or_else(corrupt)?;
+            data = tail;
+            Ok::<_, Error>(head)
+        };
+
+        let count = LE::read_u32(take(4)?);
+        let mut metadata = Self::default();
+        for _ in 0..count {
+            let key_size = LE::read_u16(take(2)?) as usize;
+            let key = core::str::from_utf8(take(key_size)?).map_err(|_| corrupt())?;
+            let value_size = LE::read_u32(take(4)?) as usize;
+            let value = core::str::from_utf8(take(value_size)?).map_err(|_| corrupt())?;
+            match key {
+                FILENAME_KEY => metadata.filename = Some(value.into()),
+                MTIME_KEY => metadata.mtime = Some(value.parse().map_err(|_| corrupt())?),
+                COMMENT_KEY => metadata.comment = Some(value.into()),
+                _ => metadata.extra.push((key.into(), value.into())),
+            }
+        }
+        Ok(metadata)
+    }
+}
@@ -0,0 +1,72 @@
+//! Metrics of the codecs, through the [`metrics`](::metrics) crate.
+//!
+//! Every block compressed or decompressed, by any codec of this crate, is recorded to the
+//! installed recorder, labeled with `operation` = `compress` or `decompress`:
+//!
+//! - `bzip3_input_bytes_total`: counter of the bytes going into libbz3
+//! - `bzip3_output_bytes_total`: counter of the bytes coming out of it
+//! - `bzip3_blocks_total`: counter of the blocks processed
+//! - `bzip3_block_duration_seconds`: histogram of the time taken per block
+//!
+//! The sizes are those of the data going through libbz3, without the block headers. Reading
+//! [stored blocks](crate::frame::STORED_BLOCK) doesn't involve libbz3, and isn't recorded.
+//! Blocks processed together by libbz3's own threads are each recorded with the time taken by
+//! all of them.
+//!
+//! The `bzip3` prefix of the names can be changed with [`set_prefix`].
+//!
+//! # Examples
+//!
+//! ```
+//! bzip3::metrics::set_prefix("myapp_bzip3");
+//! // with a recorder installed, e.g. a Prometheus exporter, this records to
+//! // `myapp_bzip3_input_bytes_total` and so on
+//! bzip3::mem::compress(b"hello, world", 100 * 1024).unwrap();
+//! ```
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::instrument::Operation;
+
+/// Default prefix of the metric names.
+pub const DEFAULT_PREFIX: &str = "bzip3";
+
+static PREFIX: RwLock<Option<String>> = RwLock::new(None);
+
+/// Sets the prefix of the metric names, `bzip3` by default, e.g. to group them with the other
+/// metrics of an application.
+///
+/// This applies to the whole process, to the blocks processed from now on.
+pub fn set_prefix(prefix: &str) {
+    *PREFIX.write().unwrap() = Some(prefix.to_string());
+}
+
+/// Returns the prefix of the metric names.
+pub fn prefix() -> String {
+    PREFIX
+        .read()
+        .unwrap()
+        .as_deref()
+        .unwrap_or(DEFAULT_PREFIX)
+        .to_string()
+}
+
+/// Records a block processed by libbz3.
+pub(crate) fn record_block(
+    operation: Operation,
+    input_size: usize,
+    output_size: usize,
+    duration: Duration,
+) {
+    let prefix = PREFIX.read().unwrap();
+    let prefix = prefix.as_deref().unwrap_or(DEFAULT_PREFIX);
+    let operation = operation.as_str();
+    ::metrics::counter!(format!("{prefix}_input_bytes_total"), "operation" => operation)
+        .increment(input_size as u64);
+    ::metrics::counter!(format!("{prefix}_output_bytes_total"), "operation" => operation)
+        .increment(output_size as u64);
+    ::metrics::counter!(format!("{prefix}_blocks_total"), "operation" => operation).increment(1);
+    ::metrics::histogram!(format!("{prefix}_block_duration_seconds"), "operation" => operation)
+        .record(duration);
+}
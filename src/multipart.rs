@@ -0,0 +1,258 @@
+//! Compressing into parts for multipart uploads to object stores, and reading them back.
+//!
+//! Object stores like S3 take large objects as parts uploaded separately, each of them but the
+//! last of a minimum size. [`MultipartEncoder`] cuts its output into such parts, always at
+//! block boundaries: the parts concatenated in order make an ordinary bzip3 file, and as each
+//! part holds whole blocks, any of them can be decoded on its own given the file header at the
+//! start of the first part, with [`part_decoder`]. [`PartsReader`] reads the parts one after
+//! another as one file, e.g. as they're downloaded.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use bzip3::multipart::{MultipartEncoder, PartsReader};
+//! use bzip3::read::Bz3Decoder;
+//!
+//! let data = vec![b'x'; 1024 * 1024];
+//! let mut encoder = MultipartEncoder::new(100 * 1024, 5 * 1024 * 1024).unwrap();
+//! let mut uploaded = Vec::new();
+//! for chunk in data.chunks(64 * 1024) {
+//!     encoder.write_all(chunk).unwrap();
+//!     // upload the parts as soon as they're complete
+//!     while let Some(part) = encoder.next_part() {
+//!         uploaded.push(part.data);
+//!     }
+//! }
+//! uploaded.extend(encoder.finish().unwrap().into_iter().map(|x| x.data));
+//!
+//! let parts = uploaded.iter().map(|x| x.as_slice());
+//! let mut decompressed = Vec::new();
+//! Bz3Decoder::new(PartsReader::new(parts))
+//!     .unwrap()
+//!     .read_to_end(&mut decompressed)
+//!     .unwrap();
+//! assert_eq!(decompressed, data);
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::mem;
+
+use crate::errors::*;
+use crate::frame::{FrameHeader, FRAME_HEADER_SIZE};
+use crate::seek::BlockTracker;
+use crate::{push, read};
+
+/// A part of the output of a [`MultipartEncoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// Number of the part, from 1 up as S3 part numbers.
+    pub number: usize,
+    /// The compressed data; the first part starts with the file header.
+    pub data: Vec<u8>,
+    /// Offset of the part's data in the whole uncompressed data.
+    pub uncompressed_offset: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Encoder cutting its output into parts of at least a given size, at block boundaries.
+///
+/// Data is written through [`Write`], and the completed parts are taken with
+/// [`MultipartEncoder::next_part`]; they're kept until then. A part is complete at the end of
+/// the first block reaching the part size, so parts are at least the part size, and exceed it
+/// by less than a compressed block. The last part, returned by [`MultipartEncoder::finish`],
+/// may be smaller.
+pub struct MultipartEncoder {
+    encoder: push::Encoder,
+    blocks: BlockTracker,
+    part_size: usize,
+    header: FrameHeader,
+    /// Output of the part being filled.
+    current: Vec<u8>,
+    /// Uncompressed offset of the part being filled.
+    current_offset: u64,
+    parts: VecDeque<Part>,
+    next_number: usize,
+}
+
+impl MultipartEncoder {
+    /// Creates an encoder with the given block size, completing parts once they reach
+    /// `part_size` bytes, e.g. 5 MiB, the minimum part size of S3.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, and [`Error::InvalidArgument`] if the
+    /// part size is zero.
+    pub fn new(block_size: usize, part_size: usize) -> Result<Self> {
+        if part_size == 0 {
+            return Err(Error::InvalidArgument("Invalid part size: 0".into()));
+        }
+        let mut encoder = push::Encoder::new(block_size)?;
+        // the file header
+        let current = encoder.output().to_vec();
+        encoder.consume(current.len());
+        Ok(Self {
+            encoder,
+            blocks: BlockTracker::new(),
+            part_size,
+            header: FrameHeader::new(block_size)?,
+            current,
+            current_offset: 0,
+            parts: VecDeque::new(),
+            next_number: 1,
+        })
+    }
+
+    /// Returns the file header, which starts the first part.
+    pub fn header(&self) -> FrameHeader {
+        self.header
+    }
+
+    /// Takes the next completed part, if any.
+    pub fn next_part(&mut self) -> Option<Part> {
+        self.parts.pop_front()
+    }
+
+    /// Compresses the partial block, and returns the parts not taken yet, the last part
+    /// included.
+    ///
+    /// There's always at least one part, even with no data written.
+    pub fn finish(mut self) -> Result<Vec<Part>> {
+        loop {
+            self.take_output();
+            if self.encoder.is_finished() {
+                break;
+            }
+            self.encoder.finish()?;
+        }
+        if !self.current.is_empty() {
+            self.complete_part();
+        }
+        Ok(self.parts.into())
+    }
+
+    /// Moves the pending output, made of whole blocks, to the part being filled, and completes
+    /// the part if it's large enough.
+    fn take_output(&mut self) {
+        let output = self.encoder.output();
+        if output.is_empty() {
+            return;
+        }
+        self.current.extend_from_slice(output);
+        self.blocks.record(output);
+        self.encoder.consume(output.len());
+        if self.current.len() >= self.part_size {
+            self.complete_part();
+        }
+    }
+
+    fn complete_part(&mut self) {
+        let offset = self.blocks.uncompressed_offset();
+        self.parts.push_back(Part {
+            number: self.next_number,
+            data: mem::take(&mut self.current),
+            uncompressed_offset: self.current_offset,
+            uncompressed_size: offset - self.current_offset,
+        });
+        self.current_offset = offset;
+        self.next_number += 1;
+    }
+}
+
+impl Write for MultipartEncoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a whole block gets compressed once filled
+        let write_size = self.encoder.feed(buf).map_err(Error::into_io_error)?;
+        self.take_output();
+        Ok(write_size)
+    }
+
+    /// Compresses the partial block, which ends the part being filled there if it's large
+    /// enough.
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush().map_err(Error::into_io_error)?;
+        self.take_output();
+        Ok(())
+    }
+}
+
+/// Returns a decoder of a single part, given the file header from the start of the first part.
+///
+/// The first part itself is decoded with a plain [`read::Bz3Decoder`].
+///
+/// # Errors
+///
+/// The same as [`read::Bz3Decoder::new`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use bzip3::multipart::{part_decoder, MultipartEncoder};
+///
+/// let data = vec![b'x'; 1024 * 1024];
+/// let mut encoder = MultipartEncoder::new(100 * 1024, 1).unwrap();
+/// encoder.write_all(&data).unwrap();
+/// let header = encoder.header();
+/// let parts = encoder.finish().unwrap();
+///
+/// let part = &parts[3];
+/// let mut decompressed = Vec::new();
+/// part_decoder(&header, part.data.as_slice())
+///     .unwrap()
+///     .read_to_end(&mut decompressed)
+///     .unwrap();
+/// let start = part.uncompressed_offset as usize;
+/// assert_eq!(decompressed, &data[start..(start + part.uncompressed_size as usize)]);
+/// ```
+pub fn part_decoder<R: Read>(
+    header: &FrameHeader,
+    part: R,
+) -> Result<read::Bz3Decoder<io::Chain<Cursor<[u8; FRAME_HEADER_SIZE]>, R>>> {
+    read::Bz3Decoder::new(Cursor::new(header.to_bytes()).chain(part))
+}
+
+/// Reads parts one after another, as one file.
+pub struct PartsReader<I>
+where
+    I: Iterator,
+    I::Item: Read,
+{
+    parts: I,
+    current: Option<I::Item>,
+}
+
+impl<I> PartsReader<I>
+where
+    I: Iterator,
+    I::Item: Read,
+{
+    /// Creates a reader of the given parts, in order.
+    pub fn new<T>(parts: T) -> Self
+    where
+        T: IntoIterator<IntoIter = I>,
+    {
+        let mut parts = parts.into_iter();
+        let current = parts.next();
+        Self { parts, current }
+    }
+}
+
+impl<I> Read for PartsReader<I>
+where
+    I: Iterator,
+    I::Item: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(part) = &mut self.current {
+            let size = part.read(buf)?;
+            if size != 0 || buf.is_empty() {
+                return Ok(size);
+            }
+            self.current = self.parts.next();
+        }
+        Ok(0)
+    }
+}
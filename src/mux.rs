@@ -0,0 +1,396 @@
+//! Several logical streams interleaved in one compressed file.
+//!
+//! A mux file holds any number of named channels, like the stdout, stderr and metadata of a
+//! job, written in whatever order the data comes. Each channel's data is gathered into blocks
+//! of its own, compressed as bzip3 blocks, and written tagged with the channel id; reading the
+//! file back hands out each block with its channel. [`MuxWriter`] writes a mux file, and
+//! [`MuxReader`] reads it, in one pass and without seeking.
+//!
+//! # Layout
+//!
+//! All integers are little-endian, and names are UTF-8.
+//!
+//! \[ [`MUX_MAGIC`] | version (u8) | block size (u32) | record1 | record2 | recordN... |
+//! end record \]
+//!
+//! Each record starts with its kind (u8):
+//!
+//! - channel: \[ 1 | channel id (u16) | name size (u16) | name \], declaring a channel before its
+//!   first block; ids are assigned from 0 up in order
+//! - block: \[ 2 | channel id (u16) | block header | compressed block \], where the block header
+//!   and block are those of a bzip3 file with the given block size
+//! - end: \[ 0 \], the last record
+
+use std::io;
+use std::io::{Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::errors::*;
+use crate::frame::BlockHeader;
+use crate::{bound, Bz3State};
+
+/// Signature at the start of a mux file.
+pub const MUX_MAGIC: &[u8; 4] = b"BZ3m";
+
+/// Version of the mux format.
+pub const MUX_VERSION: u8 = 1;
+
+const RECORD_END: u8 = 0;
+const RECORD_CHANNEL: u8 = 1;
+const RECORD_BLOCK: u8 = 2;
+
+/// The id of a channel, its position in the order the channels were added.
+pub type ChannelId = u16;
+
+fn corrupt() -> Error {
+    Error::ProcessBlock("Corrupt mux file".into())
+}
+
+struct Channel {
+    name: String,
+    /// Data not yet compressed, less than a block.
+    pending: Vec<u8>,
+}
+
+/// Writes a mux file, with data added to any channel at any time.
+///
+/// Each channel buffers up to a block of data before compressing it, so memory grows with the
+/// number of channels in use. [`MuxWriter::flush_channel`] writes out a partial block, e.g. to
+/// have the data so far readable from a file still being written.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use bzip3::mux::{MuxReader, MuxWriter};
+///
+/// let mut writer = MuxWriter::new(Vec::new(), 100 * 1024).unwrap();
+/// let stdout = writer.add_channel("stdout").unwrap();
+/// let stderr = writer.add_channel("stderr").unwrap();
+/// writer.write(stdout, b"building...\n").unwrap();
+/// writeln!(writer.channel(stderr), "warning: unused variable").unwrap();
+/// writer.write(stdout, b"done\n").unwrap();
+/// let file = writer.finish().unwrap();
+///
+/// let channels = MuxReader::new(file.as_slice()).unwrap().demux_all().unwrap();
+/// assert_eq!(channels[0], ("stdout".into(), b"building...\ndone\n".to_vec()));
+/// assert_eq!(channels[1], ("stderr".into(), b"warning: unused variable\n".to_vec()));
+/// ```
+pub struct MuxWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    state: Bz3State,
+    block_size: usize,
+    channels: Vec<Channel>,
+    buffer: Vec<u8>,
+}
+
+impl<W> MuxWriter<W>
+where
+    W: Write,
+{
+    /// Creates a mux writer, writing the file header.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors.
+    pub fn new(mut writer: W, block_size: usize) -> Result<Self> {
+        let state = Bz3State::new(block_size)?;
+        writer.write_all(MUX_MAGIC)?;
+        writer.write_u8(MUX_VERSION)?;
+        writer.write_u32::<LE>(block_size as u32)?;
+        Ok(Self {
+            writer,
+            state,
+            block_size,
+            channels: Vec::new(),
+            buffer: vec![0_u8; bound(block_size)],
+        })
+    }
+
+    /// Adds a channel, and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the name is taken or longer than 65535 bytes, or if there are
+    /// already 65536 channels, and [`Error::Io`] on all IO errors.
+    pub fn add_channel(&mut self, name: &str) -> Result<ChannelId> {
+        if self.channels.iter().any(|x| x.name == name) {
+            return Err(Error::InvalidArgument(format!(
+                "Duplicate channel name: {name}"
+            )));
+        }
+        let id = ChannelId::try_from(self.channels.len())
+            .map_err(|_| Error::InvalidArgument("Too many channels".into()))?;
+        let name_size = u16::try_from(name.len())
+            .map_err(|_| Error::InvalidArgument(format!("Channel name too long: {name}")))?;
+        self.writer.write_u8(RECORD_CHANNEL)?;
+        self.writer.write_u16::<LE>(id)?;
+        self.writer.write_u16::<LE>(name_size)?;
+        self.writer.write_all(name.as_bytes())?;
+        self.channels.push(Channel {
+            name: name.into(),
+            pending: Vec::new(),
+        });
+        Ok(id)
+    }
+
+    /// Returns the names of the channels, indexed by their ids.
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        self.channels.iter().map(|x| x.name.as_str())
+    }
+
+    /// Adds data to a channel, compressing and writing each block it completes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no channel with the given id.
+    pub fn write(&mut self, channel: ChannelId, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let pending = &mut self.channels[channel as usize].pending;
+            let size = data.len().min(self.block_size - pending.len());
+            pending.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            if pending.len() == self.block_size {
+                self.write_block(channel)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the data pending in a channel as a partial block, and flushes the inner
+    /// writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no channel with the given id.
+    pub fn flush_channel(&mut self, channel: ChannelId) -> Result<()> {
+        self.write_block(channel)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns an [`io::Write`] adding data to the given channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no channel with the given id.
+    pub fn channel(&mut self, channel: ChannelId) -> ChannelWriter<'_, W> {
+        assert!((channel as usize) < self.channels.len(), "No such channel");
+        ChannelWriter { mux: self, channel }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Writes out the data pending in all channels and the end record, and returns the inner
+    /// writer.
+    pub fn finish(mut self) -> Result<W> {
+        for channel in 0..self.channels.len() {
+            self.write_block(channel as ChannelId)?;
+        }
+        self.writer.write_u8(RECORD_END)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Compresses and writes the data pending in a channel, if any.
+    fn write_block(&mut self, channel: ChannelId) -> Result<()> {
+        let pending = &mut self.channels[channel as usize].pending;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let read_size = pending.len();
+        self.buffer[..read_size].copy_from_slice(pending);
+        pending.clear();
+        let new_size = self.state.encode_block(&mut self.buffer, read_size)?;
+
+        self.writer.write_u8(RECORD_BLOCK)?;
+        self.writer.write_u16::<LE>(channel)?;
+        let header = BlockHeader {
+            new_size: new_size as i32,
+            read_size: read_size as i32,
+        };
+        header.write_to(&mut self.writer)?;
+        self.writer.write_all(&self.buffer[..new_size])?;
+        Ok(())
+    }
+}
+
+/// An [`io::Write`] for one channel of a [`MuxWriter`], returned by [`MuxWriter::channel`].
+///
+/// Flushing it writes out the data pending in the channel.
+pub struct ChannelWriter<'a, W>
+where
+    W: Write,
+{
+    mux: &'a mut MuxWriter<W>,
+    channel: ChannelId,
+}
+
+impl<W> Write for ChannelWriter<'_, W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.mux
+            .write(self.channel, buf)
+            .map_err(Error::into_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mux
+            .flush_channel(self.channel)
+            .map_err(Error::into_io_error)
+    }
+}
+
+/// Reads a mux file, block by block in the order they were written.
+pub struct MuxReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    state: Bz3State,
+    block_size: usize,
+    channels: Vec<String>,
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<R> MuxReader<R>
+where
+    R: Read,
+{
+    /// Opens a mux file, reading its header.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] if it's not a mux file, [`Error::ProcessBlock`] if the
+    /// version is unsupported, [`Error::BlockSize`] if the block size is invalid, and
+    /// [`Error::Io`] on all IO errors.
+    pub fn new(reader: R) -> Result<Self> {
+        Self::with_max_block_size(reader, crate::BLOCK_SIZE_MAX)
+    }
+
+    /// Opens a mux file, rejecting files with a block size above `limit`.
+    ///
+    /// The reader allocates memory in proportion to the block size in the file header; this
+    /// bounds it for untrusted input.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSizeLimit`] if the block size is above `limit`, and the same as
+    /// [`MuxReader::new`] otherwise.
+    pub fn with_max_block_size(mut reader: R, limit: usize) -> Result<Self> {
+        let mut magic = [0_u8; MUX_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MUX_MAGIC {
+            return Err(Error::InvalidSignature);
+        }
+        let version = reader.read_u8()?;
+        if version != MUX_VERSION {
+            return Err(Error::ProcessBlock(format!(
+                "Unsupported mux version: {version}"
+            )));
+        }
+        let block_size = reader.read_u32::<LE>()? as usize;
+        if block_size > limit {
+            return Err(Error::BlockSizeLimit { block_size, limit });
+        }
+        Ok(Self {
+            reader,
+            state: Bz3State::new(block_size)?,
+            block_size,
+            channels: Vec::new(),
+            buffer: vec![0_u8; bound(block_size)],
+            finished: false,
+        })
+    }
+
+    /// Returns the names of the channels declared so far, indexed by their ids.
+    ///
+    /// A channel is declared before its first block, so this is complete only once the whole
+    /// file is read.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Reads and decompresses the next block, and returns its channel and data, or `None` at
+    /// the end of the file.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::ProcessBlock`] on corrupt input, and [`Error::Io`] on all IO errors, including
+    /// a file cut short.
+    pub fn next_block(&mut self) -> Result<Option<(ChannelId, &[u8])>> {
+        while !self.finished {
+            match self.reader.read_u8()? {
+                RECORD_END => self.finished = true,
+                RECORD_CHANNEL => {
+                    let id = self.reader.read_u16::<LE>()?;
+                    if id as usize != self.channels.len() {
+                        return Err(corrupt());
+                    }
+                    let mut name = vec![0_u8; self.reader.read_u16::<LE>()? as usize];
+                    self.reader.read_exact(&mut name)?;
+                    let name = String::from_utf8(name).map_err(|_| corrupt())?;
+                    if self.channels.contains(&name) {
+                        return Err(corrupt());
+                    }
+                    self.channels.push(name);
+                }
+                RECORD_BLOCK => {
+                    let channel = self.reader.read_u16::<LE>()?;
+                    if channel as usize >= self.channels.len() {
+                        return Err(corrupt());
+                    }
+                    let header = BlockHeader::read_from(&mut self.reader)?;
+                    header.validate(self.block_size)?;
+                    let read_size = header.read_size as usize;
+                    self.reader
+                        .read_exact(&mut self.buffer[..header.data_size()])?;
+                    // stored blocks hold the data as is
+                    if !header.is_stored() {
+                        self.state.decode_block(
+                            &mut self.buffer,
+                            header.new_size as usize,
+                            read_size,
+                        )?;
+                    }
+                    return Ok(Some((channel, &self.buffer[..read_size])));
+                }
+                _ => return Err(corrupt()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the rest of the file, and returns the name and data of every channel, indexed by
+    /// their ids.
+    ///
+    /// Data read before with [`MuxReader::next_block`] is not included.
+    pub fn demux_all(mut self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut data = Vec::<Vec<u8>>::new();
+        while let Some((channel, block)) = self.next_block()? {
+            let channel = channel as usize;
+            if data.len() <= channel {
+                data.resize(channel + 1, Vec::new());
+            }
+            data[channel].extend_from_slice(block);
+        }
+        data.resize(self.channels.len(), Vec::new());
+        Ok(self.channels.into_iter().zip(data).collect())
+    }
+
+    /// Consumes the mux reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -0,0 +1,182 @@
+//! Configuration shared by the codecs.
+//!
+//! [`Bz3Options`] holds the settings of the encoders and decoders in one place, and creates any
+//! of the codec frontends from them, so an application declares its configuration once, e.g.
+//! from a config file, and reuses it for the blocking, parallel and async codecs alike.
+
+use std::io::{Read, Write};
+
+use crate::errors::*;
+use crate::pool::BufferPool;
+use crate::write::AutoFlush;
+use crate::{read, write, CrcMode, BLOCK_SIZE_MAX, DEFAULT_BLOCK_SIZE};
+
+/// Settings of the encoders and decoders, and constructors of the codecs from them.
+///
+/// Each codec takes the settings it supports, and ignores the others; the constructors tell
+/// which apply.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use bzip3::options::Bz3Options;
+///
+/// let options = Bz3Options {
+///     block_size: 1024 * 1024,
+///     checksum: true,
+///     ..Default::default()
+/// };
+/// let mut encoder = options.write_encoder(Vec::new()).unwrap();
+/// encoder.write_all(b"hello, world").unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let mut decoder = options.read_decoder(compressed.as_slice()).unwrap();
+/// let mut contents = String::new();
+/// decoder.read_to_string(&mut contents).unwrap();
+/// assert_eq!(contents, "hello, world");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bz3Options {
+    /// Block size of the encoders; [`DEFAULT_BLOCK_SIZE`] by default.
+    pub block_size: usize,
+    /// Largest block size accepted by the decoders; [`BLOCK_SIZE_MAX`] by default.
+    pub max_block_size: usize,
+    /// When the encoders compress the partial block; see [`AutoFlush`].
+    pub auto_flush: AutoFlush,
+    /// Whether the encoders end the stream with a
+    /// [checksum](crate::write::Bz3Encoder::set_checksum); off by default.
+    pub checksum: bool,
+    /// How the decoders handle a block failing its CRC check.
+    pub crc_mode: CrcMode,
+    /// Whether the decoders accept multiple concatenated bzip3 files; off by default.
+    pub multiple_members: bool,
+    /// Number of threads of the parallel encoders; zero, the default, means using rayon's
+    /// global thread pool.
+    pub threads: usize,
+    /// Pool the codecs take their block buffers and states from; none by default.
+    pub buffer_pool: Option<BufferPool>,
+}
+
+impl Default for Bz3Options {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_block_size: BLOCK_SIZE_MAX,
+            auto_flush: AutoFlush::default(),
+            checksum: false,
+            crc_mode: CrcMode::default(),
+            multiple_members: false,
+            threads: 0,
+            buffer_pool: None,
+        }
+    }
+}
+
+impl Bz3Options {
+    /// Creates a [`read::Bz3Encoder`], with the block size.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn read_encoder<R: Read>(&self, reader: R) -> Result<read::Bz3Encoder<R>> {
+        read::Bz3Encoder::new(reader, self.block_size)
+    }
+
+    /// Creates a [`read::Bz3Decoder`], with the block size limit, the CRC mode, multiple
+    /// members and the buffer pool.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`read::Bz3Decoder::with_max_block_size`].
+    pub fn read_decoder<R: Read>(&self, reader: R) -> Result<read::Bz3Decoder<R>> {
+        let mut decoder =
+            read::Bz3Decoder::with_options(reader, self.max_block_size, self.buffer_pool.clone())?;
+        decoder.set_crc_mode(self.crc_mode);
+        decoder.multiple_members(self.multiple_members);
+        Ok(decoder)
+    }
+
+    /// Creates a [`write::Bz3Encoder`], with the block size, auto-flush, the checksum and the
+    /// buffer pool.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, [`Error::InvalidArgument`] if the
+    /// auto-flush settings are, and [`Error::Io`] on IO errors.
+    pub fn write_encoder<W: Write>(&self, writer: W) -> Result<write::Bz3Encoder<W>> {
+        let mut encoder = write::Bz3Encoder::new(writer, self.block_size)?;
+        if let Some(pool) = &self.buffer_pool {
+            encoder.set_buffer_pool(pool.clone());
+        }
+        encoder.set_checksum(self.checksum);
+        encoder.set_auto_flush(self.auto_flush)?;
+        Ok(encoder)
+    }
+
+    /// Creates a [`write::Bz3Decoder`], with the block size limit, the CRC mode, multiple
+    /// members and the buffer pool.
+    pub fn write_decoder<W: Write>(&self, writer: W) -> write::Bz3Decoder<W> {
+        let mut decoder = write::Bz3Decoder::new(writer);
+        if let Some(pool) = &self.buffer_pool {
+            decoder.set_buffer_pool(pool.clone());
+        }
+        decoder.set_max_block_size(self.max_block_size);
+        decoder.set_crc_mode(self.crc_mode);
+        decoder.multiple_members(self.multiple_members);
+        decoder
+    }
+
+    /// Creates a [`parallel::Bz3ParallelEncoder`](crate::parallel::Bz3ParallelEncoder), with
+    /// the block size and the number of threads.
+    ///
+    /// # Errors
+    ///
+    /// The same as
+    /// [`Bz3ParallelEncoder::with_threads`](crate::parallel::Bz3ParallelEncoder::with_threads).
+    #[cfg(feature = "parallel")]
+    pub fn parallel_encoder<W: Write>(
+        &self,
+        writer: W,
+    ) -> Result<crate::parallel::Bz3ParallelEncoder<W>> {
+        crate::parallel::Bz3ParallelEncoder::with_threads(writer, self.block_size, self.threads)
+    }
+
+    /// Creates a [`tokio::write::Bz3Encoder`](crate::tokio::write::Bz3Encoder), with the block
+    /// size, auto-flush, the checksum and the buffer pool.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, and [`Error::InvalidArgument`] if the
+    /// auto-flush settings are.
+    #[cfg(feature = "tokio")]
+    pub fn tokio_encoder<W: ::tokio::io::AsyncWrite>(
+        &self,
+        writer: W,
+    ) -> Result<crate::tokio::write::Bz3Encoder<W>> {
+        let mut encoder = crate::tokio::write::Bz3Encoder::new(writer, self.block_size)?;
+        if let Some(pool) = &self.buffer_pool {
+            encoder.set_buffer_pool(pool.clone());
+        }
+        encoder.set_checksum(self.checksum);
+        encoder.set_auto_flush(self.auto_flush)?;
+        Ok(encoder)
+    }
+
+    /// Creates a [`tokio::read::Bz3Decoder`](crate::tokio::read::Bz3Decoder), with the block
+    /// size limit, the CRC mode, multiple members and the buffer pool.
+    #[cfg(feature = "tokio")]
+    pub fn tokio_decoder<R: ::tokio::io::AsyncRead>(
+        &self,
+        reader: R,
+    ) -> crate::tokio::read::Bz3Decoder<R> {
+        let mut decoder = crate::tokio::read::Bz3Decoder::new(reader);
+        if let Some(pool) = &self.buffer_pool {
+            decoder.set_buffer_pool(pool.clone());
+        }
+        decoder.set_max_block_size(self.max_block_size);
+        decoder.set_crc_mode(self.crc_mode);
+        decoder.multiple_members(self.multiple_members);
+        decoder
+    }
+}
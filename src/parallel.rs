@@ -0,0 +1,636 @@
+//! Multithreaded BZip3 compression built on [rayon](https://docs.rs/rayon).
+//!
+//! Blocks in a bzip3 stream are independent of each other, so they can be compressed
+//! concurrently and then be concatenated in their original order.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+
+use crate::errors::*;
+use crate::frame::{FrameHeader, FRAME_HEADER_SIZE};
+use crate::pool::{new_state, BufferPool};
+use crate::seek::BlockTracker;
+use crate::{compress_block_to_vec, Bz3State, TryReadExact, TryWriteAll, DEFAULT_BLOCK_SIZE};
+
+/// Compresses each chunk yielded by `chunks` as a standalone bzip3 block.
+///
+/// Each chunk must not exceed `block_size`. The returned blocks keep the order of the input, and
+/// every one of them carries its block header, so writing a file header followed by all the
+/// blocks produces a valid bzip3 stream.
+///
+/// One [`Bz3State`] is allocated per rayon worker split, not per chunk.
+///
+/// # Errors
+///
+/// [`Error::BlockSize`] if `block_size` is invalid, or [`Error::ProcessBlock`] if any chunk
+/// fails to be compressed.
+///
+/// # Examples
+///
+/// ```
+/// use rayon::prelude::*;
+///
+/// let data = vec![b'x'; 300 * 1024];
+/// let block_size = 100 * 1024;
+/// let blocks = bzip3::parallel::par_compress_blocks(data.par_chunks(block_size), block_size)
+///     .unwrap();
+/// assert_eq!(blocks.len(), 3);
+/// ```
+pub fn par_compress_blocks<'a, I>(chunks: I, block_size: usize) -> Result<Vec<Vec<u8>>>
+where
+    I: IndexedParallelIterator<Item = &'a [u8]>,
+{
+    compress_blocks_pooled(chunks, block_size, None)
+}
+
+/// Like [`par_compress_blocks`], but takes the states from `states` if given, and puts them
+/// back once done.
+fn compress_blocks_pooled<'a, I>(
+    chunks: I,
+    block_size: usize,
+    states: Option<&BufferPool>,
+) -> Result<Vec<Vec<u8>>>
+where
+    I: IndexedParallelIterator<Item = &'a [u8]>,
+{
+    if !Bz3State::check_block_size(block_size) {
+        return Err(Error::BlockSize);
+    }
+
+    chunks
+        .map_init(
+            // the block size has been checked above
+            || WorkerState {
+                state: Some(new_state(states, block_size).unwrap()),
+                pool: states,
+            },
+            |worker, chunk| compress_block_to_vec(worker.state.as_mut().unwrap(), chunk),
+        )
+        .collect()
+}
+
+/// State of a rayon worker split, put back into its pool once the split is done.
+struct WorkerState<'a> {
+    state: Option<Bz3State>,
+    pool: Option<&'a BufferPool>,
+}
+
+impl Drop for WorkerState<'_> {
+    fn drop(&mut self) {
+        if let (Some(pool), Some(state)) = (self.pool, self.state.take()) {
+            pool.put_state(state);
+        }
+    }
+}
+
+/// Compresses whole `chunks` into standalone blocks, using the parallelism of the current
+/// rayon pool.
+///
+/// With the `libbz3-threads` feature, the work is delegated to libbz3's own threads, in batches
+/// of as many blocks as the current pool has threads.
+///
+/// The states are taken from `states` if given, and put back once done, for encoders to keep
+/// them across batches.
+pub(crate) fn compress_chunks(
+    chunks: &[&[u8]],
+    block_size: usize,
+    states: Option<&BufferPool>,
+) -> Result<Vec<Vec<u8>>> {
+    #[cfg(feature = "libbz3-threads")]
+    return native::compress_chunks(chunks, block_size, states);
+    #[cfg(not(feature = "libbz3-threads"))]
+    compress_blocks_pooled(chunks.par_iter().copied(), block_size, states)
+}
+
+#[cfg(feature = "libbz3-threads")]
+mod native {
+    use byteorder::{ByteOrder, LE};
+
+    use crate::errors::*;
+    use crate::frame::BLOCK_HEADER_SIZE;
+    use crate::pool::{new_state, BufferPool};
+    use crate::{bound, Bz3State};
+
+    pub(super) fn compress_chunks(
+        chunks: &[&[u8]],
+        block_size: usize,
+        pool: Option<&BufferPool>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let threads = rayon::current_num_threads().clamp(1, chunks.len().max(1));
+        let mut states = (0..threads)
+            .map(|_| new_state(pool, block_size))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut blocks = Vec::with_capacity(chunks.len());
+        for batch in chunks.chunks(threads) {
+            if batch.iter().any(|x| x.len() > block_size) {
+                return Err(Error::ProcessBlock("Data exceeds the block size".into()));
+            }
+            let mut buffers = batch
+                .iter()
+                .map(|chunk| {
+                    let mut buffer = vec![0_u8; BLOCK_HEADER_SIZE + bound(chunk.len())];
+                    buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + chunk.len())]
+                        .copy_from_slice(chunk);
+                    buffer
+                })
+                .collect::<Vec<_>>();
+            let mut sizes = batch.iter().map(|x| x.len()).collect::<Vec<_>>();
+            {
+                let mut data_buffers = buffers
+                    .iter_mut()
+                    .map(|x| &mut x[BLOCK_HEADER_SIZE..])
+                    .collect::<Vec<_>>();
+                Bz3State::encode_blocks(&mut states[..batch.len()], &mut data_buffers, &mut sizes)?;
+            }
+
+            for ((mut buffer, new_size), chunk) in buffers.into_iter().zip(sizes).zip(batch) {
+                LE::write_i32(&mut buffer, new_size as i32);
+                LE::write_i32(&mut buffer[4..], chunk.len() as i32);
+                buffer.truncate(BLOCK_HEADER_SIZE + new_size);
+                blocks.push(buffer);
+            }
+        }
+        if let Some(pool) = pool {
+            states.into_iter().for_each(|x| pool.put_state(x));
+        }
+        Ok(blocks)
+    }
+}
+
+/// Options for [`compress_many`].
+#[derive(Debug, Clone)]
+pub struct CompressManyOptions {
+    /// Block size of every output file.
+    pub block_size: usize,
+    /// Number of worker threads of the shared pool; zero means using rayon's global pool.
+    pub threads: usize,
+}
+
+impl Default for CompressManyOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            threads: 0,
+        }
+    }
+}
+
+/// Outcome of a single job of [`compress_many`].
+#[derive(Debug)]
+pub struct CompressJobResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Size of the written output file.
+    pub result: Result<u64>,
+}
+
+/// Compresses many files at once.
+///
+/// Each job is a pair of `(input path, output path)`. The blocks of all the files go through
+/// shared batches of one block per thread of the pool, taken from the files in order: a batch
+/// can hold many small files, or part of a large one, so all threads stay busy until the last
+/// batch, whatever the sizes of the files.
+///
+/// Memory use is bounded by the pool size: about a batch of input, and its compressed blocks.
+/// Files are read and written between batches, from the calling thread, and only those with
+/// blocks in the current batch are open.
+///
+/// A failure of one job doesn't affect the others; the results are returned in the order of
+/// `jobs`.
+pub fn compress_many<I>(jobs: I, options: &CompressManyOptions) -> Vec<CompressJobResult>
+where
+    I: IntoIterator<Item = (PathBuf, PathBuf)>,
+{
+    let jobs = jobs.into_iter().collect::<Vec<_>>();
+    let pool = match build_pool(options.threads) {
+        Ok(pool) => pool,
+        Err(e) => {
+            let message = e.to_string();
+            return jobs
+                .into_iter()
+                .map(|(input, output)| CompressJobResult {
+                    input,
+                    output,
+                    result: Err(io::Error::other(message.clone()).into()),
+                })
+                .collect();
+        }
+    };
+
+    let block_size = options.block_size;
+    let run = || ManyCompressor::new(&jobs, block_size).run();
+    let results = match pool {
+        Some(pool) => pool.install(run),
+        None => run(),
+    };
+    jobs.into_iter()
+        .zip(results)
+        .map(|((input, output), result)| CompressJobResult {
+            input,
+            output,
+            result,
+        })
+        .collect()
+}
+
+/// Builds a thread pool with `threads` threads, or returns `None` for the global pool.
+pub(crate) fn build_pool(threads: usize) -> Result<Option<rayon::ThreadPool>> {
+    if threads == 0 {
+        return Ok(None);
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(io::Error::other)?;
+    Ok(Some(pool))
+}
+
+/// An output file of [`compress_many`] being written.
+struct OpenJob {
+    /// Index in the jobs.
+    index: usize,
+    /// `None` once read to the end.
+    reader: Option<File>,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+/// The batches of [`compress_many`].
+struct ManyCompressor<'a> {
+    jobs: &'a [(PathBuf, PathBuf)],
+    block_size: usize,
+    /// Results of the jobs done.
+    results: Vec<Option<Result<u64>>>,
+    /// Next job to open.
+    next: usize,
+    /// Jobs with blocks in the batch; the last one may have more to read.
+    open: Vec<OpenJob>,
+}
+
+impl<'a> ManyCompressor<'a> {
+    fn new(jobs: &'a [(PathBuf, PathBuf)], block_size: usize) -> Self {
+        Self {
+            jobs,
+            block_size,
+            results: jobs.iter().map(|_| None).collect(),
+            next: 0,
+            open: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Vec<Result<u64>> {
+        let batch_blocks = rayon::current_num_threads();
+        let mut buffer = vec![0_u8; batch_blocks * self.block_size];
+        loop {
+            // the job and size of each chunk, which starts at its position in the batch times
+            // the block size
+            let chunks = self.fill_batch(&mut buffer, batch_blocks);
+            if chunks.is_empty() && self.open.is_empty() {
+                break;
+            }
+            let data = chunks
+                .iter()
+                .enumerate()
+                .map(|(i, &(_, size))| &buffer[(i * self.block_size)..][..size])
+                .collect::<Vec<_>>();
+            match compress_chunks(&data, self.block_size, None) {
+                Ok(blocks) => {
+                    for ((job, _), block) in chunks.iter().zip(blocks) {
+                        self.write_block(*job, &block);
+                    }
+                }
+                Err(e) => {
+                    for &(job, _) in &chunks {
+                        self.fail(job, Error::ProcessBlock(e.to_string()));
+                    }
+                }
+            }
+            self.finish_read_jobs();
+        }
+        self.results
+            .into_iter()
+            .map(|x| x.expect("every job is done"))
+            .collect()
+    }
+
+    /// Reads up to `batch_blocks` blocks into `buffer`, opening the next jobs as needed.
+    fn fill_batch(&mut self, buffer: &mut [u8], batch_blocks: usize) -> Vec<(usize, usize)> {
+        let mut chunks = Vec::with_capacity(batch_blocks);
+        while chunks.len() < batch_blocks {
+            let reading = self.open.last_mut().filter(|x| x.reader.is_some());
+            let Some(job) = reading else {
+                if self.next == self.jobs.len() {
+                    break;
+                }
+                let index = self.next;
+                self.next += 1;
+                match self.open_job(index) {
+                    Ok(job) => self.open.push(job),
+                    Err(e) => self.results[index] = Some(Err(e)),
+                }
+                continue;
+            };
+
+            let block = &mut buffer[(chunks.len() * self.block_size)..][..self.block_size];
+            match job.reader.as_mut().unwrap().try_read_exact(block) {
+                Ok(size) => {
+                    if size < self.block_size {
+                        job.reader = None;
+                    }
+                    if size != 0 {
+                        chunks.push((job.index, size));
+                    }
+                }
+                Err(e) => {
+                    let index = job.index;
+                    chunks.retain(|x| x.0 != index);
+                    self.fail(index, e.into());
+                }
+            }
+        }
+        chunks
+    }
+
+    fn open_job(&self, index: usize) -> Result<OpenJob> {
+        let (input, output) = &self.jobs[index];
+        let header = FrameHeader::new(self.block_size)?;
+        let reader = File::open(input)?;
+        let mut writer = BufWriter::new(File::create(output)?);
+        header.write_to(&mut writer)?;
+        Ok(OpenJob {
+            index,
+            reader: Some(reader),
+            writer,
+            written: FRAME_HEADER_SIZE as u64,
+        })
+    }
+
+    fn write_block(&mut self, index: usize, block: &[u8]) {
+        // the job may have failed on an earlier block of the batch
+        let Some(job) = self.open.iter_mut().find(|x| x.index == index) else {
+            return;
+        };
+        match job.writer.write_all(block) {
+            Ok(()) => job.written += block.len() as u64,
+            Err(e) => self.fail(index, e.into()),
+        }
+    }
+
+    /// Completes the jobs read to the end, whose blocks have all been written.
+    fn finish_read_jobs(&mut self) {
+        let (done, open) = std::mem::take(&mut self.open)
+            .into_iter()
+            .partition::<Vec<_>, _>(|x| x.reader.is_none());
+        self.open = open;
+        for mut job in done {
+            self.results[job.index] =
+                Some(job.writer.flush().map(|_| job.written).map_err(Into::into));
+        }
+    }
+
+    fn fail(&mut self, index: usize, e: Error) {
+        self.open.retain(|x| x.index != index);
+        self.results[index] = Some(Err(e));
+    }
+}
+
+/// Write-based bzip3 encoder compressing blocks concurrently.
+///
+/// Data is buffered until a batch of whole blocks (one per thread) is available, the batch is
+/// compressed in parallel, and the blocks are then committed to the writer strictly in their
+/// original order.
+///
+/// Dropping the encoder compresses the remaining data but ignores errors; call
+/// [`finish`](Self::finish) to get them.
+///
+/// # Output stability
+///
+/// For the same sequence of `write` and `flush` calls with the same block size, the output is
+/// byte-for-byte identical to [`write::Bz3Encoder`](crate::write::Bz3Encoder)'s, regardless of
+/// the number of threads: blocks are cut at the same positions (every `block_size` bytes, and
+/// at each `flush` with pending data), and never reordered.
+pub struct Bz3ParallelEncoder<W>
+where
+    W: Write,
+{
+    /// `None` once finished.
+    writer: Option<W>,
+    block_size: usize,
+    /// Data waiting to be compressed; at most `batch_size` bytes.
+    buffer: Vec<u8>,
+    batch_size: usize,
+    pool: Option<rayon::ThreadPool>,
+    /// States of the workers, kept from one batch to the next.
+    states: BufferPool,
+    /// Compressed blocks left over by a failed write, the first one from `pending_pos`.
+    pending: VecDeque<Vec<u8>>,
+    pending_pos: usize,
+    blocks: BlockTracker,
+}
+
+impl<W> fmt::Debug for Bz3ParallelEncoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bz3ParallelEncoder")
+            .field("block_size", &self.block_size)
+            .field("blocks", &self.blocks.blocks())
+            .field("pending_input", &self.buffer.len())
+            .field(
+                "threads",
+                &self.pool.as_ref().map(|x| x.current_num_threads()),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3ParallelEncoder<W>
+where
+    W: Write,
+{
+    /// Creates a parallel encoder running on rayon's global thread pool.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        Self::with_threads(writer, block_size, 0)
+    }
+
+    /// Creates a parallel encoder running on rayon's global thread pool, with the
+    /// [default block size](crate::DEFAULT_BLOCK_SIZE).
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::Io`] if writing the file header fails.
+    pub fn with_defaults(writer: W) -> Result<Self> {
+        Self::new(writer, crate::DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Returns the number of blocks written so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks.blocks()
+    }
+
+    /// Creates a parallel encoder with a block size like `"16MiB"`; see
+    /// [`parse_block_size`](crate::parse_block_size).
+    pub fn with_block_size_str(writer: W, block_size: &str) -> Result<Self> {
+        Self::new(writer, crate::parse_block_size(block_size)?)
+    }
+
+    /// Creates a parallel encoder running on its own pool of `threads` threads; zero means
+    /// using rayon's global thread pool.
+    pub fn with_threads(mut writer: W, block_size: usize, threads: usize) -> Result<Self> {
+        let header = FrameHeader::new(block_size)?;
+        let pool = build_pool(threads)?;
+        header.write_to(&mut writer)?;
+
+        let threads = match &pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        };
+        Ok(Self {
+            writer: Some(writer),
+            block_size,
+            buffer: Vec::new(),
+            batch_size: threads * block_size,
+            pool,
+            states: BufferPool::new(threads),
+            pending: VecDeque::new(),
+            pending_pos: 0,
+            blocks: BlockTracker::new(),
+        })
+    }
+
+    /// Compresses the buffered data, and returns the inner writer.
+    ///
+    /// Unlike dropping the encoder, this reports the errors of the last batch.
+    pub fn finish(mut self) -> Result<W> {
+        self.compress_batch()?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Sets a callback invoked with `(block_index, compressed_offset, uncompressed_offset)` each
+    /// time a block has been written, e.g. to build an index stored out-of-band while encoding.
+    ///
+    /// Offsets are relative to the start of the file header, as in a
+    /// [`Bz3Index`](crate::seek::Bz3Index). Blocks written before this is called aren't reported,
+    /// but still count for the offsets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let blocks = Arc::new(Mutex::new(Vec::new()));
+    /// let mut encoder = bzip3::parallel::Bz3ParallelEncoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// let blocks2 = Arc::clone(&blocks);
+    /// encoder.set_block_callback(move |block, compressed_offset, uncompressed_offset| {
+    ///     blocks2.lock().unwrap().push((block, compressed_offset, uncompressed_offset));
+    /// });
+    /// encoder.write_all(&vec![b'x'; 250 * 1024]).unwrap();
+    /// encoder.flush().unwrap();
+    ///
+    /// let blocks = blocks.lock().unwrap();
+    /// assert_eq!(blocks.len(), 3);
+    /// assert_eq!(blocks[0], (0, 9, 0));
+    /// assert_eq!(blocks[2].2, 200 * 1024);
+    /// ```
+    pub fn set_block_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(usize, u64, u64) + Send + Sync + 'static,
+    {
+        self.blocks.set_callback(callback);
+    }
+
+    /// Compresses all the buffered data, and commits the blocks in order.
+    fn compress_batch(&mut self) -> Result<()> {
+        self.write_pending()?;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunks = self.buffer.chunks(self.block_size).collect::<Vec<_>>();
+        let block_size = self.block_size;
+        let states = Some(&self.states);
+        let blocks = match &self.pool {
+            Some(pool) => pool.install(|| compress_chunks(&chunks, block_size, states))?,
+            None => compress_chunks(&chunks, block_size, states)?,
+        };
+        debug_assert_eq!(blocks.len(), chunks.len());
+        self.buffer.clear();
+        self.commit(blocks)?;
+        Ok(())
+    }
+
+    /// The ordered-commit stage: blocks are written in the exact order of their chunks.
+    fn commit(&mut self, blocks: Vec<Vec<u8>>) -> io::Result<()> {
+        for block in &blocks {
+            self.blocks.record(block);
+        }
+        self.pending.extend(blocks);
+        self.write_pending()
+    }
+
+    /// Writes the pending blocks to `self.writer`.
+    fn write_pending(&mut self) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        while let Some(block) = self.pending.front() {
+            // what's written is consumed even if the writer fills up, so it isn't written twice
+            self.pending_pos += writer.try_write_all(&block[self.pending_pos..])?;
+            if self.pending_pos < block.len() {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.pending.pop_front();
+            self.pending_pos = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Write for Bz3ParallelEncoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // blocks left over from a failed write go first
+        self.write_pending()?;
+        let write_size = buf.len().min(self.batch_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..write_size]);
+        if self.buffer.len() == self.batch_size {
+            // `buf` is taken either way; a failed write of the blocks comes up with the next call
+            if let Err(e) = self.compress_batch() {
+                if write_size == 0 {
+                    return Err(e.into_io_error());
+                }
+            }
+        }
+        Ok(write_size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compress_batch().map_err(Error::into_io_error)?;
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl<W> Drop for Bz3ParallelEncoder<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Persisting serializable values compressed, in one call.
+//!
+//! [`to_writer_compressed`] and [`from_reader_compressed`] use [bincode](bincode) as the
+//! serialization format. For any other format, [`to_writer_compressed_with`] and
+//! [`from_reader_compressed_with`] hand the codecs to the serializer of choice.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use bzip3::payload;
+//!
+//! let state = HashMap::from([("apples", 3), ("pears", 5)]);
+//! let compressed = payload::to_writer_compressed(Vec::new(), &state, 100 * 1024).unwrap();
+//! let restored: HashMap<String, i32> =
+//!     payload::from_reader_compressed(compressed.as_slice()).unwrap();
+//! assert_eq!(restored["pears"], 5);
+//! ```
+
+use std::error::Error as StdError;
+use std::io;
+use std::io::{Read, Write};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::errors::*;
+use crate::{read, write};
+
+fn bincode_error(e: bincode::ErrorKind) -> Error {
+    match e {
+        bincode::ErrorKind::Io(e) => Error::Io(e),
+        e => Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+/// Serializes `value` with bincode into a bzip3 file written to `writer`, and returns the
+/// writer.
+///
+/// # Errors
+///
+/// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors,
+/// including serialization errors.
+pub fn to_writer_compressed<W, T>(writer: W, value: &T, block_size: usize) -> Result<W>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    to_writer_compressed_with(writer, block_size, |encoder| {
+        bincode::serialize_into(encoder, value).map_err(|e| bincode_error(*e))
+    })
+}
+
+/// Deserializes a value with bincode from a bzip3 file read from `reader`.
+///
+/// The whole file is decompressed into memory first, which keeps lengths read from corrupt
+/// input from causing huge allocations. Nothing may follow the value.
+///
+/// # Errors
+///
+/// [`Error::InvalidSignature`] if it's not a bzip3 file, and [`Error::Io`] on all IO errors,
+/// including invalid input and deserialization errors.
+pub fn from_reader_compressed<R, T>(reader: R) -> Result<T>
+where
+    R: Read,
+    T: DeserializeOwned,
+{
+    let mut data = Vec::new();
+    read::Bz3Decoder::new(reader)?.read_to_end(&mut data)?;
+    // the options of `bincode::serialize`, but rejecting trailing data
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(&data)
+        .map_err(|e| bincode_error(*e))
+}
+
+/// Compresses what `serialize` writes to the given encoder into a bzip3 file written to
+/// `writer`, and returns the writer.
+///
+/// Errors of `serialize` are returned as [`Error::Io`] errors.
+///
+/// # Examples
+///
+/// With `serde_json`:
+///
+/// ```
+/// use bzip3::payload;
+///
+/// let compressed = payload::to_writer_compressed_with(Vec::new(), 100 * 1024, |encoder| {
+///     serde_json::to_writer(encoder, &[1, 2, 3])
+/// })
+/// .unwrap();
+/// let value: Vec<i32> = payload::from_reader_compressed_with(compressed.as_slice(), |decoder| {
+///     serde_json::from_reader(decoder)
+/// })
+/// .unwrap();
+/// assert_eq!(value, [1, 2, 3]);
+/// ```
+pub fn to_writer_compressed_with<W, F, E>(writer: W, block_size: usize, serialize: F) -> Result<W>
+where
+    W: Write,
+    F: FnOnce(&mut write::Bz3Encoder<W>) -> std::result::Result<(), E>,
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let mut encoder = write::Bz3Encoder::new(writer, block_size)?;
+    serialize(&mut encoder).map_err(into_error)?;
+    Ok(encoder.finish()?)
+}
+
+/// Deserializes a value with `deserialize` from a decoder of the bzip3 file read from
+/// `reader`.
+///
+/// The whole file is read, and nothing may follow the value. Errors of `deserialize` are
+/// returned as [`Error::Io`] errors.
+///
+/// The value is deserialized while decompressing; with untrusted input, make sure the format
+/// bounds its allocations.
+pub fn from_reader_compressed_with<R, T, F, E>(reader: R, deserialize: F) -> Result<T>
+where
+    R: Read,
+    F: FnOnce(&mut read::Bz3Decoder<R>) -> std::result::Result<T, E>,
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let mut decoder = read::Bz3Decoder::new(reader)?;
+    let value = deserialize(&mut decoder).map_err(into_error)?;
+    // the rest of the file still gets decoded, which checks it
+    if io::copy(&mut decoder, &mut io::sink())? != 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Data after the deserialized value",
+        )));
+    }
+    Ok(value)
+}
+
+/// Keeps [`Error`]s as they are, and wraps other errors in [`Error::Io`].
+fn into_error<E: Into<Box<dyn StdError + Send + Sync>>>(e: E) -> Error {
+    let e = e.into();
+    match e.downcast::<Error>() {
+        Ok(e) => *e,
+        Err(e) => match e.downcast::<io::Error>() {
+            Ok(e) => Error::Io(*e),
+            Err(e) => Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)),
+        },
+    }
+}
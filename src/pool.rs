@@ -0,0 +1,304 @@
+//! Reuse of block buffers across codecs.
+//!
+//! Every encoder and decoder allocates a buffer of about a block, and a libbz3 state several
+//! times the block size, which for large blocks are huge allocations per stream. Services going
+//! through many short-lived streams can share a [`BufferPool`] among their codecs instead: the
+//! codecs take their block buffers and states from the pool, and put them back when dropped,
+//! for the next codec of the same block size.
+//!
+//! The block buffers are [aligned](set_alignment) to 64 bytes by default, for libbz3 and the
+//! copies in and out of them to work on aligned memory.
+//!
+//! [`BufferPool`] needs the `std` feature; the alignment applies without it too.
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+use crate::errors::*;
+use crate::Bz3State;
+
+/// Default alignment of the block buffers, the size of a cache line on most hardware.
+pub const DEFAULT_ALIGNMENT: usize = 64;
+
+static ALIGNMENT: AtomicUsize = AtomicUsize::new(DEFAULT_ALIGNMENT);
+
+/// Sets the alignment of the block buffers allocated from now on, e.g. to the cache line size
+/// or the vector width of the hardware targeted.
+///
+/// This applies to the whole process. Buffers in a pool not matching the new alignment are
+/// reallocated when taken.
+///
+/// # Errors
+///
+/// [`Error::InvalidArgument`] if the alignment isn't a power of two.
+pub fn set_alignment(alignment: usize) -> Result<()> {
+    if !alignment.is_power_of_two() {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid alignment: {alignment}"
+        )));
+    }
+    ALIGNMENT.store(alignment, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the alignment of the block buffers.
+pub fn alignment() -> usize {
+    ALIGNMENT.load(Ordering::Relaxed)
+}
+
+/// Block buffer, with the byte at a given position aligned to [`alignment`].
+///
+/// This is a larger allocation, with the buffer starting where the alignment falls.
+#[derive(Default)]
+pub(crate) struct BlockBuffer {
+    data: Vec<u8>,
+    offset: usize,
+    len: usize,
+}
+
+impl BlockBuffer {
+    /// Allocates a zeroed buffer of `len` bytes, with the byte at `aligned_at` aligned.
+    pub(crate) fn new(len: usize, aligned_at: usize) -> Self {
+        let mut buffer = Self {
+            data: vec![0_u8; len + alignment() - 1],
+            offset: 0,
+            len,
+        };
+        let aligned = buffer.align(aligned_at);
+        debug_assert!(aligned);
+        buffer
+    }
+
+    /// A small buffer holding `data`, with no alignment.
+    pub(crate) fn from_slice(data: &[u8]) -> Self {
+        Self {
+            data: data.to_vec(),
+            offset: 0,
+            len: data.len(),
+        }
+    }
+
+    /// Moves the buffer within the allocation for the byte at `aligned_at` to be aligned.
+    ///
+    /// Returns false if the allocation leaves no room for that, its contents are then left
+    /// as they are. Otherwise, they're shifted around.
+    fn align(&mut self, aligned_at: usize) -> bool {
+        let offset = self
+            .data
+            .as_ptr()
+            .wrapping_add(aligned_at)
+            .align_offset(alignment());
+        if offset + self.len > self.data.len() {
+            return false;
+        }
+        self.offset = offset;
+        true
+    }
+}
+
+impl Deref for BlockBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.offset..(self.offset + self.len)]
+    }
+}
+
+impl DerefMut for BlockBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..(self.offset + self.len)]
+    }
+}
+
+/// Pool of block buffers and states, kept by size, shared by cloning.
+///
+/// At most the given number of buffers of each size, and of states of each block size, is
+/// kept; more are freed when put back.
+/// Buffers are handed out with the contents left by their previous user; the codecs only ever
+/// read what they wrote themselves.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use bzip3::pool::BufferPool;
+///
+/// let pool = BufferPool::new(4);
+/// for _ in 0..10 {
+///     let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 1024 * 1024).unwrap();
+///     encoder.set_buffer_pool(pool.clone());
+///     encoder.write_all(b"hello, world").unwrap();
+///     encoder.finish().unwrap();
+/// }
+/// // all the encoders went with the same buffer
+/// assert_eq!(pool.len(), 1);
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+/// Stand-in for the pool without std, which no codec can have.
+#[cfg(not(feature = "std"))]
+pub(crate) enum BufferPool {}
+
+#[cfg(not(feature = "std"))]
+impl BufferPool {
+    fn take(&self, _size: usize, _aligned_at: usize) -> BlockBuffer {
+        match *self {}
+    }
+
+    pub(crate) fn put(&self, _buffer: BlockBuffer) {
+        match *self {}
+    }
+
+    fn take_state(&self, _block_size: usize) -> Result<Bz3State> {
+        match *self {}
+    }
+
+    pub(crate) fn put_state(&self, _state: Bz3State) {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "std")]
+struct PoolInner {
+    buffers: HashMap<usize, Vec<BlockBuffer>>,
+    states: HashMap<usize, Vec<Bz3State>>,
+    max_per_size: usize,
+}
+
+#[cfg(feature = "std")]
+impl BufferPool {
+    /// Creates an empty pool keeping up to `max_per_size` buffers of each size, e.g. the number
+    /// of streams of the same block size running at once.
+    pub fn new(max_per_size: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                buffers: HashMap::new(),
+                states: HashMap::new(),
+                max_per_size,
+            })),
+        }
+    }
+
+    /// Returns the number of buffers kept.
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.buffers.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frees all the buffers and states kept.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.buffers.clear();
+        inner.states.clear();
+    }
+
+    /// Takes a buffer of the given size, allocating one if there's none.
+    pub(crate) fn take(&self, size: usize, aligned_at: usize) -> BlockBuffer {
+        let buffer = self
+            .inner
+            .lock()
+            .unwrap()
+            .buffers
+            .get_mut(&size)
+            .and_then(Vec::pop);
+        if let Some(mut buffer) = buffer {
+            if buffer.align(aligned_at) {
+                return buffer;
+            }
+        }
+        BlockBuffer::new(size, aligned_at)
+    }
+
+    /// Puts a buffer back. Buffers smaller than a block are just freed.
+    pub(crate) fn put(&self, buffer: BlockBuffer) {
+        if buffer.len() < crate::BLOCK_SIZE_MIN {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let max_per_size = inner.max_per_size;
+        let buffers = inner.buffers.entry(buffer.len()).or_default();
+        if buffers.len() < max_per_size {
+            buffers.push(buffer);
+        }
+    }
+
+    /// Takes a state of the given block size, creating one if there's none.
+    pub(crate) fn take_state(&self, block_size: usize) -> Result<Bz3State> {
+        let state = self
+            .inner
+            .lock()
+            .unwrap()
+            .states
+            .get_mut(&block_size)
+            .and_then(Vec::pop);
+        match state {
+            Some(state) => Ok(state),
+            None => Bz3State::new(block_size),
+        }
+    }
+
+    /// Puts a state back.
+    pub(crate) fn put_state(&self, state: Bz3State) {
+        let mut inner = self.inner.lock().unwrap();
+        let max_per_size = inner.max_per_size;
+        let states = inner.states.entry(state.block_size).or_default();
+        if states.len() < max_per_size {
+            states.push(state);
+        }
+    }
+}
+
+/// Allocates a buffer from the pool if there's one, with the byte at `aligned_at` aligned.
+pub(crate) fn allocate(pool: Option<&BufferPool>, size: usize, aligned_at: usize) -> BlockBuffer {
+    match pool {
+        Some(pool) => pool.take(size, aligned_at),
+        None => BlockBuffer::new(size, aligned_at),
+    }
+}
+
+/// Creates a state, or takes it from the pool if there's one.
+pub(crate) fn new_state(pool: Option<&BufferPool>, block_size: usize) -> Result<Bz3State> {
+    match pool {
+        Some(pool) => pool.take_state(block_size),
+        None => Bz3State::new(block_size),
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// The sizes with the number of items kept, in order.
+        fn counts<T>(items: &HashMap<usize, Vec<T>>) -> Vec<(usize, usize)> {
+            let mut counts = items
+                .iter()
+                .filter(|(_, x)| !x.is_empty())
+                .map(|(&size, x)| (size, x.len()))
+                .collect::<Vec<_>>();
+            counts.sort_unstable();
+            counts
+        }
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("BufferPool")
+            .field("buffers", &counts(&inner.buffers))
+            .field("states", &counts(&inner.states))
+            .field("max_per_size", &inner.max_per_size)
+            .finish()
+    }
+}
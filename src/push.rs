@@ -0,0 +1,1258 @@
+//! Sans-IO block codec core shared by the stream codecs.
+//!
+//! The types here never perform IO themselves: the caller hands input in and takes output out,
+//! which lets the same state machine back blocking, async and push-style frontends. They are
+//! what's left of the codecs without the `std` feature, for targets with an allocator but no
+//! operating system.
+//!
+//! Both codecs work the same way: take what's in `output()` and [`consume`](Encoder::consume)
+//! it, then `feed()` more input, which is only accepted once the output is all taken; and when
+//! the input ends, call `finish()`, for the encoder until [`Encoder::is_finished`].
+//!
+//! # Examples
+//!
+//! ```
+//! use bzip3::push::{Decoder, Encoder};
+//!
+//! let mut encoder = Encoder::new(100 * 1024).unwrap();
+//! let mut compressed = Vec::new();
+//! let mut input: &[u8] = b"hello, world";
+//! while !input.is_empty() {
+//!     compressed.extend_from_slice(encoder.output());
+//!     encoder.consume(encoder.output().len());
+//!     let n = encoder.feed(input).unwrap();
+//!     input = &input[n..];
+//! }
+//! while !encoder.is_finished() {
+//!     encoder.finish().unwrap();
+//!     compressed.extend_from_slice(encoder.output());
+//!     encoder.consume(encoder.output().len());
+//! }
+//!
+//! let mut decoder = Decoder::new();
+//! let mut decompressed = Vec::new();
+//! let mut input = compressed.as_slice();
+//! while !input.is_empty() || !decoder.output().is_empty() {
+//!     decompressed.extend_from_slice(decoder.output());
+//!     decoder.consume(decoder.output().len());
+//!     let n = decoder.feed(input).unwrap();
+//!     input = &input[n..];
+//! }
+//! decoder.finish().unwrap();
+//! assert_eq!(decompressed, b"hello, world");
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::ops::Range;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::{mpsc, Mutex};
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::chunking::{Chunker, Chunking};
+use crate::crc::{block_crc, stored_crc};
+use crate::errors::*;
+use crate::frame::{
+    checksum_block, find_magic, parse_checksum, BlockHeader, FrameHeader, SkippableFrame,
+    BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE, SKIPPABLE_MAGIC,
+};
+use crate::instrument::{Operation, StreamSpan, StreamTrace};
+use crate::metadata::{Metadata, METADATA_TAG};
+use crate::pool::{self, BlockBuffer, BufferPool};
+use crate::{bound, Bz3State, CrcMode, BLOCK_SIZE_MAX, MAGIC_NUMBER};
+
+enum Phase {
+    /// Waiting for the file header.
+    FrameHeader,
+    /// Waiting for the header of the next block.
+    BlockHeader,
+    /// Waiting for the compressed or stored data of a block.
+    BlockData(BlockHeader),
+    /// Skipping the data of an extension block, of the given size.
+    Extension(usize),
+    /// Skipping the payload of a skippable frame, of the given size.
+    Skippable(usize),
+    /// Ignoring whatever follows the last block.
+    Trailing,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::FrameHeader => "frame header",
+            Phase::BlockHeader => "block header",
+            Phase::BlockData(_) => "block data",
+            Phase::Extension(_) => "extension block",
+            Phase::Skippable(_) => "skippable frame",
+            Phase::Trailing => "trailing data",
+        }
+    }
+}
+
+/// When an encoder compresses the partial block without waiting for a full one, for
+/// interactive and streaming protocols not to hold data back; set with
+/// [`write::Bz3Encoder::set_auto_flush`](crate::write::Bz3Encoder::set_auto_flush), or
+/// [`Encoder::set_auto_flush`].
+///
+/// Smaller blocks compress worse, so these are best set to the latency the protocol can
+/// afford, not lower.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AutoFlush {
+    /// Flush once no data has been written for this long; off by default. This needs the
+    /// `std` feature, for the clock; [`Encoder`] ignores it without.
+    pub idle: Option<Duration>,
+    /// Flush once this many bytes are pending; off by default.
+    pub max_pending: Option<usize>,
+}
+
+/// Size of the scratch space for skipping a skippable frame before the first file header.
+const SKIP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Push-based bzip3 decoder.
+///
+/// The decoder never asks for more input than it needs for the current header or block, so it
+/// doesn't over-read the underlying stream.
+pub struct Decoder {
+    phase: Phase,
+    state: Option<Bz3State>,
+    frame_header: [u8; FRAME_HEADER_SIZE],
+    block_header: [u8; BLOCK_HEADER_SIZE],
+    /// Bytes of the current header or block data received so far.
+    filled: usize,
+    /// Holds the compressed data of the current block, and then its decompressed data.
+    buffer: BlockBuffer,
+    output_pos: usize,
+    output_len: usize,
+    multiple_members: bool,
+    ignore_trailing_data: bool,
+    /// Size of the input processed so far.
+    consumed: u64,
+    /// Hash of the data decoded so far in the current frame.
+    hasher: Xxh3,
+    /// Number of blocks decoded so far.
+    blocks: usize,
+    crc_mode: CrcMode,
+    /// Blocks which failed the CRC check in permissive mode.
+    crc_failures: Vec<usize>,
+    /// Whether each decoded block is checked against its CRC again.
+    paranoid: bool,
+    max_block_size: usize,
+    metadata: Option<Metadata>,
+    pool: Option<BufferPool>,
+    trace: StreamTrace,
+    /// Whether processing the input has failed.
+    failed: bool,
+}
+
+impl Decoder {
+    /// Creates a decoder expecting the file header first.
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::FrameHeader,
+            state: None,
+            frame_header: [0_u8; FRAME_HEADER_SIZE],
+            block_header: [0_u8; BLOCK_HEADER_SIZE],
+            filled: 0,
+            buffer: BlockBuffer::default(),
+            output_pos: 0,
+            output_len: 0,
+            multiple_members: false,
+            ignore_trailing_data: false,
+            consumed: 0,
+            hasher: Xxh3::new(),
+            blocks: 0,
+            crc_mode: CrcMode::Strict,
+            crc_failures: Vec::new(),
+            paranoid: false,
+            max_block_size: BLOCK_SIZE_MAX,
+            metadata: None,
+            pool: None,
+            trace: StreamTrace::new(Operation::Decompress),
+            failed: false,
+        }
+    }
+
+    /// Sets the pool the block buffer and the state are taken from, and put back to.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.pool = Some(pool);
+    }
+
+    /// Sets how a block failing its CRC check is handled; see [`CrcMode`].
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.crc_mode = mode;
+    }
+
+    /// Blocks which failed the CRC check, in permissive mode.
+    pub fn crc_failures(&self) -> &[usize] {
+        &self.crc_failures
+    }
+
+    /// Sets whether the output of each block is checked against the CRC stored in the block,
+    /// failing with [`Error::VerifyFailed`].
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.paranoid = enabled;
+    }
+
+    /// Sets the largest block size accepted in a file header, which bounds the memory used;
+    /// larger fails with [`Error::BlockSizeLimit`].
+    pub fn set_max_block_size(&mut self, limit: usize) {
+        self.max_block_size = limit;
+    }
+
+    /// Sets whether a file header may follow a block, starting another member.
+    ///
+    /// Otherwise, the next member is rejected as an invalid block header.
+    pub fn set_multiple_members(&mut self, enabled: bool) {
+        self.multiple_members = enabled;
+    }
+
+    /// Sets whether anything following the last block that isn't a block is ignored, instead of
+    /// failing with [`Error::TrailingData`].
+    pub fn set_ignore_trailing_data(&mut self, enabled: bool) {
+        self.ignore_trailing_data = enabled;
+    }
+
+    /// The metadata of the stream, once its extension block has been processed.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Whether the first block hasn't been reached yet, so metadata may still come.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn before_blocks(&self) -> bool {
+        self.blocks == 0
+            && matches!(
+                self.phase,
+                Phase::FrameHeader | Phase::BlockHeader | Phase::Extension(_) | Phase::Skippable(_)
+            )
+    }
+
+    /// Number of blocks decoded so far.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// The libbz3 state, once the file header has been parsed.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn state(&self) -> Option<&Bz3State> {
+        self.state.as_ref()
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.state.as_mut()
+    }
+
+    /// Block size of the stream, once the file header has been parsed.
+    pub fn block_size(&self) -> Option<usize> {
+        self.state.as_ref().map(|x| x.block_size)
+    }
+
+    /// Decompressed data ready to be taken.
+    pub fn output(&self) -> &[u8] {
+        &self.buffer[self.output_pos..self.output_len]
+    }
+
+    /// Marks `n` bytes of [`Decoder::output`] as taken.
+    pub fn consume(&mut self, n: usize) {
+        debug_assert!(self.output_pos + n <= self.output_len);
+        self.output_pos += n;
+    }
+
+    /// The space where the next input bytes go.
+    ///
+    /// This is empty while there's pending output; take it out first.
+    pub fn input_buffer(&mut self) -> &mut [u8] {
+        if !self.output().is_empty() {
+            return &mut [];
+        }
+        match &self.phase {
+            Phase::FrameHeader => &mut self.frame_header[self.filled..],
+            Phase::BlockHeader => &mut self.block_header[self.filled..],
+            Phase::BlockData(header) => &mut self.buffer[self.filled..header.data_size()],
+            // small extension data is kept to be inspected; larger is thrown away, and any
+            // scratch space works for that
+            &Phase::Extension(size) if size <= self.buffer.len() => {
+                &mut self.buffer[self.filled..size]
+            }
+            Phase::Extension(size) | Phase::Skippable(size) => {
+                let size = (size - self.filled).min(self.buffer.len());
+                &mut self.buffer[..size]
+            }
+            Phase::Trailing => &mut self.buffer[..],
+        }
+    }
+
+    /// Processes `n` bytes that have been written to [`Decoder::input_buffer`].
+    pub fn advance(&mut self, n: usize) -> Result<()> {
+        let result = self.advance_phase(n);
+        self.failed |= result.is_err();
+        result
+    }
+
+    fn advance_phase(&mut self, n: usize) -> Result<()> {
+        self.filled += n;
+        self.consumed += n as u64;
+        match &self.phase {
+            Phase::FrameHeader => {
+                if self.filled < FRAME_HEADER_SIZE {
+                    return Ok(());
+                }
+                if let Some((_, size)) = SkippableFrame::parse_header(&self.frame_header) {
+                    if self.buffer.is_empty() {
+                        self.buffer = BlockBuffer::new(SKIP_BUFFER_SIZE, 0);
+                    }
+                    self.start_phase(Phase::Skippable(size));
+                    if size == 0 {
+                        self.advance_phase(0)?;
+                    }
+                    return Ok(());
+                }
+                if self.state.is_some() && !self.multiple_members {
+                    // only skippable frames may follow the last block
+                    if self.ignore_trailing_data {
+                        self.start_phase(Phase::Trailing);
+                        return Ok(());
+                    }
+                    let offset = self.consumed - FRAME_HEADER_SIZE as u64;
+                    if self.frame_header.starts_with(MAGIC_NUMBER) {
+                        return Err(Error::MisplacedFrameHeader { offset });
+                    }
+                    return Err(Error::TrailingData { offset });
+                }
+                let header = FrameHeader::parse(&self.frame_header)?;
+                if header.block_size > self.max_block_size {
+                    return Err(Error::BlockSizeLimit {
+                        block_size: header.block_size,
+                        limit: self.max_block_size,
+                    });
+                }
+                if self.block_size() != Some(header.block_size) {
+                    self.trace.set_block_size(header.block_size);
+                    let state = pool::new_state(self.pool.as_ref(), header.block_size)?;
+                    self.release_state();
+                    self.state = Some(state);
+                    let buffer = pool::allocate(self.pool.as_ref(), bound(header.block_size), 0);
+                    self.release_buffer();
+                    self.buffer = buffer;
+                    // all taken, but may lie beyond the new buffer
+                    self.output_pos = 0;
+                    self.output_len = 0;
+                }
+                self.hasher.reset();
+                self.start_phase(Phase::BlockHeader);
+            }
+            Phase::BlockHeader => {
+                if self.filled < BLOCK_HEADER_SIZE {
+                    return Ok(());
+                }
+                if self.multiple_members && self.block_header.starts_with(MAGIC_NUMBER) {
+                    // the magic number can't be a valid block header, so this is the next member
+                    self.frame_header[..BLOCK_HEADER_SIZE].copy_from_slice(&self.block_header);
+                    self.phase = Phase::FrameHeader;
+                    return Ok(());
+                }
+                if self.block_header.starts_with(SKIPPABLE_MAGIC) {
+                    // neither can a skippable frame
+                    self.frame_header[..BLOCK_HEADER_SIZE].copy_from_slice(&self.block_header);
+                    self.phase = Phase::FrameHeader;
+                    return Ok(());
+                }
+                let header = BlockHeader::parse(&self.block_header);
+                if let Some(size) = header.extension_size() {
+                    self.start_phase(Phase::Extension(size));
+                    return Ok(());
+                }
+                if header.validate(self.block_size().unwrap()).is_err() {
+                    if self.ignore_trailing_data {
+                        self.start_phase(Phase::Trailing);
+                        return Ok(());
+                    }
+                    let offset = self.consumed - BLOCK_HEADER_SIZE as u64;
+                    if self.block_header.starts_with(MAGIC_NUMBER) {
+                        return Err(Error::MisplacedFrameHeader { offset });
+                    }
+                    return Err(Error::TrailingData { offset });
+                }
+                self.start_phase(Phase::BlockData(header));
+                if header.data_size() == 0 {
+                    self.advance_phase(0)?;
+                }
+            }
+            &Phase::BlockData(header) => {
+                if self.filled < header.data_size() {
+                    return Ok(());
+                }
+                let mut buffer = mem::take(&mut self.buffer);
+                let result = self.decode_block(header, &mut buffer);
+                self.buffer = buffer;
+                result?;
+                self.output_pos = 0;
+                self.output_len = header.read_size as usize;
+                self.start_phase(Phase::BlockHeader);
+            }
+            &Phase::Extension(size) => {
+                if self.filled < size {
+                    return Ok(());
+                }
+                let data = self.buffer.get(..size);
+                if let Some(expected) = data.and_then(parse_checksum) {
+                    let actual = self.hasher.digest();
+                    if actual != expected {
+                        return Err(Error::ChecksumMismatch { expected, actual });
+                    }
+                }
+                if let Some(data) = data.filter(|x| x.starts_with(METADATA_TAG)) {
+                    self.metadata = Some(Metadata::parse(data)?);
+                }
+                self.start_phase(Phase::BlockHeader);
+            }
+            &Phase::Skippable(size) => {
+                if self.filled < size {
+                    return Ok(());
+                }
+                self.start_phase(Phase::FrameHeader);
+            }
+            Phase::Trailing => {}
+        }
+        Ok(())
+    }
+
+    /// Copies as much of `input` as currently needed, and processes it.
+    ///
+    /// Returns the number of bytes consumed; zero if there's pending output.
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize> {
+        let buffer = self.input_buffer();
+        let size = buffer.len().min(input.len());
+        buffer[..size].copy_from_slice(&input[..size]);
+        self.advance(size)?;
+        Ok(size)
+    }
+
+    /// Checks whether it's fine for the input to end here.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] if the file header is incomplete, and
+    /// [`Error::TruncatedBlock`] if a block is incomplete.
+    pub fn finish(&mut self) -> Result<()> {
+        let result = self.check_end();
+        self.failed |= result.is_err();
+        result
+    }
+
+    fn check_end(&self) -> Result<()> {
+        let (have, need) = match self.phase {
+            // a skippable frame may have followed the last block
+            Phase::FrameHeader if self.state.is_some() && self.filled == 0 => return Ok(()),
+            Phase::FrameHeader if self.state.is_some() && self.ignore_trailing_data => {
+                return Ok(())
+            }
+            Phase::FrameHeader => return Err(Error::InvalidSignature),
+            Phase::Skippable(_) => return Err(Error::unexpected_eof("Truncated skippable frame")),
+            Phase::BlockHeader if self.filled == 0 => return Ok(()),
+            Phase::BlockHeader if self.ignore_trailing_data => return Ok(()),
+            Phase::Trailing => return Ok(()),
+            Phase::BlockHeader => (self.filled, BLOCK_HEADER_SIZE),
+            Phase::BlockData(header) => (
+                BLOCK_HEADER_SIZE + self.filled,
+                BLOCK_HEADER_SIZE + header.data_size(),
+            ),
+            Phase::Extension(size) => (BLOCK_HEADER_SIZE + self.filled, BLOCK_HEADER_SIZE + size),
+        };
+        Err(Error::TruncatedBlock {
+            block_index: self.blocks,
+            have,
+            need,
+        })
+    }
+
+    /// The block whose data comes next, if none of it has been fed and there's no pending
+    /// output, for the caller to decode it in its own buffer with
+    /// [`Decoder::decode_block_into`] instead.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn pending_block(&self) -> Option<BlockHeader> {
+        match self.phase {
+            Phase::BlockData(header) if self.filled == 0 && self.output().is_empty() => {
+                Some(header)
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the block from [`Decoder::pending_block`], whose data is at the start of `buf`,
+    /// as if the data had been fed; the decompressed data is left at the start of `buf`.
+    ///
+    /// `buf` must hold at least the data size and [`bound`] of the original size.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn decode_block_into(&mut self, header: BlockHeader, buf: &mut [u8]) -> Result<()> {
+        debug_assert!(self.pending_block() == Some(header));
+        debug_assert!(buf.len() >= header.data_size() && buf.len() >= bound(header.read_size as _));
+        self.consumed += header.data_size() as u64;
+        let result = self.decode_block(header, buf);
+        self.failed |= result.is_err();
+        result?;
+        self.start_phase(Phase::BlockHeader);
+        Ok(())
+    }
+
+    /// Decodes a block in place in `buf`, which holds its data.
+    fn decode_block(&mut self, header: BlockHeader, buf: &mut [u8]) -> Result<()> {
+        let read_size = header.read_size as usize;
+        self.trace
+            .add_block(BLOCK_HEADER_SIZE + header.data_size(), read_size);
+        if header.is_stored() {
+            self.blocks += 1;
+            self.hasher.update(&buf[..read_size]);
+            return Ok(());
+        }
+        // decoding happens in place, so the CRC and the position of a file header, which tells
+        // why decoding failed, have to be taken out first
+        let data = &buf[..(header.new_size as usize)];
+        let expected_crc = self.paranoid.then(|| stored_crc(data)).flatten();
+        let magic = find_magic(data);
+        let _span = self.trace.enter();
+        let result = self.state.as_mut().unwrap().decode_block_at(
+            buf,
+            header.new_size as usize,
+            read_size,
+            self.blocks,
+        );
+        self.blocks += 1;
+        match result {
+            Ok(()) => {
+                // checked on the very bytes handed out
+                if expected_crc.is_some_and(|x| x != block_crc(&buf[..read_size])) {
+                    return Err(Error::VerifyFailed {
+                        block_index: self.blocks - 1,
+                    });
+                }
+            }
+            Err(Error::BadCrc { block_index }) if self.crc_mode == CrcMode::Permissive => {
+                self.crc_failures.push(block_index);
+            }
+            Err(e) => {
+                return Err(match magic {
+                    Some(pos) => Error::MisplacedFrameHeader {
+                        offset: self.consumed - (header.new_size as usize - pos) as u64,
+                    },
+                    None => e,
+                });
+            }
+        }
+        self.hasher.update(&buf[..read_size]);
+        Ok(())
+    }
+
+    fn start_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.filled = 0;
+    }
+
+    /// Puts the buffer back to the pool, if any, leaving it empty.
+    fn release_buffer(&mut self) {
+        let buffer = mem::take(&mut self.buffer);
+        if let Some(pool) = &self.pool {
+            pool.put(buffer);
+        }
+    }
+
+    /// Puts the state back to the pool, if any, leaving none.
+    fn release_state(&mut self) {
+        if let (Some(state), Some(pool)) = (self.state.take(), &self.pool) {
+            pool.put_state(state);
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Decoder");
+        self.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl Decoder {
+    /// Adds the state of the decoder to the `Debug` output of a codec; never the data.
+    pub(crate) fn debug_fields(&self, s: &mut fmt::DebugStruct<'_, '_>) {
+        s.field("block_size", &self.block_size())
+            .field("blocks", &self.blocks)
+            .field("phase", &self.phase.name())
+            .field("partial_input", &self.filled)
+            .field("pending_output", &self.output().len())
+            .field("input_consumed", &self.consumed)
+            .field("failed", &self.failed);
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        self.release_buffer();
+        self.release_state();
+    }
+}
+
+/// Push-based bzip3 encoder.
+///
+/// Input is gathered straight into the block buffer, and each block is compressed in place right
+/// after the space reserved for its header, so a finished block is output as one contiguous
+/// slice.
+///
+/// The state and the block buffer are only allocated once there's input, as many encoders are
+/// created for streams which end up empty, e.g. one per connection.
+pub struct Encoder {
+    /// `None` until the first block is compressed.
+    state: Option<Bz3State>,
+    block_size: usize,
+    frame_header: [u8; FRAME_HEADER_SIZE],
+    /// Bytes of the file header output so far.
+    frame_header_pos: usize,
+    /// Block header followed by the block data; empty until there's input, or only holding the
+    /// checksum of an empty stream.
+    buffer: BlockBuffer,
+    /// Size of the input gathered for the current block.
+    input_len: usize,
+    output_pos: usize,
+    output_len: usize,
+    /// Hash of the data compressed so far.
+    hasher: Xxh3,
+    /// Whether [`Encoder::finish`] appends a checksum.
+    checksum: bool,
+    /// Copy of the current block's input, to store it as is if it doesn't compress; `None`
+    /// unless stored blocks are enabled.
+    stored_copy: Option<Vec<u8>>,
+    /// Finds the block boundaries in content-defined chunking mode.
+    chunker: Option<Chunker>,
+    finished: bool,
+    pool: Option<BufferPool>,
+    /// `None` unless double buffering is enabled.
+    background: Option<Background>,
+    auto_flush: AutoFlush,
+    /// When the last input came in.
+    #[cfg(feature = "std")]
+    last_input: Option<Instant>,
+    trace: StreamTrace,
+    /// Number of blocks compressed so far.
+    blocks: usize,
+    /// Whether compressing a block has failed.
+    failed: bool,
+}
+
+/// A block handed to the background thread, and back once compressed.
+struct Job {
+    state: Bz3State,
+    /// Block header followed by the block data.
+    buffer: BlockBuffer,
+    input_len: usize,
+    stored_copy: Option<Vec<u8>>,
+    /// Size of the block once compressed, header included.
+    block_len: usize,
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    span: StreamSpan,
+}
+
+/// Thread compressing a block at a time, while the next one is gathered.
+///
+/// The thread is only started with the first block.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Background {
+    jobs: Option<mpsc::Sender<Job>>,
+    /// In a mutex only for the encoders to stay `Sync`.
+    results: Option<Mutex<mpsc::Receiver<Result<Job>>>>,
+    thread: Option<thread::JoinHandle<()>>,
+    in_flight: bool,
+}
+
+#[cfg(feature = "std")]
+impl Background {
+    fn in_flight(&self) -> bool {
+        self.in_flight
+    }
+
+    fn submit(&mut self, job: Job) -> Result<()> {
+        debug_assert!(!self.in_flight);
+        if self.jobs.is_none() {
+            let (jobs, job_receiver) = mpsc::channel::<Job>();
+            let (result_sender, results) = mpsc::channel();
+            let thread = thread::Builder::new()
+                .name("bzip3-encoder".into())
+                .spawn(move || {
+                    for mut job in job_receiver {
+                        let result = {
+                            let _span = job.span.enter();
+                            encode_block(
+                                &mut job.state,
+                                &mut job.buffer,
+                                job.input_len,
+                                job.stored_copy.as_deref(),
+                            )
+                        };
+                        let result = result.map(|x| {
+                            job.block_len = x;
+                            job
+                        });
+                        if result_sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                })?;
+            self.jobs = Some(jobs);
+            self.results = Some(Mutex::new(results));
+            self.thread = Some(thread);
+        }
+        // the thread only stops once `jobs` is dropped
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+        self.in_flight = true;
+        Ok(())
+    }
+
+    /// Waits for the block being compressed, if any.
+    fn wait(&mut self) -> Result<Option<Job>> {
+        if !self.in_flight {
+            return Ok(None);
+        }
+        self.in_flight = false;
+        match self.results.as_mut().unwrap().get_mut().unwrap().recv() {
+            Ok(result) => result.map(Some),
+            Err(_) => Err(Error::ProcessBlock(
+                "The background compression thread panicked".into(),
+            )),
+        }
+    }
+}
+
+/// Stand-in without std, which has no threads for double buffering.
+#[cfg(not(feature = "std"))]
+enum Background {}
+
+#[cfg(not(feature = "std"))]
+impl Background {
+    fn in_flight(&self) -> bool {
+        match *self {}
+    }
+
+    fn submit(&mut self, _job: Job) -> Result<()> {
+        match *self {}
+    }
+
+    fn wait(&mut self) -> Result<Option<Job>> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Compresses the block input at `BLOCK_HEADER_SIZE` in `buffer`, and writes its header; or
+/// stores `stored_copy` of the input instead, if given and compressing doesn't shrink it.
+///
+/// Returns the size of the block, header included.
+fn encode_block(
+    state: &mut Bz3State,
+    buffer: &mut [u8],
+    input_len: usize,
+    stored_copy: Option<&[u8]>,
+) -> Result<usize> {
+    let (header, data) = buffer.split_at_mut(BLOCK_HEADER_SIZE);
+    let mut new_size = state.encode_block(data, input_len)?;
+    let mut block_header = BlockHeader {
+        new_size: new_size as i32,
+        read_size: input_len as i32,
+    };
+    if let Some(copy) = stored_copy.filter(|_| new_size >= input_len) {
+        data[..copy.len()].copy_from_slice(copy);
+        new_size = copy.len();
+        block_header = BlockHeader::stored(new_size);
+    }
+    header.copy_from_slice(&block_header.to_bytes());
+    Ok(BLOCK_HEADER_SIZE + new_size)
+}
+
+impl Encoder {
+    /// Creates an encoder with the given block size.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(block_size: usize) -> Result<Self> {
+        let frame_header = FrameHeader::new(block_size)?.to_bytes();
+        let trace = StreamTrace::new(Operation::Compress);
+        trace.set_block_size(block_size);
+        Ok(Self {
+            state: None,
+            block_size,
+            frame_header,
+            frame_header_pos: 0,
+            buffer: BlockBuffer::default(),
+            input_len: 0,
+            output_pos: 0,
+            output_len: 0,
+            hasher: Xxh3::new(),
+            checksum: false,
+            stored_copy: None,
+            chunker: None,
+            finished: false,
+            pool: None,
+            background: None,
+            auto_flush: AutoFlush::default(),
+            #[cfg(feature = "std")]
+            last_input: None,
+            trace,
+            blocks: 0,
+            failed: false,
+        })
+    }
+
+    /// Sets the pool the block buffer and the state are taken from, and put back to.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.pool = Some(pool);
+    }
+
+    /// Sets whether [`Encoder::finish`] appends a checksum of all the data.
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+
+    /// Sets whether blocks that would grow by compressing them are written as stored blocks.
+    pub fn set_stored_blocks(&mut self, enabled: bool) {
+        self.stored_copy = enabled.then(Vec::new);
+    }
+
+    /// Sets whether blocks are compressed in a background thread, while the next block is
+    /// gathered into a second buffer; call it before feeding anything.
+    ///
+    /// The output then lags a block behind: a full block only comes out once the next one is
+    /// full too, or on [`Encoder::flush`].
+    #[cfg(feature = "std")]
+    pub(crate) fn set_double_buffering(&mut self, enabled: bool) {
+        debug_assert!(!self.background.as_ref().is_some_and(Background::in_flight));
+        self.background = enabled.then(Background::default);
+    }
+
+    /// Sets content-defined chunking, or fixed-size blocks with `None`.
+    ///
+    /// This takes effect from the next block; call it before feeding anything.
+    pub fn set_chunking(&mut self, chunking: Option<Chunking>) -> Result<()> {
+        if let Some(chunking) = &chunking {
+            chunking.validate(self.block_size)?;
+        }
+        self.chunker = chunking.map(Chunker::new);
+        Ok(())
+    }
+
+    /// Sets when the partial block is due to be compressed; see [`AutoFlush`].
+    ///
+    /// The partial block is compressed right away if it's over the new size limit.
+    pub fn set_auto_flush(&mut self, auto_flush: AutoFlush) -> Result<()> {
+        if auto_flush.max_pending == Some(0) {
+            return Err(Error::InvalidArgument("Invalid auto-flush size: 0".into()));
+        }
+        self.auto_flush = auto_flush;
+        if self.input_len != 0 && self.input_len >= self.block_limit() {
+            self.compress_block()?;
+        }
+        Ok(())
+    }
+
+    /// When the data not output yet is due to be flushed, having been idle for the time set
+    /// with [`Encoder::set_auto_flush`]; `None` if there's no such data, or no idle time set.
+    #[cfg(feature = "std")]
+    pub(crate) fn idle_deadline(&self) -> Option<Instant> {
+        let in_flight = self.background.as_ref().is_some_and(Background::in_flight);
+        if self.input_len == 0 && !in_flight {
+            return None;
+        }
+        Some(self.last_input? + self.auto_flush.idle?)
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Number of blocks compressed so far.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// The libbz3 state, once the first block has been compressed; `None` while it's with the
+    /// block compressing in the background.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn state(&self) -> Option<&Bz3State> {
+        self.state.as_ref()
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.state.as_mut()
+    }
+
+    /// Size of the input gathered for the block not compressed yet.
+    pub fn pending_input(&self) -> usize {
+        self.input_len
+    }
+
+    /// Checks that a frame with the given block size can be started, the block size being valid
+    /// and fitting the chunk sizes.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn check_frame(&self, block_size: usize) -> Result<()> {
+        if !Bz3State::check_block_size(block_size) {
+            return Err(Error::BlockSize);
+        }
+        if let Some(chunker) = &self.chunker {
+            chunker.chunking().validate(block_size)?;
+        }
+        Ok(())
+    }
+
+    /// Starts another frame with the given block size, once [`Encoder::is_finished`]; its file
+    /// header is the next output. The settings carry over, and the checksum covers the new
+    /// frame only.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn start_frame(&mut self, block_size: usize) -> Result<()> {
+        debug_assert!(self.is_finished());
+        self.check_frame(block_size)?;
+        let frame_header = FrameHeader::new(block_size)?.to_bytes();
+        if let Some(chunker) = &mut self.chunker {
+            chunker.reset();
+        }
+        if block_size != self.block_size {
+            self.release_state();
+            self.release_buffer();
+            self.block_size = block_size;
+            self.trace.set_block_size(block_size);
+        }
+        self.frame_header = frame_header;
+        self.frame_header_pos = 0;
+        self.input_len = 0;
+        self.output_pos = 0;
+        self.output_len = 0;
+        self.hasher.reset();
+        self.finished = false;
+        Ok(())
+    }
+
+    /// Largest size of a block's input.
+    fn block_limit(&self) -> usize {
+        let limit = self
+            .chunker
+            .as_ref()
+            .map_or(self.block_size, |x| x.max_size());
+        self.auto_flush.max_pending.map_or(limit, |x| x.min(limit))
+    }
+
+    /// Compressed data ready to be taken.
+    pub fn output(&self) -> &[u8] {
+        if self.frame_header_pos < FRAME_HEADER_SIZE {
+            return &self.frame_header[self.frame_header_pos..];
+        }
+        &self.buffer[self.output_pos..self.output_len]
+    }
+
+    /// Marks `n` bytes of [`Encoder::output`] as taken.
+    pub fn consume(&mut self, n: usize) {
+        if self.frame_header_pos < FRAME_HEADER_SIZE {
+            debug_assert!(self.frame_header_pos + n <= FRAME_HEADER_SIZE);
+            self.frame_header_pos += n;
+        } else {
+            debug_assert!(self.output_pos + n <= self.output_len);
+            self.output_pos += n;
+        }
+    }
+
+    /// Takes the buffer holding the pending output, a block, for the caller to keep; the next
+    /// block goes into another buffer, from the pool if any.
+    ///
+    /// Returns the buffer, and where the output lies in it.
+    #[cfg_attr(not(feature = "bytes"), allow(dead_code))]
+    pub(crate) fn take_output(&mut self) -> (BlockBuffer, Range<usize>) {
+        debug_assert!(self.frame_header_pos == FRAME_HEADER_SIZE);
+        let range = self.output_pos..self.output_len;
+        self.output_pos = 0;
+        self.output_len = 0;
+        (mem::take(&mut self.buffer), range)
+    }
+
+    /// The space where the next input bytes go.
+    ///
+    /// This is empty while there's pending output; take it out first.
+    pub fn input_buffer(&mut self) -> &mut [u8] {
+        if !self.output().is_empty() {
+            return &mut [];
+        }
+        if self.buffer.len() < BLOCK_HEADER_SIZE + self.block_size {
+            // without a pool, a zeroed allocation: fresh pages from the OS for large blocks,
+            // with no memset, committed only as blocks fill them
+            let size = BLOCK_HEADER_SIZE + bound(self.block_size);
+            // aligned where the block data starts
+            let buffer = pool::allocate(self.pool.as_ref(), size, BLOCK_HEADER_SIZE);
+            self.release_buffer();
+            self.buffer = buffer;
+        }
+        let limit = self.block_limit();
+        &mut self.buffer[(BLOCK_HEADER_SIZE + self.input_len)..(BLOCK_HEADER_SIZE + limit)]
+    }
+
+    /// Processes `n` bytes that have been written to [`Encoder::input_buffer`].
+    ///
+    /// A full block is compressed right away.
+    pub fn advance(&mut self, n: usize) -> Result<()> {
+        #[cfg(feature = "std")]
+        if n != 0 && self.auto_flush.idle.is_some() {
+            self.last_input = Some(Instant::now());
+        }
+        self.input_len += n;
+        debug_assert!(self.input_len <= self.block_limit());
+        if self.input_len == self.block_limit() {
+            self.compress_block()?;
+        }
+        Ok(())
+    }
+
+    /// Copies as much of `input` as fits in the current block, and processes it. In
+    /// content-defined chunking mode, the block also ends at the first boundary found in
+    /// `input`.
+    ///
+    /// Returns the number of bytes consumed; zero if there's pending output.
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize> {
+        let mut size = self.input_buffer().len().min(input.len());
+        let boundary = match &mut self.chunker {
+            Some(chunker) => chunker.scan(self.input_len, &input[..size]),
+            None => None,
+        };
+        if let Some(boundary) = boundary {
+            size = boundary;
+        }
+        self.input_buffer()[..size].copy_from_slice(&input[..size]);
+        self.advance(size)?;
+        // `advance` already compressed a block of the maximum size
+        if boundary.is_some() && self.input_len != 0 {
+            self.compress_block()?;
+        }
+        Ok(size)
+    }
+
+    /// Compresses the partial block gathered so far, if any.
+    ///
+    /// This does nothing while there's pending output. With double buffering, this may only
+    /// output the block compressing in the background; it's all out once this leaves no output.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.output().is_empty() && self.input_len != 0 {
+            self.compress_block()?;
+        }
+        if self.output().is_empty() {
+            self.wait_background()?;
+        }
+        Ok(())
+    }
+
+    /// Compresses the partial block, and then outputs the checksum if enabled. Nothing may be
+    /// fed afterwards.
+    ///
+    /// This does nothing while there's pending output; call it again once that's taken.
+    pub fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        if !self.output().is_empty() || self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if self.checksum {
+            let block = checksum_block(self.hasher.digest());
+            if self.buffer.is_empty() {
+                // no need for a whole block buffer
+                self.buffer = BlockBuffer::from_slice(&block);
+            } else {
+                self.buffer[..block.len()].copy_from_slice(&block);
+            }
+            self.output_pos = 0;
+            self.output_len = block.len();
+        }
+        Ok(())
+    }
+
+    /// Whether [`Encoder::finish`] is done, and all the output has been taken.
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.output().is_empty()
+    }
+
+    fn compress_block(&mut self) -> Result<()> {
+        debug_assert!(!self.finished);
+        let result = if self.background.is_some() {
+            self.compress_block_background()
+        } else {
+            self.compress_block_now()
+        };
+        self.failed |= result.is_err();
+        result
+    }
+
+    fn compress_block_now(&mut self) -> Result<()> {
+        let input = &self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + self.input_len)];
+        self.hasher.update(input);
+        if let Some(copy) = &mut self.stored_copy {
+            copy.clear();
+            copy.extend_from_slice(input);
+        }
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => self
+                .state
+                .insert(pool::new_state(self.pool.as_ref(), self.block_size)?),
+        };
+        let block_len = {
+            let _span = self.trace.enter();
+            encode_block(
+                state,
+                &mut self.buffer,
+                self.input_len,
+                self.stored_copy.as_deref(),
+            )?
+        };
+        self.trace.add_block(self.input_len, block_len);
+        self.blocks += 1;
+        if let Some(chunker) = &mut self.chunker {
+            chunker.reset();
+        }
+        self.input_len = 0;
+        self.output_pos = 0;
+        self.output_len = block_len;
+        Ok(())
+    }
+
+    /// Hands the block to the background thread, taking the block compressed before, if any,
+    /// as the output; its buffer is where the next block goes once the output is taken.
+    fn compress_block_background(&mut self) -> Result<()> {
+        let input = &self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + self.input_len)];
+        self.hasher.update(input);
+        // the copy goes with the block, leaving the one of the block before, or an empty one,
+        // which tells stored blocks are enabled
+        let stored_copy = self.stored_copy.as_mut().map(|copy| {
+            let mut copy = mem::take(copy);
+            copy.clear();
+            copy.extend_from_slice(input);
+            copy
+        });
+        let buffer = mem::take(&mut self.buffer);
+        let input_len = mem::take(&mut self.input_len);
+        // all taken, but lying beyond the empty buffer left
+        self.output_pos = 0;
+        self.output_len = 0;
+        if let Some(chunker) = &mut self.chunker {
+            chunker.reset();
+        }
+        self.wait_background()?;
+        let state = match self.state.take() {
+            Some(state) => state,
+            None => pool::new_state(self.pool.as_ref(), self.block_size)?,
+        };
+        let job = Job {
+            state,
+            buffer,
+            input_len,
+            stored_copy,
+            block_len: 0,
+            span: self.trace.span().clone(),
+        };
+        self.background.as_mut().unwrap().submit(job)
+    }
+
+    /// Waits for the block compressing in the background, if any, making it the output.
+    ///
+    /// There must be no output pending, and no input gathered yet into the buffer.
+    fn wait_background(&mut self) -> Result<()> {
+        let job = match &mut self.background {
+            Some(background) => background.wait(),
+            None => Ok(None),
+        };
+        self.failed |= job.is_err();
+        let job = job?;
+        let Some(job) = job else {
+            return Ok(());
+        };
+        debug_assert!(self.output().is_empty() && self.input_len == 0);
+        self.trace.add_block(job.input_len, job.block_len);
+        self.blocks += 1;
+        self.release_state();
+        self.state = Some(job.state);
+        if let (Some(copy), Some(job_copy)) = (&mut self.stored_copy, job.stored_copy) {
+            *copy = job_copy;
+        }
+        self.release_buffer();
+        self.buffer = job.buffer;
+        self.output_pos = 0;
+        self.output_len = job.block_len;
+        Ok(())
+    }
+
+    /// Puts the buffer back to the pool, if any, leaving it empty.
+    fn release_buffer(&mut self) {
+        let buffer = mem::take(&mut self.buffer);
+        if let Some(pool) = &self.pool {
+            pool.put(buffer);
+        }
+    }
+
+    /// Puts the state back to the pool, if any, leaving none.
+    fn release_state(&mut self) {
+        if let (Some(state), Some(pool)) = (self.state.take(), &self.pool) {
+            pool.put_state(state);
+        }
+    }
+}
+
+impl fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Encoder");
+        self.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl Encoder {
+    /// Adds the state of the encoder to the `Debug` output of a codec; never the data.
+    pub(crate) fn debug_fields(&self, s: &mut fmt::DebugStruct<'_, '_>) {
+        let in_flight = self.background.as_ref().is_some_and(Background::in_flight);
+        s.field("block_size", &self.block_size)
+            .field("blocks", &self.blocks)
+            .field("pending_input", &self.input_len)
+            .field("pending_output", &self.output().len())
+            .field("block_in_flight", &in_flight)
+            .field("finished", &self.finished)
+            .field("failed", &self.failed);
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        let job = self.background.as_mut().map(Background::wait);
+        if let (Some(Ok(Some(job))), Some(pool)) = (job, &self.pool) {
+            pool.put(job.buffer);
+            pool.put_state(job.state);
+        }
+        self.release_buffer();
+        self.release_state();
+    }
+}
@@ -1,21 +1,25 @@
 //! Read-based BZip3 compressor and decompressor.
 
+use std::fmt;
 use std::io;
-use std::io::{ErrorKind, Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
 
 use crate::errors::*;
-use crate::{bound, Bz3State, TryReadExact, BLOCK_SIZE_MAX, BLOCK_SIZE_MIN, MAGIC_NUMBER};
+use crate::frame::{FrameHeader, FRAME_HEADER_SIZE, MAGIC_PREFIX, SKIPPABLE_MAGIC};
+use crate::instrument::{Operation, StreamTrace};
+use crate::metadata::Metadata;
+use crate::pool::{BlockBuffer, BufferPool};
+use crate::{bound, push, Bz3State, CrcMode, Level, TryReadExact};
 
 pub struct Bz3Encoder<R>
 where
     R: Read,
 {
-    state: Bz3State,
+    /// `None` until the first block.
+    state: Option<Bz3State>,
     reader: R,
     /// Temporary buffer for [`Read::read`].
-    buffer: Vec<u8>,
+    buffer: BlockBuffer,
     buffer_pos: usize,
     buffer_len: usize,
     block_size: usize,
@@ -24,6 +28,26 @@ where
     /// Its function is to ensure that, after EOF is
     /// reached, all further `read` calls emit zero read size return-value.
     eof: bool,
+    trace: StreamTrace,
+    /// Number of blocks compressed so far.
+    blocks: usize,
+    /// Whether compressing a block has failed.
+    failed: bool,
+}
+
+impl<R> fmt::Debug for Bz3Encoder<R>
+where
+    R: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bz3Encoder")
+            .field("block_size", &self.block_size)
+            .field("blocks", &self.blocks)
+            .field("pending_output", &(self.buffer_len - self.buffer_pos))
+            .field("eof", &self.eof)
+            .field("failed", &self.failed)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<R> Bz3Encoder<R>
@@ -32,56 +56,142 @@ where
 {
     /// Creates a new read-based bzip3 encoder.
     ///
-    /// Valid block size is between [`BLOCK_SIZE_MIN`] and [`BLOCK_SIZE_MAX`] bytes.
+    /// Valid block size is between [`BLOCK_SIZE_MIN`](crate::BLOCK_SIZE_MIN) and
+    /// [`BLOCK_SIZE_MAX`](crate::BLOCK_SIZE_MAX) bytes.
     ///
     /// # Errors
     ///
     /// This returns [`Error::BlockSize`] if the block size is invalid.
     pub fn new(reader: R, block_size: usize) -> Result<Self> {
-        let state = Bz3State::new(block_size)?;
-
-        let buffer_size = bound(block_size) + MAGIC_NUMBER.len() + 4;
-        let mut buffer = vec![0_u8; buffer_size];
-
-        let mut header = Vec::new();
-        header.write_all(MAGIC_NUMBER).unwrap();
-        header.write_i32::<LE>(block_size as i32).unwrap();
-        buffer[..header.len()].copy_from_slice(&header);
+        let header = FrameHeader::new(block_size)?.to_bytes();
+        let trace = StreamTrace::new(Operation::Compress);
+        trace.set_block_size(block_size);
 
         Ok(Self {
-            state,
+            state: None,
             reader,
-            buffer,
+            // the state and the block buffer are allocated on the first block
+            buffer: BlockBuffer::from_slice(&header),
             buffer_pos: 0,
             buffer_len: header.len(), /* default buffer holds the header */
             block_size,
             eof: false,
+            trace,
+            blocks: 0,
+            failed: false,
         })
     }
 
+    /// Creates a new read-based bzip3 encoder with the
+    /// [default block size](crate::DEFAULT_BLOCK_SIZE).
+    pub fn with_defaults(reader: R) -> Self {
+        Self::new(reader, crate::DEFAULT_BLOCK_SIZE).expect("the default block size is valid")
+    }
+
+    /// Creates a new read-based bzip3 encoder with the block size of a compression [`Level`].
+    pub fn with_level(reader: R, level: Level) -> Result<Self> {
+        Self::new(reader, level.block_size())
+    }
+
+    /// Creates a new read-based bzip3 encoder with a block size like `"16MiB"`; see
+    /// [`parse_block_size`](crate::parse_block_size).
+    pub fn with_block_size_str(reader: R, block_size: &str) -> Result<Self> {
+        Self::new(reader, crate::parse_block_size(block_size)?)
+    }
+
+    /// Reads the whole input, and returns its compressed data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use bzip3::read::{Bz3Decoder, Bz3Encoder};
+    ///
+    /// let encoder = Bz3Encoder::new(&b"hello, world"[..], 100 * 1024).unwrap();
+    /// let compressed = encoder.encode_to_vec().unwrap();
+    /// let decoder = Bz3Decoder::new(compressed.as_slice()).unwrap();
+    /// assert_eq!(decoder.decode_to_vec().unwrap(), b"hello, world");
+    /// ```
+    pub fn encode_to_vec(mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.read_to_end(&mut buf).map_err(Error::from_io_error)?;
+        Ok(buf)
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks
+    }
+
+    /// Returns the libbz3 state, once the first block has been compressed, e.g. to check the
+    /// [CRC](Bz3State::last_crc) of the last block, or the [last error](Bz3State::last_error)
+    /// of libbz3.
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.state.as_ref()
+    }
+
+    /// Returns the libbz3 state mutably, once the first block has been compressed, e.g. for
+    /// the [description](Bz3State::error) of the last error.
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.state.as_mut()
+    }
+
     /// Compress and fill the buffer.
     ///
     /// Return the size read from `self.reader`; zero indicates EOF.
     fn compress_block(&mut self) -> Result<usize> {
-        let buffer = &mut self.buffer[..];
+        if self.buffer.len() < bound(self.block_size) + FRAME_HEADER_SIZE {
+            // aligned where the block data starts
+            self.buffer = BlockBuffer::new(bound(self.block_size) + FRAME_HEADER_SIZE, 8);
+        }
+        let (read_size, block_len) = Self::compress_block_into(
+            &mut self.state,
+            &mut self.reader,
+            self.block_size,
+            &mut self.buffer,
+            &mut self.trace,
+        )?;
+        self.buffer_pos = 0;
+        self.buffer_len = block_len;
+        Ok(read_size)
+    }
 
+    /// Reads a block from `reader`, and compresses it into `buffer`, which must hold at least
+    /// [`bound`] of the block size plus the block header.
+    ///
+    /// Return the size read from `reader`, zero indicating EOF, and the size of the block in
+    /// `buffer`.
+    fn compress_block_into(
+        state: &mut Option<Bz3State>,
+        reader: &mut R,
+        block_size: usize,
+        buffer: &mut [u8],
+        trace: &mut StreamTrace,
+    ) -> Result<(usize, usize)> {
         // structure of a block: [ new_size (i32) | read_size (i32) | compressed data ]
         // skip 8 bytes to write the buffer first
         let data_buffer = &mut buffer[8..];
 
-        let read_size = self
-            .reader
-            .try_read_exact(&mut data_buffer[..self.block_size])?;
+        let read_size = reader.try_read_exact(&mut data_buffer[..block_size])?;
+        if read_size == 0 {
+            return Ok((0, 0));
+        }
 
-        let new_size = self.state.encode_block(data_buffer, read_size)?;
+        let state = match state {
+            Some(state) => state,
+            None => state.insert(Bz3State::new(block_size)?),
+        };
+        let new_size = {
+            let _span = trace.enter();
+            state.encode_block(data_buffer, read_size)?
+        };
+        trace.add_block(read_size, 4 + 4 + new_size);
 
         // go back and fill new_size and read_size
-        use byteorder::ByteOrder;
+        use byteorder::{ByteOrder, LE};
         LE::write_i32(buffer, new_size as i32);
         LE::write_i32(&mut buffer[4..], read_size as i32);
 
-        self.buffer_len = 4 + 4 + new_size;
-        Ok(read_size)
+        Ok((read_size, 4 + 4 + new_size))
     }
 }
 
@@ -98,10 +208,21 @@ where
                 return Ok(0);
             }
 
-            // reset buffer position, and re-fill the buffer
-            self.buffer_pos = 0;
-            match self.compress_block() {
-                Ok(read_size) => {
+            // a block that fits goes straight into `buf`, sparing a copy
+            let direct = buf.len() >= 8 + bound(self.block_size);
+            let result = if direct {
+                Self::compress_block_into(
+                    &mut self.state,
+                    &mut self.reader,
+                    self.block_size,
+                    buf,
+                    &mut self.trace,
+                )
+            } else {
+                self.compress_block().map(|x| (x, self.buffer_len))
+            };
+            match result {
+                Ok((read_size, block_len)) => {
                     // `try_read_exact` defines this is reaching EOF
                     // but still have some data
                     if read_size < self.block_size {
@@ -112,11 +233,17 @@ where
                         self.eof = true;
                         return Ok(0);
                     }
+                    self.blocks += 1;
+                    if direct {
+                        return Ok(block_len);
+                    }
                 }
                 Err(Error::ProcessBlock(msg)) => {
-                    return Err(io::Error::new(ErrorKind::Other, msg));
+                    self.failed = true;
+                    return Err(io::Error::other(msg));
                 }
                 Err(Error::Io(e)) => {
+                    self.failed = true;
                     return Err(e);
                 }
                 Err(_) => {
@@ -145,17 +272,23 @@ pub struct Bz3Decoder<R>
 where
     R: Read,
 {
-    state: Bz3State,
     reader: R,
-    /// Temporary buffer for [`Read::read`].
-    buffer: Vec<u8>,
-    buffer_pos: usize,
-    buffer_len: usize,
-    block_size: usize,
+    decoder: push::Decoder,
     /// Underlying `reader` EOF indicator.
     eof: bool,
 }
 
+impl<R> fmt::Debug for Bz3Decoder<R>
+where
+    R: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("eof", &self.eof).finish_non_exhaustive()
+    }
+}
+
 impl<R> Bz3Decoder<R>
 where
     R: Read,
@@ -164,108 +297,252 @@ where
     ///
     /// # Errors
     ///
-    /// [`Error::InvalidSignature`] for invalid file header signature, and
+    /// [`Error::InvalidSignature`] for invalid file header signature,
+    /// [`Error::UnsupportedVersion`] for a file of a newer format version, and
     /// [`Error::Io`] on all IO errors.
-    pub fn new(mut reader: R) -> Result<Self> {
-        let mut signature = [0_u8; MAGIC_NUMBER.len()];
-        let result = reader.read_exact(&mut signature);
-        if let Err(e) = result {
-            if e.kind() != ErrorKind::UnexpectedEof {
-                return Err(e.into());
-            }
-        }
-        if &signature != MAGIC_NUMBER {
-            return Err(Error::InvalidSignature);
-        }
+    pub fn new(reader: R) -> Result<Self> {
+        Self::with_max_block_size(reader, crate::BLOCK_SIZE_MAX)
+    }
 
-        let block_size = reader.read_i32::<LE>()? as usize;
-        let state = Bz3State::new(block_size)?;
+    /// Creates a read-based bzip3 decoder, rejecting files with a block size above `limit`.
+    ///
+    /// The decoder allocates memory in proportion to the block size in the file header, up to
+    /// several times [`BLOCK_SIZE_MAX`](crate::BLOCK_SIZE_MAX); this bounds it for untrusted
+    /// input.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSizeLimit`] if the block size is above `limit`, and the same as
+    /// [`Bz3Decoder::new`] otherwise.
+    pub fn with_max_block_size(reader: R, limit: usize) -> Result<Self> {
+        Self::with_options(reader, limit, None)
+    }
 
-        let buffer_size = bound(block_size);
-        let buffer = vec![0_u8; buffer_size];
+    /// Creates a read-based bzip3 decoder, taking the block buffer and the state from the given
+    /// pool, and putting them back when dropped; see [`BufferPool`].
+    ///
+    /// # Errors
+    ///
+    /// The same as [`Bz3Decoder::new`].
+    pub fn with_buffer_pool(reader: R, pool: BufferPool) -> Result<Self> {
+        Self::with_options(reader, crate::BLOCK_SIZE_MAX, Some(pool))
+    }
 
-        Ok(Self {
-            state,
+    pub(crate) fn with_options(reader: R, limit: usize, pool: Option<BufferPool>) -> Result<Self> {
+        let mut decoder = Self {
             reader,
-            buffer_pos: 0,
-            buffer_len: 0,
-            buffer,
-            block_size,
+            decoder: push::Decoder::new(),
             eof: false,
-        })
+        };
+        decoder.decoder.set_max_block_size(limit);
+        if let Some(pool) = pool {
+            decoder.decoder.set_buffer_pool(pool);
+        }
+        // read the file header
+        while decoder.decoder.block_size().is_none() {
+            if !decoder.fill()? {
+                decoder.decoder.finish()?;
+            }
+        }
+        Ok(decoder)
     }
 
     /// Returns the bzip3 block size associated with the current state.
     pub fn block_size(&self) -> usize {
-        self.block_size
+        self.decoder.block_size().unwrap()
     }
 
-    /// Decompress and fill the buffer.
+    /// Sets how a block failing its CRC check is handled.
     ///
-    /// Returning true indicates EOF.
+    /// In [`CrcMode::Strict`] mode, the default, reading fails with an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error wrapping [`Error::BadCrc`]. In
+    /// [`CrcMode::Permissive`] mode, the data of the block is output anyway, and the block is
+    /// added to [`Bz3Decoder::crc_failures`].
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.decoder.set_crc_mode(mode);
+    }
+
+    /// Returns the blocks which failed the CRC check so far in permissive mode, numbered as in
+    /// [`Error::BadCrc`].
+    pub fn crc_failures(&self) -> &[usize] {
+        self.decoder.crc_failures()
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the file header has been read, e.g. to check the
+    /// [CRC](Bz3State::last_crc) of the last block decoded, or the
+    /// [last error](Bz3State::last_error) of libbz3.
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.decoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the file header has been read, e.g. for the
+    /// [description](Bz3State::error) of the last error.
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.decoder.state_mut()
+    }
+
+    /// Sets whether the output of each block is verified after decoding, to catch silent memory
+    /// corruption.
     ///
-    /// # Errors:
+    /// The CRC stored in every block is checked by libbz3 while decoding; in paranoid mode, it's
+    /// computed once more over the decompressed data right before it's handed out, and reading
+    /// fails with an [`InvalidData`](io::ErrorKind::InvalidData) error wrapping
+    /// [`Error::VerifyFailed`] on a mismatch. This costs another pass over the data. Blocks
+    /// passed through in [`CrcMode::Permissive`] mode aren't verified.
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.decoder.set_paranoid(enabled);
+    }
+
+    /// Sets whether anything following the last block that isn't a block, like garbage
+    /// appended to the file, is ignored.
     ///
-    /// Types: [`Error::ProcessBlock`], [`io::Error`]
-    fn decompress_block(&mut self) -> Result<bool> {
-        // Handle the block head. If there's no data to read, it reaches EOF of the bzip3 stream.
-        let mut new_size_buf = [0_u8; 4];
-        let len = self.reader.try_read_exact(&mut new_size_buf)?;
-        let new_size = match len {
-            0 => {
-                // a normal EOF
-                return Ok(true);
-            }
-            4 => {
-                use byteorder::ByteOrder;
-                LE::read_i32(&new_size_buf)
-            }
-            _ => {
-                // unexpected EOF; corrupt stream
-                return Err(Error::Io(io::Error::new(
-                    ErrorKind::UnexpectedEof,
-                    "Corrupt file; insufficient block head info",
-                )));
-            }
-        };
-        let read_size = self.reader.read_i32::<LE>()? as usize;
+    /// Otherwise, the default, reading fails there with an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error wrapping [`Error::TrailingData`]. Note
+    /// that a damaged block header looks just like trailing data, so the blocks following it are
+    /// ignored too.
+    pub fn set_ignore_trailing_data(&mut self, enabled: bool) {
+        self.decoder.set_ignore_trailing_data(enabled);
+    }
 
-        debug_assert!(self.buffer.len() >= read_size);
+    /// Sets whether the input may hold multiple concatenated bzip3 files, decoded one after
+    /// another as a single stream, like the output of
+    /// [block size growth](crate::write::Bz3Encoder::set_block_size_growth).
+    ///
+    /// This is off by default, and then a file header following the first file fails with
+    /// [`Error::MisplacedFrameHeader`].
+    pub fn multiple_members(&mut self, enabled: bool) {
+        self.decoder.set_multiple_members(enabled);
+    }
 
-        let buffer = &mut self.buffer;
-        self.reader.read_exact(&mut buffer[..(new_size as usize)])?;
+    /// Reads the [metadata](crate::metadata) of the file, if it has any.
+    ///
+    /// This reads up to the first block, where the metadata would have been. The data of the
+    /// blocks is still to be read afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    /// use bzip3::metadata::Metadata;
+    ///
+    /// let metadata = Metadata {
+    ///     filename: Some("hello.txt".into()),
+    ///     ..Default::default()
+    /// };
+    /// let mut encoder =
+    ///     bzip3::write::Bz3Encoder::with_metadata(Vec::new(), 100 * 1024, &metadata).unwrap();
+    /// encoder.write_all(b"hello, world").unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    ///
+    /// let mut decoder = bzip3::read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    /// assert_eq!(decoder.read_metadata().unwrap(), Some(&metadata));
+    /// let mut contents = String::new();
+    /// decoder.read_to_string(&mut contents).unwrap();
+    /// assert_eq!(contents, "hello, world");
+    /// ```
+    pub fn read_metadata(&mut self) -> Result<Option<&Metadata>> {
+        while self.decoder.before_blocks() && self.decoder.metadata().is_none() {
+            if !self.fill()? {
+                self.decoder.finish()?;
+                break;
+            }
+        }
+        Ok(self.decoder.metadata())
+    }
 
-        self.state
-            .decode_block(buffer, new_size as usize, read_size)?;
+    /// Returns the [metadata](crate::metadata) read so far; see [`Bz3Decoder::read_metadata`].
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.decoder.metadata()
+    }
 
-        self.buffer_len = read_size;
-        Ok(false)
+    /// Reads the whole stream, and returns its decompressed data.
+    ///
+    /// Unlike [`Read::read_to_end`], this fails with the [`Error`] the decoder ran into, rather
+    /// than an [`io::Error`] wrapping it.
+    pub fn decode_to_vec(mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.read_to_end(&mut buf).map_err(Error::from_io_error)?;
+        Ok(buf)
     }
 
-    /// Decompresses the next block, but skips empty blocks.
+    /// Decodes the next block straight into `buf` if it's large enough, saving a copy.
     ///
-    /// Currently, `decompress_block` will be called (once and only once)
-    /// on each `read` call,
-    /// and if it meets an empty block, `self.buffer_len` will be zero.
-    /// Thus, the `Read::read` function will return zero which means
-    /// the stream reaches EOF, but actually it doesn't.
+    /// Returns the size of the block's data, or `None` if the block has to go through the
+    /// decoder.
+    fn read_block_into(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let Some(header) = self.decoder.pending_block() else {
+            return Ok(None);
+        };
+        let data_size = header.data_size();
+        let read_size = header.read_size as usize;
+        if buf.len() < data_size.max(bound(read_size)) {
+            return Ok(None);
+        }
+        let size = self.reader.try_read_exact(&mut buf[..data_size])?;
+        if size < data_size {
+            // let the decoder tell about the truncated block
+            self.decoder
+                .feed(&buf[..size])
+                .map_err(Error::into_io_error)?;
+            return Ok(Some(0));
+        }
+        self.decoder
+            .decode_block_into(header, buf)
+            .map_err(Error::into_io_error)?;
+        Ok(Some(read_size))
+    }
+
+    /// Reads more input into the decoder.
     ///
-    /// Returns EOF flag; true indicates EOF
-    fn decompress_next_nonempty_block(&mut self) -> Result<bool> {
-        // use loop to skip empty blocks
-        // one empty block has a `read_size` of zero
-        // Example stream:
-        // 00000000: 0800 0000 0000 0000 0100 0000 ffff ffff  ................
-        loop {
-            let eof = self.decompress_block()?;
-            if eof {
-                return Ok(true);
-            }
-            if self.buffer_len /* the `read_size` */ == 0 {
-                continue;
+    /// Returns false if `self.reader` reaches EOF.
+    fn fill(&mut self) -> Result<bool> {
+        let buffer = self.decoder.input_buffer();
+        let read_size = loop {
+            match self.reader.read(buffer) {
+                Ok(size) => break size,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
             }
+        };
+        if read_size == 0 {
             return Ok(false);
         }
+        self.decoder.advance(read_size)?;
+        Ok(true)
+    }
+}
+
+impl<R> Bz3Decoder<R>
+where
+    R: Read + Seek,
+{
+    /// Returns the size of the whole uncompressed data, e.g. to preallocate or to report
+    /// progress.
+    ///
+    /// See [`seek::uncompressed_len`](crate::seek::uncompressed_len); the file is expected to
+    /// start at position zero of the reader. The read position is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Cursor, Read};
+    ///
+    /// let compressed = bzip3::mem::compress(&vec![b'x'; 250 * 1024], 100 * 1024).unwrap();
+    /// let mut decoder = bzip3::read::Bz3Decoder::new(Cursor::new(compressed)).unwrap();
+    /// let mut decompressed = Vec::with_capacity(decoder.uncompressed_len().unwrap() as usize);
+    /// decoder.read_to_end(&mut decompressed).unwrap();
+    /// assert_eq!(decompressed.len(), 250 * 1024);
+    /// ```
+    pub fn uncompressed_len(&mut self) -> Result<u64> {
+        let position = self.reader.stream_position()?;
+        let len = crate::seek::uncompressed_len(&mut self.reader);
+        self.reader.seek(SeekFrom::Start(position))?;
+        len
     }
 }
 
@@ -274,42 +551,176 @@ where
     R: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.eof {
-            return Ok(0);
-        }
-        if self.buffer_pos == self.buffer_len {
-            self.buffer_pos = 0;
-            // re-fill the buffer
-            match self.decompress_next_nonempty_block() {
-                Ok(false) => {}
-                Ok(true) => {
-                    self.eof = true;
-                    return Ok(0);
-                }
-                Err(Error::ProcessBlock(msg)) => {
-                    return Err(io::Error::new(ErrorKind::Other, msg));
-                }
-                Err(Error::Io(e)) => {
-                    return Err(e);
+        loop {
+            // empty blocks produce no output; keep going until there's some, or EOF
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.len());
+                buf[..size].copy_from_slice(&output[..size]);
+                self.decoder.consume(size);
+                return Ok(size);
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            if let Some(size) = self.read_block_into(buf)? {
+                if size != 0 {
+                    return Ok(size);
                 }
-                Err(_) => {
-                    unreachable!();
+                continue;
+            }
+            if !self.fill().map_err(Error::into_io_error)? {
+                self.decoder.finish().map_err(Error::into_io_error)?;
+                self.eof = true;
+            }
+        }
+    }
+
+    /// Decodes the blocks straight into the spare capacity of `buf`, saving the copy through
+    /// the decoder's buffer.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        loop {
+            let output = self.decoder.output();
+            if !output.is_empty() {
+                buf.extend_from_slice(output);
+                let size = output.len();
+                self.decoder.consume(size);
+                continue;
+            }
+            if self.eof {
+                return Ok(buf.len() - start);
+            }
+            if let Some(header) = self.decoder.pending_block() {
+                let len = buf.len();
+                let spare = buf.capacity() - len;
+                let size = header.data_size().max(bound(header.read_size as usize));
+                // a block fitting only in the data size, e.g. in an exact reservation, goes
+                // through the decoder rather than growing `buf`
+                if spare >= size || spare < header.read_size as usize {
+                    buf.resize(len + size, 0);
+                    let result = self.read_block_into(&mut buf[len..]);
+                    buf.truncate(len + result.as_ref().map_or(0, |x| x.unwrap_or(0)));
+                    if result?.is_some() {
+                        continue;
+                    }
                 }
             }
+            if !self.fill().map_err(Error::into_io_error)? {
+                self.decoder.finish().map_err(Error::into_io_error)?;
+                self.eof = true;
+            }
         }
+    }
+}
 
-        assert!(self.buffer_pos < self.buffer_len);
-        // have data from buffer to read
-        let remaining_size = self.buffer_len - self.buffer_pos;
+/// The peeked bytes put back in front of the reader.
+type Peeked<R> = io::Chain<Cursor<Vec<u8>>, R>;
 
-        let mut required_length = buf.len();
-        if required_length > remaining_size {
-            required_length = remaining_size;
+enum MaybeInner<R>
+where
+    R: Read,
+{
+    Bz3(Box<Bz3Decoder<Peeked<R>>>),
+    Plain(Peeked<R>),
+}
+
+/// Reader decompressing bzip3 data, and passing anything else through unchanged.
+///
+/// The first bytes are peeked to tell bzip3 data, starting with a file header or a
+/// [skippable frame](crate::frame::SKIPPABLE_MAGIC), from other data. Data which looks like
+/// bzip3 but is broken fails as with [`Bz3Decoder`], rather than being passed through.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Read;
+/// use bzip3::read::MaybeBz3Decoder;
+///
+/// let compressed = bzip3::mem::compress(b"hello, world", 100 * 1024).unwrap();
+/// for input in [&compressed[..], b"hello, world"] {
+///     let mut contents = String::new();
+///     MaybeBz3Decoder::new(input)
+///         .unwrap()
+///         .read_to_string(&mut contents)
+///         .unwrap();
+///     assert_eq!(contents, "hello, world");
+/// }
+/// ```
+pub struct MaybeBz3Decoder<R>
+where
+    R: Read,
+{
+    inner: MaybeInner<R>,
+}
+
+impl<R> fmt::Debug for MaybeBz3Decoder<R>
+where
+    R: Read,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            MaybeInner::Bz3(decoder) => f.debug_tuple("MaybeBz3Decoder").field(decoder).finish(),
+            MaybeInner::Plain(_) => f
+                .debug_tuple("MaybeBz3Decoder")
+                .field(&"passthrough")
+                .finish(),
         }
+    }
+}
 
-        buf[..required_length]
-            .copy_from_slice(&self.buffer[self.buffer_pos..(self.buffer_pos + required_length)]);
-        self.buffer_pos += required_length;
-        Ok(required_length)
+impl<R> MaybeBz3Decoder<R>
+where
+    R: Read,
+{
+    /// Returns the number of blocks decoded so far; zero for data passed through.
+    pub fn block_count(&self) -> usize {
+        match &self.inner {
+            MaybeInner::Bz3(decoder) => decoder.block_count(),
+            MaybeInner::Plain(_) => 0,
+        }
+    }
+
+    /// Creates a decoder, reading the first bytes of `reader` to tell whether it's bzip3 data.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`Bz3Decoder::new`] for bzip3 data, and [`Error::Io`] on IO errors
+    /// otherwise.
+    pub fn new(reader: R) -> Result<Self> {
+        Self::with_max_block_size(reader, crate::BLOCK_SIZE_MAX)
+    }
+
+    /// Like [`MaybeBz3Decoder::new`], but rejecting bzip3 data with a block size above `limit`;
+    /// see [`Bz3Decoder::with_max_block_size`].
+    pub fn with_max_block_size(mut reader: R, limit: usize) -> Result<Self> {
+        let mut magic = vec![0_u8; MAGIC_PREFIX.len()];
+        let size = reader.try_read_exact(&mut magic)?;
+        magic.truncate(size);
+        let compressed = magic == MAGIC_PREFIX || magic == SKIPPABLE_MAGIC;
+        let reader = Cursor::new(magic).chain(reader);
+        let inner = if compressed {
+            MaybeInner::Bz3(Box::new(Bz3Decoder::with_max_block_size(reader, limit)?))
+        } else {
+            MaybeInner::Plain(reader)
+        };
+        Ok(Self { inner })
+    }
+
+    /// Whether the data is bzip3 data being decompressed.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.inner, MaybeInner::Bz3(_))
+    }
+}
+
+impl<R> Read for MaybeBz3Decoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            MaybeInner::Bz3(decoder) => decoder.read(buf),
+            MaybeInner::Plain(reader) => reader.read(buf),
+        }
     }
 }
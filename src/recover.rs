@@ -0,0 +1,413 @@
+//! Recovery of data from damaged bzip3 files.
+//!
+//! Blocks of a bzip3 file are independent of each other, so a damaged region only takes the
+//! blocks it overlaps with it. [`Bz3RecoveringDecoder`] skips such regions, by looking for the
+//! next position where a block header with plausible sizes is followed by data passing the CRC
+//! check of the block. [`salvage_blocks`] gives the blocks one by one instead, with their
+//! locations, for recovery tools.
+
+use std::io;
+use std::io::{ErrorKind, Read};
+use std::ops::Range;
+
+use crate::errors::*;
+use crate::frame::{BlockHeader, FrameHeader, BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE};
+use crate::{bound, Bz3State, MAGIC_NUMBER};
+
+/// Size of the reads from the underlying reader.
+const READ_SIZE: usize = 64 * 1024;
+
+/// A block found by [`Scanner::next_block`], whose data is in the scanner's buffer.
+struct ScannedBlock {
+    offset: u64,
+    size: usize,
+    crc_valid: bool,
+}
+
+/// Walks through the blocks of a possibly damaged file, resynchronizing after damaged regions.
+struct Scanner<R> {
+    reader: R,
+    state: Bz3State,
+    /// Input read but not processed yet is `window[pos..]`.
+    window: Vec<u8>,
+    pos: usize,
+    /// Offset of `window[pos]` in the file.
+    offset: u64,
+    eof: bool,
+    /// Decompressed data of the last block.
+    buffer: Vec<u8>,
+    /// Start of the damaged region being skipped.
+    damage_start: Option<u64>,
+    skipped: Vec<Range<u64>>,
+    /// Whether to take blocks failing the CRC check where a block is expected, i.e. right after
+    /// the file header or another block.
+    accept_bad_crc: bool,
+}
+
+impl<R> Scanner<R>
+where
+    R: Read,
+{
+    fn new(mut reader: R) -> Result<Self> {
+        let header = FrameHeader::read_from(&mut reader)?;
+        Ok(Self {
+            reader,
+            state: Bz3State::new(header.block_size)?,
+            window: Vec::new(),
+            pos: 0,
+            offset: FRAME_HEADER_SIZE as u64,
+            eof: false,
+            buffer: vec![0_u8; bound(header.block_size)],
+            damage_start: None,
+            skipped: Vec::new(),
+            accept_bad_crc: false,
+        })
+    }
+
+    /// Reads until `size` bytes of input are available, or the reader reaches EOF.
+    ///
+    /// Returns the size of the available input.
+    fn fill(&mut self, size: usize) -> io::Result<usize> {
+        if self.window.len() - self.pos < size {
+            self.window.drain(..self.pos);
+            self.pos = 0;
+        }
+        while self.window.len() < size && !self.eof {
+            let len = self.window.len();
+            self.window.resize(size.max(len + READ_SIZE), 0);
+            let result = self.reader.read(&mut self.window[len..]);
+            match result {
+                Ok(read_size) => {
+                    self.window.truncate(len + read_size);
+                    self.eof = read_size == 0;
+                }
+                Err(e) => {
+                    self.window.truncate(len);
+                    if e.kind() != ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(self.window.len() - self.pos)
+    }
+
+    fn advance(&mut self, size: usize) {
+        self.pos += size;
+        self.offset += size as u64;
+    }
+
+    /// Whether a block header could be one from the encoder.
+    fn is_plausible(&self, header: &BlockHeader) -> bool {
+        header.validate(self.state.block_size).is_ok()
+            && (header.is_stored() || header.new_size as usize <= bound(header.read_size as usize))
+    }
+
+    /// Skips a byte, as part of a damaged region.
+    fn skip_damaged(&mut self) {
+        self.damage_start.get_or_insert(self.offset);
+        self.advance(1);
+    }
+
+    /// Closes the damaged region being skipped, if any.
+    fn end_damage(&mut self) {
+        if let Some(start) = self.damage_start.take() {
+            self.skipped.push(start..self.offset);
+        }
+    }
+
+    /// Decodes the next intact block, skipping damaged regions and extension blocks.
+    ///
+    /// Returns `None` at the end of the file.
+    fn next_block(&mut self) -> io::Result<Option<ScannedBlock>> {
+        loop {
+            let available = self.fill(BLOCK_HEADER_SIZE)?;
+            if available == 0 {
+                self.end_damage();
+                return Ok(None);
+            }
+            if available < BLOCK_HEADER_SIZE {
+                self.skip_damaged();
+                continue;
+            }
+
+            let window = &self.window[self.pos..];
+            if window.starts_with(MAGIC_NUMBER)
+                && self.fill(FRAME_HEADER_SIZE)? >= FRAME_HEADER_SIZE
+            {
+                let window = &self.window[self.pos..];
+                if let Ok(header) =
+                    FrameHeader::parse(window[..FRAME_HEADER_SIZE].try_into().unwrap())
+                {
+                    // another file starts here; go on with its blocks
+                    self.end_damage();
+                    if header.block_size != self.state.block_size {
+                        self.state =
+                            Bz3State::new(header.block_size).map_err(Error::into_io_error)?;
+                        self.buffer = vec![0_u8; bound(header.block_size)];
+                    }
+                    self.advance(FRAME_HEADER_SIZE);
+                    continue;
+                }
+            }
+
+            let window = &self.window[self.pos..];
+            let header = BlockHeader::parse(window[..BLOCK_HEADER_SIZE].try_into().unwrap());
+            if let Some(size) = header.extension_size() {
+                // larger extensions are more likely to be garbage than anything
+                let size = BLOCK_HEADER_SIZE + size;
+                if size <= BLOCK_HEADER_SIZE + self.buffer.len() && self.fill(size)? >= size {
+                    self.end_damage();
+                    self.advance(size);
+                    continue;
+                }
+            } else if self.is_plausible(&header) {
+                let new_size = header.data_size();
+                let read_size = header.read_size as usize;
+                let size = BLOCK_HEADER_SIZE + new_size;
+                if self.fill(size)? >= size {
+                    let data = &self.window[(self.pos + BLOCK_HEADER_SIZE)..(self.pos + size)];
+                    self.buffer[..new_size].copy_from_slice(data);
+                    let crc_valid = if header.is_stored() {
+                        // stored data has no CRC telling it from garbage, so it's taken only
+                        // where a block is expected
+                        self.damage_start.is_none().then_some(true)
+                    } else {
+                        match self
+                            .state
+                            .decode_block(&mut self.buffer, new_size, read_size)
+                        {
+                            Ok(()) => Some(true),
+                            Err(_) if self.state.crc_failed(-1) => {
+                                let expected = self.damage_start.is_none();
+                                (self.accept_bad_crc && expected).then_some(false)
+                            }
+                            Err(_) => None,
+                        }
+                    };
+                    if let Some(crc_valid) = crc_valid {
+                        self.end_damage();
+                        let block = ScannedBlock {
+                            offset: self.offset,
+                            size: read_size,
+                            crc_valid,
+                        };
+                        self.advance(size);
+                        return Ok(Some(block));
+                    }
+                }
+            }
+            self.skip_damaged();
+        }
+    }
+}
+
+/// Read-based bzip3 decoder which skips damaged regions of the input instead of failing.
+///
+/// When a block can't be decoded, its header is taken as damaged too, and the input is scanned
+/// forward byte by byte for the next intact block, like `bzip2recover` does for bzip2 files.
+/// The skipped regions are available from [`Bz3RecoveringDecoder::skipped`]; the data of the
+/// blocks they held is lost.
+///
+/// Extension blocks larger than a compressed block can be are taken as damage, as garbage may
+/// look like their header. Only the file header must be intact, for the block size. Another
+/// file header found on the way, as in files spliced together, switches to its block size.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use bzip3::recover::Bz3RecoveringDecoder;
+///
+/// let data = vec![b'x'; 250 * 1024];
+/// let mut compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+/// // damage the second block
+/// let index = bzip3::seek::scan_index(Cursor::new(&compressed)).unwrap();
+/// let offset = index.entries()[1].compressed_offset as usize;
+/// compressed[(offset + 8)..(offset + 12)].fill(0xff);
+///
+/// let mut decoder = Bz3RecoveringDecoder::new(compressed.as_slice()).unwrap();
+/// let mut recovered = Vec::new();
+/// decoder.read_to_end(&mut recovered).unwrap();
+/// assert_eq!(recovered.len(), 150 * 1024);
+/// assert_eq!(decoder.skipped().len(), 1);
+/// ```
+pub struct Bz3RecoveringDecoder<R>
+where
+    R: Read,
+{
+    scanner: Scanner<R>,
+    output_pos: usize,
+    output_len: usize,
+}
+
+impl<R> Bz3RecoveringDecoder<R>
+where
+    R: Read,
+{
+    /// Creates a recovering decoder, reading the file header.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidSignature`] and [`Error::BlockSize`] if the file header is damaged, and
+    /// [`Error::Io`] on IO errors.
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            scanner: Scanner::new(reader)?,
+            output_pos: 0,
+            output_len: 0,
+        })
+    }
+
+    /// Returns the bzip3 block size from the file header, or the last one found in the input.
+    pub fn block_size(&self) -> usize {
+        self.scanner.state.block_size
+    }
+
+    /// Returns the regions of the input skipped so far, as offsets from the start of the file.
+    pub fn skipped(&self) -> &[Range<u64>] {
+        &self.scanner.skipped
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.scanner.reader
+    }
+}
+
+impl<R> Read for Bz3RecoveringDecoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output_len {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            match self.scanner.next_block()? {
+                Some(block) => {
+                    self.output_pos = 0;
+                    self.output_len = block.size;
+                }
+                None => return Ok(0),
+            }
+        }
+        let output = &self.scanner.buffer[self.output_pos..self.output_len];
+        let size = output.len().min(buf.len());
+        buf[..size].copy_from_slice(&output[..size]);
+        self.output_pos += size;
+        Ok(size)
+    }
+}
+
+/// A block extracted by [`salvage_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SalvagedBlock {
+    /// Offset of the block header from the start of the file.
+    pub compressed_offset: u64,
+    /// Offset of the block's data in the uncompressed data of the intact file, if known.
+    ///
+    /// This is only known as long as no damaged region precedes the block.
+    pub uncompressed_offset: Option<u64>,
+    /// The decompressed data.
+    pub data: Vec<u8>,
+    /// Whether the data passed the CRC check.
+    ///
+    /// A block failing it is only given if it's where a block is expected, right after the file
+    /// header or another block, so its header can be trusted; its data is likely damaged though.
+    pub crc_valid: bool,
+}
+
+/// Extracts every decodable block from a possibly damaged bzip3 file.
+///
+/// Damaged regions are skipped as with [`Bz3RecoveringDecoder`]; the iterator yields the blocks
+/// around them, in the order of the file. An IO error ends the iteration.
+///
+/// # Errors
+///
+/// [`Error::InvalidSignature`] and [`Error::BlockSize`] if the file header is damaged, and
+/// [`Error::Io`] on IO errors.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let data = vec![b'x'; 250 * 1024];
+/// let mut compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+/// // damage the CRC of the second block
+/// let index = bzip3::seek::scan_index(Cursor::new(&compressed)).unwrap();
+/// let offset = index.entries()[1].compressed_offset as usize;
+/// compressed[(offset + 8)..(offset + 12)].fill(0xff);
+///
+/// let blocks = bzip3::recover::salvage_blocks(compressed.as_slice())
+///     .unwrap()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(blocks.len(), 3);
+/// assert_eq!(blocks[1].compressed_offset, offset as u64);
+/// assert!(blocks[0].crc_valid && !blocks[1].crc_valid && blocks[2].crc_valid);
+/// assert_eq!(blocks[2].uncompressed_offset, Some(200 * 1024));
+/// ```
+pub fn salvage_blocks<R: Read>(reader: R) -> Result<SalvageBlocks<R>> {
+    let mut scanner = Scanner::new(reader)?;
+    scanner.accept_bad_crc = true;
+    Ok(SalvageBlocks {
+        scanner,
+        uncompressed_offset: Some(0),
+        done: false,
+    })
+}
+
+/// Iterator returned by [`salvage_blocks`].
+pub struct SalvageBlocks<R> {
+    scanner: Scanner<R>,
+    /// Uncompressed offset of the next block, while there's been no damage.
+    uncompressed_offset: Option<u64>,
+    done: bool,
+}
+
+impl<R> SalvageBlocks<R> {
+    /// Returns the regions of the input skipped so far, as offsets from the start of the file.
+    pub fn skipped(&self) -> &[Range<u64>] {
+        &self.scanner.skipped
+    }
+}
+
+impl<R> Iterator for SalvageBlocks<R>
+where
+    R: Read,
+{
+    type Item = Result<SalvagedBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let damaged = self.scanner.skipped.len();
+        let block = match self.scanner.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e.into()));
+            }
+        };
+        if self.scanner.skipped.len() != damaged {
+            self.uncompressed_offset = None;
+        }
+        let uncompressed_offset = self.uncompressed_offset;
+        if let Some(offset) = &mut self.uncompressed_offset {
+            *offset += block.size as u64;
+        }
+        Some(Ok(SalvagedBlock {
+            compressed_offset: block.offset,
+            uncompressed_offset,
+            data: self.scanner.buffer[..block.size].to_vec(),
+            crc_valid: block.crc_valid,
+        }))
+    }
+}
@@ -0,0 +1,1137 @@
+//! Seekable bzip3 files.
+//!
+//! [`Bz3SeekableDecoder`] gives random access to the uncompressed data of any bzip3 file, and
+//! files written by [`Bz3IndexedEncoder`] can be opened without scanning them with
+//! [`scan_index`]. [`RemoteBz3Reader`] does the same over a [`ReadAt`] source, such as an
+//! object in remote storage, fetching only the blocks being read.
+//!
+//! A seekable file is a regular bzip3 file followed by a seek index: an
+//! [extension block](crate::frame::EXTENSION_BLOCK) tagged `SEEK`, mapping every block to its
+//! compressed and uncompressed offsets. Decoders of this crate skip it, so such a file
+//! decompresses just like a plain one. Other bzip3 implementations don't know about extension
+//! blocks though, and may reject the file.
+//!
+//! # Index layout
+//!
+//! All integers are little-endian, and offsets are relative to the start of the file header.
+//!
+//! \[ [`EXTENSION_BLOCK`] (i32) | extension data size (i32) | `SEEK` | entry1 | entry2 |
+//! entryN... | uncompressed size (u64) | compressed size (u64) | entry count (u32) | `SEEK` \]
+//!
+//! Each entry is \[ compressed offset (u64) | uncompressed offset (u64) \], and the compressed
+//! size is where the index begins. The trailing entry count and tag let a reader find the index
+//! from the end of the file.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LE};
+
+use crate::errors::*;
+use crate::frame::{
+    BlockHeader, FrameHeader, BLOCK_HEADER_SIZE, EXTENSION_BLOCK, EXTENSION_TAG_SIZE,
+    FRAME_HEADER_SIZE,
+};
+use crate::{bound, push, Bz3State, TryWriteAll, BLOCK_SIZE_MAX};
+
+mod remote;
+
+#[cfg(feature = "http")]
+pub use remote::HttpSource;
+pub use remote::{ReadAt, RemoteBz3Reader};
+
+/// Extension tag of the seek index.
+pub const INDEX_TAG: &[u8; EXTENSION_TAG_SIZE] = b"SEEK";
+
+/// Signature of an external index file, as written by [`Bz3Index::save`].
+pub const INDEX_FILE_MAGIC: &[u8; 4] = b"BZ3i";
+
+/// Version of the index format, shared by external index files and the serde representation.
+pub const INDEX_FILE_VERSION: u8 = 1;
+
+const ENTRY_SIZE: usize = 2 * 8 /* u64 */;
+/// Size of the extension data besides the entries.
+const INDEX_OVERHEAD: usize = 2 * EXTENSION_TAG_SIZE + 2 * 8 /* u64 */ + 4 /* u32 */;
+
+/// Location of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexEntry {
+    /// Offset of the block header.
+    pub compressed_offset: u64,
+    /// Offset of the block data in the uncompressed stream.
+    pub uncompressed_offset: u64,
+}
+
+/// Seek index of a bzip3 file.
+///
+/// An index is always consistent: the offsets are increasing, and within the sizes. It can be
+/// embedded in the file ([`Bz3Index::read_trailer`]), kept in an external file
+/// ([`Bz3Index::save`]), or with the `serde` feature, serialized into any format serde supports.
+/// The serialized form carries the [format version](INDEX_FILE_VERSION), which is checked on
+/// deserialization along with the consistency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "serde_repr::IndexRepr", try_from = "serde_repr::IndexRepr")
+)]
+pub struct Bz3Index {
+    entries: Vec<IndexEntry>,
+    uncompressed_size: u64,
+    compressed_size: u64,
+}
+
+impl Bz3Index {
+    fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+            uncompressed_size: 0,
+            compressed_size: FRAME_HEADER_SIZE as u64,
+        }
+    }
+
+    /// Creates an index from its parts.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::InvalidArgument`] if the parts are inconsistent.
+    pub fn from_parts(
+        entries: Vec<IndexEntry>,
+        uncompressed_size: u64,
+        compressed_size: u64,
+    ) -> Result<Self> {
+        let index = Self {
+            entries,
+            uncompressed_size,
+            compressed_size,
+        };
+        if !index.is_consistent() {
+            return Err(Error::InvalidArgument("Inconsistent seek index".into()));
+        }
+        Ok(index)
+    }
+
+    /// Returns the version of the index format.
+    pub fn version(&self) -> u8 {
+        INDEX_FILE_VERSION
+    }
+
+    /// Returns the number of blocks.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries of all the blocks, in order.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Size of the whole uncompressed stream.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Size of the file header and all the blocks, the index not included.
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// Returns the number of the block holding the given uncompressed offset.
+    pub fn block_containing(&self, uncompressed_offset: u64) -> Option<usize> {
+        if uncompressed_offset >= self.uncompressed_size {
+            return None;
+        }
+        // the last block starting at or before the offset; empty blocks are skipped over
+        let count = self
+            .entries
+            .partition_point(|x| x.uncompressed_offset <= uncompressed_offset);
+        Some(count - 1)
+    }
+
+    /// Returns the uncompressed size of a block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block number is out of range.
+    pub fn block_uncompressed_size(&self, block: usize) -> u64 {
+        let end = match self.entries.get(block + 1) {
+            Some(next) => next.uncompressed_offset,
+            None => self.uncompressed_size,
+        };
+        end - self.entries[block].uncompressed_offset
+    }
+
+    /// Serializes the index into an extension block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data_size = INDEX_OVERHEAD + ENTRY_SIZE * self.entries.len();
+        let mut bytes = vec![0_u8; BLOCK_HEADER_SIZE + data_size];
+        bytes[..BLOCK_HEADER_SIZE].copy_from_slice(&BlockHeader::extension(data_size).to_bytes());
+
+        let data = &mut bytes[BLOCK_HEADER_SIZE..];
+        data[..EXTENSION_TAG_SIZE].copy_from_slice(INDEX_TAG);
+        let mut pos = EXTENSION_TAG_SIZE;
+        for entry in &self.entries {
+            LE::write_u64(&mut data[pos..], entry.compressed_offset);
+            LE::write_u64(&mut data[(pos + 8)..], entry.uncompressed_offset);
+            pos += ENTRY_SIZE;
+        }
+        LE::write_u64(&mut data[pos..], self.uncompressed_size);
+        LE::write_u64(&mut data[(pos + 8)..], self.compressed_size);
+        LE::write_u32(&mut data[(pos + 16)..], self.entries.len() as u32);
+        data[(pos + 20)..].copy_from_slice(INDEX_TAG);
+        bytes
+    }
+
+    /// Writes the index as an external index file (conventionally `*.bz3i`), to be kept
+    /// alongside an archive that can't get an embedded index.
+    ///
+    /// # Format
+    ///
+    /// All integers are little-endian.
+    ///
+    /// \[ [`INDEX_FILE_MAGIC`] | version (u8) | entry count (u64) | uncompressed size (u64) |
+    /// compressed size (u64) | entry1 | entry2 | entryN... \]
+    ///
+    /// Entries are the same as in the [embedded index](self).
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(INDEX_FILE_MAGIC)?;
+        writer.write_u8(INDEX_FILE_VERSION)?;
+        writer.write_u64::<LE>(self.entries.len() as u64)?;
+        writer.write_u64::<LE>(self.uncompressed_size)?;
+        writer.write_u64::<LE>(self.compressed_size)?;
+        for entry in &self.entries {
+            writer.write_u64::<LE>(entry.compressed_offset)?;
+            writer.write_u64::<LE>(entry.uncompressed_offset)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an external index file written by [`Bz3Index::save`].
+    ///
+    /// # Errors
+    ///
+    /// Besides IO errors, this returns [`Error::InvalidSignature`] if it's not an index file,
+    /// [`Error::UnsupportedVersion`] if the version is unsupported, and [`Error::ProcessBlock`] if
+    /// the index is corrupt.
+    pub fn load<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0_u8; INDEX_FILE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_FILE_MAGIC {
+            return Err(Error::InvalidSignature);
+        }
+        let version = reader.read_u8()?;
+        if version != INDEX_FILE_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let count = reader.read_u64::<LE>()?;
+        let uncompressed_size = reader.read_u64::<LE>()?;
+        let compressed_size = reader.read_u64::<LE>()?;
+        // don't trust the count for allocation
+        let mut entries = Vec::with_capacity(count.min(1 << 16) as usize);
+        for _ in 0..count {
+            entries.push(IndexEntry {
+                compressed_offset: reader.read_u64::<LE>()?,
+                uncompressed_offset: reader.read_u64::<LE>()?,
+            });
+        }
+
+        let index = Self {
+            entries,
+            uncompressed_size,
+            compressed_size,
+        };
+        if !index.is_consistent() {
+            return Err(Error::ProcessBlock(
+                "Corrupt index file; invalid seek index".into(),
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Reads the index at the end of a seekable file.
+    ///
+    /// The file is expected to start at position zero of `reader`. Returns `None` if there's no
+    /// index, as with files produced by other encoders.
+    ///
+    /// # Errors
+    ///
+    /// Besides IO errors, this returns [`Error::ProcessBlock`] if the index is there but
+    /// inconsistent.
+    pub fn read_trailer<R: Read + Seek>(mut reader: R) -> Result<Option<Self>> {
+        const FOOTER_SIZE: usize = 4 /* u32 */ + EXTENSION_TAG_SIZE;
+
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        if file_size < (FRAME_HEADER_SIZE + BLOCK_HEADER_SIZE + INDEX_OVERHEAD) as u64 {
+            return Ok(None);
+        }
+        let mut footer = [0_u8; FOOTER_SIZE];
+        reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        reader.read_exact(&mut footer)?;
+        if &footer[4..] != INDEX_TAG {
+            return Ok(None);
+        }
+
+        let count = LE::read_u32(&footer) as u64;
+        let data_size = INDEX_OVERHEAD as u64 + ENTRY_SIZE as u64 * count;
+        let index_size = BLOCK_HEADER_SIZE as u64 + data_size;
+        if index_size > file_size - FRAME_HEADER_SIZE as u64 {
+            return Ok(None);
+        }
+        let index_offset = file_size - index_size;
+        let mut bytes = vec![0_u8; index_size as usize];
+        reader.seek(SeekFrom::Start(index_offset))?;
+        reader.read_exact(&mut bytes)?;
+
+        let header = BlockHeader::parse(bytes[..BLOCK_HEADER_SIZE].try_into().unwrap());
+        let data = &bytes[BLOCK_HEADER_SIZE..];
+        if header.new_size != EXTENSION_BLOCK
+            || header.read_size as u64 != data_size
+            || &data[..EXTENSION_TAG_SIZE] != INDEX_TAG
+        {
+            return Ok(None);
+        }
+
+        let entries = data[EXTENSION_TAG_SIZE..]
+            .chunks_exact(ENTRY_SIZE)
+            .take(count as usize)
+            .map(|x| IndexEntry {
+                compressed_offset: LE::read_u64(x),
+                uncompressed_offset: LE::read_u64(&x[8..]),
+            })
+            .collect::<Vec<_>>();
+        let pos = EXTENSION_TAG_SIZE + ENTRY_SIZE * count as usize;
+        let index = Self {
+            entries,
+            uncompressed_size: LE::read_u64(&data[pos..]),
+            compressed_size: LE::read_u64(&data[(pos + 8)..]),
+        };
+        if index.compressed_size != index_offset || !index.is_consistent() {
+            return Err(Error::ProcessBlock(
+                "Corrupt file; invalid seek index".into(),
+            ));
+        }
+        Ok(Some(index))
+    }
+
+    /// Checks that the offsets are increasing and within the sizes, and that no block holds more
+    /// than [`BLOCK_SIZE_MAX`] bytes, so the uncompressed size is bounded by the number of
+    /// entries rather than taken on trust.
+    fn is_consistent(&self) -> bool {
+        match self.entries.first() {
+            Some(first) if first.uncompressed_offset != 0 => return false,
+            None if self.uncompressed_size != 0 => return false,
+            _ => {}
+        }
+        let mut compressed_offset = FRAME_HEADER_SIZE as u64;
+        let mut uncompressed_offset = 0;
+        for entry in &self.entries {
+            if entry.compressed_offset < compressed_offset
+                || entry.uncompressed_offset < uncompressed_offset
+                || entry.uncompressed_offset - uncompressed_offset > BLOCK_SIZE_MAX as u64
+            {
+                return false;
+            }
+            compressed_offset = entry.compressed_offset + BLOCK_HEADER_SIZE as u64;
+            uncompressed_offset = entry.uncompressed_offset;
+        }
+        compressed_offset <= self.compressed_size
+            && uncompressed_offset <= self.uncompressed_size
+            && self.uncompressed_size - uncompressed_offset <= BLOCK_SIZE_MAX as u64
+    }
+}
+
+/// Callback receiving the block index, compressed offset and uncompressed offset of each block
+/// written by an encoder.
+type BlockCallback = Box<dyn FnMut(usize, u64, u64) + Send + Sync>;
+
+/// Keeps track of the location of the blocks written by an encoder, and reports them to an
+/// optional callback.
+///
+/// This is how the plain encoders let an index be built out-of-band, without a second pass.
+pub(crate) struct BlockTracker {
+    callback: Option<BlockCallback>,
+    blocks: usize,
+    compressed_offset: u64,
+    uncompressed_offset: u64,
+}
+
+impl BlockTracker {
+    /// Creates a tracker for a file whose header has been written.
+    pub(crate) fn new() -> Self {
+        Self {
+            callback: None,
+            blocks: 0,
+            compressed_offset: FRAME_HEADER_SIZE as u64,
+            uncompressed_offset: 0,
+        }
+    }
+
+    /// Number of blocks recorded so far.
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    pub(crate) fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub(crate) fn set_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(usize, u64, u64) + Send + Sync + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Starts over for another file whose header has been written, keeping the callback.
+    pub(crate) fn reset(&mut self) {
+        self.blocks = 0;
+        self.compressed_offset = FRAME_HEADER_SIZE as u64;
+        self.uncompressed_offset = 0;
+    }
+
+    /// Size of the uncompressed data of the blocks recorded so far.
+    pub(crate) fn uncompressed_offset(&self) -> u64 {
+        self.uncompressed_offset
+    }
+
+    /// Records the file header of another frame, which only counts for the offsets.
+    pub(crate) fn record_frame_header(&mut self) {
+        self.compressed_offset += FRAME_HEADER_SIZE as u64;
+    }
+
+    /// Records a block, header included, that has just been written.
+    ///
+    /// Extension blocks only count for the offsets.
+    pub(crate) fn record(&mut self, block: &[u8]) {
+        let header = BlockHeader::parse(block[..BLOCK_HEADER_SIZE].try_into().unwrap());
+        if header.is_extension() {
+            self.compressed_offset += block.len() as u64;
+            return;
+        }
+        if let Some(callback) = &mut self.callback {
+            callback(
+                self.blocks,
+                self.compressed_offset,
+                self.uncompressed_offset,
+            );
+        }
+        self.blocks += 1;
+        self.compressed_offset += block.len() as u64;
+        self.uncompressed_offset += header.read_size as u64;
+    }
+}
+
+/// Builds the index of a bzip3 file by walking through its block headers.
+///
+/// This works for files from any bzip3 encoder. Only the block headers are read; the compressed
+/// data is hopped over by seeking, so it's cheap even for large files. Extension blocks, such as
+/// an existing seek index, are skipped.
+///
+/// The file is expected to start at position zero of `reader`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let compressed = bzip3::mem::compress(&vec![0_u8; 250 * 1024], 100 * 1024).unwrap();
+/// let index = bzip3::seek::scan_index(Cursor::new(compressed)).unwrap();
+/// assert_eq!(index.entries().len(), 3);
+/// assert_eq!(index.uncompressed_size(), 250 * 1024);
+/// ```
+pub fn scan_index<R: Read + Seek>(mut reader: R) -> Result<Bz3Index> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let frame_header = FrameHeader::read_from(&mut reader)?;
+
+    let mut index = Bz3Index::empty();
+    let mut offset = FRAME_HEADER_SIZE as u64;
+    while offset < file_size {
+        let header = match BlockHeader::read_from(&mut reader) {
+            Ok(header) => header,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Corrupt file; insufficient block head info",
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let data_size = match header.extension_size() {
+            Some(size) => size,
+            None => {
+                header.validate(frame_header.block_size)?;
+                index.entries.push(IndexEntry {
+                    compressed_offset: offset,
+                    uncompressed_offset: index.uncompressed_size,
+                });
+                index.uncompressed_size += header.read_size as u64;
+                header.data_size()
+            }
+        };
+
+        offset += (BLOCK_HEADER_SIZE + data_size) as u64;
+        if offset > file_size {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        if !header.is_extension() {
+            index.compressed_size = offset;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(index)
+}
+
+/// Returns the size of the uncompressed data of a bzip3 file, without decompressing it.
+///
+/// This takes the size from the seek index of the file if it has one, and falls back to
+/// [`scan_index`] otherwise. The file is expected to start at position zero of `reader`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let compressed = bzip3::mem::compress(&vec![0_u8; 250 * 1024], 100 * 1024).unwrap();
+/// let len = bzip3::seek::uncompressed_len(Cursor::new(compressed)).unwrap();
+/// assert_eq!(len, 250 * 1024);
+/// ```
+pub fn uncompressed_len<R: Read + Seek>(mut reader: R) -> Result<u64> {
+    Ok(find_index(&mut reader)?.uncompressed_size)
+}
+
+/// Reads the seek index of a file, or builds it if there's none.
+pub(crate) fn find_index<R: Read + Seek>(reader: &mut R) -> Result<Bz3Index> {
+    match Bz3Index::read_trailer(&mut *reader)? {
+        Some(index) => Ok(index),
+        None => scan_index(reader),
+    }
+}
+
+/// Decompresses a range of the uncompressed data, decoding only the blocks it overlaps.
+///
+/// The range is clipped to the size of the uncompressed data. The file is expected to start at
+/// position zero of `source`. See [`range_reader`] for a streaming variant.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let data = (0..250 * 1024).map(|x| x as u8).collect::<Vec<_>>();
+/// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+/// let index = bzip3::seek::scan_index(Cursor::new(&compressed)).unwrap();
+///
+/// let range = bzip3::seek::read_range(Cursor::new(&compressed), &index, 1000..150_000).unwrap();
+/// assert_eq!(range, &data[1000..150_000]);
+/// ```
+pub fn read_range<R: Read + Seek>(
+    mut source: R,
+    index: &Bz3Index,
+    range: Range<u64>,
+) -> Result<Vec<u8>> {
+    let end = range.end.min(index.uncompressed_size);
+    let Some(first_block) = index
+        .block_containing(range.start)
+        .filter(|_| range.start < end)
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut blocks = BlockDecoder::new(&mut source)?;
+    // grown as the blocks are decoded, so a corrupt index can't make it reserve more than
+    // the data actually holds
+    let mut output = Vec::new();
+    for block in first_block..index.entries.len() {
+        let block_offset = index.entries[block].uncompressed_offset;
+        if block_offset >= end {
+            break;
+        }
+        let data = blocks.load(&mut source, index, block)?;
+        let from = range.start.saturating_sub(block_offset) as usize;
+        let to = ((end - block_offset) as usize).min(data.len());
+        output.extend_from_slice(&data[from..to]);
+    }
+    Ok(output)
+}
+
+/// Decompresses several ranges of the uncompressed data, decoding the blocks they overlap
+/// concurrently with rayon.
+///
+/// `open` is called by each worker to get its own reader of the file, which is expected to start
+/// at position zero, e.g. by opening the file again. Every overlapped block is decoded only once,
+/// even if several ranges overlap it, and all of them are held in memory until the ranges are
+/// assembled. Ranges are clipped to the size of the uncompressed data, as in [`read_range`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let data = (0..350 * 1024).map(|x| x as u8).collect::<Vec<_>>();
+/// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+/// let index = bzip3::seek::scan_index(Cursor::new(&compressed)).unwrap();
+///
+/// let ranges = [1000..2000, 150_000..320_000];
+/// let open = || Ok(Cursor::new(compressed.as_slice()));
+/// let output = bzip3::seek::read_ranges_parallel(open, &index, &ranges).unwrap();
+/// assert_eq!(output[0], &data[1000..2000]);
+/// assert_eq!(output[1], &data[150_000..320_000]);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn read_ranges_parallel<F, R>(
+    open: F,
+    index: &Bz3Index,
+    ranges: &[Range<u64>],
+) -> Result<Vec<Vec<u8>>>
+where
+    F: Fn() -> io::Result<R> + Sync,
+    R: Read + Seek,
+{
+    use rayon::prelude::*;
+
+    let clip = |range: &Range<u64>| range.start..range.end.min(index.uncompressed_size);
+    let mut blocks = Vec::new();
+    for range in ranges.iter().map(clip).filter(|x| !x.is_empty()) {
+        let first = index.block_containing(range.start).unwrap();
+        let last = index.block_containing(range.end - 1).unwrap();
+        blocks.extend(first..=last);
+    }
+    blocks.sort_unstable();
+    blocks.dedup();
+
+    let decoded = blocks
+        .par_iter()
+        .map_init(
+            || None,
+            |worker: &mut Option<(R, BlockDecoder)>, &block| {
+                if worker.is_none() {
+                    let mut reader = open()?;
+                    let decoder = BlockDecoder::new(&mut reader)?;
+                    *worker = Some((reader, decoder));
+                }
+                let (reader, decoder) = worker.as_mut().unwrap();
+                Ok(decoder.load(reader, index, block)?.to_vec())
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+
+    let output = ranges
+        .iter()
+        .map(clip)
+        .map(|range| {
+            let mut output = Vec::with_capacity(range.end.saturating_sub(range.start) as usize);
+            if range.is_empty() {
+                return output;
+            }
+            let first = blocks
+                .binary_search(&index.block_containing(range.start).unwrap())
+                .unwrap();
+            for (&block, data) in blocks[first..].iter().zip(&decoded[first..]) {
+                let block_offset = index.entries[block].uncompressed_offset;
+                if block_offset >= range.end {
+                    break;
+                }
+                let from = range.start.saturating_sub(block_offset) as usize;
+                let to = ((range.end - block_offset) as usize).min(data.len());
+                output.extend_from_slice(&data[from..to]);
+            }
+            output
+        })
+        .collect();
+    Ok(output)
+}
+
+/// Returns a reader over a range of the uncompressed data, decoding blocks as they're reached.
+///
+/// This is the streaming variant of [`read_range`], for ranges too large to hold in memory.
+pub fn range_reader<R: Read + Seek>(
+    source: R,
+    index: Bz3Index,
+    range: Range<u64>,
+) -> Result<io::Take<Bz3SeekableDecoder<R>>> {
+    let mut decoder = Bz3SeekableDecoder::with_index(source, index)?;
+    decoder.seek(SeekFrom::Start(range.start))?;
+    Ok(decoder.take(range.end.saturating_sub(range.start)))
+}
+
+/// Encoder writing a seekable bzip3 file.
+///
+/// The output is a regular bzip3 stream followed by a [seek index](self), which is written by
+/// [`Bz3IndexedEncoder::finish`], or on drop.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Write};
+/// use bzip3::seek::{Bz3Index, Bz3IndexedEncoder};
+///
+/// let mut encoder = Bz3IndexedEncoder::new(Vec::new(), 100 * 1024).unwrap();
+/// encoder.write_all(&vec![b'x'; 250 * 1024]).unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let index = Bz3Index::read_trailer(Cursor::new(&compressed)).unwrap().unwrap();
+/// assert_eq!(index.entries().len(), 3);
+/// assert_eq!(index.entries()[1].uncompressed_offset, 100 * 1024);
+/// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), vec![b'x'; 250 * 1024]);
+/// ```
+pub struct Bz3IndexedEncoder<W>
+where
+    W: Write,
+{
+    /// `None` once finished.
+    writer: Option<W>,
+    encoder: push::Encoder,
+    index: Bz3Index,
+    /// Whether the pending output has been added to the index.
+    output_indexed: bool,
+}
+
+impl<W> fmt::Debug for Bz3IndexedEncoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3IndexedEncoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3IndexedEncoder<W>
+where
+    W: Write,
+{
+    /// Creates a seekable bzip3 encoder.
+    ///
+    /// Valid block size is between [`BLOCK_SIZE_MIN`](crate::BLOCK_SIZE_MIN) and
+    /// [`BLOCK_SIZE_MAX`](crate::BLOCK_SIZE_MAX) bytes.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(mut writer: W, block_size: usize) -> Result<Self> {
+        FrameHeader::new(block_size)?.write_to(&mut writer)?;
+        let mut encoder = push::Encoder::new(block_size)?;
+        // the file header has been written above
+        encoder.consume(FRAME_HEADER_SIZE);
+        Ok(Self {
+            writer: Some(writer),
+            encoder,
+            index: Bz3Index::empty(),
+            output_indexed: false,
+        })
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// The index of the blocks written so far.
+    pub fn index(&self) -> &Bz3Index {
+        &self.index
+    }
+
+    /// Compresses the partial block, writes the index, and returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_index()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn write_index(&mut self) -> Result<()> {
+        self.flush()?;
+        let index = self.index.to_bytes();
+        self.writer.as_mut().unwrap().write_all(&index)?;
+        Ok(())
+    }
+
+    /// Writes all pending compressed data to `self.writer`, indexing it.
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.encoder.output();
+        if output.is_empty() {
+            return Ok(());
+        }
+        if !self.output_indexed {
+            let header = BlockHeader::parse(output[..BLOCK_HEADER_SIZE].try_into().unwrap());
+            self.index.entries.push(IndexEntry {
+                compressed_offset: self.index.compressed_size,
+                uncompressed_offset: self.index.uncompressed_size,
+            });
+            self.index.uncompressed_size += header.read_size as u64;
+            self.index.compressed_size += output.len() as u64;
+            self.output_indexed = true;
+        }
+        // what's written is consumed even if the writer fills up, so it isn't written twice
+        let size = output.len();
+        let written = self.writer.as_mut().unwrap().try_write_all(output)?;
+        self.encoder.consume(written);
+        if written < size {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.output_indexed = false;
+        Ok(())
+    }
+}
+
+impl<W> Drop for Bz3IndexedEncoder<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_index();
+        }
+    }
+}
+
+impl<W> Write for Bz3IndexedEncoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_output()?;
+        let write_size = self.encoder.feed(buf).map_err(Error::into_io_error)?;
+        self.write_output()?;
+        Ok(write_size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush().map_err(Error::into_io_error)?;
+        self.write_output()
+    }
+}
+
+/// Decoder with random access to the uncompressed data of a bzip3 file.
+///
+/// Seeking is free; a read only decodes the block holding the current position. The block
+/// locations come from the [seek index](self) if the file has one, and from [`scan_index`]
+/// otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read, Seek, SeekFrom};
+/// use bzip3::seek::Bz3SeekableDecoder;
+///
+/// let data = (0..250 * 1024).map(|x| x as u8).collect::<Vec<_>>();
+/// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+///
+/// let mut decoder = Bz3SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+/// decoder.seek(SeekFrom::End(-10)).unwrap();
+/// let mut tail = Vec::new();
+/// decoder.read_to_end(&mut tail).unwrap();
+/// assert_eq!(tail, &data[(data.len() - 10)..]);
+/// ```
+pub struct Bz3SeekableDecoder<R>
+where
+    R: Read + Seek,
+{
+    reader: R,
+    index: Bz3Index,
+    blocks: BlockDecoder,
+    /// Position in the uncompressed stream.
+    position: u64,
+}
+
+impl<R> fmt::Debug for Bz3SeekableDecoder<R>
+where
+    R: Read + Seek,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3SeekableDecoder");
+        self.blocks.debug_fields(&mut s, &self.index, self.position);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<R> Bz3SeekableDecoder<R>
+where
+    R: Read + Seek,
+{
+    /// Creates a seekable decoder, using the seek index of the file or scanning for the blocks.
+    ///
+    /// The file is expected to start at position zero of `reader`.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let index = find_index(&mut reader)?;
+        Self::with_index(reader, index)
+    }
+
+    /// Creates a seekable decoder with a known index.
+    ///
+    /// The file is expected to start at position zero of `reader`.
+    pub fn with_index(mut reader: R, index: Bz3Index) -> Result<Self> {
+        Ok(Self {
+            blocks: BlockDecoder::new(&mut reader)?,
+            reader,
+            index,
+            position: 0,
+        })
+    }
+
+    /// The index in use.
+    pub fn index(&self) -> &Bz3Index {
+        &self.index
+    }
+
+    /// Returns the size of the uncompressed data.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.index.uncompressed_size
+    }
+
+    /// Decodes a single block, given its number in the index.
+    ///
+    /// This doesn't move the read position. The last decoded block stays cached, so reading
+    /// within it afterwards doesn't decode it again.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if there's no such block, and the errors of decoding it
+    /// otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use bzip3::seek::Bz3SeekableDecoder;
+    ///
+    /// let data = (0..250 * 1024).map(|x| (x / 1024) as u8).collect::<Vec<_>>();
+    /// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+    ///
+    /// let mut decoder = Bz3SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+    /// assert_eq!(decoder.decode_block_at(2).unwrap(), &data[(200 * 1024)..]);
+    /// assert!(matches!(
+    ///     decoder.decode_block_at(3),
+    ///     Err(bzip3::Error::InvalidArgument(_))
+    /// ));
+    /// ```
+    pub fn decode_block_at(&mut self, block_index: usize) -> Result<Vec<u8>> {
+        if block_index >= self.index.entries.len() {
+            return Err(Error::InvalidArgument(format!(
+                "Block index {block_index} out of range for {} blocks",
+                self.index.entries.len()
+            )));
+        }
+        let data = self
+            .blocks
+            .load(&mut self.reader, &self.index, block_index)?;
+        Ok(data.to_vec())
+    }
+}
+
+impl<R> Read for Bz3SeekableDecoder<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(block) = self.index.block_containing(self.position) else {
+            return Ok(0);
+        };
+        let data = self
+            .blocks
+            .load(&mut self.reader, &self.index, block)
+            .map_err(Error::into_io_error)?;
+
+        let start = (self.position - self.index.entries[block].uncompressed_offset) as usize;
+        let size = buf.len().min(data.len() - start);
+        buf[..size].copy_from_slice(&data[start..(start + size)]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<R> Seek for Bz3SeekableDecoder<R>
+where
+    R: Read + Seek,
+{
+    /// Seeks in the uncompressed stream. Seeking beyond the end is allowed, and reads there
+    /// return nothing.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        seek_position(&mut self.position, self.index.uncompressed_size, pos)
+    }
+}
+
+/// Moves a position in a stream of `len` bytes.
+fn seek_position(position: &mut u64, len: u64, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(x) => {
+            *position = x;
+            return Ok(x);
+        }
+        SeekFrom::End(x) => (len, x),
+        SeekFrom::Current(x) => (*position, x),
+    };
+    match base.checked_add_signed(offset) {
+        Some(x) => {
+            *position = x;
+            Ok(x)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )),
+    }
+}
+
+/// Decodes single blocks, keeping the last one.
+struct BlockDecoder {
+    state: Bz3State,
+    /// Header and compressed data of a block, and then its decompressed data after the header.
+    buffer: Vec<u8>,
+    /// The block whose data is in `buffer`, and its size.
+    cached_block: Option<(usize, usize)>,
+}
+
+impl BlockDecoder {
+    /// Adds the state of a seekable reader to its `Debug` output; never the data.
+    fn debug_fields(&self, s: &mut fmt::DebugStruct<'_, '_>, index: &Bz3Index, position: u64) {
+        s.field("block_size", &self.state.block_size)
+            .field("blocks", &index.len())
+            .field("uncompressed_size", &index.uncompressed_size())
+            .field("position", &position)
+            .field("cached_block", &self.cached_block.map(|x| x.0));
+    }
+
+    /// Creates a decoder for the file starting at position zero of `reader`.
+    fn new<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let header = FrameHeader::read_from(reader)?;
+        Ok(Self {
+            state: Bz3State::new(header.block_size)?,
+            buffer: vec![0_u8; BLOCK_HEADER_SIZE + bound(header.block_size)],
+            cached_block: None,
+        })
+    }
+
+    /// Returns the data of the given block, decoding it if it isn't the cached one.
+    fn load<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+        index: &Bz3Index,
+        block: usize,
+    ) -> Result<&[u8]> {
+        if let Some((cached, size)) = self.cached_block {
+            if cached == block {
+                return Ok(&self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + size)]);
+            }
+        }
+        self.cached_block = None;
+
+        reader.seek(SeekFrom::Start(index.entries[block].compressed_offset))?;
+        reader.read_exact(&mut self.buffer[..BLOCK_HEADER_SIZE])?;
+        let header = self.check_header(index, block)?;
+        let new_size = header.data_size();
+        reader.read_exact(&mut self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + new_size)])?;
+        self.decode(block, header)
+    }
+
+    /// Like [`BlockDecoder::load`], but fetches the whole block with a single read.
+    fn load_at<S: ReadAt + ?Sized>(
+        &mut self,
+        source: &S,
+        index: &Bz3Index,
+        block: usize,
+    ) -> Result<&[u8]> {
+        if let Some((cached, size)) = self.cached_block {
+            if cached == block {
+                return Ok(&self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + size)]);
+            }
+        }
+        self.cached_block = None;
+
+        // the block spans up to the next one, but there may be extension blocks in between
+        let offset = index.entries[block].compressed_offset;
+        let end = match index.entries.get(block + 1) {
+            Some(next) => next.compressed_offset,
+            None => index.compressed_size,
+        };
+        let span = ((end - offset) as usize).min(self.buffer.len());
+        if span < BLOCK_HEADER_SIZE {
+            return Err(Error::ProcessBlock(
+                "Corrupt file; block doesn't match the seek index".into(),
+            ));
+        }
+        source.read_exact_at(&mut self.buffer[..span], offset)?;
+        let header = self.check_header(index, block)?;
+        if BLOCK_HEADER_SIZE + header.data_size() > span {
+            return Err(Error::ProcessBlock(
+                "Corrupt file; block doesn't match the seek index".into(),
+            ));
+        }
+        self.decode(block, header)
+    }
+
+    /// Parses and checks the block header at the start of `self.buffer`.
+    fn check_header(&self, index: &Bz3Index, block: usize) -> Result<BlockHeader> {
+        let header = BlockHeader::parse(self.buffer[..BLOCK_HEADER_SIZE].try_into().unwrap());
+        header.validate(self.state.block_size)?;
+        if header.read_size as u64 != index.block_uncompressed_size(block) {
+            return Err(Error::ProcessBlock(
+                "Corrupt file; block doesn't match the seek index".into(),
+            ));
+        }
+        Ok(header)
+    }
+
+    /// Decodes the block in `self.buffer`, and caches it.
+    fn decode(&mut self, block: usize, header: BlockHeader) -> Result<&[u8]> {
+        let read_size = header.read_size as usize;
+        if !header.is_stored() {
+            self.state.decode_block_at(
+                &mut self.buffer[BLOCK_HEADER_SIZE..],
+                header.new_size as usize,
+                read_size,
+                block,
+            )?;
+        }
+        self.cached_block = Some((block, read_size));
+        Ok(&self.buffer[BLOCK_HEADER_SIZE..(BLOCK_HEADER_SIZE + read_size)])
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_repr {
+    use super::{Bz3Index, IndexEntry, INDEX_FILE_VERSION};
+
+    /// Serialized form of [`Bz3Index`].
+    #[derive(serde::Serialize, serde::Deserialize)]
+    pub(super) struct IndexRepr {
+        version: u8,
+        entries: Vec<IndexEntry>,
+        uncompressed_size: u64,
+        compressed_size: u64,
+    }
+
+    impl From<Bz3Index> for IndexRepr {
+        fn from(value: Bz3Index) -> Self {
+            Self {
+                version: INDEX_FILE_VERSION,
+                entries: value.entries,
+                uncompressed_size: value.uncompressed_size,
+                compressed_size: value.compressed_size,
+            }
+        }
+    }
+
+    impl TryFrom<IndexRepr> for Bz3Index {
+        type Error = String;
+
+        fn try_from(value: IndexRepr) -> Result<Self, Self::Error> {
+            if value.version != INDEX_FILE_VERSION {
+                return Err(format!("unsupported index version: {}", value.version));
+            }
+            Bz3Index::from_parts(
+                value.entries,
+                value.uncompressed_size,
+                value.compressed_size,
+            )
+            .map_err(|e| e.to_string())
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Random access over positional-read sources.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::errors::*;
+
+use super::{find_index, seek_position, BlockDecoder, Bz3Index};
+
+/// A source of bytes that can be read at any offset, like a file or an object in remote storage.
+///
+/// Unlike [`Read`] + [`Seek`], there's no shared position, so every read says where it starts.
+pub trait ReadAt {
+    /// Reads bytes starting at `offset` into `buf`, returning how many were read.
+    ///
+    /// Zero means `offset` is at or beyond the end of the source.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Returns the size of the source.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Reads exactly `buf.len()` bytes starting at `offset`.
+    ///
+    /// # Errors
+    ///
+    /// An [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error if the source ends before
+    /// that.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(size) => {
+                    buf = &mut buf[size..];
+                    offset += size as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let data = &self[(offset as usize)..];
+        let size = buf.len().min(data.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        Ok(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl ReadAt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::read_at(self, buf, offset);
+        #[cfg(windows)]
+        return std::os::windows::fs::FileExt::seek_read(self, buf, offset);
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl<T> ReadAt for &T
+where
+    T: ReadAt + ?Sized,
+{
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+/// [`Read`] + [`Seek`] view of a [`ReadAt`] source, for parsing the file header and the index.
+struct Cursor<'a, S: ?Sized> {
+    source: &'a S,
+    position: u64,
+}
+
+impl<'a, S> Cursor<'a, S>
+where
+    S: ReadAt + ?Sized,
+{
+    fn new(source: &'a S) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+}
+
+impl<S> Read for Cursor<'_, S>
+where
+    S: ReadAt + ?Sized,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.source.read_at(buf, self.position)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<S> Seek for Cursor<'_, S>
+where
+    S: ReadAt + ?Sized,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = match pos {
+            SeekFrom::End(_) => self.source.size()?,
+            _ => 0,
+        };
+        seek_position(&mut self.position, len, pos)
+    }
+}
+
+/// Seekable bzip3 decoder over a [`ReadAt`] source, fetching only the blocks being read.
+///
+/// This is [`Bz3SeekableDecoder`](super::Bz3SeekableDecoder) for sources where every access
+/// is costly, like objects in remote storage: each block is fetched with a single read, spanning
+/// from its offset in the index to the next block's.
+///
+/// Finding the index of a file without a [seek index](super) takes a read per block, so for
+/// remote sources, prefer keeping an index at hand and using [`RemoteBz3Reader::with_index`].
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Seek, SeekFrom};
+/// use bzip3::seek::RemoteBz3Reader;
+///
+/// let data = (0..250 * 1024).map(|x| x as u8).collect::<Vec<_>>();
+/// let compressed = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+///
+/// let mut reader = RemoteBz3Reader::new(compressed).unwrap();
+/// reader.seek(SeekFrom::Start(150_000)).unwrap();
+/// let mut buf = [0_u8; 100];
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(buf, data[150_000..150_100]);
+/// ```
+pub struct RemoteBz3Reader<S>
+where
+    S: ReadAt,
+{
+    source: S,
+    index: Bz3Index,
+    blocks: BlockDecoder,
+    /// Position in the uncompressed data.
+    position: u64,
+}
+
+impl<S> fmt::Debug for RemoteBz3Reader<S>
+where
+    S: ReadAt,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("RemoteBz3Reader");
+        self.blocks.debug_fields(&mut s, &self.index, self.position);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<S> RemoteBz3Reader<S>
+where
+    S: ReadAt,
+{
+    /// Creates a reader, using the seek index of the file or scanning for the blocks.
+    pub fn new(source: S) -> Result<Self> {
+        let index = find_index(&mut Cursor::new(&source))?;
+        Self::with_index(source, index)
+    }
+
+    /// Creates a reader with a known index.
+    pub fn with_index(source: S, index: Bz3Index) -> Result<Self> {
+        Ok(Self {
+            blocks: BlockDecoder::new(&mut Cursor::new(&source))?,
+            source,
+            index,
+            position: 0,
+        })
+    }
+
+    /// The index in use.
+    pub fn index(&self) -> &Bz3Index {
+        &self.index
+    }
+
+    /// Returns the size of the uncompressed data.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.index.uncompressed_size
+    }
+
+    /// Acquires a reference to the underlying source.
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    /// Consumes the reader, returning the underlying source.
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S> Read for RemoteBz3Reader<S>
+where
+    S: ReadAt,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(block) = self.index.block_containing(self.position) else {
+            return Ok(0);
+        };
+        let data = self
+            .blocks
+            .load_at(&self.source, &self.index, block)
+            .map_err(Error::into_io_error)?;
+
+        let start = (self.position - self.index.entries[block].uncompressed_offset) as usize;
+        let size = buf.len().min(data.len() - start);
+        buf[..size].copy_from_slice(&data[start..(start + size)]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl<S> Seek for RemoteBz3Reader<S>
+where
+    S: ReadAt,
+{
+    /// Seeks in the uncompressed stream. Seeking beyond the end is allowed, and reads there
+    /// return nothing.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        seek_position(&mut self.position, self.index.uncompressed_size, pos)
+    }
+}
+
+/// Object served over HTTP, read with range requests.
+///
+/// The size is taken from the `Content-Length` of a `HEAD` request on creation. Reads fail with
+/// an [`Unsupported`](io::ErrorKind::Unsupported) error if the server ignores the `Range`
+/// header.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Read;
+/// use bzip3::seek::{HttpSource, RemoteBz3Reader};
+///
+/// let source = HttpSource::new("https://example.com/archive.bz3").unwrap();
+/// let reader = RemoteBz3Reader::new(source).unwrap();
+/// let mut head = Vec::new();
+/// reader.take(1024).read_to_end(&mut head).unwrap();
+/// ```
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+    size: u64,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    /// Creates a source for the object at `url`, with a default agent.
+    pub fn new(url: impl Into<String>) -> io::Result<Self> {
+        Self::with_agent(ureq::Agent::new(), url)
+    }
+
+    /// Creates a source for the object at `url`, sending requests with `agent`.
+    pub fn with_agent(agent: ureq::Agent, url: impl Into<String>) -> io::Result<Self> {
+        let url = url.into();
+        let response = agent.head(&url).call().map_err(io::Error::other)?;
+        let size = response
+            .header("Content-Length")
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length"))?;
+        Ok(Self { agent, url, size })
+    }
+
+    /// The URL of the object.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(feature = "http")]
+impl ReadAt for HttpSource {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if buf.is_empty() || offset >= self.size {
+            return Ok(0);
+        }
+        let end = self.size.min(offset + buf.len() as u64);
+        let response = self
+            .agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={}-{}", offset, end - 1))
+            .call()
+            .map_err(io::Error::other)?;
+        if response.status() != 206 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The server doesn't support range requests",
+            ));
+        }
+        let size = (end - offset) as usize;
+        response.into_reader().read_exact(&mut buf[..size])?;
+        Ok(size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+}
@@ -0,0 +1,228 @@
+//! Compressed temporary storage, spilling to disk when large.
+//!
+//! [`Bz3TempBuffer`] takes data through [`Write`] and keeps it compressed: in memory while the
+//! compressed data is small, and in a temporary file once it grows beyond a threshold. When
+//! all the data is in, [`Bz3TempBuffer::into_reader`] gives it back decompressed. This cuts the
+//! disk space of intermediate results in batch pipelines, at the cost of compressing them.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::errors::*;
+use crate::{read, write};
+
+/// A temporary file, deleted on drop.
+struct TempFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates a new file with a unique name in `dir`.
+    fn new_in(dir: &Path) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!(
+                "bzip3-temp-{}-{}.bz3",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = dir.join(name);
+            let result = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            match result {
+                Ok(file) => return Ok(Self { file, path }),
+                // left over from an earlier process with the same id
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Where the compressed data goes.
+enum Storage {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// Writer into memory up to a threshold, and into a temporary file beyond.
+struct SpillWriter {
+    storage: Storage,
+    threshold: usize,
+    dir: PathBuf,
+    size: u64,
+}
+
+impl Write for SpillWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Storage::Memory(data) = &self.storage {
+            if data.len() + buf.len() > self.threshold {
+                let mut file = TempFile::new_in(&self.dir)?;
+                file.file.write_all(data)?;
+                self.storage = Storage::File(file);
+            }
+        }
+        match &mut self.storage {
+            Storage::Memory(data) => data.extend_from_slice(buf),
+            Storage::File(file) => file.file.write_all(buf)?,
+        }
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.storage {
+            Storage::Memory(_) => Ok(()),
+            Storage::File(file) => file.file.flush(),
+        }
+    }
+}
+
+/// Temporary buffer keeping the data written to it compressed, in memory or in a temporary
+/// file.
+///
+/// The compressed data stays in memory up to the threshold given, and moves to a temporary
+/// file in [`std::env::temp_dir`], or the directory given, once it exceeds it. The file is
+/// deleted when the buffer, or the reader it turns into, is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+/// use bzip3::temp::Bz3TempBuffer;
+///
+/// let mut buffer = Bz3TempBuffer::new(1024 * 1024, 64 * 1024 * 1024).unwrap();
+/// for i in 0..1000 {
+///     writeln!(buffer, "record {i}").unwrap();
+/// }
+/// let mut contents = String::new();
+/// buffer.into_reader().unwrap().read_to_string(&mut contents).unwrap();
+/// assert!(contents.ends_with("record 999\n"));
+/// ```
+pub struct Bz3TempBuffer {
+    encoder: write::Bz3Encoder<SpillWriter>,
+    len: u64,
+}
+
+impl Bz3TempBuffer {
+    /// Creates a buffer compressing with the given block size, and spilling the compressed
+    /// data to a temporary file once it exceeds `threshold` bytes.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(block_size: usize, threshold: usize) -> Result<Self> {
+        Self::new_in(block_size, threshold, std::env::temp_dir())
+    }
+
+    /// Like [`Bz3TempBuffer::new`], but creating the temporary file in `dir`.
+    pub fn new_in<P: AsRef<Path>>(block_size: usize, threshold: usize, dir: P) -> Result<Self> {
+        let writer = SpillWriter {
+            storage: Storage::Memory(Vec::new()),
+            threshold,
+            dir: dir.as_ref().to_path_buf(),
+            size: 0,
+        };
+        Ok(Self {
+            encoder: write::Bz3Encoder::new(writer, block_size)?,
+            len: 0,
+        })
+    }
+
+    /// Returns the size of the data written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the size of the compressed data so far, not counting the partial block.
+    pub fn compressed_size(&self) -> u64 {
+        self.encoder.get_ref().size
+    }
+
+    /// Whether the compressed data has moved to a temporary file.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.encoder.get_ref().storage, Storage::File(_))
+    }
+
+    /// Compresses the partial block, and returns a reader of the decompressed data.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Io`] on all IO errors.
+    pub fn into_reader(self) -> Result<TempBufferReader> {
+        let writer = self.encoder.finish()?;
+        let storage = match writer.storage {
+            Storage::Memory(data) => StorageReader::Memory(Cursor::new(data)),
+            Storage::File(mut file) => {
+                file.file.seek(SeekFrom::Start(0))?;
+                StorageReader::File(BufReader::new(file))
+            }
+        };
+        Ok(TempBufferReader {
+            decoder: read::Bz3Decoder::new(storage)?,
+        })
+    }
+}
+
+impl Write for Bz3TempBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.encoder.write(buf)?;
+        self.len += size as u64;
+        Ok(size)
+    }
+
+    /// Does nothing; flushing the partial block would only cost compression.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader of the compressed data of a [`Bz3TempBuffer`].
+enum StorageReader {
+    Memory(Cursor<Vec<u8>>),
+    File(BufReader<TempFile>),
+}
+
+impl Read for StorageReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            StorageReader::Memory(reader) => reader.read(buf),
+            StorageReader::File(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Reader of the decompressed data of a [`Bz3TempBuffer`], returned by
+/// [`Bz3TempBuffer::into_reader`].
+pub struct TempBufferReader {
+    decoder: read::Bz3Decoder<StorageReader>,
+}
+
+impl Read for TempBufferReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
@@ -0,0 +1,224 @@
+//! Helpers for testing code built on this crate.
+//!
+//! This has deterministic data generators, round-trip assertions running the data through the
+//! blocking codecs, and [`Malformation`]s turning a valid file into the broken ones a decoder
+//! must reject, so crates wrapping bzip3 can test their error paths without crafting the files
+//! by hand.
+//!
+//! # Examples
+//!
+//! ```
+//! use bzip3::testutil::{self, Malformation};
+//!
+//! let data = testutil::compressible_data(300 * 1024, 1);
+//! let compressed = testutil::assert_round_trip(&data, 100 * 1024);
+//!
+//! for malformation in Malformation::ALL {
+//!     let broken = malformation.apply(&compressed);
+//!     assert!(bzip3::mem::decompress(&broken).is_err(), "{malformation:?}");
+//! }
+//! ```
+
+use std::io::{Read, Write};
+
+use byteorder::{ByteOrder, LE};
+
+use crate::frame::{BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE};
+use crate::{mem, read, write};
+
+/// SplitMix64, small and good enough for test data, and the same on every platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns `size` bytes of random data, which doesn't compress.
+///
+/// The same `seed` always gives the same data.
+pub fn random_data(size: usize, seed: u64) -> Vec<u8> {
+    let mut rng = SplitMix64(seed);
+    let mut data = Vec::with_capacity(size + 8);
+    while data.len() < size {
+        data.extend_from_slice(&rng.next().to_le_bytes());
+    }
+    data.truncate(size);
+    data
+}
+
+/// Returns `size` bytes of text-like data, random words separated by spaces and newlines, which
+/// compresses about as well as text does.
+///
+/// The same `seed` always gives the same data.
+pub fn compressible_data(size: usize, seed: u64) -> Vec<u8> {
+    const WORDS: &[&[u8]] = &[
+        b"block",
+        b"stream",
+        b"file",
+        b"header",
+        b"data",
+        b"size",
+        b"the",
+        b"of",
+        b"a",
+        b"and",
+        b"compress",
+        b"decode",
+        b"reader",
+        b"writer",
+        b"buffer",
+        b"error",
+        b"to",
+        b"is",
+        b"in",
+        b"bzip3",
+    ];
+    let mut rng = SplitMix64(seed);
+    let mut data = Vec::with_capacity(size + 16);
+    while data.len() < size {
+        let x = rng.next();
+        data.extend_from_slice(WORDS[(x % WORDS.len() as u64) as usize]);
+        data.push(if x >> 32 & 0xf == 0 { b'\n' } else { b' ' });
+    }
+    data.truncate(size);
+    data
+}
+
+/// Panics if `actual` isn't `expected`, telling the first offset they differ at rather than
+/// printing both.
+#[track_caller]
+fn assert_same(actual: &[u8], expected: &[u8], what: &str) {
+    if actual == expected {
+        return;
+    }
+    let offset = actual
+        .iter()
+        .zip(expected)
+        .position(|(a, b)| a != b)
+        .unwrap_or(actual.len().min(expected.len()));
+    panic!(
+        "{what}: got {} bytes instead of {}, differing from offset {offset}",
+        actual.len(),
+        expected.len()
+    );
+}
+
+/// Compresses `data` with [`write::Bz3Encoder`], and asserts it decompresses back to `data`
+/// with [`read::Bz3Decoder`], [`write::Bz3Decoder`] and [`mem::decompress`]. Returns the
+/// compressed data.
+///
+/// # Panics
+///
+/// If any codec fails, or gives other data; and if `block_size` is invalid.
+#[track_caller]
+pub fn assert_round_trip(data: &[u8], block_size: usize) -> Vec<u8> {
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), block_size).expect("creating encoder");
+    encoder.write_all(data).expect("compressing");
+    let compressed = encoder.finish().expect("compressing");
+    assert_decompresses_to(&compressed, data);
+    compressed
+}
+
+/// Asserts the bzip3 file `compressed` decompresses to `expected` with [`read::Bz3Decoder`],
+/// [`write::Bz3Decoder`] and [`mem::decompress`].
+///
+/// # Panics
+///
+/// If any codec fails, or gives other data.
+#[track_caller]
+pub fn assert_decompresses_to(compressed: &[u8], expected: &[u8]) {
+    let mut decoder = read::Bz3Decoder::new(compressed).expect("read::Bz3Decoder");
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).expect("read::Bz3Decoder");
+    assert_same(&output, expected, "read::Bz3Decoder");
+
+    let mut output = Vec::new();
+    write::Bz3Decoder::new(&mut output)
+        .write_all(compressed)
+        .expect("write::Bz3Decoder");
+    assert_same(&output, expected, "write::Bz3Decoder");
+
+    let output = mem::decompress(compressed).expect("mem::decompress");
+    assert_same(&output, expected, "mem::decompress");
+}
+
+/// A way of breaking a bzip3 file, with the error the decoders give for it.
+///
+/// [`Malformation::apply`] takes a valid file, as written by the encoders of this crate, with
+/// at least one block, and without a leading [skippable frame](crate::frame::SKIPPABLE_MAGIC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformation {
+    /// The signature is no longer `BZ3v`; [`Error::InvalidSignature`](crate::Error::InvalidSignature).
+    BadMagic,
+    /// The format version is 9;
+    /// [`Error::UnsupportedVersion`](crate::Error::UnsupportedVersion).
+    UnsupportedVersion,
+    /// The block size in the file header is 0; [`Error::BlockSize`](crate::Error::BlockSize).
+    InvalidBlockSize,
+    /// The file ends inside the file header, after the signature;
+    /// [`Error::InvalidSignature`](crate::Error::InvalidSignature), as for a file too short to
+    /// be a bzip3 file.
+    TruncatedHeader,
+    /// The last byte of the file is cut off;
+    /// [`Error::TruncatedBlock`](crate::Error::TruncatedBlock).
+    TruncatedBlock,
+    /// The CRC of the first block is flipped; [`Error::BadCrc`](crate::Error::BadCrc) of
+    /// block 0.
+    CorruptBlock,
+    /// The first block claims more original data than the block size;
+    /// [`Error::TrailingData`](crate::Error::TrailingData) at the offset of the block, as its
+    /// header is no longer valid.
+    OversizedBlock,
+    /// Garbage follows the last block; [`Error::TrailingData`](crate::Error::TrailingData).
+    TrailingGarbage,
+}
+
+impl Malformation {
+    /// All the malformations.
+    pub const ALL: [Malformation; 8] = [
+        Malformation::BadMagic,
+        Malformation::UnsupportedVersion,
+        Malformation::InvalidBlockSize,
+        Malformation::TruncatedHeader,
+        Malformation::TruncatedBlock,
+        Malformation::CorruptBlock,
+        Malformation::OversizedBlock,
+        Malformation::TrailingGarbage,
+    ];
+
+    /// Returns `compressed` broken this way.
+    ///
+    /// # Panics
+    ///
+    /// If `compressed` is too short to hold the file header and a block header.
+    pub fn apply(&self, compressed: &[u8]) -> Vec<u8> {
+        assert!(
+            compressed.len() > FRAME_HEADER_SIZE + BLOCK_HEADER_SIZE,
+            "not a bzip3 file with a block"
+        );
+        let mut data = compressed.to_vec();
+        let block = FRAME_HEADER_SIZE;
+        match self {
+            Malformation::BadMagic => data[..4].copy_from_slice(b"BZ2h"),
+            Malformation::UnsupportedVersion => data[4] = b'9',
+            Malformation::InvalidBlockSize => LE::write_i32(&mut data[5..9], 0),
+            Malformation::TruncatedHeader => data.truncate(7),
+            Malformation::TruncatedBlock => {
+                data.pop();
+            }
+            Malformation::CorruptBlock => data[block + BLOCK_HEADER_SIZE] ^= 0xff,
+            Malformation::OversizedBlock => {
+                let block_size = LE::read_i32(&data[5..9]);
+                LE::write_i32(&mut data[block + 4..block + 8], block_size + 1);
+            }
+            Malformation::TrailingGarbage => data.extend_from_slice(b"trailing garbage"),
+        }
+        data
+    }
+}
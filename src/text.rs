@@ -0,0 +1,53 @@
+//! Text read from compressed files.
+//!
+//! [`lines`] iterates the lines of a bzip3-compressed text, e.g. of a log compressed by
+//! [`Bz3LogWriter`](crate::log::Bz3LogWriter), and [`read_to_string`] reads it whole.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::Write;
+//!
+//! let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+//! encoder.write_all(b"GET /\nPOST /login\n").unwrap();
+//! let compressed = encoder.finish().unwrap();
+//!
+//! let lines = bzip3::text::lines(compressed.as_slice())
+//!     .collect::<std::io::Result<Vec<_>>>()
+//!     .unwrap();
+//! assert_eq!(lines, ["GET /", "POST /login"]);
+//! ```
+
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+
+use crate::errors::*;
+use crate::read;
+
+/// Returns an iterator over the lines of the bzip3 file read from `reader`, as
+/// [`BufRead::lines`] does.
+///
+/// An error reading the file header is the first and only item.
+pub fn lines<R: Read>(reader: R) -> impl Iterator<Item = io::Result<String>> {
+    let (lines, error) = match read::Bz3Decoder::new(reader) {
+        Ok(decoder) => (Some(BufReader::new(decoder).lines()), None),
+        Err(e) => (None, Some(e.into_io_error())),
+    };
+    error
+        .map(Err)
+        .into_iter()
+        .chain(lines.into_iter().flatten())
+}
+
+/// Reads all the text of the bzip3 file read from `reader`.
+///
+/// # Errors
+///
+/// The errors of [`read::Bz3Decoder`] as IO errors, and an
+/// [`InvalidData`](io::ErrorKind::InvalidData) error if the text isn't UTF-8.
+pub fn read_to_string<R: Read>(reader: R) -> io::Result<String> {
+    let mut decoder = read::Bz3Decoder::new(reader).map_err(Error::into_io_error)?;
+    let mut text = String::new();
+    decoder.read_to_string(&mut text)?;
+    Ok(text)
+}
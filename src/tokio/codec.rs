@@ -0,0 +1,143 @@
+//! [`tokio_util::codec`] support for message-oriented transports.
+
+use std::fmt;
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::errors::*;
+use crate::frame::{BlockHeader, FrameHeader, BLOCK_HEADER_SIZE, FRAME_HEADER_SIZE};
+use crate::{bound, Bz3State};
+
+/// Codec compressing each message into its own bzip3 frame.
+///
+/// A message is encoded as a complete bzip3 file holding exactly one block, so it's delimited by
+/// its block header and needs no extra length prefix; a message taken off the wire can be
+/// decompressed by any bzip3 decoder on its own. Messages can't be larger than the block size.
+///
+/// # Examples
+///
+/// ```
+/// use bytes::{Bytes, BytesMut};
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = bzip3::tokio::codec::Bz3Codec::new(100 * 1024).unwrap();
+/// let mut wire = BytesMut::new();
+/// codec.encode(Bytes::from_static(b"hello"), &mut wire).unwrap();
+/// codec.encode(Bytes::from_static(b"world"), &mut wire).unwrap();
+///
+/// assert_eq!(codec.decode(&mut wire).unwrap().unwrap(), "hello");
+/// assert_eq!(codec.decode(&mut wire).unwrap().unwrap(), "world");
+/// assert_eq!(codec.decode(&mut wire).unwrap(), None);
+/// ```
+pub struct Bz3Codec {
+    state: Bz3State,
+    block_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl fmt::Debug for Bz3Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bz3Codec")
+            .field("block_size", &self.block_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Bz3Codec {
+    /// Creates a codec for messages up to `block_size` bytes.
+    ///
+    /// Incoming frames with a larger block size are rejected, which bounds the memory a peer can
+    /// make the decoder use.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(block_size: usize) -> Result<Self> {
+        Ok(Self {
+            state: Bz3State::new(block_size)?,
+            block_size,
+            buffer: vec![0_u8; bound(block_size)],
+        })
+    }
+
+    /// Returns the block size, which is also the maximum message size.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+impl Encoder<&[u8]> for Bz3Codec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> Result<()> {
+        if item.len() > self.block_size {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Message larger than the block size",
+            )));
+        }
+        self.buffer[..item.len()].copy_from_slice(item);
+        let new_size = self.state.encode_block(&mut self.buffer, item.len())?;
+
+        let block_header = BlockHeader {
+            new_size: new_size as i32,
+            read_size: item.len() as i32,
+        };
+        dst.reserve(FRAME_HEADER_SIZE + BLOCK_HEADER_SIZE + new_size);
+        dst.put_slice(&FrameHeader::new(self.block_size)?.to_bytes());
+        dst.put_slice(&block_header.to_bytes());
+        dst.put_slice(&self.buffer[..new_size]);
+        Ok(())
+    }
+}
+
+impl Encoder<Bytes> for Bz3Codec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<()> {
+        self.encode(item.as_ref(), dst)
+    }
+}
+
+impl Decoder for Bz3Codec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>> {
+        const HEADERS_SIZE: usize = FRAME_HEADER_SIZE + BLOCK_HEADER_SIZE;
+        if src.len() < HEADERS_SIZE {
+            return Ok(None);
+        }
+
+        let frame_header = FrameHeader::parse(src[..FRAME_HEADER_SIZE].try_into().unwrap())?;
+        if frame_header.block_size > self.block_size {
+            return Err(Error::BlockSizeLimit {
+                block_size: frame_header.block_size,
+                limit: self.block_size,
+            });
+        }
+        let block_header =
+            BlockHeader::parse(src[FRAME_HEADER_SIZE..HEADERS_SIZE].try_into().unwrap());
+        block_header.validate(frame_header.block_size)?;
+
+        let data_size = block_header.data_size();
+        let read_size = block_header.read_size as usize;
+        if src.len() < HEADERS_SIZE + data_size {
+            src.reserve(HEADERS_SIZE + data_size - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADERS_SIZE);
+        if block_header.is_stored() {
+            // the message as is
+            return Ok(Some(src.split_to(read_size)));
+        }
+        self.buffer[..data_size].copy_from_slice(&src[..data_size]);
+        src.advance(data_size);
+        self.state
+            .decode_block(&mut self.buffer, data_size, read_size)?;
+        Ok(Some(BytesMut::from(&self.buffer[..read_size])))
+    }
+}
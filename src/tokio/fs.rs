@@ -0,0 +1,93 @@
+//! Async counterparts of the [`stream`](crate::stream) helpers, working on files.
+
+use std::path::Path;
+
+use ::tokio::fs::File;
+use ::tokio::io::{self, AsyncWriteExt, BufReader, BufWriter};
+use bytesize::MIB;
+
+use crate::errors::*;
+
+use super::bufread::Bz3Decoder;
+use super::write::Bz3Encoder;
+
+/// Options for [`compress_file`] and [`decompress_file`].
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    /// Size of the buffer in front of the file IO.
+    ///
+    /// tokio runs every file operation on the blocking pool, so small reads and writes are
+    /// costly.
+    pub buffer_size: usize,
+    /// Whether to fsync the output file before returning.
+    pub sync: bool,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: MIB as usize,
+            sync: false,
+        }
+    }
+}
+
+/// Compresses the file at `input` into a new file at `output`.
+///
+/// An existing output file gets truncated. Returns the size of the output file.
+///
+/// # Examples
+///
+/// ```no_run
+/// # tokio_test::block_on(async {
+/// use bzip3::tokio::fs::{compress_file, FileOptions};
+///
+/// compress_file("data", "data.bz3", 16 * 1024 * 1024, &FileOptions::default())
+///     .await
+///     .unwrap();
+/// # })
+/// ```
+pub async fn compress_file<P, Q>(
+    input: P,
+    output: Q,
+    block_size: usize,
+    options: &FileOptions,
+) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut reader = BufReader::with_capacity(options.buffer_size, File::open(input).await?);
+    let mut file = File::create(output).await?;
+
+    let mut encoder = Bz3Encoder::new(&mut file, block_size)?;
+    io::copy_buf(&mut reader, &mut encoder).await?;
+    encoder.shutdown().await?;
+
+    if options.sync {
+        file.sync_all().await?;
+    }
+    Ok(file.metadata().await?.len())
+}
+
+/// Decompresses the file at `input` into a new file at `output`.
+///
+/// An existing output file gets truncated. Returns the size of the output file.
+pub async fn decompress_file<P, Q>(input: P, output: Q, options: &FileOptions) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let reader = BufReader::with_capacity(options.buffer_size, File::open(input).await?);
+    let mut decoder = Bz3Decoder::new(reader);
+    let mut writer = BufWriter::with_capacity(options.buffer_size, File::create(output).await?);
+
+    let size = io::copy(&mut decoder, &mut writer).await?;
+    writer.flush().await?;
+
+    let file = writer.into_inner();
+    if options.sync {
+        file.sync_all().await?;
+    }
+    Ok(size)
+}
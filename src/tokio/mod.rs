@@ -0,0 +1,69 @@
+//! Async BZip3 codecs for [tokio](https://docs.rs/tokio).
+//!
+//! The codecs are poll-based state machines; they never block inside a `poll_*` call, except
+//! for the CPU work of (de)compressing a block.
+//!
+//! The layout follows the [async-compression](https://docs.rs/async-compression) crate:
+//! [`bufread`] codecs read from an `AsyncBufRead`, [`write`] codecs write to an `AsyncWrite`,
+//! and all of them have `get_ref`/`get_mut`/`get_pin_mut`/`into_inner`, with the decoders
+//! having `multiple_members`. [`read`] additionally takes a plain `AsyncRead`; its decoder
+//! converts to the [`bufread`] one over a `BufReader` and back, carrying on with the stream.
+//!
+//! # Cancellation safety
+//!
+//! All the progress of a codec, down to a partially read header, lives in the codec itself and
+//! not in the futures polling it. Dropping a pending `read`/`write` future (e.g. in a `select!`
+//! branch) and calling it again later neither loses nor duplicates any data.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::time::Duration;
+
+use ::tokio::time::{sleep, Sleep};
+
+use crate::errors::*;
+
+pub mod bufread;
+#[cfg(feature = "tokio-util")]
+pub mod codec;
+pub mod fs;
+pub mod parallel;
+pub mod read;
+pub mod write;
+
+/// Timer failing a read that waits on the inner reader for too long.
+#[derive(Default)]
+pub(crate) struct Watchdog {
+    timeout: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Watchdog {
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.sleep = None;
+    }
+
+    /// To be called when the inner reader made progress.
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+
+    /// To be called when the inner reader is pending; starts the timer if it isn't running.
+    ///
+    /// Returns a [`TimedOut`](io::ErrorKind::TimedOut) error wrapping [`Error::Timeout`] once
+    /// the time is up.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let Some(timeout) = self.timeout else {
+            return Ok(());
+        };
+        let timer = self.sleep.get_or_insert_with(|| Box::pin(sleep(timeout)));
+        if timer.as_mut().poll(cx).is_ready() {
+            self.sleep = None;
+            return Err(Error::Timeout(timeout).into_io_error());
+        }
+        Ok(())
+    }
+}
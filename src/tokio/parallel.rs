@@ -0,0 +1,246 @@
+//! AsyncWrite-based BZip3 compressor, compressing blocks concurrently on the blocking pool.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use ::tokio::io::AsyncWrite;
+use ::tokio::task::{spawn_blocking, JoinHandle};
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::frame::FrameHeader;
+use crate::{compress_block_to_vec, Bz3State};
+
+type Job = JoinHandle<(Bz3State, Result<Vec<u8>>)>;
+
+pin_project! {
+    /// Async bzip3 encoder that compresses each block with
+    /// [`spawn_blocking`](::tokio::task::spawn_blocking), so the runtime threads never do the
+    /// compression work.
+    ///
+    /// Up to `max_concurrency` blocks are compressed at the same time; when all of them are busy,
+    /// writes wait for the oldest one. Blocks are written in order, and the output is identical
+    /// to what [`write::Bz3Encoder`](super::write::Bz3Encoder) produces.
+    ///
+    /// Like the other async encoders, it must be shut down to write the final partial block.
+    ///
+    /// # Panics
+    ///
+    /// Writing to the encoder panics when called from outside of a tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let mut compressed = Vec::new();
+    /// let mut encoder =
+    ///     bzip3::tokio::parallel::Bz3ParallelEncoder::new(&mut compressed, 100 * 1024, 4).unwrap();
+    /// encoder.write_all(&vec![b'x'; 1024 * 1024]).await.unwrap();
+    /// encoder.shutdown().await.unwrap();
+    /// # }
+    /// ```
+    pub struct Bz3ParallelEncoder<W> {
+        #[pin]
+        writer: W,
+        block_size: usize,
+        max_concurrency: usize,
+        // data of the block being gathered
+        input: Vec<u8>,
+        // blocks being compressed, in the output order
+        jobs: VecDeque<Job>,
+        // idle states, reused by the next blocks
+        states: Vec<Bz3State>,
+        // compressed data being written to `writer`
+        output: Vec<u8>,
+        output_pos: usize,
+        // blocks compressed so far
+        blocks: usize,
+    }
+}
+
+impl<W> fmt::Debug for Bz3ParallelEncoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bz3ParallelEncoder")
+            .field("block_size", &self.block_size)
+            .field("max_concurrency", &self.max_concurrency)
+            .field("pending_input", &self.input.len())
+            .field("blocks_in_flight", &self.jobs.len())
+            .field("blocks", &self.blocks)
+            .field("pending_output", &(self.output.len() - self.output_pos))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3ParallelEncoder<W>
+where
+    W: AsyncWrite,
+{
+    /// Creates an encoder compressing up to `max_concurrency` blocks at a time.
+    ///
+    /// Each in-flight block holds its own state and buffers, so the memory usage grows with
+    /// `max_concurrency`.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_concurrency` is zero.
+    pub fn new(writer: W, block_size: usize, max_concurrency: usize) -> Result<Self> {
+        assert!(max_concurrency > 0, "max_concurrency must be positive");
+        let header = FrameHeader::new(block_size)?;
+        Ok(Self {
+            writer,
+            block_size,
+            max_concurrency,
+            input: Vec::with_capacity(block_size),
+            jobs: VecDeque::with_capacity(max_concurrency),
+            states: Vec::new(),
+            output: header.to_bytes().to_vec(),
+            output_pos: 0,
+            blocks: 0,
+        })
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the compressed stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        self.project().writer
+    }
+
+    /// Consumes the encoder, returning the underlying writer.
+    ///
+    /// Data buffered in the encoder is lost.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Hands the gathered input over to the blocking pool.
+    fn spawn_block(self: Pin<&mut Self>) -> Result<()> {
+        let this = self.project();
+        let mut state = match this.states.pop() {
+            Some(state) => state,
+            None => Bz3State::new(*this.block_size)?,
+        };
+        let data = std::mem::replace(this.input, Vec::with_capacity(*this.block_size));
+        this.jobs.push_back(spawn_blocking(move || {
+            let result = compress_block_to_vec(&mut state, &data);
+            (state, result)
+        }));
+        Ok(())
+    }
+
+    /// Writes out finished blocks in order, until at most `max_jobs` blocks are in flight and
+    /// there's no pending output.
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        max_jobs: usize,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            if *this.output_pos < this.output.len() {
+                let output = &this.output[*this.output_pos..];
+                let size = ready!(this.writer.as_mut().poll_write(cx, output))?;
+                if size == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                *this.output_pos += size;
+                continue;
+            }
+            if this.jobs.len() <= max_jobs {
+                return Poll::Ready(Ok(()));
+            }
+
+            let job = this.jobs.front_mut().unwrap();
+            let (state, result) = ready!(Pin::new(job).poll(cx)).map_err(io::Error::other)?;
+            this.jobs.pop_front();
+            this.states.push(state);
+            *this.output = result.map_err(Error::into_io_error)?;
+            *this.output_pos = 0;
+            *this.blocks += 1;
+        }
+    }
+
+    /// Compresses the partial block, and writes out all the blocks.
+    fn poll_finish(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.input.is_empty() {
+            let max_jobs = self.max_concurrency - 1;
+            ready!(self.as_mut().poll_complete(cx, max_jobs))?;
+            self.as_mut().spawn_block().map_err(Error::into_io_error)?;
+        }
+        self.poll_complete(cx, 0)
+    }
+}
+
+impl<W> AsyncWrite for Bz3ParallelEncoder<W>
+where
+    W: AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // push out whatever is already done, without waiting for anything
+        if let Poll::Ready(Err(e)) = self.as_mut().poll_complete(cx, 0) {
+            return Poll::Ready(Err(e));
+        }
+
+        if self.input.len() == self.block_size {
+            // the previous write filled the block while all the jobs were busy
+            let max_jobs = self.max_concurrency - 1;
+            ready!(self.as_mut().poll_complete(cx, max_jobs))?;
+            self.as_mut().spawn_block().map_err(Error::into_io_error)?;
+        }
+
+        let this = self.as_mut().project();
+        let size = buf.len().min(*this.block_size - this.input.len());
+        this.input.extend_from_slice(&buf[..size]);
+        if this.input.len() == *this.block_size && this.jobs.len() < *this.max_concurrency {
+            self.spawn_block().map_err(Error::into_io_error)?;
+        }
+        Poll::Ready(Ok(size))
+    }
+
+    /// Compresses the partial block like [`write::Bz3Encoder`](super::write::Bz3Encoder) does
+    /// on flush, waits for all the blocks to be written, and then flushes the inner writer.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_finish(cx))?;
+        self.project().writer.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_finish(cx))?;
+        self.project().writer.poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,278 @@
+//! AsyncRead-based BZip3 decompressor.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ::tokio::io::{AsyncRead, BufReader, ReadBuf};
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::pool::BufferPool;
+use crate::{push, Bz3State, CrcMode};
+
+use super::{bufread, Watchdog};
+
+pin_project! {
+    /// Async bzip3 decoder, reading compressed data from an [`AsyncRead`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # tokio_test::block_on(async {
+    /// use tokio::io::AsyncReadExt;
+    ///
+    /// let compressed = bzip3::mem::compress(b"hello, world", 100 * 1024).unwrap();
+    /// let mut decoder = bzip3::tokio::read::Bz3Decoder::new(compressed.as_slice());
+    /// let mut contents = String::new();
+    /// decoder.read_to_string(&mut contents).await.unwrap();
+    /// assert_eq!(contents, "hello, world");
+    /// # })
+    /// ```
+    pub struct Bz3Decoder<R> {
+        #[pin]
+        reader: R,
+        decoder: push::Decoder,
+        eof: bool,
+        watchdog: Watchdog,
+    }
+}
+
+impl<R> fmt::Debug for Bz3Decoder<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.field("eof", &self.eof).finish_non_exhaustive()
+    }
+}
+
+impl<R> Bz3Decoder<R>
+where
+    R: AsyncRead,
+{
+    /// Creates an async bzip3 decoder.
+    ///
+    /// Unlike [`read::Bz3Decoder::new`](crate::read::Bz3Decoder::new), the file header is only
+    /// read on the first poll, so an invalid header is reported by the first read.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: push::Decoder::new(),
+            eof: false,
+            watchdog: Watchdog::default(),
+        }
+    }
+
+    /// Returns the bzip3 block size, once the file header has been read.
+    pub fn block_size(&self) -> Option<usize> {
+        self.decoder.block_size()
+    }
+
+    /// Sets how a block failing its CRC check is handled.
+    ///
+    /// See [`read::Bz3Decoder::set_crc_mode`](crate::read::Bz3Decoder::set_crc_mode).
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.decoder.set_crc_mode(mode);
+    }
+
+    /// Returns the blocks which failed the CRC check so far in permissive mode, numbered as in
+    /// [`Error::BadCrc`].
+    pub fn crc_failures(&self) -> &[usize] {
+        self.decoder.crc_failures()
+    }
+
+    /// Sets whether the output of each block is verified after decoding.
+    ///
+    /// See [`read::Bz3Decoder::set_paranoid`](crate::read::Bz3Decoder::set_paranoid).
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.decoder.set_paranoid(enabled);
+    }
+
+    /// Sets the largest block size accepted.
+    ///
+    /// See [`read::Bz3Decoder::with_max_block_size`](crate::read::Bz3Decoder::with_max_block_size).
+    pub fn set_max_block_size(&mut self, limit: usize) {
+        self.decoder.set_max_block_size(limit);
+    }
+
+    /// Sets whether the input may hold multiple concatenated bzip3 files, decoded one after
+    /// another as a single stream.
+    ///
+    /// This is off by default, and then anything following the first file is an error.
+    pub fn multiple_members(&mut self, enabled: bool) {
+        self.decoder.set_multiple_members(enabled);
+    }
+
+    /// Sets a timeout for the inner reader to make progress.
+    ///
+    /// When a read waits on the inner reader for longer than that, e.g. because of a peer
+    /// stalling in the middle of a block, it fails with a
+    /// [`TimedOut`](io::ErrorKind::TimedOut) error wrapping [`Error::Timeout`]. `None`, the
+    /// default, waits forever.
+    ///
+    /// # Panics
+    ///
+    /// Reading with a timeout panics when called from outside of a tokio runtime with the time
+    /// driver enabled.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.watchdog.set_timeout(timeout);
+    }
+
+    /// Sets a pool to take the block buffer and the state from, and put them back to when
+    /// dropped.
+    ///
+    /// See [`write::Bz3Decoder::set_buffer_pool`](crate::write::Bz3Decoder::set_buffer_pool).
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.decoder.set_buffer_pool(pool);
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the file header has been read.
+    ///
+    /// See [`read::Bz3Decoder::state`](crate::read::Bz3Decoder::state).
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.decoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the file header has been read.
+    ///
+    /// See [`read::Bz3Decoder::state_mut`](crate::read::Bz3Decoder::state_mut).
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.decoder.state_mut()
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Reading from it directly corrupts the decompressed stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Acquires a pinned mutable reference to the underlying reader.
+    ///
+    /// Reading from it directly corrupts the decompressed stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut R> {
+        self.project().reader
+    }
+
+    /// Consumes the decoder, returning the underlying reader.
+    ///
+    /// Data buffered in the decoder is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Takes the decoder apart, for a [`bufread::Bz3Decoder`] to carry on with the stream.
+    pub(super) fn into_parts(self) -> (R, push::Decoder, bool, Watchdog) {
+        (self.reader, self.decoder, self.eof, self.watchdog)
+    }
+
+    pub(super) fn from_parts(
+        reader: R,
+        decoder: push::Decoder,
+        eof: bool,
+        watchdog: Watchdog,
+    ) -> Self {
+        Self {
+            reader,
+            decoder,
+            eof,
+            watchdog,
+        }
+    }
+}
+
+/// Carries on with the stream through a [`BufReader`], keeping the progress and the settings.
+impl<R> From<Bz3Decoder<R>> for bufread::Bz3Decoder<BufReader<R>>
+where
+    R: AsyncRead,
+{
+    fn from(decoder: Bz3Decoder<R>) -> Self {
+        let (reader, decoder, eof, watchdog) = decoder.into_parts();
+        bufread::Bz3Decoder::from_parts(BufReader::new(reader), decoder, eof, watchdog)
+    }
+}
+
+/// Carries on with the stream without the [`BufReader`], keeping the progress and the settings.
+///
+/// This gives the decoder back if the `BufReader` holds data the decoder hasn't taken yet, which
+/// would be lost.
+impl<R> TryFrom<bufread::Bz3Decoder<BufReader<R>>> for Bz3Decoder<R>
+where
+    R: AsyncRead,
+{
+    type Error = bufread::Bz3Decoder<BufReader<R>>;
+
+    fn try_from(
+        decoder: bufread::Bz3Decoder<BufReader<R>>,
+    ) -> std::result::Result<Self, Self::Error> {
+        if !decoder.get_ref().buffer().is_empty() {
+            return Err(decoder);
+        }
+        let (reader, decoder, eof, watchdog) = decoder.into_parts();
+        Ok(Self::from_parts(
+            reader.into_inner(),
+            decoder,
+            eof,
+            watchdog,
+        ))
+    }
+}
+
+impl<R> AsyncRead for Bz3Decoder<R>
+where
+    R: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            let output = this.decoder.output();
+            if !output.is_empty() {
+                let size = output.len().min(buf.remaining());
+                buf.put_slice(&output[..size]);
+                this.decoder.consume(size);
+                return Poll::Ready(Ok(()));
+            }
+            if *this.eof || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // read straight into the decoder; nothing is lost if this returns pending
+            let mut input = ReadBuf::new(this.decoder.input_buffer());
+            match this.reader.as_mut().poll_read(cx, &mut input) {
+                Poll::Ready(result) => {
+                    this.watchdog.reset();
+                    result?;
+                }
+                Poll::Pending => {
+                    this.watchdog.poll_expired(cx)?;
+                    return Poll::Pending;
+                }
+            }
+            let read_size = input.filled().len();
+            if read_size == 0 {
+                this.decoder.finish().map_err(Error::into_io_error)?;
+                *this.eof = true;
+            } else {
+                this.decoder
+                    .advance(read_size)
+                    .map_err(Error::into_io_error)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! AsyncWrite-based BZip3 compressor and decompressor.
+
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use ::tokio::io::AsyncWrite;
+use ::tokio::time::{sleep_until, Instant, Sleep};
+use pin_project_lite::pin_project;
+
+use crate::errors::*;
+use crate::pool::BufferPool;
+use crate::write::AutoFlush;
+use crate::{push, Bz3State, CrcMode};
+
+pin_project! {
+    /// Async bzip3 encoder, writing compressed data to an [`AsyncWrite`].
+    ///
+    /// Call [`shutdown`](::tokio::io::AsyncWriteExt::shutdown) when done: it compresses the final
+    /// partial block and shuts down the inner writer. Dropping the encoder without shutting it
+    /// down loses the buffered data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # tokio_test::block_on(async {
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let mut encoder = bzip3::tokio::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.write_all(b"hello, world").await.unwrap();
+    /// encoder.shutdown().await.unwrap();
+    /// # })
+    /// ```
+    pub struct Bz3Encoder<W> {
+        #[pin]
+        writer: W,
+        encoder: push::Encoder,
+        idle_timer: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+impl<W> fmt::Debug for Bz3Encoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Encoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3Encoder<W>
+where
+    W: AsyncWrite,
+{
+    /// Creates an async bzip3 encoder.
+    ///
+    /// The file header is written along with the first block, or on flush.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::BlockSize`] if the block size is invalid.
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        Ok(Self {
+            writer,
+            encoder: push::Encoder::new(block_size)?,
+            idle_timer: None,
+        })
+    }
+
+    /// Creates an async bzip3 encoder with the [default block size](crate::DEFAULT_BLOCK_SIZE).
+    pub fn with_defaults(writer: W) -> Self {
+        Self::new(writer, crate::DEFAULT_BLOCK_SIZE).expect("the default block size is valid")
+    }
+
+    /// Creates an async bzip3 encoder with a block size like `"16MiB"`; see
+    /// [`parse_block_size`](crate::parse_block_size).
+    pub fn with_block_size_str(writer: W, block_size: &str) -> Result<Self> {
+        Self::new(writer, crate::parse_block_size(block_size)?)
+    }
+
+    /// Sets whether shutting down appends a checksum of all the data.
+    ///
+    /// See [`write::Bz3Encoder::set_checksum`](crate::write::Bz3Encoder::set_checksum).
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.encoder.set_checksum(enabled);
+    }
+
+    /// Sets when the partial block is compressed and written without waiting for a full block;
+    /// see [`AutoFlush`].
+    ///
+    /// The size limit applies on write, the block then being written with the next write or
+    /// flush. The idle time applies while [`Bz3Encoder::idle_flush`] is awaited, e.g. in a
+    /// `select!` along with the source of the data.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the size limit is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # tokio_test::block_on(async {
+    /// use std::time::Duration;
+    /// use tokio::io::AsyncWriteExt;
+    /// use bzip3::write::AutoFlush;
+    ///
+    /// let mut encoder = bzip3::tokio::write::Bz3Encoder::new(Vec::new(), 1024 * 1024).unwrap();
+    /// encoder
+    ///     .set_auto_flush(AutoFlush {
+    ///         idle: Some(Duration::from_millis(10)),
+    ///         max_pending: None,
+    ///     })
+    ///     .unwrap();
+    /// encoder.write_all(b"hello, world").await.unwrap();
+    /// // nothing more to write for a while
+    /// encoder.idle_flush().await.unwrap();
+    /// let compressed = encoder.get_ref().clone();
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), b"hello, world");
+    /// # })
+    /// ```
+    pub fn set_auto_flush(&mut self, auto_flush: AutoFlush) -> Result<()> {
+        self.encoder.set_auto_flush(auto_flush)
+    }
+
+    /// Flushes like [`poll_flush`](AsyncWrite::poll_flush) once the data written but not
+    /// output yet has been idle for the time set with [`Bz3Encoder::set_auto_flush`], and
+    /// writes a block left by the size limit right away.
+    ///
+    /// This never completes while there's nothing to flush, so it's meant to be raced against
+    /// the next data to write.
+    pub fn poll_idle_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.encoder.output().is_empty() {
+            ready!(self.as_mut().poll_write_output(cx))?;
+            return self.project().writer.poll_flush(cx);
+        }
+        let Some(deadline) = self.encoder.idle_deadline() else {
+            return Poll::Pending;
+        };
+        let deadline = Instant::from_std(deadline);
+        let this = self.as_mut().project();
+        let timer = this
+            .idle_timer
+            .get_or_insert_with(|| Box::pin(sleep_until(deadline)));
+        if timer.deadline() != deadline {
+            timer.as_mut().reset(deadline);
+        }
+        ready!(timer.as_mut().poll(cx));
+        self.poll_flush(cx)
+    }
+
+    /// Waits for the data written to be idle, and flushes it; see
+    /// [`Bz3Encoder::poll_idle_flush`].
+    pub async fn idle_flush(&mut self) -> io::Result<()>
+    where
+        W: Unpin,
+    {
+        poll_fn(|cx| Pin::new(&mut *self).poll_idle_flush(cx)).await
+    }
+
+    /// Sets a pool to take the block buffer and the state from, and put them back to when
+    /// dropped.
+    ///
+    /// See [`write::Bz3Encoder::set_buffer_pool`](crate::write::Bz3Encoder::set_buffer_pool).
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.encoder.set_buffer_pool(pool);
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the first block has been compressed.
+    ///
+    /// See [`write::Bz3Encoder::state`](crate::write::Bz3Encoder::state).
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.encoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the first block has been compressed.
+    ///
+    /// See [`write::Bz3Encoder::state_mut`](crate::write::Bz3Encoder::state_mut).
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.encoder.state_mut()
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the compressed stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        self.project().writer
+    }
+
+    /// Consumes the encoder, returning the underlying writer.
+    ///
+    /// Data buffered in the encoder is lost.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes all pending compressed data to the inner writer.
+    fn poll_write_output(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            let output = this.encoder.output();
+            if output.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let size = ready!(this.writer.as_mut().poll_write(cx, output))?;
+            if size == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.encoder.consume(size);
+        }
+    }
+
+    /// Compresses the partial block, and writes all pending compressed data.
+    fn poll_finish_block(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_write_output(cx))?;
+        self.as_mut()
+            .project()
+            .encoder
+            .flush()
+            .map_err(Error::into_io_error)?;
+        self.poll_write_output(cx)
+    }
+}
+
+impl<W> AsyncWrite for Bz3Encoder<W>
+where
+    W: AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.as_mut().poll_write_output(cx))?;
+        let size = self
+            .project()
+            .encoder
+            .feed(buf)
+            .map_err(Error::into_io_error)?;
+        Poll::Ready(Ok(size))
+    }
+
+    /// Compresses the partial block like [`write::Bz3Encoder`](crate::write::Bz3Encoder) does on
+    /// flush, and then flushes the inner writer.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_finish_block(cx))?;
+        self.project().writer.poll_flush(cx)
+    }
+
+    /// Compresses the partial block, writes the checksum if enabled, and then shuts down the
+    /// inner writer.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.as_mut().poll_write_output(cx))?;
+            if self.encoder.is_finished() {
+                break;
+            }
+            self.as_mut()
+                .project()
+                .encoder
+                .finish()
+                .map_err(Error::into_io_error)?;
+        }
+        self.project().writer.poll_shutdown(cx)
+    }
+}
+
+pin_project! {
+    /// Async bzip3 decoder, writing decompressed data to an [`AsyncWrite`].
+    ///
+    /// Call [`shutdown`](::tokio::io::AsyncWriteExt::shutdown) when done; it checks that the
+    /// compressed stream is complete, and shuts down the inner writer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # tokio_test::block_on(async {
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// let compressed = bzip3::mem::compress(b"hello, world", 100 * 1024).unwrap();
+    /// let mut decoder = bzip3::tokio::write::Bz3Decoder::new(Vec::new());
+    /// decoder.write_all(&compressed).await.unwrap();
+    /// decoder.shutdown().await.unwrap();
+    /// assert_eq!(decoder.into_inner(), b"hello, world");
+    /// # })
+    /// ```
+    pub struct Bz3Decoder<W> {
+        #[pin]
+        writer: W,
+        decoder: push::Decoder,
+    }
+}
+
+impl<W> fmt::Debug for Bz3Decoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+impl<W> Bz3Decoder<W>
+where
+    W: AsyncWrite,
+{
+    /// Creates an async bzip3 decoder.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            decoder: push::Decoder::new(),
+        }
+    }
+
+    /// Returns the bzip3 block size, once the file header has been written.
+    pub fn block_size(&self) -> Option<usize> {
+        self.decoder.block_size()
+    }
+
+    /// Sets how a block failing its CRC check is handled.
+    ///
+    /// See [`read::Bz3Decoder::set_crc_mode`](crate::read::Bz3Decoder::set_crc_mode).
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.decoder.set_crc_mode(mode);
+    }
+
+    /// Returns the blocks which failed the CRC check so far in permissive mode, numbered as in
+    /// [`Error::BadCrc`].
+    pub fn crc_failures(&self) -> &[usize] {
+        self.decoder.crc_failures()
+    }
+
+    /// Sets whether the output of each block is verified after decoding.
+    ///
+    /// See [`read::Bz3Decoder::set_paranoid`](crate::read::Bz3Decoder::set_paranoid).
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.decoder.set_paranoid(enabled);
+    }
+
+    /// Sets whether the input may hold multiple concatenated bzip3 files, decoded one after
+    /// another as a single stream.
+    ///
+    /// This is off by default, and then anything following the first file is an error.
+    pub fn multiple_members(&mut self, enabled: bool) {
+        self.decoder.set_multiple_members(enabled);
+    }
+
+    /// Sets a pool to take the block buffer and the state from, and put them back to when
+    /// dropped.
+    ///
+    /// See [`write::Bz3Decoder::set_buffer_pool`](crate::write::Bz3Decoder::set_buffer_pool).
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.decoder.set_buffer_pool(pool);
+    }
+
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the file header has been read.
+    ///
+    /// See [`write::Bz3Decoder::state`](crate::write::Bz3Decoder::state).
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.decoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the file header has been read.
+    ///
+    /// See [`write::Bz3Decoder::state_mut`](crate::write::Bz3Decoder::state_mut).
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.decoder.state_mut()
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the decompressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Acquires a pinned mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly corrupts the decompressed stream.
+    pub fn get_pin_mut(self: Pin<&mut Self>) -> Pin<&mut W> {
+        self.project().writer
+    }
+
+    /// Consumes the decoder, returning the underlying writer.
+    ///
+    /// Data buffered in the decoder is lost.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes all pending decompressed data to the inner writer.
+    fn poll_write_output(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        loop {
+            let output = this.decoder.output();
+            if output.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let size = ready!(this.writer.as_mut().poll_write(cx, output))?;
+            if size == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            this.decoder.consume(size);
+        }
+    }
+}
+
+impl<W> AsyncWrite for Bz3Decoder<W>
+where
+    W: AsyncWrite,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.as_mut().poll_write_output(cx))?;
+        let size = self
+            .project()
+            .decoder
+            .feed(buf)
+            .map_err(Error::into_io_error)?;
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_write_output(cx))?;
+        self.project().writer.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_write_output(cx))?;
+        let this = self.project();
+        this.decoder.finish().map_err(Error::into_io_error)?;
+        this.writer.poll_shutdown(cx)
+    }
+}
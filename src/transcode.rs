@@ -0,0 +1,111 @@
+//! Converting other compressed formats to bzip3 without going through a temporary file.
+//!
+//! The data is streamed from one codec to the other, so memory stays bounded by the block
+//! sizes whatever the size of the files.
+//!
+//! [`recompress_from`] takes the decoder of any format as a [`Read`], e.g. from the `flate2`,
+//! `xz2` or `zstd` crates; with the `bzip2` feature, [`bz2_to_bz3`] and [`bz3_to_bz2`] convert
+//! bzip2 files both ways.
+//!
+//! # Examples
+//!
+//! Converting a gzip file, made of one or more gzip members:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use flate2::read::MultiGzDecoder;
+//! use bzip3::transcode;
+//!
+//! let input = MultiGzDecoder::new(File::open("logs.gz").unwrap());
+//! let output = File::create("logs.bz3").unwrap();
+//! transcode::recompress_from(input, output, 16 * 1024 * 1024).unwrap();
+//! ```
+//!
+//! The other formats go the same way, with `xz2::read::XzDecoder::new_multi_decoder` or
+//! `zstd::Decoder::new`. Use the decoders handling concatenated streams where there's a choice,
+//! since the single-stream ones stop silently at the end of the first stream.
+
+use std::io;
+use std::io::{Read, Write};
+
+use crate::errors::*;
+#[cfg(feature = "bzip2")]
+use crate::read;
+use crate::write;
+
+/// Compresses all the data read from `decoder` into a bzip3 file written to `writer`, and
+/// returns the writer.
+///
+/// `decoder` is typically the decoder of another compressed format. Reads may return any
+/// number of bytes, and [`Interrupted`](io::ErrorKind::Interrupted) errors are retried;
+/// reading stops at the first read returning zero bytes.
+///
+/// # Errors
+///
+/// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors,
+/// including the errors of `decoder`.
+pub fn recompress_from<R, W>(mut decoder: R, writer: W, block_size: usize) -> Result<W>
+where
+    R: Read,
+    W: Write,
+{
+    let mut encoder = write::Bz3Encoder::new(writer, block_size)?;
+    // the encoder gathers the input into whole blocks, whatever the sizes of the reads
+    io::copy(&mut decoder, &mut encoder)?;
+    Ok(encoder.finish()?)
+}
+
+/// Converts a bzip2 file read from `reader` into a bzip3 file written to `writer`, and returns
+/// the writer.
+///
+/// Files made of several bzip2 streams, like the output of `pbzip2`, are converted whole.
+///
+/// # Errors
+///
+/// [`Error::BlockSize`] if the block size is invalid, and [`Error::Io`] on all IO errors,
+/// including invalid bzip2 input.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use bzip3::transcode;
+///
+/// let input = File::open("corpus.bz2").unwrap();
+/// let output = File::create("corpus.bz3").unwrap();
+/// transcode::bz2_to_bz3(input, output, 16 * 1024 * 1024).unwrap();
+/// ```
+#[cfg(feature = "bzip2")]
+pub fn bz2_to_bz3<R, W>(reader: R, writer: W, block_size: usize) -> Result<W>
+where
+    R: Read,
+    W: Write,
+{
+    recompress_from(bzip2::read::MultiBzDecoder::new(reader), writer, block_size)
+}
+
+/// Converts a bzip3 file read from `reader` into a bzip2 file written to `writer`, and returns
+/// the writer.
+///
+/// `level` is the bzip2 compression level, from 1 to 9, as with `bzip2 -1` to `bzip2 -9`.
+///
+/// # Errors
+///
+/// [`Error::InvalidSignature`] if it's not a bzip3 file, [`Error::InvalidArgument`] if the level
+/// is invalid, and [`Error::Io`] on all IO errors, including invalid input.
+#[cfg(feature = "bzip2")]
+pub fn bz3_to_bz2<R, W>(reader: R, writer: W, level: u32) -> Result<W>
+where
+    R: Read,
+    W: Write,
+{
+    if !(1..=9).contains(&level) {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid bzip2 compression level: {level}"
+        )));
+    }
+    let mut decoder = read::Bz3Decoder::new(reader)?;
+    let mut encoder = bzip2::write::BzEncoder::new(writer, bzip2::Compression::new(level));
+    io::copy(&mut decoder, &mut encoder)?;
+    Ok(encoder.finish()?)
+}
@@ -0,0 +1,205 @@
+//! JavaScript entry points, for browsers and edge runtimes.
+//!
+//! Build for `wasm32-unknown-unknown` with this feature and `bundled`, as there's no system
+//! libbz3 to link there, e.g. with `wasm-pack build --features wasm-bindgen,bundled`. Data goes
+//! in and out as `Uint8Array`s, and errors are thrown as JavaScript `Error`s:
+//!
+//! ```js
+//! import { compress, decompress } from "bzip3";
+//!
+//! const data = new TextEncoder().encode("hello, world");
+//! const compressed = compress(data, 1024 * 1024);
+//! console.log(new TextDecoder().decode(decompress(compressed)));
+//! ```
+//!
+//! For files too large to hold in memory, [`CompressTransformer`] and [`DecompressTransformer`]
+//! plug the [`push`] codecs into Web Streams, a block at a time, e.g. compressing a file picked
+//! by the user while uploading it:
+//!
+//! ```js
+//! import { compressStream } from "bzip3";
+//!
+//! const body = compressStream(file.stream(), 1024 * 1024);
+//! await fetch("/upload", { method: "POST", body, duplex: "half" });
+//! ```
+//!
+//! or, as transformers of a `TransformStream`:
+//!
+//! ```js
+//! import { DecompressTransformer } from "bzip3";
+//!
+//! const response = await fetch("/data.bz3");
+//! const stream = response.body.pipeThrough(new TransformStream(new DecompressTransformer()));
+//! ```
+
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+use web_sys::{ReadableStream, TransformStream, TransformStreamDefaultController};
+
+use crate::push;
+
+/// Compresses `data` into a complete bzip3 file, with blocks of `block_size` bytes.
+///
+/// # Errors
+///
+/// Throws if the block size is invalid, or compressing a block fails.
+#[wasm_bindgen]
+pub fn compress(data: &[u8], block_size: usize) -> Result<Vec<u8>, JsError> {
+    Ok(crate::mem::compress(data, block_size)?)
+}
+
+/// Decompresses a complete bzip3 file.
+///
+/// # Errors
+///
+/// Throws if `data` isn't a valid bzip3 file.
+#[wasm_bindgen]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(crate::mem::decompress(data)?)
+}
+
+/// `readable`, a stream of `Uint8Array`s, compressed.
+///
+/// # Errors
+///
+/// Throws if the block size is invalid.
+#[wasm_bindgen(js_name = compressStream)]
+pub fn compress_stream(
+    readable: &ReadableStream,
+    block_size: usize,
+) -> Result<ReadableStream, JsValue> {
+    let transformer = CompressTransformer::new(block_size)?;
+    pipe_through(readable, transformer.into())
+}
+
+/// `readable`, a stream of `Uint8Array`s of a bzip3 file, decompressed.
+#[wasm_bindgen(js_name = decompressStream)]
+pub fn decompress_stream(readable: &ReadableStream) -> Result<ReadableStream, JsValue> {
+    pipe_through(readable, DecompressTransformer::new().into())
+}
+
+fn pipe_through(
+    readable: &ReadableStream,
+    transformer: JsValue,
+) -> Result<ReadableStream, JsValue> {
+    let transform = TransformStream::new_with_transformer(transformer.unchecked_ref())?;
+    Ok(readable.pipe_through(transform.unchecked_ref()))
+}
+
+/// Enqueues `output` as a `Uint8Array` chunk, unless it's empty.
+fn enqueue(controller: &TransformStreamDefaultController, output: &[u8]) -> Result<(), JsValue> {
+    if !output.is_empty() {
+        controller.enqueue_with_chunk(&Uint8Array::from(output))?;
+    }
+    Ok(())
+}
+
+/// Transformer of a `TransformStream` compressing `Uint8Array` chunks into a bzip3 file.
+///
+/// Output chunks are whole blocks, or the file header; a block is only compressed once full,
+/// or at the end of the input.
+#[wasm_bindgen]
+pub struct CompressTransformer {
+    encoder: push::Encoder,
+}
+
+#[wasm_bindgen]
+impl CompressTransformer {
+    /// # Errors
+    ///
+    /// Throws if the block size is invalid.
+    #[wasm_bindgen(constructor)]
+    pub fn new(block_size: usize) -> Result<CompressTransformer, JsError> {
+        Ok(Self {
+            encoder: push::Encoder::new(block_size)?,
+        })
+    }
+
+    pub fn transform(
+        &mut self,
+        chunk: &[u8],
+        controller: &TransformStreamDefaultController,
+    ) -> Result<(), JsValue> {
+        let mut input = chunk;
+        while !input.is_empty() {
+            let size = self.encoder.feed(input).map_err(JsError::from)?;
+            input = &input[size..];
+            self.enqueue_output(controller)?;
+        }
+        Ok(())
+    }
+
+    /// Compresses the partial block, and ends the file.
+    pub fn flush(&mut self, controller: &TransformStreamDefaultController) -> Result<(), JsValue> {
+        loop {
+            self.enqueue_output(controller)?;
+            if self.encoder.is_finished() {
+                return Ok(());
+            }
+            self.encoder.finish().map_err(JsError::from)?;
+        }
+    }
+
+    fn enqueue_output(
+        &mut self,
+        controller: &TransformStreamDefaultController,
+    ) -> Result<(), JsValue> {
+        let output = self.encoder.output();
+        enqueue(controller, output)?;
+        self.encoder.consume(output.len());
+        Ok(())
+    }
+}
+
+/// Transformer of a `TransformStream` decompressing `Uint8Array` chunks of a bzip3 file.
+///
+/// Output chunks are whole decompressed blocks, so memory use is bounded by the block size of
+/// the file, not its size.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct DecompressTransformer {
+    decoder: push::Decoder,
+}
+
+#[wasm_bindgen]
+impl DecompressTransformer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> DecompressTransformer {
+        Self::default()
+    }
+
+    /// # Errors
+    ///
+    /// Throws, erroring the stream, on invalid data.
+    pub fn transform(
+        &mut self,
+        chunk: &[u8],
+        controller: &TransformStreamDefaultController,
+    ) -> Result<(), JsValue> {
+        let mut input = chunk;
+        while !input.is_empty() {
+            let size = self.decoder.feed(input).map_err(JsError::from)?;
+            input = &input[size..];
+            self.enqueue_output(controller)?;
+        }
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Throws if the file is truncated.
+    pub fn flush(&mut self, controller: &TransformStreamDefaultController) -> Result<(), JsValue> {
+        self.decoder.finish().map_err(JsError::from)?;
+        self.enqueue_output(controller)
+    }
+
+    fn enqueue_output(
+        &mut self,
+        controller: &TransformStreamDefaultController,
+    ) -> Result<(), JsValue> {
+        let output = self.decoder.output();
+        enqueue(controller, output)?;
+        self.decoder.consume(output.len());
+        Ok(())
+    }
+}
@@ -1,64 +1,556 @@
 //! Write-based BZip3 compressor and decompressor.
 
+use std::fmt;
 use std::io;
-use std::io::{Cursor, Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::io::Write;
+use std::time::Instant;
 
+use crate::chunking::Chunking;
 use crate::errors::*;
-use crate::{bound, Bz3State, BLOCK_SIZE_MAX, BLOCK_SIZE_MIN, MAGIC_NUMBER};
+use crate::metadata::Metadata;
+use crate::pool::BufferPool;
+use crate::seek::BlockTracker;
+use crate::{push, Bz3State, CrcMode, Level, TryWriteAll};
+
+pub use crate::push::AutoFlush;
 
 pub struct Bz3Encoder<W>
 where
     W: Write,
 {
-    writer: W,
-    state: Bz3State,
-    buffer: Vec<u8>,
-    buffer_pos: usize,
-    block_size: usize,
+    /// `None` once finished.
+    writer: Option<W>,
+    encoder: push::Encoder,
+    blocks: BlockTracker,
+    /// Largest block size to grow to; `None` unless block size growth is enabled.
+    max_block_size: Option<usize>,
+    /// Uncompressed offset where the current frame starts.
+    frame_offset: u64,
+    /// Whether the pending output has been recorded in `blocks`.
+    output_recorded: bool,
 }
 
+impl<W> fmt::Debug for Bz3Encoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Encoder");
+        self.encoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
+    }
+}
+
+/// Number of blocks of a frame after which block size growth starts the next frame.
+const GROWTH_FRAME_BLOCKS: u64 = 4;
+
 impl<W> Bz3Encoder<W>
 where
     W: Write,
 {
     /// Creates a new bzip3 stream encoder.
     ///
-    /// Valid block size is between [`BLOCK_SIZE_MIN`] and [`BLOCK_SIZE_MAX`] bytes.
+    /// Valid block size is between [`BLOCK_SIZE_MIN`](crate::BLOCK_SIZE_MIN) and
+    /// [`BLOCK_SIZE_MAX`](crate::BLOCK_SIZE_MAX) bytes.
     ///
     /// # Errors
     ///
     /// This returns [`Error::BlockSize`] if the block size is invalid.
-    pub fn new(mut writer: W, block_size: usize) -> Result<Self> {
-        let state = Bz3State::new(block_size)?;
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        let mut encoder = Self {
+            writer: Some(writer),
+            encoder: push::Encoder::new(block_size)?,
+            blocks: BlockTracker::new(),
+            max_block_size: None,
+            frame_offset: 0,
+            output_recorded: false,
+        };
+        // the file header
+        let header = encoder.encoder.output();
+        encoder.writer.as_mut().unwrap().write_all(header)?;
+        encoder.encoder.consume(header.len());
+        Ok(encoder)
+    }
+
+    /// Creates a new bzip3 stream encoder with the
+    /// [default block size](crate::DEFAULT_BLOCK_SIZE).
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::Io`] if writing the file header fails.
+    pub fn with_defaults(writer: W) -> Result<Self> {
+        Self::new(writer, crate::DEFAULT_BLOCK_SIZE)
+    }
 
-        let mut header = Cursor::new([0_u8; MAGIC_NUMBER.len() + 4 /* i32 */]);
-        header.write_all(MAGIC_NUMBER).unwrap();
-        header.write_i32::<LE>(block_size as i32).unwrap();
-        writer.write_all(header.get_ref())?;
+    /// Creates a new bzip3 stream encoder with the block size of a compression [`Level`].
+    pub fn with_level(writer: W, level: Level) -> Result<Self> {
+        Self::new(writer, level.block_size())
+    }
 
-        let buffer_size = bound(block_size);
-        let buffer = vec![0; buffer_size];
+    /// Creates a new bzip3 stream encoder with a block size like `"16MiB"`; see
+    /// [`parse_block_size`](crate::parse_block_size).
+    pub fn with_block_size_str(writer: W, block_size: &str) -> Result<Self> {
+        Self::new(writer, crate::parse_block_size(block_size)?)
+    }
 
-        Ok(Self {
-            writer,
-            state,
-            buffer,
-            buffer_pos: 0,
-            block_size,
-        })
-    }
-
-    /// Compresses up to a whole block and write to `self.writer`.
-    fn compress_block(&mut self) -> Result<()> {
-        // self.buffer_pos as the size of data available to be compressed
-        let data_size = self.buffer_pos;
-        debug_assert!(data_size <= self.block_size);
-        let new_size = self.state.encode_block(&mut self.buffer, data_size)?;
-        self.writer.write_i32::<LE>(new_size as i32)?;
-        self.writer.write_i32::<LE>(data_size as i32)?;
-        self.writer.write_all(&self.buffer[..new_size])?;
+    /// Creates a new bzip3 stream encoder, writing the given [metadata](crate::metadata) after
+    /// the file header.
+    ///
+    /// # Errors
+    ///
+    /// Besides the errors of [`Bz3Encoder::new`], this returns [`Error::InvalidArgument`] if the
+    /// metadata is too large.
+    pub fn with_metadata(writer: W, block_size: usize, metadata: &Metadata) -> Result<Self> {
+        let block = metadata.to_block()?;
+        let mut encoder = Self::new(writer, block_size)?;
+        encoder.writer.as_mut().unwrap().write_all(&block)?;
+        Ok(encoder)
+    }
+
+    /// Sets whether to end the stream with a checksum of all the uncompressed data, which
+    /// decoders verify.
+    ///
+    /// The checksum is an [extension block](crate::frame::CHECKSUM_TAG) written by
+    /// [`Bz3Encoder::finish`], or on drop. Other bzip3 implementations may reject files with
+    /// extension blocks. This is off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.set_checksum(true);
+    /// encoder.write_all(b"hello, world").unwrap();
+    /// let mut compressed = encoder.finish().unwrap();
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), b"hello, world");
+    ///
+    /// // flip a bit of the checksum
+    /// *compressed.last_mut().unwrap() ^= 1;
+    /// assert!(matches!(
+    ///     bzip3::mem::decompress(&compressed),
+    ///     Err(bzip3::Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData
+    /// ));
+    /// ```
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.encoder.set_checksum(enabled);
+    }
+
+    /// Sets whether to write the blocks that would grow by compressing them, like those of
+    /// already compressed data, as [stored blocks](crate::frame::STORED_BLOCK), holding the
+    /// data as it is.
+    ///
+    /// The decoders of this crate read stored blocks transparently, but other bzip3
+    /// implementations and older versions of this crate reject the files having any. This is
+    /// off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// // random-looking data, which doesn't compress
+    /// let data = (0..100_000_u64)
+    ///     .map(|x| (x.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) as u8)
+    ///     .collect::<Vec<_>>();
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.set_stored_blocks(true);
+    /// encoder.write_all(&data).unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    /// assert!(compressed.len() <= data.len() + 9 + 8);
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    /// ```
+    pub fn set_stored_blocks(&mut self, enabled: bool) {
+        self.encoder.set_stored_blocks(enabled);
+    }
+
+    /// Sets [content-defined chunking](crate::chunking), ending blocks at boundaries found in
+    /// the data instead of every block size bytes, or fixed-size blocks with `None`, the
+    /// default.
+    ///
+    /// Call this before writing anything; blocks already gathered are cut the old way.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the chunk sizes are invalid, or the maximum size exceeds
+    /// the block size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use bzip3::chunking::Chunking;
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 1024 * 1024).unwrap();
+    /// encoder.set_chunking(Some(Chunking::new(256 * 1024))).unwrap();
+    /// encoder.write_all(b"hello, world").unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), b"hello, world");
+    /// ```
+    pub fn set_chunking(&mut self, chunking: Option<Chunking>) -> Result<()> {
+        self.encoder.set_chunking(chunking)
+    }
+
+    /// Sets whether blocks are compressed in a background thread, off by default.
+    ///
+    /// With double buffering, a full block is handed to the thread, and writing goes on into a
+    /// second block buffer meanwhile, so the work of producing the data overlaps with
+    /// compressing it. This takes a thread, another block buffer, and the output lags a block
+    /// behind: a block is written once the next one is full, or on [`Write::flush`]. For more
+    /// threads, see `parallel::Bz3ParallelEncoder`, with the `parallel` feature.
+    ///
+    /// Call this before writing anything.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.set_double_buffering(true);
+    /// for i in 0..100_000 {
+    ///     writeln!(encoder, "record {i}").unwrap();
+    /// }
+    /// let compressed = encoder.finish().unwrap();
+    /// let decompressed = bzip3::mem::decompress(&compressed).unwrap();
+    /// assert!(decompressed.ends_with(b"record 99999\n"));
+    /// ```
+    pub fn set_double_buffering(&mut self, enabled: bool) {
+        self.encoder.set_double_buffering(enabled);
+    }
+
+    /// Sets when the partial block is compressed and written without waiting for a full block;
+    /// see [`AutoFlush`].
+    ///
+    /// The size limit applies on write, as if the blocks were that small. The encoder has no
+    /// timer of its own: with an idle time set, call [`Bz3Encoder::flush_if_idle`] regularly,
+    /// e.g. from the event loop of the protocol, or when [`Bz3Encoder::idle_deadline`] passes.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::InvalidArgument`] if the size limit is zero, and [`Error::Io`] on IO errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use std::time::Duration;
+    /// use bzip3::write::AutoFlush;
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 1024 * 1024).unwrap();
+    /// encoder
+    ///     .set_auto_flush(AutoFlush {
+    ///         idle: Some(Duration::ZERO),
+    ///         max_pending: Some(64 * 1024),
+    ///     })
+    ///     .unwrap();
+    /// encoder.write_all(b"hello, world").unwrap();
+    /// assert!(encoder.flush_if_idle().unwrap());
+    /// let compressed = encoder.get_ref().clone();
+    /// assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), b"hello, world");
+    /// ```
+    pub fn set_auto_flush(&mut self, auto_flush: AutoFlush) -> Result<()> {
+        self.encoder.set_auto_flush(auto_flush)?;
+        self.write_output()?;
+        Ok(())
+    }
+
+    /// Returns when the data written but not output yet is due to be flushed, for having been
+    /// idle for the time set with [`Bz3Encoder::set_auto_flush`]; `None` if there's no such
+    /// data, or no idle time set.
+    pub fn idle_deadline(&self) -> Option<Instant> {
+        self.encoder.idle_deadline()
+    }
+
+    /// Flushes the data written but not output yet if it's been idle for the time set with
+    /// [`Bz3Encoder::set_auto_flush`].
+    ///
+    /// Returns whether it did.
+    pub fn flush_if_idle(&mut self) -> io::Result<bool> {
+        match self.idle_deadline() {
+            Some(deadline) if deadline <= Instant::now() => {
+                self.flush()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Changes the block size, finishing the current frame and starting a new one, e.g. to tune
+    /// a long-lived stream as its traffic changes.
+    ///
+    /// The partial block is compressed, and the checksum written if enabled, as at the end of
+    /// the stream; the data from then on goes to a new frame with the new block size. This
+    /// does nothing if the block size is unchanged. As with
+    /// [block size growth](Bz3Encoder::set_block_size_growth), the output is made of multiple
+    /// concatenated bzip3 files, which decoders need to be told to expect.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if the block size is invalid, [`Error::InvalidArgument`] if it's
+    /// smaller than the maximum chunk size of [content-defined chunking](Bz3Encoder::set_chunking),
+    /// and [`Error::Io`] on IO errors. Nothing changes on the first two.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.write_all(b"hello, ").unwrap();
+    /// encoder.set_block_size(1024 * 1024).unwrap();
+    /// encoder.write_all(b"world").unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    ///
+    /// let mut decoder = bzip3::read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    /// decoder.multiple_members(true);
+    /// let mut contents = String::new();
+    /// decoder.read_to_string(&mut contents).unwrap();
+    /// assert_eq!(contents, "hello, world");
+    /// ```
+    pub fn set_block_size(&mut self, block_size: usize) -> Result<()> {
+        if block_size == self.encoder.block_size() {
+            return Ok(());
+        }
+        self.encoder.check_frame(block_size)?;
+        self.start_frame(block_size)?;
+        Ok(())
+    }
+
+    /// Returns the current block size.
+    pub fn block_size(&self) -> usize {
+        self.encoder.block_size()
+    }
+
+    /// Sets block size growth for streams of unknown size, up to the given block size, or
+    /// disables it with `None`, the default.
+    ///
+    /// The stream then starts with the block size given at creation, which keeps the latency
+    /// and memory use low for short streams, and doubles it as more data comes in: once a frame
+    /// holds 4 blocks, the next data goes to a new frame, with the block size doubled, up to
+    /// `max_block_size`. Larger blocks compress better, and each frame only costs a file header,
+    /// and a checksum if enabled.
+    ///
+    /// The output is made of multiple concatenated bzip3 files, which decoders need to be told
+    /// to expect, as with [`read::Bz3Decoder::multiple_members`](crate::read::Bz3Decoder::multiple_members).
+    /// The offsets reported to the [block callback](Bz3Encoder::set_block_callback) are
+    /// relative to the start of the first file.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::BlockSize`] if `max_block_size` is invalid, or smaller than the current block
+    /// size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// let data = vec![b'x'; 1024 * 1024];
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 65 * 1024).unwrap();
+    /// encoder.set_block_size_growth(Some(1024 * 1024)).unwrap();
+    /// encoder.write_all(&data).unwrap();
+    /// let compressed = encoder.finish().unwrap();
+    ///
+    /// let mut decoder = bzip3::read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    /// decoder.multiple_members(true);
+    /// let mut decompressed = Vec::new();
+    /// decoder.read_to_end(&mut decompressed).unwrap();
+    /// assert_eq!(decompressed, data);
+    /// ```
+    pub fn set_block_size_growth(&mut self, max_block_size: Option<usize>) -> Result<()> {
+        if let Some(size) = max_block_size {
+            if !Bz3State::check_block_size(size) || size < self.encoder.block_size() {
+                return Err(Error::BlockSize);
+            }
+        }
+        self.max_block_size = max_block_size;
+        Ok(())
+    }
+
+    /// Sets a pool to take the block buffer and the state from, and put them back to when
+    /// dropped; see [`BufferPool`].
+    ///
+    /// Call this before writing anything; a buffer or state already allocated is put back to
+    /// the pool too, but wasn't taken from it.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.encoder.set_buffer_pool(pool);
+    }
+
+    /// Returns the number of blocks compressed so far.
+    pub fn block_count(&self) -> usize {
+        self.encoder.blocks()
+    }
+
+    /// Returns the libbz3 state, once the first block has been compressed, e.g. to check the
+    /// [CRC](Bz3State::last_crc) of the last block, or the [last error](Bz3State::last_error)
+    /// of libbz3.
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.encoder.state()
+    }
+
+    /// Returns the libbz3 state mutably, once the first block has been compressed, e.g. for
+    /// the [description](Bz3State::error) of the last error.
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.encoder.state_mut()
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the inner writer, e.g. to take the output written to a
+    /// `Vec` so far.
+    ///
+    /// Writing to it directly corrupts the output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.as_mut().unwrap()
+    }
+
+    /// Ends the file on the inner writer, and carries on with another file on `writer`, e.g. to
+    /// rotate log files, or to reconnect a network sink.
+    ///
+    /// The current frame is finished as with [`Bz3Encoder::finish`], and the old writer is
+    /// flushed and returned. The new writer gets a file header right away, and a checksum at the
+    /// end if enabled, but not the [metadata](Bz3Encoder::with_metadata). The settings, the
+    /// state and the buffers carry over, and the offsets reported to the
+    /// [block callback](Bz3Encoder::set_block_callback) start over from the new file.
+    ///
+    /// # Errors
+    ///
+    /// On IO errors with either writer, the encoder is left in an unspecified state, and
+    /// should be dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// encoder.write_all(b"hello, ").unwrap();
+    /// let first = encoder.replace_inner(Vec::new()).unwrap();
+    /// encoder.write_all(b"world").unwrap();
+    /// let second = encoder.finish().unwrap();
+    ///
+    /// assert_eq!(bzip3::mem::decompress(&first).unwrap(), b"hello, ");
+    /// assert_eq!(bzip3::mem::decompress(&second).unwrap(), b"world");
+    /// ```
+    pub fn replace_inner(&mut self, mut writer: W) -> io::Result<W> {
+        self.try_finish()?;
+        self.encoder
+            .start_frame(self.encoder.block_size())
+            .map_err(Error::into_io_error)?;
+        let header = self.encoder.output();
+        writer.write_all(header)?;
+        self.encoder.consume(header.len());
+        self.blocks.reset();
+        self.frame_offset = 0;
+        let mut writer = self.writer.replace(writer).unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Compresses the partial block, writes the checksum if enabled, and returns the inner
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        loop {
+            self.write_output()?;
+            if self.encoder.is_finished() {
+                return Ok(());
+            }
+            self.encoder.finish().map_err(Error::into_io_error)?;
+        }
+    }
+
+    /// Sets a callback invoked with `(block_index, compressed_offset, uncompressed_offset)` each
+    /// time a block has been written, e.g. to build an index stored out-of-band while encoding.
+    ///
+    /// Offsets are relative to the start of the file header, as in a
+    /// [`Bz3Index`](crate::seek::Bz3Index). Blocks written before this is called aren't reported,
+    /// but still count for the offsets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let blocks = Arc::new(Mutex::new(Vec::new()));
+    /// let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * 1024).unwrap();
+    /// let blocks2 = Arc::clone(&blocks);
+    /// encoder.set_block_callback(move |block, compressed_offset, uncompressed_offset| {
+    ///     blocks2.lock().unwrap().push((block, compressed_offset, uncompressed_offset));
+    /// });
+    /// encoder.write_all(&vec![b'x'; 250 * 1024]).unwrap();
+    /// encoder.flush().unwrap();
+    ///
+    /// let blocks = blocks.lock().unwrap();
+    /// assert_eq!(blocks.len(), 3);
+    /// assert_eq!(blocks[0], (0, 9, 0));
+    /// assert_eq!(blocks[2].2, 200 * 1024);
+    /// ```
+    pub fn set_block_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(usize, u64, u64) + Send + Sync + 'static,
+    {
+        self.blocks.set_callback(callback);
+    }
+
+    /// Starts a new frame with the block size grown, if it's due and the current frame ends
+    /// with a whole block.
+    fn grow_block_size(&mut self) -> io::Result<()> {
+        let Some(max_block_size) = self.max_block_size else {
+            return Ok(());
+        };
+        let block_size = self.encoder.block_size();
+        let frame_size = self.blocks.uncompressed_offset() - self.frame_offset;
+        if block_size >= max_block_size
+            || frame_size < GROWTH_FRAME_BLOCKS * block_size as u64
+            || self.encoder.pending_input() != 0
+        {
+            return Ok(());
+        }
+        self.start_frame((block_size * 2).min(max_block_size))
+    }
+
+    /// Finishes the current frame, and starts another with the given block size.
+    fn start_frame(&mut self, block_size: usize) -> io::Result<()> {
+        self.try_finish()?;
+        self.encoder
+            .start_frame(block_size)
+            .map_err(Error::into_io_error)?;
+        let header = self.encoder.output();
+        self.writer.as_mut().unwrap().write_all(header)?;
+        self.blocks.record_frame_header();
+        self.encoder.consume(header.len());
+        self.frame_offset = self.blocks.uncompressed_offset();
+        Ok(())
+    }
+
+    /// Writes all pending compressed data, which is a whole block, to `self.writer`.
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.encoder.output();
+        if output.is_empty() {
+            return Ok(());
+        }
+        if !self.output_recorded {
+            self.blocks.record(output);
+            self.output_recorded = true;
+        }
+        // what's written is consumed even if the writer fills up, so it isn't written twice
+        let size = output.len();
+        let written = self.writer.as_mut().unwrap().try_write_all(output)?;
+        self.encoder.consume(written);
+        if written < size {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.output_recorded = false;
         Ok(())
     }
 }
@@ -68,7 +560,9 @@ where
     W: Write,
 {
     fn drop(&mut self) {
-        let _ = self.flush();
+        if self.writer.is_some() {
+            let _ = self.try_finish();
+        }
     }
 }
 
@@ -77,68 +571,49 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let mut write_size = buf.len();
-        let remaining_size = self.block_size - self.buffer_pos;
-
-        if write_size > remaining_size {
-            write_size = remaining_size;
-        }
-
-        self.buffer[self.buffer_pos..(self.buffer_pos + write_size)]
-            .copy_from_slice(&buf[..write_size]);
-
-        self.buffer_pos += write_size;
-
-        if self.buffer_pos == self.block_size {
-            // process the whole buffer
-            // here the whole data with block_size is filled and needs to be compressed
-            self.compress_block().map_err(Error::into_io_error)?;
-            self.buffer_pos = 0;
+        // output left over from a failed write goes first
+        self.write_output()?;
+        // only once more data comes, not to end with an empty frame
+        self.grow_block_size()?;
+        // a whole block gets compressed once filled
+        let write_size = self.encoder.feed(buf).map_err(Error::into_io_error)?;
+        // `buf` is taken either way; a failed write of the output comes up with the next call
+        if let Err(e) = self.write_output() {
+            if write_size == 0 {
+                return Err(e);
+            }
         }
-
         Ok(write_size)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        if self.buffer_pos != 0 {
-            self.compress_block().map_err(Error::into_io_error)?;
+        // with double buffering, the block compressing in the background comes out first
+        loop {
+            self.encoder.flush().map_err(Error::into_io_error)?;
+            if self.encoder.output().is_empty() {
+                return Ok(());
+            }
+            self.write_output()?;
         }
-        self.buffer_pos = 0;
-        Ok(())
     }
 }
 
-const BLOCK_HEADER_SIZE: usize = 2 * 4 /* i32 */;
-
 pub struct Bz3Decoder<W>
 where
     W: Write,
 {
     writer: W,
-    state: Option<Bz3State>,
-    buffer: Vec<u8>,
-    buffer_pos: usize,
-    header_len: usize,
-    block_header_buf: [u8; BLOCK_HEADER_SIZE], /* (i32, i32) */
-    block_header_buf_pos: usize,
-    /// If present, the block header has been read, and this decoder now is waiting
-    /// for reading the block data.
-    block_header: Option<BlockHeader>,
+    decoder: push::Decoder,
 }
 
-struct BlockHeader {
-    new_size: i32,
-    read_size: i32,
-}
-
-impl BlockHeader {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let new_size = reader.read_i32::<LE>()?;
-        let read_size = reader.read_i32::<LE>()?;
-        Ok(Self {
-            new_size,
-            read_size,
-        })
+impl<W> fmt::Debug for Bz3Decoder<W>
+where
+    W: Write,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Bz3Decoder");
+        self.decoder.debug_fields(&mut s);
+        s.finish_non_exhaustive()
     }
 }
 
@@ -147,48 +622,109 @@ where
     W: Write,
 {
     pub fn new(writer: W) -> Self {
-        let header_len = MAGIC_NUMBER.len() + 4 /* i32 */;
         Self {
-            state: None, /* can't initialize Bz3State; block size hasn't been read */
             writer,
-            buffer: vec![0_u8; header_len], /* a minimum space for reading magic/header first */
-            buffer_pos: 0,
-            header_len,
-            block_header_buf: [0_u8; 8],
-            block_header_buf_pos: 0,
-            block_header: None,
+            decoder: push::Decoder::new(),
         }
     }
 
-    fn initialize(&mut self) -> Result<()> {
-        let mut cursor = Cursor::new(&mut self.buffer);
-        let mut magic = [0_u8; MAGIC_NUMBER.len()];
-        cursor.read_exact(&mut magic).unwrap();
-        if &magic != MAGIC_NUMBER {
-            return Err(Error::InvalidSignature);
-        }
-        let block_size = cursor.read_i32::<LE>().unwrap() as usize;
-        // reinitialize the buffer
-        let buffer_size = bound(block_size);
-        self.buffer = vec![0_u8; buffer_size];
-        self.state = Some(Bz3State::new(block_size)?);
-        Ok(())
+    /// Returns the number of blocks decoded so far.
+    pub fn block_count(&self) -> usize {
+        self.decoder.blocks()
     }
 
-    fn decompress_block(&mut self) -> Result<()> {
-        let state = self.state.as_mut();
-        let state = state.unwrap();
+    /// Returns the libbz3 state, once the file header has been read, e.g. to check the
+    /// [CRC](Bz3State::last_crc) of the last block decoded, or the
+    /// [last error](Bz3State::last_error) of libbz3.
+    pub fn state(&self) -> Option<&Bz3State> {
+        self.decoder.state()
+    }
 
-        let Some(block_header) = &self.block_header else {
-            unreachable!()
-        };
-        state.decode_block(
-            &mut self.buffer,
-            block_header.new_size as _,
-            block_header.read_size as _,
-        )?;
-        self.writer
-            .write_all(&self.buffer[..block_header.read_size as usize])?;
+    /// Returns the libbz3 state mutably, once the file header has been read, e.g. for the
+    /// [description](Bz3State::error) of the last error.
+    pub fn state_mut(&mut self) -> Option<&mut Bz3State> {
+        self.decoder.state_mut()
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the inner writer, e.g. to take the output written to a
+    /// `Vec` so far.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the [metadata](crate::metadata) of the file, once it has been written.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.decoder.metadata()
+    }
+
+    /// Sets the largest block size accepted in the file header; writing a file with a larger one
+    /// fails with [`Error::BlockSizeLimit`].
+    ///
+    /// See [`read::Bz3Decoder::with_max_block_size`](crate::read::Bz3Decoder::with_max_block_size).
+    pub fn set_max_block_size(&mut self, limit: usize) {
+        self.decoder.set_max_block_size(limit);
+    }
+
+    /// Sets how a block failing its CRC check is handled.
+    ///
+    /// See [`read::Bz3Decoder::set_crc_mode`](crate::read::Bz3Decoder::set_crc_mode).
+    pub fn set_crc_mode(&mut self, mode: CrcMode) {
+        self.decoder.set_crc_mode(mode);
+    }
+
+    /// Returns the blocks which failed the CRC check so far in permissive mode, numbered as in
+    /// [`Error::BadCrc`].
+    pub fn crc_failures(&self) -> &[usize] {
+        self.decoder.crc_failures()
+    }
+
+    /// Sets whether the output of each block is verified after decoding.
+    ///
+    /// See [`read::Bz3Decoder::set_paranoid`](crate::read::Bz3Decoder::set_paranoid).
+    pub fn set_paranoid(&mut self, enabled: bool) {
+        self.decoder.set_paranoid(enabled);
+    }
+
+    /// Sets whether anything following the last block that isn't a block is ignored.
+    ///
+    /// See [`read::Bz3Decoder::set_ignore_trailing_data`](crate::read::Bz3Decoder::set_ignore_trailing_data).
+    pub fn set_ignore_trailing_data(&mut self, enabled: bool) {
+        self.decoder.set_ignore_trailing_data(enabled);
+    }
+
+    /// Sets a pool to take the block buffer and the state from, and put them back to when
+    /// dropped; see [`BufferPool`].
+    ///
+    /// Call this before writing anything; a buffer or state already allocated is put back to
+    /// the pool too, but wasn't taken from it.
+    pub fn set_buffer_pool(&mut self, pool: BufferPool) {
+        self.decoder.set_buffer_pool(pool);
+    }
+
+    /// Sets whether the input may hold multiple concatenated bzip3 files.
+    ///
+    /// See [`read::Bz3Decoder::multiple_members`](crate::read::Bz3Decoder::multiple_members).
+    pub fn multiple_members(&mut self, enabled: bool) {
+        self.decoder.set_multiple_members(enabled);
+    }
+
+    /// Writes all the pending decompressed data to `self.writer`.
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.decoder.output();
+        let size = output.len();
+        if size != 0 {
+            // what's written is consumed even if the writer fills up, so it isn't written twice
+            let written = self.writer.try_write_all(output)?;
+            self.decoder.consume(written);
+            if written < size {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
         Ok(())
     }
 }
@@ -198,69 +734,22 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if self.state.is_none() {
-            // wait for the bzip3 header to initialize the decoder
-            let mut write_size = buf.len();
-            let needed_size = self.header_len - self.buffer_pos;
-            if write_size > needed_size {
-                write_size = needed_size;
-            }
-            self.buffer[self.buffer_pos..(self.buffer_pos + write_size)]
-                .copy_from_slice(&buf[..write_size]);
-            self.buffer_pos += write_size;
-            if self.buffer_pos == self.header_len {
-                // header prepared
-                self.initialize().map_err(Error::into_io_error)?;
-                self.buffer_pos = 0;
-            }
-            return Ok(write_size);
-        }
-
-        if self.block_header.is_none() {
-            // wait for the block header
-            let mut write_size = buf.len();
-            let needed_size = BLOCK_HEADER_SIZE - self.block_header_buf_pos;
-            if write_size > needed_size {
-                write_size = needed_size;
-            }
-            self.block_header_buf
-                [self.block_header_buf_pos..(self.block_header_buf_pos + write_size)]
-                .copy_from_slice(&buf[..write_size]);
-
-            self.block_header_buf_pos += write_size;
-            if self.block_header_buf_pos == BLOCK_HEADER_SIZE {
-                // resolve block header
-                let mut cursor = Cursor::new(&self.block_header_buf);
-                let block_header = BlockHeader::read_from(&mut cursor)?;
-                self.block_header = Some(block_header);
-                self.block_header_buf_pos = 0;
+        // when a block is filled, it's immediately decompressed and written to `self.writer`
+        self.write_output()?;
+        let size = self.decoder.feed(buf).map_err(Error::into_io_error)?;
+        // `buf` is taken either way; a failed write of the output comes up with the next call
+        if let Err(e) = self.write_output() {
+            if size == 0 {
+                return Err(e);
             }
-            Ok(write_size)
-        } else {
-            // wait for the block data
-            let block_header = self.block_header.as_ref().unwrap();
-            let needed_size = block_header.new_size as usize - self.buffer_pos;
-            let mut write_size = buf.len();
-            if write_size > needed_size {
-                write_size = needed_size;
-            }
-            self.buffer[self.buffer_pos..(self.buffer_pos + write_size)]
-                .copy_from_slice(&buf[..write_size]);
-            self.buffer_pos += write_size;
-            if self.buffer_pos == block_header.new_size as usize {
-                self.decompress_block().map_err(Error::into_io_error)?;
-                // reset block header, wait for the next block's header
-                self.block_header = None;
-                self.buffer_pos = 0;
-            }
-            Ok(write_size)
         }
+        Ok(size)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        // this call seems to be not such meaningful
-        // because in `write()`, when the block buffer is filled,
-        // it immediately decompresses the block and writes to `self.reader`
-        Ok(())
+        // in `write()`, when the block buffer is filled, it's immediately decompressed and
+        // written to `self.writer`; only output left over by a failed write is pending here
+        self.write_output()?;
+        self.writer.flush()
     }
 }
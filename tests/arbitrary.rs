@@ -0,0 +1,54 @@
+#![cfg(feature = "arbitrary")]
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use bzip3::frame::{ArbitraryFrame, BlockHeader, FrameHeader};
+use bzip3::read::Bz3Decoder;
+
+mod common;
+
+use common::generate_random_data;
+
+#[test]
+fn headers() {
+    for _ in 0..100 {
+        let input = generate_random_data(64);
+        let mut u = Unstructured::new(&input);
+
+        let header = FrameHeader::arbitrary(&mut u).unwrap();
+        assert_eq!(FrameHeader::parse(&header.to_bytes()).unwrap(), header);
+
+        let header = BlockHeader::arbitrary(&mut u).unwrap();
+        assert!(header.new_size >= 0 || header.is_stored() || header.is_extension());
+        assert!(header.read_size >= 0);
+        assert_eq!(BlockHeader::parse(&header.to_bytes()), header);
+    }
+}
+
+#[test]
+fn frames() {
+    let mut corrupted = 0;
+    for _ in 0..100 {
+        let input = generate_random_data(256);
+        let frame = ArbitraryFrame::arbitrary(&mut Unstructured::new(&input)).unwrap();
+
+        let result = Bz3Decoder::new(frame.bytes.as_slice()).and_then(|x| x.decode_to_vec());
+        let decompressed = bzip3::mem::decompress(&frame.bytes);
+        match frame.corruption {
+            None => {
+                assert_eq!(result.unwrap(), frame.data);
+                assert_eq!(decompressed.unwrap(), frame.data);
+            }
+            Some(corruption) => {
+                corrupted += 1;
+                if let Ok(x) = result {
+                    assert!(frame.data.starts_with(&x), "{corruption:?}");
+                }
+                if let Ok(x) = decompressed {
+                    assert!(frame.data.starts_with(&x), "{corruption:?}");
+                }
+            }
+        }
+    }
+    assert!(corrupted > 0);
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "tar")]
+
+use std::fs;
+
+use bzip3::archive::{compress_dir, extract, ArchiveOptions};
+
+const KB: usize = 1024;
+
+#[test]
+fn compress_and_extract_dir() {
+    let dir = std::env::temp_dir().join(format!("bzip3-archive-{}", std::process::id()));
+    let (source, dest) = (dir.join("source"), dir.join("dest"));
+    fs::create_dir_all(source.join("nested/deeper")).unwrap();
+    let files = [
+        ("a.txt", b"hello, world".repeat(10)),
+        (
+            "nested/b.bin",
+            (0..(300 * KB)).map(|x| (x % 251) as u8).collect(),
+        ),
+        ("nested/deeper/empty", Vec::new()),
+    ];
+    for (name, data) in &files {
+        fs::write(source.join(name), data).unwrap();
+    }
+
+    let options = ArchiveOptions {
+        block_size: 100 * KB,
+        checksum: true,
+        ..Default::default()
+    };
+    let compressed = compress_dir(&source, Vec::new(), &options).unwrap();
+    extract(compressed.as_slice(), &dest).unwrap();
+    for (name, data) in &files {
+        assert_eq!(&fs::read(dest.join(name)).unwrap(), data);
+    }
+
+    assert!(extract(&b"not bzip3"[..], &dest).is_err());
+    let options = ArchiveOptions {
+        block_size: 1,
+        ..Default::default()
+    };
+    assert!(compress_dir(&source, Vec::new(), &options).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
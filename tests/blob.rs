@@ -0,0 +1,61 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use bzip3::blob::CompressedBlob;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn round_trip() {
+    let data = generate_random_data(350 * KB);
+    let blob = CompressedBlob::new(&data, 100 * KB).unwrap();
+    assert_eq!(blob.len(), data.len() as u64);
+    assert_eq!(blob.index().len(), 4);
+    assert_eq!(blob.to_vec().unwrap(), data);
+    assert_eq!(bzip3::mem::decompress(blob.as_compressed()).unwrap(), data);
+
+    let from_compressed = CompressedBlob::from_compressed(blob.as_compressed().to_vec()).unwrap();
+    assert_eq!(from_compressed, blob);
+
+    let blob = CompressedBlob::new(&[], 100 * KB).unwrap();
+    assert!(blob.is_empty());
+    assert!(blob.to_vec().unwrap().is_empty());
+    assert!(blob.get(0..10).unwrap().is_empty());
+}
+
+#[test]
+fn ranges() {
+    let data = generate_random_data(350 * KB);
+    let blob = CompressedBlob::new(&data, 100 * KB).unwrap();
+
+    for range in [
+        0..10,
+        1000..(250 * KB),
+        (100 * KB)..(200 * KB),
+        (340 * KB)..(350 * KB),
+    ] {
+        let slice = blob.get(range.start as u64..range.end as u64).unwrap();
+        assert_eq!(slice, &data[range]);
+    }
+    // clipped to the size
+    assert_eq!(blob.get(1000..u64::MAX).unwrap(), &data[1000..]);
+    assert!(blob
+        .get((400 * KB) as u64..(500 * KB) as u64)
+        .unwrap()
+        .is_empty());
+
+    let mut reader = blob.reader().unwrap();
+    reader.seek(SeekFrom::Start((120 * KB) as u64)).unwrap();
+    let mut buf = vec![0_u8; 100 * KB];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, &data[(120 * KB)..(220 * KB)]);
+}
+
+#[test]
+fn invalid() {
+    assert!(CompressedBlob::new(&[0_u8; 10], 1).is_err());
+    assert!(CompressedBlob::from_compressed(b"not bzip3".to_vec()).is_err());
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "bytes")]
+
+use std::io::Write;
+
+use bytes::{Buf, Bytes};
+
+use bzip3::bytes::{BytesDecoder, BytesEncoder};
+use bzip3::pool::BufferPool;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+fn compress(data: &[u8], pool: &BufferPool) -> Vec<Bytes> {
+    let mut encoder = BytesEncoder::new(100 * KB).unwrap();
+    encoder.set_buffer_pool(pool.clone());
+    encoder.set_checksum(true);
+    // input split across the chunks of a `Buf`
+    let (a, b) = data.split_at(data.len() / 3);
+    encoder
+        .push_buf(Bytes::copy_from_slice(a).chain(b))
+        .unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let pool = BufferPool::new(8);
+    for size in [0, 1, 100 * KB, 250 * KB] {
+        let data = generate_random_data(size);
+        let compressed = compress(&data, &pool);
+        let mut encoder = bzip3::write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(true);
+        encoder.write_all(&data).unwrap();
+        assert!(compressed.concat() == encoder.finish().unwrap());
+
+        // fed in small pieces, and all at once
+        for piece_size in [1000, usize::MAX] {
+            let mut decoder = BytesDecoder::new();
+            decoder.set_buffer_pool(pool.clone());
+            let mut decompressed = Vec::new();
+            for chunk in &compressed {
+                for piece in chunk.chunks(piece_size.min(chunk.len()).max(1)) {
+                    decoder.push_buf(piece).unwrap();
+                    while let Some(output) = decoder.next_output() {
+                        decompressed.extend_from_slice(&output);
+                    }
+                }
+            }
+            decompressed.extend(decoder.finish().unwrap().concat());
+            assert!(decompressed == data);
+        }
+    }
+}
+
+#[test]
+fn pooled_buffers() {
+    let pool = BufferPool::new(8);
+    let data = generate_random_data(250 * KB);
+    let compressed = compress(&data, &pool);
+    // held by the output
+    assert!(pool.is_empty());
+    drop(compressed);
+    assert_eq!(pool.len(), 3);
+
+    let compressed = compress(&data, &pool);
+    let mut decoder = BytesDecoder::new();
+    decoder.set_buffer_pool(pool.clone());
+    decoder.push_buf(compressed.concat().as_slice()).unwrap();
+    let decompressed = decoder.finish().unwrap();
+    assert_eq!(decompressed.len(), 3);
+    assert!(decompressed.concat() == data);
+    let kept = pool.len();
+    drop(decompressed);
+    assert_eq!(pool.len(), kept + 3);
+}
+
+#[test]
+fn truncated() {
+    let compressed = compress(&generate_random_data(250 * KB), &BufferPool::new(1)).concat();
+    let mut decoder = BytesDecoder::new();
+    decoder
+        .push_buf(&compressed[..(compressed.len() / 2)])
+        .unwrap();
+    assert!(matches!(
+        decoder.finish(),
+        Err(bzip3::Error::TruncatedBlock { .. })
+    ));
+}
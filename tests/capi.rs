@@ -0,0 +1,136 @@
+#![cfg(feature = "capi")]
+
+use std::ffi::CStr;
+use std::ptr;
+
+use bzip3::capi::*;
+
+mod common;
+
+use common::generate_compressible_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn capi_round_trip() {
+    let data = generate_compressible_data(1200 * KB);
+
+    assert!(bz3rs_encoder_new(10).is_null());
+    let encoder = bz3rs_encoder_new(100 * KB);
+    assert!(!encoder.is_null());
+
+    // small buffers on both sides
+    let mut compressed = Vec::new();
+    let mut output = [0_u8; 1000];
+    let (mut consumed, mut written) = (0, 0);
+    for chunk in data.chunks(7000) {
+        let mut input = chunk;
+        while !input.is_empty() {
+            let result = unsafe {
+                bz3rs_encoder_compress(
+                    encoder,
+                    input.as_ptr(),
+                    input.len(),
+                    &mut consumed,
+                    output.as_mut_ptr(),
+                    output.len(),
+                    &mut written,
+                )
+            };
+            assert_eq!(result, BZ3RS_OK);
+            input = &input[consumed..];
+            compressed.extend_from_slice(&output[..written]);
+        }
+    }
+    loop {
+        let result = unsafe {
+            bz3rs_encoder_finish(encoder, output.as_mut_ptr(), output.len(), &mut written)
+        };
+        compressed.extend_from_slice(&output[..written]);
+        if result == BZ3RS_OK {
+            break;
+        }
+        assert_eq!(result, BZ3RS_MORE_OUTPUT);
+    }
+    let result = unsafe {
+        bz3rs_encoder_compress(
+            encoder,
+            data.as_ptr(),
+            1,
+            &mut consumed,
+            output.as_mut_ptr(),
+            output.len(),
+            &mut written,
+        )
+    };
+    assert_eq!(result, BZ3RS_ERR_INVALID_ARGUMENT);
+    unsafe { bz3rs_encoder_free(encoder) };
+    assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+
+    let mut decompress = |input: &[u8], limit: Option<usize>| {
+        let decoder = bz3rs_decoder_new();
+        if let Some(limit) = limit {
+            unsafe { bz3rs_decoder_set_max_block_size(decoder, limit) };
+        }
+        let mut decompressed = Vec::new();
+        let mut result = BZ3RS_OK;
+        for mut chunk in input.chunks(5000) {
+            loop {
+                result = unsafe {
+                    bz3rs_decoder_decompress(
+                        decoder,
+                        chunk.as_ptr(),
+                        chunk.len(),
+                        &mut consumed,
+                        output.as_mut_ptr(),
+                        output.len(),
+                        &mut written,
+                    )
+                };
+                decompressed.extend_from_slice(&output[..written]);
+                chunk = &chunk[consumed..];
+                if result < 0 || (result == BZ3RS_OK && chunk.is_empty()) {
+                    break;
+                }
+            }
+            if result < 0 {
+                break;
+            }
+        }
+        if result == BZ3RS_OK {
+            result = unsafe { bz3rs_decoder_finish(decoder) };
+        }
+        let message = unsafe { bz3rs_decoder_error(decoder) };
+        let message = (!message.is_null()).then(|| {
+            unsafe { CStr::from_ptr(message) }
+                .to_str()
+                .unwrap()
+                .to_owned()
+        });
+        unsafe { bz3rs_decoder_free(decoder) };
+        (result, decompressed, message)
+    };
+
+    let (result, decompressed, message) = decompress(&compressed, None);
+    assert_eq!(result, BZ3RS_OK);
+    assert!(decompressed == data);
+    assert_eq!(message, None);
+
+    let (result, _, message) = decompress(&compressed[..(compressed.len() - 1)], None);
+    assert_eq!(result, BZ3RS_ERR_TRUNCATED_BLOCK);
+    assert!(message.unwrap().starts_with("Truncated block"));
+
+    let (result, _, _) = decompress(&compressed, Some(65 * KB));
+    assert_eq!(result, BZ3RS_ERR_BLOCK_SIZE_LIMIT);
+
+    let (result, _, _) = decompress(b"not a bzip3 file", None);
+    assert_eq!(result, BZ3RS_ERR_INVALID_SIGNATURE);
+
+    unsafe {
+        bz3rs_encoder_free(ptr::null_mut());
+        assert_eq!(
+            bz3rs_decoder_finish(ptr::null_mut()),
+            BZ3RS_ERR_INVALID_ARGUMENT
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::{Read, Write};
+
+use bzip3::codec::StreamCodec;
+use bzip3::options::Bz3Options;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn codec() {
+    let codec: Box<dyn StreamCodec> = Box::new(Bz3Options {
+        block_size: 100 * KB,
+        checksum: true,
+        ..Default::default()
+    });
+    assert_eq!(codec.name(), "bzip3");
+    assert_eq!(codec.extension(), "bz3");
+    assert_eq!(codec.magic(), bzip3::MAGIC_NUMBER);
+
+    let data = generate_random_data(250 * KB);
+    let mut compressed = Vec::new();
+    let mut writer = codec.wrap_writer(Box::new(&mut compressed)).unwrap();
+    writer.write_all(&data).unwrap();
+    writer.finish().unwrap();
+    assert!(codec.matches(&compressed));
+    assert!(!codec.matches(&data[..]));
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+
+    let mut reader = codec.wrap_reader(Box::new(compressed.as_slice())).unwrap();
+    let mut decompressed = Vec::new();
+    reader.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+
+    assert!(codec.wrap_reader(Box::new(&data[..])).is_err());
+}
@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests.
+
+// each test crate only uses some of them
+#![allow(dead_code)]
+
+use rand::{thread_rng, RngCore};
+
+pub fn generate_random_data(size: usize) -> Vec<u8> {
+    let mut data = vec![0_u8; size];
+    thread_rng().fill_bytes(&mut data);
+    data
+}
+
+/// Generates `size` bytes of repetitive, well compressible data.
+pub fn generate_compressible_data(size: usize) -> Vec<u8> {
+    (0..size.div_ceil(4) as u32)
+        .flat_map(|x| (x % 1000).to_le_bytes())
+        .take(size)
+        .collect()
+}
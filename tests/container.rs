@@ -0,0 +1,54 @@
+use std::io::{Cursor, Read};
+
+use bzip3::container::{ContainerReader, ContainerWriter};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn members() {
+    let files = [
+        ("first", generate_random_data(250 * KB)),
+        ("empty", Vec::new()),
+        ("dir/third", generate_random_data(10 * KB)),
+    ];
+    let mut writer = ContainerWriter::new(Vec::new()).unwrap();
+    for (name, data) in &files {
+        writer.add(name, data.as_slice(), 100 * KB).unwrap();
+    }
+    assert!(writer.add("first", &b""[..], 100 * KB).is_err());
+    let container = writer.finish().unwrap();
+
+    let mut reader = ContainerReader::new(Cursor::new(&container)).unwrap();
+    let names = reader
+        .members()
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["first", "empty", "dir/third"]);
+    // in any order
+    for (name, data) in files.iter().rev() {
+        let index = reader.find(name).unwrap();
+        assert_eq!(reader.members()[index].uncompressed_size, data.len() as u64);
+        let mut decompressed = Vec::new();
+        reader
+            .open(index)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(&decompressed == data);
+    }
+    assert!(reader.open_by_name("missing").is_err());
+
+    // each member is a standalone bzip3 file
+    let member = &reader.members()[0];
+    let start = member.offset as usize + 2 + member.name.len();
+    let file = &container[start..(start + member.compressed_size as usize)];
+    assert!(bzip3::mem::decompress(file).unwrap() == files[0].1);
+
+    assert!(ContainerReader::new(Cursor::new(&container[..(container.len() - 1)])).is_err());
+    assert!(ContainerReader::new(Cursor::new(b"BZ3v1")).is_err());
+}
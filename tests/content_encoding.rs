@@ -0,0 +1,89 @@
+#![cfg(feature = "http")]
+
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use http::{header, Request, Response};
+use http_body_util::{BodyExt, Full};
+use tower::{service_fn, Layer, ServiceExt};
+
+use bzip3::content_encoding::{Bz3Body, Bz3Layer};
+
+mod common;
+
+use common::generate_compressible_data;
+
+const KB: usize = 1024;
+
+#[tokio::test]
+async fn content_encoding() {
+    // echoes the request body, which the layer has decompressed
+    let echo = service_fn(|request: Request<Bz3Body<Full<Bytes>>>| async move {
+        assert!(request.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        Ok::<_, Infallible>(Response::new(Full::new(body)))
+    });
+    let service = Bz3Layer::new()
+        .block_size(100 * KB)
+        .unwrap()
+        .max_block_size(100 * KB)
+        .layer(echo);
+
+    let data = Bytes::from(generate_compressible_data(800 * KB));
+    let compressed = Bz3Body::compress(Full::new(data.clone()), 100 * KB)
+        .unwrap()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+
+    let request = |body: &Bytes, accept_encoding| {
+        let mut request = Request::builder().header(header::CONTENT_ENCODING, "bzip3");
+        if let Some(value) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, value);
+        }
+        request.body(Full::new(body.clone())).unwrap()
+    };
+
+    let response = service
+        .clone()
+        .oneshot(request(&compressed, Some("br;q=0.5, BZIP3")))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "bzip3");
+    assert_eq!(response.headers()[header::VARY], "accept-encoding");
+    let body = Bz3Body::decompress(response.into_body());
+    assert_eq!(body.collect().await.unwrap().to_bytes(), data);
+
+    for accept_encoding in [None, Some("gzip"), Some("bzip3;q=0")] {
+        let response = service
+            .clone()
+            .oneshot(request(&compressed, accept_encoding))
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            response.into_body().collect().await.unwrap().to_bytes(),
+            data
+        );
+    }
+
+    // block size above the limit of the layer
+    let compressed = Bz3Body::compress(Full::new(data.clone()), 200 * KB)
+        .unwrap()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    let body = Bz3Body::decompress_with_max_block_size(Full::new(compressed.clone()), 100 * KB);
+    let e = body.collect().await.unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<bzip3::Error>(),
+        Some(bzip3::Error::BlockSizeLimit { .. })
+    ));
+
+    // truncated
+    let body = Bz3Body::decompress(Full::new(compressed.slice(..1000)));
+    assert!(body.collect().await.is_err());
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "embedded-io")]
+
+use embedded_io::{Error as _, ErrorKind, Read, Write};
+
+use bzip3::embedded::{Bz3Decoder, Bz3Encoder, Error};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn round_trip() {
+    let data = generate_random_data(250 * KB);
+    let mut compressed = vec![0_u8; 2 * data.len()];
+    let capacity = compressed.len();
+    let mut writer = compressed.as_mut_slice();
+    let mut encoder = Bz3Encoder::new(&mut writer, 100 * KB).unwrap();
+    encoder.encoder_mut().set_checksum(true);
+    for chunk in data.chunks(1000) {
+        encoder.write_all(chunk).unwrap();
+    }
+    encoder.finish().unwrap();
+    let size = capacity - writer.len();
+    compressed.truncate(size);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+
+    let mut decoder = Bz3Decoder::new(compressed.as_slice()).unwrap();
+    assert_eq!(decoder.decoder().block_size(), Some(100 * KB));
+    let mut decompressed = Vec::new();
+    let mut buf = [0_u8; 777];
+    loop {
+        let size = decoder.read(&mut buf).unwrap();
+        if size == 0 {
+            break;
+        }
+        decompressed.extend_from_slice(&buf[..size]);
+    }
+    assert_eq!(decompressed, data);
+    assert_eq!(decoder.decoder().blocks(), 3);
+}
+
+#[test]
+fn flush() {
+    let mut compressed = [0_u8; 1024];
+    let mut writer = compressed.as_mut_slice();
+    let mut encoder = Bz3Encoder::new(&mut writer, 100 * KB).unwrap();
+    encoder.write_all(b"hello, ").unwrap();
+    encoder.flush().unwrap();
+    encoder.write_all(b"world").unwrap();
+    encoder.finish().unwrap();
+    let size = 1024 - writer.len();
+
+    let mut decoder = Bz3Decoder::new(&compressed[..size]).unwrap();
+    let mut decompressed = [0_u8; 12];
+    decoder.read_exact(&mut decompressed).unwrap();
+    assert_eq!(&decompressed, b"hello, world");
+    assert_eq!(decoder.decoder().blocks(), 2);
+    assert_eq!(decoder.read(&mut decompressed).unwrap(), 0);
+}
+
+#[test]
+fn errors() {
+    let error = Bz3Decoder::new(b"BZ2h91AY".as_slice()).unwrap_err();
+    assert!(matches!(error, Error::Bz3(bzip3::Error::InvalidSignature)));
+
+    let compressed = bzip3::mem::compress(&generate_random_data(10 * KB), 200 * KB).unwrap();
+    let error = Bz3Decoder::with_max_block_size(compressed.as_slice(), 100 * KB).unwrap_err();
+    assert!(matches!(
+        error,
+        Error::Bz3(bzip3::Error::BlockSizeLimit { .. })
+    ));
+
+    let truncated = &compressed[..(compressed.len() - 1)];
+    let mut decoder = Bz3Decoder::new(truncated).unwrap();
+    let error = decoder.read(&mut [0_u8; 1024]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    // the writer fills up
+    let mut compressed = [0_u8; 100];
+    let mut encoder = Bz3Encoder::new(compressed.as_mut_slice(), 100 * KB).unwrap();
+    encoder.write_all(&generate_random_data(10 * KB)).unwrap();
+    let error = encoder.finish().unwrap_err();
+    assert!(matches!(error, Error::Io(_)));
+    assert_eq!(error.kind(), ErrorKind::WriteZero);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "embedded-io-async")]
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use futures::executor::block_on;
+
+use bzip3::embedded_async::{Bz3Decoder, Bz3Encoder, Error};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+/// Reader handing out at most 100 bytes at a time, as a UART would.
+struct Chunked<'a>(&'a [u8]);
+
+impl ErrorType for Chunked<'_> {
+    type Error = ErrorKind;
+}
+
+impl Read for Chunked<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let size = buf.len().min(self.0.len()).min(100);
+        buf[..size].copy_from_slice(&self.0[..size]);
+        self.0 = &self.0[size..];
+        Ok(size)
+    }
+}
+
+#[test]
+fn round_trip() {
+    block_on(async {
+        let data = generate_random_data(250 * KB);
+        let mut compressed = vec![0_u8; 2 * data.len()];
+        let capacity = compressed.len();
+        let mut writer = compressed.as_mut_slice();
+        let mut encoder = Bz3Encoder::new(&mut writer, 100 * KB).unwrap();
+        encoder.encoder_mut().set_checksum(true);
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).await.unwrap();
+        }
+        encoder.finish().await.unwrap();
+        let size = capacity - writer.len();
+        compressed.truncate(size);
+        assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+
+        let mut decoder = Bz3Decoder::new(Chunked(&compressed)).await.unwrap();
+        let mut decompressed = Vec::new();
+        let mut buf = [0_u8; 777];
+        loop {
+            let size = decoder.read(&mut buf).await.unwrap();
+            if size == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buf[..size]);
+        }
+        assert_eq!(decompressed, data);
+        assert_eq!(decoder.decoder().blocks(), 3);
+    });
+}
+
+#[test]
+fn errors() {
+    block_on(async {
+        let error = Bz3Decoder::new(b"BZ2h91AY".as_slice()).await.unwrap_err();
+        assert!(matches!(error, Error::Bz3(bzip3::Error::InvalidSignature)));
+
+        let compressed = bzip3::mem::compress(&generate_random_data(10 * KB), 200 * KB).unwrap();
+        let error = Bz3Decoder::with_max_block_size(compressed.as_slice(), 100 * KB)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Bz3(bzip3::Error::BlockSizeLimit { .. })
+        ));
+
+        let truncated = &compressed[..(compressed.len() - 1)];
+        let mut decoder = Bz3Decoder::new(Chunked(truncated)).await.unwrap();
+        let error = decoder.read(&mut [0_u8; 1024]).await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Bz3(bzip3::Error::TruncatedBlock { .. })
+        ));
+    });
+}
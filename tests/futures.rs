@@ -0,0 +1,110 @@
+#![cfg(feature = "futures")]
+
+use futures::executor::block_on;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+use bzip3::futures::{read, write};
+use bzip3::mem;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn read_decoder() {
+    block_on(async {
+        for size in [0, 1, 100 * KB, 1000 * KB] {
+            let data = generate_random_data(size);
+            let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+            let mut decoder = read::Bz3Decoder::new(compressed.as_slice());
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed).await.unwrap();
+            assert_eq!(decompressed, data);
+
+            let truncated = &compressed[..(compressed.len() - 1)];
+            let mut decoder = read::Bz3Decoder::new(truncated);
+            assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+        }
+    });
+}
+
+#[test]
+fn write_encoder() {
+    block_on(async {
+        for size in [0, 1, 100 * KB, 1000 * KB] {
+            let data = generate_random_data(size);
+
+            let mut compressed = Vec::new();
+            let mut encoder = write::Bz3Encoder::new(&mut compressed, 100 * KB).unwrap();
+            encoder.write_all(&data).await.unwrap();
+            encoder.close().await.unwrap();
+            assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+        }
+    });
+}
+
+#[test]
+fn bytes_stream() {
+    use bytes::Bytes;
+    use futures::{stream, TryStreamExt};
+
+    use bzip3::futures::stream::{compress_stream, decompress_stream};
+
+    block_on(async {
+        for size in [0, 1, 100 * KB, 1000 * KB] {
+            let data = generate_random_data(size);
+            let chunks = || {
+                stream::iter(
+                    data.chunks(7 * KB)
+                        .map(|x| Ok(Bytes::copy_from_slice(x)))
+                        .collect::<Vec<_>>(),
+                )
+            };
+
+            let compressed = compress_stream(chunks(), 100 * KB).unwrap();
+            let compressed = compressed.try_collect::<Vec<_>>().await.unwrap().concat();
+            assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+
+            let compressed_chunks = compressed
+                .chunks(3 * KB)
+                .map(|x| Ok(Bytes::copy_from_slice(x)))
+                .collect::<Vec<_>>();
+            let decompressed = decompress_stream(stream::iter(compressed_chunks));
+            let decompressed = decompressed.try_collect::<Vec<_>>().await.unwrap().concat();
+            assert_eq!(decompressed, data);
+
+            let truncated = Bytes::copy_from_slice(&compressed[..(compressed.len() - 1)]);
+            let decompressed = decompress_stream(stream::iter([Ok(truncated)]));
+            assert!(decompressed.try_collect::<Vec<_>>().await.is_err());
+        }
+    });
+}
+
+#[test]
+fn sink() {
+    use bytes::Bytes;
+    use futures::channel::mpsc;
+    use futures::{SinkExt, StreamExt};
+
+    use bzip3::futures::sink::Bz3Sink;
+
+    block_on(async {
+        for size in [0, 1, 100 * KB, 1000 * KB] {
+            let data = generate_random_data(size);
+
+            let (sender, receiver) = mpsc::unbounded::<Bytes>();
+            let sender = sender.sink_map_err(std::io::Error::other);
+            let mut sink = Bz3Sink::new(sender, 100 * KB).unwrap();
+            for chunk in data.chunks(7 * KB) {
+                sink.feed(Bytes::copy_from_slice(chunk)).await.unwrap();
+            }
+            sink.close().await.unwrap();
+
+            let compressed = receiver.collect::<Vec<_>>().await.concat();
+            assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+        }
+    });
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "tonic")]
+
+use bytes::{BufMut, Bytes, BytesMut};
+use http_body_util::{BodyExt, Full};
+use prost::Message;
+use tonic::server::Grpc;
+use tonic::{Code, Status};
+use tower::service_fn;
+
+use bzip3::grpc::Bz3Codec;
+
+mod common;
+
+use common::generate_compressible_data;
+
+const KB: usize = 1024;
+
+/// Wraps a message in a gRPC frame.
+fn grpc_frame(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::new();
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(message);
+    frame.freeze()
+}
+
+/// Calls a unary echo service through a [`Bz3Codec`], and returns the response message, or the
+/// status code.
+async fn echo(codec: Bz3Codec<Vec<u8>, Vec<u8>>, message: &[u8]) -> Result<Bytes, Code> {
+    let service = service_fn(|request: tonic::Request<Vec<u8>>| async move {
+        Ok::<_, Status>(tonic::Response::new(request.into_inner()))
+    });
+    let request = http::Request::builder()
+        .header("content-type", "application/grpc")
+        .body(Full::new(grpc_frame(message)))
+        .unwrap();
+    let response = Grpc::new(codec).unary(service, request).await;
+
+    let status = response.headers().get("grpc-status").cloned();
+    let body = response.into_body().collect().await.unwrap();
+    let status = status.or_else(|| body.trailers()?.get("grpc-status").cloned());
+    let code = Code::from_bytes(status.unwrap().as_bytes());
+    if code != Code::Ok {
+        return Err(code);
+    }
+    let body = body.to_bytes();
+    let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+    assert_eq!(body.len(), 5 + len);
+    Ok(body.slice(5..))
+}
+
+#[tokio::test]
+async fn grpc_codec() {
+    let data = generate_compressible_data(400 * KB);
+    let compressed = bzip3::mem::compress(&data.encode_to_vec(), 100 * KB).unwrap();
+
+    let response = echo(Bz3Codec::new(100 * KB).unwrap(), &compressed)
+        .await
+        .unwrap();
+    assert!(response.starts_with(bzip3::MAGIC_NUMBER));
+    let message = bzip3::mem::decompress(&response).unwrap();
+    assert_eq!(Vec::<u8>::decode(message.as_slice()).unwrap(), data);
+
+    // empty messages
+    let compressed = bzip3::mem::compress(&[], 100 * KB).unwrap();
+    let response = echo(Bz3Codec::default(), &compressed).await.unwrap();
+    assert!(bzip3::mem::decompress(&response).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn grpc_codec_limits() {
+    let data = generate_compressible_data(400 * KB);
+    let compressed = bzip3::mem::compress(&data.encode_to_vec(), 100 * KB).unwrap();
+
+    let codec = Bz3Codec::default().max_message_size(data.len() - 1);
+    assert_eq!(echo(codec, &compressed).await, Err(Code::ResourceExhausted));
+    // the block size alone is above the limit
+    let codec = Bz3Codec::default().max_message_size(10 * KB);
+    assert_eq!(echo(codec, &compressed).await, Err(Code::ResourceExhausted));
+
+    assert_eq!(
+        echo(Bz3Codec::default(), b"not bzip3").await,
+        Err(Code::Internal)
+    );
+    assert!(Bz3Codec::<Vec<u8>, Vec<u8>>::new(KB).is_err());
+}
@@ -0,0 +1,79 @@
+use std::fs;
+use std::io::Write;
+use std::time::Duration;
+
+use bzip3::log::{Bz3LogWriter, LogOptions};
+
+const KB: usize = 1024;
+
+fn read_log(path: &std::path::Path) -> String {
+    let data = bzip3::mem::decompress(&fs::read(path).unwrap()).unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn size_rotation() {
+    let dir = std::env::temp_dir().join(format!("bzip3-log-size-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.log.bz3");
+    fs::write(&path, b"left over").unwrap();
+
+    let options = LogOptions {
+        block_size: 100 * KB,
+        max_size: Some(20),
+        max_files: 2,
+        checksum: true,
+        ..Default::default()
+    };
+    let mut writer = Bz3LogWriter::new(&path, options).unwrap();
+    // the existing file is rotated away
+    assert_eq!(fs::read(writer.rotated_path(1)).unwrap(), b"left over");
+    for line in 0..4 {
+        // each file takes two lines of 10 bytes
+        writeln!(writer, "line {line:04}").unwrap();
+    }
+    writer.write_all(b"a line longer than the limit\n").unwrap();
+    writer.finish().unwrap();
+
+    assert_eq!(read_log(&path), "a line longer than the limit\n");
+    assert_eq!(
+        read_log(&dir.join("app.log.bz3.1")),
+        "line 0002\nline 0003\n"
+    );
+    assert_eq!(
+        read_log(&dir.join("app.log.bz3.2")),
+        "line 0000\nline 0001\n"
+    );
+    // the oldest is gone
+    assert!(!dir.join("app.log.bz3.3").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn age_rotation() {
+    let dir = std::env::temp_dir().join(format!("bzip3-log-age-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.log.bz3");
+
+    let options = LogOptions {
+        block_size: 100 * KB,
+        max_age: Some(Duration::from_millis(100)),
+        max_files: 0,
+        ..Default::default()
+    };
+    let mut writer = Bz3LogWriter::new(&path, options).unwrap();
+    writer.write_all(b"old\n").unwrap();
+    writer.write_all(b"still old\n").unwrap();
+    std::thread::sleep(Duration::from_millis(150));
+    writer.write_all(b"new\n").unwrap();
+    writer.flush().unwrap();
+    assert_eq!(read_log(&path), "new\n");
+    // no files kept
+    assert!(!writer.rotated_path(1).exists());
+    writer.rotate().unwrap();
+    writer.finish().unwrap();
+    assert_eq!(read_log(&path), "");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "metrics")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use metrics::{
+    Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+/// Sums of the values recorded, by name and label.
+#[derive(Default)]
+struct TestRecorder {
+    values: Arc<Mutex<HashMap<String, f64>>>,
+}
+
+struct Handle {
+    name: String,
+    values: Arc<Mutex<HashMap<String, f64>>>,
+}
+
+impl Handle {
+    fn add(&self, value: f64) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(self.name.clone())
+            .or_default() += value;
+    }
+}
+
+impl CounterFn for Handle {
+    fn increment(&self, value: u64) {
+        self.add(value as f64);
+    }
+
+    fn absolute(&self, _value: u64) {
+        unreachable!()
+    }
+}
+
+impl HistogramFn for Handle {
+    fn record(&self, value: f64) {
+        self.add(value);
+    }
+}
+
+impl TestRecorder {
+    fn handle(&self, key: &Key) -> Arc<Handle> {
+        let label = key
+            .labels()
+            .map(|x| x.value())
+            .collect::<Vec<_>>()
+            .join(",");
+        Arc::new(Handle {
+            name: format!("{}{{{label}}}", key.name()),
+            values: Arc::clone(&self.values),
+        })
+    }
+
+    fn get(&self, name: &str) -> f64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Recorder for TestRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.handle(key))
+    }
+
+    fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.handle(key))
+    }
+}
+
+#[test]
+fn metrics() {
+    let data = generate_random_data(250 * KB);
+
+    let recorder = TestRecorder::default();
+    metrics::with_local_recorder(&recorder, || {
+        let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+        assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    });
+    for operation in ["compress", "decompress"] {
+        assert_eq!(
+            recorder.get(&format!("bzip3_blocks_total{{{operation}}}")),
+            3.0
+        );
+        assert!(recorder.get(&format!("bzip3_block_duration_seconds{{{operation}}}")) > 0.0);
+    }
+    assert_eq!(
+        recorder.get("bzip3_input_bytes_total{compress}"),
+        data.len() as f64
+    );
+    assert_eq!(
+        recorder.get("bzip3_output_bytes_total{decompress}"),
+        data.len() as f64
+    );
+    assert_eq!(
+        recorder.get("bzip3_output_bytes_total{compress}"),
+        recorder.get("bzip3_input_bytes_total{decompress}")
+    );
+
+    bzip3::metrics::set_prefix("test_bzip3");
+    assert_eq!(bzip3::metrics::prefix(), "test_bzip3");
+    let recorder = TestRecorder::default();
+    metrics::with_local_recorder(&recorder, || {
+        bzip3::mem::compress(&data, 100 * KB).unwrap();
+    });
+    assert_eq!(recorder.get("test_bzip3_blocks_total{compress}"), 3.0);
+    assert_eq!(recorder.get("bzip3_blocks_total{compress}"), 0.0);
+}
@@ -0,0 +1,68 @@
+use std::io::{Read, Write};
+
+use bzip3::multipart::{part_decoder, MultipartEncoder, PartsReader};
+use bzip3::read::Bz3Decoder;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn parts() {
+    let data = generate_random_data(1000 * KB);
+    let mut encoder = MultipartEncoder::new(100 * KB, 250 * KB).unwrap();
+    let header = encoder.header();
+    let mut parts = Vec::new();
+    for chunk in data.chunks(30 * KB) {
+        encoder.write_all(chunk).unwrap();
+        parts.extend(std::iter::from_fn(|| encoder.next_part()));
+    }
+    parts.extend(encoder.finish().unwrap());
+
+    assert!(parts.len() > 1);
+    let (last, full) = parts.split_last().unwrap();
+    assert!(full.iter().all(|x| x.data.len() >= 250 * KB));
+    assert!(last.data.len() < 250 * KB + 100 * KB + 1024);
+    let numbers = parts.iter().map(|x| x.number).collect::<Vec<_>>();
+    assert_eq!(numbers, (1..=parts.len()).collect::<Vec<_>>());
+    assert_eq!(
+        last.uncompressed_offset + last.uncompressed_size,
+        data.len() as u64
+    );
+
+    // stitched together
+    let mut decompressed = Vec::new();
+    Bz3Decoder::new(PartsReader::new(parts.iter().map(|x| x.data.as_slice())))
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert!(decompressed == data);
+
+    // each on its own
+    for part in &parts[1..] {
+        let mut decompressed = Vec::new();
+        part_decoder(&header, part.data.as_slice())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        let start = part.uncompressed_offset as usize;
+        assert!(decompressed == data[start..(start + part.uncompressed_size as usize)]);
+    }
+    let first = &parts[0];
+    assert_eq!(first.uncompressed_offset, 0);
+    let decompressed = bzip3::mem::decompress(&first.data).unwrap();
+    assert!(decompressed == data[..(first.uncompressed_size as usize)]);
+}
+
+#[test]
+fn empty_input() {
+    let encoder = MultipartEncoder::new(100 * KB, 250 * KB).unwrap();
+    let parts = encoder.finish().unwrap();
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].uncompressed_size, 0);
+    assert!(bzip3::mem::decompress(&parts[0].data).unwrap().is_empty());
+
+    assert!(MultipartEncoder::new(100 * KB, 0).is_err());
+}
@@ -0,0 +1,120 @@
+use std::io::Write;
+
+use rand::{thread_rng, Rng};
+
+use bzip3::errors::Error;
+use bzip3::mux::{MuxReader, MuxWriter};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn interleaved_channels() {
+    let names = ["stdout", "stderr", "empty", "metadata"];
+    let mut writer = MuxWriter::new(Vec::new(), 100 * KB).unwrap();
+    for name in names {
+        writer.add_channel(name).unwrap();
+    }
+    assert!(writer.add_channel("stdout").is_err());
+
+    // pieces of random sizes to the channels in random order
+    let mut expected = vec![Vec::new(); names.len()];
+    let mut rng = thread_rng();
+    for _ in 0..200 {
+        let channel = [0, 1, 3][rng.gen_range(0..3)];
+        let data = generate_random_data(rng.gen_range(0..5 * KB));
+        writer.write(channel, &data).unwrap();
+        expected[channel as usize].extend_from_slice(&data);
+    }
+    let big = generate_random_data(250 * KB);
+    writer.channel(3).write_all(&big).unwrap();
+    expected[3].extend_from_slice(&big);
+    let file = writer.finish().unwrap();
+
+    let channels = MuxReader::new(file.as_slice())
+        .unwrap()
+        .demux_all()
+        .unwrap();
+    assert_eq!(channels.len(), names.len());
+    for ((name, data), (expected_name, expected)) in
+        channels.iter().zip(names.iter().zip(&expected))
+    {
+        assert_eq!(name, expected_name);
+        assert!(data == expected);
+    }
+
+    // truncated files fail rather than losing data silently
+    assert!(MuxReader::new(&file[..(file.len() - 1)])
+        .unwrap()
+        .demux_all()
+        .is_err());
+    assert!(MuxReader::new(&b"BZ3v1"[..]).is_err());
+}
+
+#[test]
+fn flush_channel() {
+    let mut writer = MuxWriter::new(Vec::new(), 100 * KB).unwrap();
+    let log = writer.add_channel("log").unwrap();
+    writer.write(log, b"first line\n").unwrap();
+    writer.channel(log).flush().unwrap();
+
+    writer.write(log, b"second line\n").unwrap();
+
+    // the flushed data is readable before the file is finished
+    let partial = writer.get_ref().clone();
+    let mut reader = MuxReader::new(partial.as_slice()).unwrap();
+    assert_eq!(
+        reader.next_block().unwrap(),
+        Some((log, &b"first line\n"[..]))
+    );
+    assert_eq!(reader.channels(), ["log"]);
+    assert!(reader.next_block().is_err());
+
+    let file = writer.finish().unwrap();
+    let mut reader = MuxReader::new(file.as_slice()).unwrap();
+    assert_eq!(
+        reader.next_block().unwrap(),
+        Some((log, &b"first line\n"[..]))
+    );
+    assert_eq!(
+        reader.next_block().unwrap(),
+        Some((log, &b"second line\n"[..]))
+    );
+    assert_eq!(reader.next_block().unwrap(), None);
+}
+
+#[test]
+fn max_block_size() {
+    let mut writer = MuxWriter::new(Vec::new(), 200 * KB).unwrap();
+    writer.add_channel("a").unwrap();
+    let file = writer.finish().unwrap();
+    assert!(matches!(
+        MuxReader::with_max_block_size(file.as_slice(), 100 * KB),
+        Err(Error::BlockSizeLimit { .. })
+    ));
+    assert!(MuxReader::with_max_block_size(file.as_slice(), 200 * KB).is_ok());
+}
+
+#[test]
+fn stored_block() {
+    use bzip3::frame::BlockHeader;
+
+    let mut writer = MuxWriter::new(Vec::new(), 100 * KB).unwrap();
+    let channel = writer.add_channel("a").unwrap();
+    let mut file = writer.finish().unwrap();
+
+    // a block record of a stored block, in place of the end record
+    assert_eq!(file.pop(), Some(0));
+    file.push(2);
+    file.extend_from_slice(&channel.to_le_bytes());
+    file.extend_from_slice(&BlockHeader::stored(5).to_bytes());
+    file.extend_from_slice(b"hello");
+    file.push(0);
+
+    let mut reader = MuxReader::new(file.as_slice()).unwrap();
+    assert_eq!(reader.next_block().unwrap(), Some((channel, &b"hello"[..])));
+    assert_eq!(reader.next_block().unwrap(), None);
+}
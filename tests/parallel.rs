@@ -0,0 +1,233 @@
+#![cfg(feature = "parallel")]
+
+use std::io::{self, Write};
+
+use byteorder::{WriteBytesExt, LE};
+use rayon::prelude::*;
+
+use bzip3::parallel::{self, par_compress_blocks, Bz3ParallelEncoder, CompressManyOptions};
+use bzip3::{mem, read, write, MAGIC_NUMBER};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn par_compress_blocks_matches_serial() {
+    let block_size = 100 * KB;
+    for size in [0, 1, 100 * KB, 100 * KB + 1, 1024 * KB] {
+        let data = generate_random_data(size);
+
+        let blocks = par_compress_blocks(data.par_chunks(block_size), block_size).unwrap();
+        let mut parallel = Vec::new();
+        parallel.write_all(MAGIC_NUMBER).unwrap();
+        parallel.write_i32::<LE>(block_size as i32).unwrap();
+        for block in blocks {
+            parallel.write_all(&block).unwrap();
+        }
+
+        let mut serial = Vec::new();
+        let mut encoder = write::Bz3Encoder::new(&mut serial, block_size).unwrap();
+        encoder.write_all(&data).unwrap();
+        drop(encoder);
+        assert_eq!(parallel, serial);
+
+        let mut decompressed = Vec::new();
+        let mut decoder = read::Bz3Decoder::new(parallel.as_slice()).unwrap();
+        io::copy(&mut decoder, &mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
+
+#[test]
+fn par_compress_blocks_rejects_oversized_chunks() {
+    let data = vec![0_u8; 200 * KB];
+    assert!(par_compress_blocks(data.par_chunks(150 * KB), 100 * KB).is_err());
+    assert!(par_compress_blocks(data.par_chunks(1), 1).is_err());
+}
+
+#[test]
+fn compress_parallel() {
+    for size in [0, 1, 300 * KB, 1024 * KB + 7] {
+        let data = generate_random_data(size);
+        for threads in [0, 1, 3] {
+            let compressed = mem::compress_parallel(&data, 100 * KB, threads).unwrap();
+            assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+            assert_eq!(mem::decompress(&compressed).unwrap(), data);
+        }
+    }
+    assert!(mem::compress_parallel(b"", 1, 2).is_err());
+}
+
+#[test]
+fn decompress_parallel() {
+    for size in [0, 1, 300 * KB, 1024 * KB + 7] {
+        let data = generate_random_data(size);
+        let compressed = mem::compress(&data, 100 * KB).unwrap();
+        assert_eq!(mem::decompress_parallel(&compressed).unwrap(), data);
+
+        let truncated = &compressed[..(compressed.len() - 1)];
+        assert!(mem::decompress_parallel(truncated).is_err());
+    }
+    assert!(mem::decompress_parallel(b"not bzip3").is_err());
+
+    // block headers alone, declaring far more output than can be allocated
+    let mut crafted = Vec::new();
+    crafted.write_all(MAGIC_NUMBER).unwrap();
+    crafted
+        .write_i32::<LE>(bzip3::BLOCK_SIZE_MAX as i32)
+        .unwrap();
+    for _ in 0..300_000 {
+        crafted.write_i32::<LE>(0).unwrap();
+        crafted
+            .write_i32::<LE>(bzip3::BLOCK_SIZE_MAX as i32)
+            .unwrap();
+    }
+    let error = mem::decompress_parallel(&crafted).unwrap_err();
+    assert!(matches!(error, bzip3::Error::Io(e) if e.kind() == io::ErrorKind::OutOfMemory));
+}
+
+#[test]
+fn compress_many() {
+    let dir = std::env::temp_dir().join(format!("bzip3-compress-many-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // files sharing batches, and one spanning several, around one failing
+    let sizes = [0, 10, 100 * KB, 200 * KB, 1024 * KB + 3, 7];
+    let mut jobs = Vec::new();
+    for (i, size) in sizes.into_iter().enumerate() {
+        let input = dir.join(format!("{i}"));
+        std::fs::write(&input, generate_random_data(size)).unwrap();
+        jobs.push((input, dir.join(format!("{i}.bz3"))));
+    }
+    let missing = (dir.join("missing"), dir.join("missing.bz3"));
+    jobs.insert(2, missing.clone());
+
+    let options = CompressManyOptions {
+        block_size: 100 * KB,
+        threads: 3,
+    };
+    let results = parallel::compress_many(jobs.clone(), &options);
+    assert_eq!(results.len(), jobs.len());
+    for (result, (input, output)) in results.iter().zip(&jobs) {
+        assert_eq!(&result.input, input);
+        assert_eq!(&result.output, output);
+    }
+    for result in &results {
+        if result.input == missing.0 {
+            assert!(result.result.is_err());
+            continue;
+        }
+        let compressed = std::fs::read(&result.output).unwrap();
+        assert_eq!(*result.result.as_ref().unwrap(), compressed.len() as u64);
+        let original = std::fs::read(&result.input).unwrap();
+        assert_eq!(compressed, mem::compress(&original, 100 * KB).unwrap());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Feeds `data` in pieces of `piece_size` bytes, flushing after every `flush_every` pieces.
+fn encode_in_pieces<W: Write>(mut encoder: W, data: &[u8], piece_size: usize, flush_every: usize) {
+    for (i, piece) in data.chunks(piece_size).enumerate() {
+        encoder.write_all(piece).unwrap();
+        if flush_every != 0 && i % flush_every == flush_every - 1 {
+            encoder.flush().unwrap();
+        }
+    }
+}
+
+#[test]
+fn parallel_encoder_output_is_serial_identical() {
+    let block_size = 65 * KB;
+    for size in [0, 1, 65 * KB, 65 * KB + 1, 1000 * KB] {
+        let data = generate_random_data(size);
+        for (piece_size, flush_every) in [(1000 * KB, 0), (8192, 0), (30 * KB, 3), (100 * KB, 1)] {
+            let mut serial = Vec::new();
+            encode_in_pieces(
+                write::Bz3Encoder::new(&mut serial, block_size).unwrap(),
+                &data,
+                piece_size,
+                flush_every,
+            );
+
+            for threads in [0, 1, 2, 5] {
+                let mut parallel = Vec::new();
+                encode_in_pieces(
+                    Bz3ParallelEncoder::with_threads(&mut parallel, block_size, threads).unwrap(),
+                    &data,
+                    piece_size,
+                    flush_every,
+                );
+                assert_eq!(
+                    parallel,
+                    serial,
+                    "{:?}",
+                    (size, piece_size, flush_every, threads)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_encoder_finish() {
+    let data = generate_random_data(1000 * KB);
+    for threads in [1, 3] {
+        let mut encoder = Bz3ParallelEncoder::with_threads(Vec::new(), 100 * KB, threads).unwrap();
+        // several batches, going through the same states
+        for piece in data.chunks(250 * KB) {
+            encoder.write_all(piece).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+    }
+}
+
+#[test]
+fn parallel_encoder_full_writer() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Writer taking up to `room` bytes, and none after.
+    struct Limited {
+        data: Vec<u8>,
+        room: Rc<Cell<usize>>,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let size = buf.len().min(self.room.get());
+            self.data.extend_from_slice(&buf[..size]);
+            self.room.set(self.room.get() - size);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let data = generate_random_data(1000 * KB);
+    let room = Rc::new(Cell::new(150 * KB));
+    let writer = Limited {
+        data: Vec::new(),
+        room: Rc::clone(&room),
+    };
+    let mut encoder = Bz3ParallelEncoder::with_threads(writer, 100 * KB, 2).unwrap();
+    let mut pos = 0;
+    let error = loop {
+        match encoder.write(&data[pos..]) {
+            Ok(size) => pos += size,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    // with room again, the encoder goes on with the data not written yet
+    room.set(usize::MAX);
+    encoder.write_all(&data[pos..]).unwrap();
+    let compressed = encoder.finish().unwrap().data;
+    assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "serde")]
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use bzip3::payload::*;
+use bzip3::Error;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct State {
+    name: String,
+    values: Vec<u64>,
+}
+
+#[test]
+fn payload_round_trip() {
+    let state = State {
+        name: "state".into(),
+        values: (0..100_000).map(|x| x % 7).collect(),
+    };
+
+    let compressed = to_writer_compressed(Vec::new(), &state, 100 * 1024).unwrap();
+    assert_eq!(
+        from_reader_compressed::<_, State>(compressed.as_slice()).unwrap(),
+        state
+    );
+
+    let compressed = to_writer_compressed_with(Vec::new(), 100 * 1024, |encoder| {
+        serde_json::to_writer(encoder, &state)
+    })
+    .unwrap();
+    let decoded: State = from_reader_compressed_with(compressed.as_slice(), |decoder| {
+        serde_json::from_reader(decoder)
+    })
+    .unwrap();
+    assert_eq!(decoded, state);
+
+    // a different type, trailing data, and not bzip3 at all
+    let compressed = to_writer_compressed(Vec::new(), &(1_u32, 2_u32), 100 * 1024).unwrap();
+    assert!(matches!(
+        from_reader_compressed::<_, u32>(compressed.as_slice()),
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+    ));
+    assert!(from_reader_compressed::<_, State>(compressed.as_slice()).is_err());
+    assert!(matches!(
+        from_reader_compressed::<_, State>(&b"not bzip3"[..]),
+        Err(Error::InvalidSignature)
+    ));
+}
@@ -0,0 +1,96 @@
+use std::io::{Read, Write};
+
+use bzip3::pool;
+use bzip3::pool::BufferPool;
+use bzip3::{read, write};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn reuse() {
+    let pool = BufferPool::new(2);
+    // shrinking data, so that stale contents of reused buffers would show
+    for size in [250 * KB, 150 * KB, 10 * KB, 1, 0] {
+        let data = generate_random_data(size);
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_buffer_pool(pool.clone());
+        encoder.set_checksum(true);
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder =
+            read::Bz3Decoder::with_buffer_pool(compressed.as_slice(), pool.clone()).unwrap();
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+        drop(decoder);
+
+        let mut decompressed = Vec::new();
+        let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+        decoder.set_buffer_pool(pool.clone());
+        decoder.write_all(&compressed).unwrap();
+        drop(decoder);
+        assert!(decompressed == data);
+    }
+    // one buffer of the encoders, and one of the decoders, and a state going to both
+    assert_eq!(pool.len(), 2);
+    assert!(format!("{pool:?}").contains("states: [(102400, 1)]"));
+    pool.clear();
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn bounded() {
+    let pool = BufferPool::new(2);
+    let encoders = (0..5)
+        .map(|_| {
+            let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+            encoder.set_buffer_pool(pool.clone());
+            encoder.write_all(b"hello, world").unwrap();
+            encoder
+        })
+        .collect::<Vec<_>>();
+    assert!(pool.is_empty());
+    drop(encoders);
+    assert_eq!(pool.len(), 2);
+
+    // buffers of another size are kept apart
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 200 * KB).unwrap();
+    encoder.set_buffer_pool(pool.clone());
+    encoder.write_all(b"hello, world").unwrap();
+    assert_eq!(pool.len(), 2);
+    drop(encoder);
+    assert_eq!(pool.len(), 3);
+}
+
+#[test]
+fn alignment() {
+    assert!(matches!(
+        pool::set_alignment(0),
+        Err(bzip3::Error::InvalidArgument(_))
+    ));
+    assert!(pool::set_alignment(48).is_err());
+
+    let data = generate_random_data(250 * KB);
+    let buffers = BufferPool::new(1);
+    // the buffers kept from the first round don't match the larger alignment
+    for alignment in [16, 4096] {
+        pool::set_alignment(alignment).unwrap();
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_buffer_pool(buffers.clone());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder =
+            read::Bz3Decoder::with_buffer_pool(compressed.as_slice(), buffers.clone()).unwrap();
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+    }
+    pool::set_alignment(pool::DEFAULT_ALIGNMENT).unwrap();
+    assert_eq!(pool::alignment(), pool::DEFAULT_ALIGNMENT);
+}
@@ -0,0 +1,166 @@
+use std::io::{Cursor, Read};
+use std::ops::Range;
+
+use bzip3::mem;
+use bzip3::recover::Bz3RecoveringDecoder;
+use bzip3::seek::{scan_index, Bz3IndexedEncoder};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+/// Reader returning at most 1000 bytes per read, to exercise the buffering.
+struct SmallReads<'a>(&'a [u8]);
+
+impl Read for SmallReads<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = buf.len().min(1000);
+        self.0.read(&mut buf[..size])
+    }
+}
+
+fn recover(compressed: &[u8]) -> (Vec<u8>, Vec<Range<u64>>) {
+    let mut decoder = Bz3RecoveringDecoder::new(SmallReads(compressed)).unwrap();
+    let mut recovered = Vec::new();
+    decoder.read_to_end(&mut recovered).unwrap();
+    (recovered, decoder.skipped().to_vec())
+}
+
+#[test]
+fn intact_file() {
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    assert_eq!(recover(&compressed), (data.clone(), Vec::new()));
+
+    // extension blocks aren't damage
+    let mut encoder = Bz3IndexedEncoder::new(Vec::new(), 100 * KB).unwrap();
+    std::io::Write::write_all(&mut encoder, &data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(recover(&compressed), (data, Vec::new()));
+}
+
+#[test]
+fn spliced_files() {
+    let first = generate_random_data(250 * KB);
+    let second = generate_random_data(300 * KB);
+    let first_compressed = mem::compress(&first, 100 * KB).unwrap();
+    let second_compressed = mem::compress(&second, 200 * KB).unwrap();
+
+    // the first file is cut inside its last block
+    let last_block = scan_index(Cursor::new(&first_compressed))
+        .unwrap()
+        .entries()[2]
+        .compressed_offset as usize;
+    let mut spliced = first_compressed[..(last_block + 100)].to_vec();
+    spliced.extend_from_slice(&second_compressed);
+
+    let (recovered, skipped) = recover(&spliced);
+    assert!(recovered[..(200 * KB)] == first[..(200 * KB)]);
+    assert!(recovered[(200 * KB)..] == second[..]);
+    assert_eq!(
+        skipped,
+        vec![(last_block as u64)..((last_block + 100) as u64)]
+    );
+}
+
+#[test]
+fn damaged_blocks() {
+    let data = generate_random_data(1000 * KB + 3);
+    let mut compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    let entries = index.entries();
+
+    // the CRC of block 2, the header of block 5 and the data of block 6, and the last block
+    let block_2 = entries[2].compressed_offset as usize;
+    compressed[block_2 + 8] ^= 1;
+    let block_5 = entries[5].compressed_offset as usize;
+    let block_7 = entries[7].compressed_offset as usize;
+    compressed[block_5..(block_5 + 4)].fill(0xaa);
+    compressed[(block_7 - 10)..block_7].fill(0);
+    compressed.truncate(compressed.len() - 5);
+
+    let (recovered, skipped) = recover(&compressed);
+    let block = |n: usize| &data[(n * 100 * KB)..((n + 1) * 100 * KB)];
+    let expected = [0, 1, 3, 4, 7, 8, 9]
+        .into_iter()
+        .flat_map(block)
+        .copied()
+        .collect::<Vec<_>>();
+    assert!(recovered == expected);
+    assert_eq!(
+        skipped,
+        [
+            entries[2].compressed_offset..entries[3].compressed_offset,
+            entries[5].compressed_offset..entries[7].compressed_offset,
+            entries[10].compressed_offset..(compressed.len() as u64),
+        ]
+    );
+}
+
+#[test]
+fn garbage_between_blocks() {
+    let data = generate_random_data(300 * KB);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    let block_1 = index.entries()[1].compressed_offset as usize;
+
+    let mut damaged = compressed[..block_1].to_vec();
+    damaged.extend_from_slice(&generate_random_data(5000));
+    damaged.extend_from_slice(&compressed[block_1..]);
+    let (recovered, skipped) = recover(&damaged);
+    assert_eq!(recovered, data);
+    assert_eq!(skipped, vec![(block_1 as u64)..(block_1 as u64 + 5000)]);
+}
+
+#[test]
+fn damaged_file_header() {
+    let compressed = mem::compress(b"hello", 100 * KB).unwrap();
+    let mut damaged = compressed.clone();
+    damaged[0] = 0;
+    assert!(matches!(
+        Bz3RecoveringDecoder::new(damaged.as_slice()),
+        Err(bzip3::Error::InvalidSignature)
+    ));
+}
+
+#[test]
+fn salvage_blocks() {
+    use bzip3::recover::salvage_blocks;
+
+    let data = generate_random_data(500 * KB);
+    let mut compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    let entries = index.entries();
+
+    // the CRC of block 1, and the header of block 3
+    compressed[entries[1].compressed_offset as usize + 8] ^= 1;
+    let block_3 = entries[3].compressed_offset as usize;
+    compressed[block_3..(block_3 + 4)].fill(0xaa);
+
+    let mut blocks = salvage_blocks(SmallReads(&compressed)).unwrap();
+    let salvaged = blocks.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(
+        blocks.skipped(),
+        vec![entries[3].compressed_offset..entries[4].compressed_offset]
+    );
+    let offsets = salvaged
+        .iter()
+        .map(|x| (x.compressed_offset, x.uncompressed_offset, x.crc_valid))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        offsets,
+        [
+            (entries[0].compressed_offset, Some(0), true),
+            (entries[1].compressed_offset, Some(100 * KB as u64), false),
+            (entries[2].compressed_offset, Some(200 * KB as u64), true),
+            (entries[4].compressed_offset, None, true),
+        ]
+    );
+    for (block, n) in salvaged.iter().zip([0, 1, 2, 4]) {
+        if block.crc_valid {
+            assert!(block.data == data[(n * 100 * KB)..((n + 1) * 100 * KB)]);
+        }
+    }
+}
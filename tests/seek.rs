@@ -0,0 +1,582 @@
+use std::io::{Cursor, Read, Write};
+
+use rand::{thread_rng, RngCore};
+
+use bzip3::frame::{BlockHeader, FRAME_HEADER_SIZE};
+use bzip3::seek::{Bz3Index, Bz3IndexedEncoder};
+use bzip3::{mem, read, write};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+fn compress_indexed(data: &[u8], block_size: usize) -> Vec<u8> {
+    let mut encoder = Bz3IndexedEncoder::new(Vec::new(), block_size).unwrap();
+    for chunk in data.chunks(30 * KB) {
+        encoder.write_all(chunk).unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn indexed_encoder() {
+    for size in [0, 1, 100 * KB, 1000 * KB + 3] {
+        let data = generate_random_data(size);
+        let plain = mem::compress(&data, 100 * KB).unwrap();
+        let compressed = compress_indexed(&data, 100 * KB);
+
+        // a plain bzip3 stream followed by the index
+        let index = Bz3Index::read_trailer(Cursor::new(&compressed))
+            .unwrap()
+            .unwrap();
+        assert_eq!(index.compressed_size(), plain.len() as u64);
+        assert_eq!(&compressed[..plain.len()], plain);
+        assert_eq!(index.uncompressed_size(), data.len() as u64);
+        assert_eq!(index.entries().len(), data.len().div_ceil(100 * KB));
+
+        for (i, entry) in index.entries().iter().enumerate() {
+            assert_eq!(entry.uncompressed_offset, (i * 100 * KB) as u64);
+            let offset = entry.compressed_offset as usize;
+            let header = BlockHeader::parse(compressed[offset..(offset + 8)].try_into().unwrap());
+            assert_eq!(
+                header.read_size as usize,
+                (data.len() - i * 100 * KB).min(100 * KB)
+            );
+        }
+        if let Some(first) = index.entries().first() {
+            assert_eq!(first.compressed_offset, FRAME_HEADER_SIZE as u64);
+        }
+
+        // decoders skip the index
+        assert_eq!(mem::decompress(&compressed).unwrap(), data);
+        let mut decompressed = Vec::new();
+        let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+        decoder.write_all(&compressed).unwrap();
+        drop(decoder);
+        assert_eq!(decompressed, data);
+        #[cfg(feature = "parallel")]
+        assert_eq!(mem::decompress_parallel(&compressed).unwrap(), data);
+
+        assert_eq!(Bz3Index::read_trailer(Cursor::new(&plain)).unwrap(), None);
+
+        let mut truncated = read::Bz3Decoder::new(&compressed[..(compressed.len() - 1)]).unwrap();
+        assert!(truncated.read_to_end(&mut Vec::new()).is_err());
+    }
+}
+
+#[test]
+fn corrupt_index() {
+    let data = generate_random_data(300 * KB);
+    let mut compressed = compress_indexed(&data, 100 * KB);
+    let index = Bz3Index::read_trailer(Cursor::new(&compressed))
+        .unwrap()
+        .unwrap();
+
+    // the uncompressed offset of the second entry
+    let entry_offset = index.compressed_size() as usize + 8 + 4 + 16 + 8;
+    let original = compressed[entry_offset..(entry_offset + 8)].to_vec();
+    compressed[entry_offset..(entry_offset + 8)].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(Bz3Index::read_trailer(Cursor::new(&compressed)).is_err());
+    compressed[entry_offset..(entry_offset + 8)].copy_from_slice(&original);
+
+    // an uncompressed size more than the last block could hold, which read_range would otherwise
+    // try to allocate
+    let size_offset = compressed.len() - 8 - 8 - 4 - 4;
+    compressed[size_offset..(size_offset + 8)].copy_from_slice(&(1_u64 << 50).to_le_bytes());
+    assert!(Bz3Index::read_trailer(Cursor::new(&compressed)).is_err());
+    assert!(
+        Bz3Index::from_parts(index.entries().to_vec(), 1 << 50, index.compressed_size()).is_err()
+    );
+}
+
+#[test]
+fn seekable_decoder() {
+    use std::io::{Seek, SeekFrom};
+
+    use bzip3::seek::Bz3SeekableDecoder;
+
+    let data = generate_random_data(1000 * KB + 3);
+    for compressed in [
+        compress_indexed(&data, 100 * KB),
+        mem::compress(&data, 100 * KB).unwrap(),
+    ] {
+        let mut decoder = Bz3SeekableDecoder::new(Cursor::new(&compressed)).unwrap();
+        assert_eq!(decoder.index().uncompressed_size(), data.len() as u64);
+
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+
+        let mut rng = thread_rng();
+        for _ in 0..50 {
+            let start = rng.next_u32() as usize % data.len();
+            let size = (rng.next_u32() as usize % (300 * KB)).min(data.len() - start);
+            assert_eq!(
+                decoder.seek(SeekFrom::Start(start as u64)).unwrap(),
+                start as u64
+            );
+            let mut range = vec![0_u8; size];
+            decoder.read_exact(&mut range).unwrap();
+            assert_eq!(range, &data[start..(start + size)]);
+        }
+
+        decoder.seek(SeekFrom::End(-5)).unwrap();
+        decoder.seek(SeekFrom::Current(-5)).unwrap();
+        let mut tail = Vec::new();
+        decoder.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[(data.len() - 10)..]);
+
+        assert!(decoder
+            .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+            .is_err());
+        decoder.seek(SeekFrom::End(10)).unwrap();
+        assert_eq!(decoder.read(&mut [0_u8; 10]).unwrap(), 0);
+    }
+
+    let empty = compress_indexed(&[], 100 * KB);
+    let mut decoder = Bz3SeekableDecoder::new(Cursor::new(empty)).unwrap();
+    assert_eq!(decoder.read(&mut [0_u8; 10]).unwrap(), 0);
+
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let truncated = &compressed[..(compressed.len() - 1)];
+    assert!(Bz3SeekableDecoder::new(Cursor::new(truncated)).is_err());
+}
+
+#[test]
+fn scan_index() {
+    use bzip3::seek::scan_index;
+
+    for size in [0, 1, 100 * KB, 1000 * KB + 3] {
+        let data = generate_random_data(size);
+        let indexed = compress_indexed(&data, 100 * KB);
+        let index = Bz3Index::read_trailer(Cursor::new(&indexed))
+            .unwrap()
+            .unwrap();
+
+        // the index is skipped when scanning
+        assert_eq!(scan_index(Cursor::new(&indexed)).unwrap(), index);
+        let plain = mem::compress(&data, 100 * KB).unwrap();
+        assert_eq!(scan_index(Cursor::new(&plain)).unwrap(), index);
+
+        let mut flushed = Vec::new();
+        let mut encoder = write::Bz3Encoder::new(&mut flushed, 100 * KB).unwrap();
+        for chunk in data.chunks(30 * KB) {
+            encoder.write_all(chunk).unwrap();
+            encoder.flush().unwrap();
+        }
+        drop(encoder);
+        let index = scan_index(Cursor::new(&flushed)).unwrap();
+        assert_eq!(index.entries().len(), data.len().div_ceil(30 * KB));
+        assert_eq!(index.uncompressed_size(), data.len() as u64);
+        assert_eq!(index.compressed_size(), flushed.len() as u64);
+
+        let truncated = &plain[..(plain.len() - 1)];
+        assert!(scan_index(Cursor::new(truncated)).is_err());
+    }
+}
+
+#[test]
+fn decode_block_at() {
+    use std::io::{Seek, SeekFrom};
+
+    use bzip3::seek::Bz3SeekableDecoder;
+
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = compress_indexed(&data, 100 * KB);
+    let mut decoder = Bz3SeekableDecoder::new(Cursor::new(&compressed)).unwrap();
+    decoder.seek(SeekFrom::Start(5)).unwrap();
+
+    let block_count = decoder.index().entries().len();
+    for i in (0..block_count).rev() {
+        let block = decoder.decode_block_at(i).unwrap();
+        let start = i * 100 * KB;
+        assert_eq!(block, &data[start..(start + block.len())]);
+    }
+    assert!(decoder.decode_block_at(block_count).is_err());
+
+    // the read position is kept
+    let mut buf = [0_u8; 10];
+    decoder.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, data[5..15]);
+}
+
+#[test]
+fn index_file() {
+    use bzip3::seek::{scan_index, Bz3SeekableDecoder, INDEX_FILE_MAGIC};
+
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+
+    let mut index_file = Vec::new();
+    index.save(&mut index_file).unwrap();
+    assert_eq!(&index_file[..4], INDEX_FILE_MAGIC);
+    let loaded = Bz3Index::load(index_file.as_slice()).unwrap();
+    assert_eq!(loaded, index);
+
+    let mut decoder = Bz3SeekableDecoder::with_index(Cursor::new(&compressed), loaded).unwrap();
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+
+    // truncated, bad magic, unknown version, inconsistent
+    assert!(Bz3Index::load(&index_file[..(index_file.len() - 1)]).is_err());
+    let mut bad = index_file.clone();
+    bad[0] = b'X';
+    assert!(matches!(
+        Bz3Index::load(bad.as_slice()),
+        Err(bzip3::Error::InvalidSignature)
+    ));
+    let mut bad = index_file.clone();
+    bad[4] = 2;
+    assert!(matches!(
+        Bz3Index::load(bad.as_slice()),
+        Err(bzip3::Error::UnsupportedVersion(2))
+    ));
+    let mut bad = index_file.clone();
+    let last = bad.len() - 8;
+    bad[last..].copy_from_slice(&0_u64.to_le_bytes());
+    assert!(matches!(
+        Bz3Index::load(bad.as_slice()),
+        Err(bzip3::Error::ProcessBlock(_))
+    ));
+}
+
+#[test]
+fn read_range() {
+    use bzip3::seek::{range_reader, read_range, scan_index};
+
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+
+    let size = data.len() as u64;
+    let ranges = [
+        0..0,
+        0..1,
+        0..size,
+        5..(100 * KB as u64),
+        (100 * KB as u64)..(200 * KB as u64 + 1),
+        12345..777777,
+        (size - 3)..(size + 100),
+        size..(size + 1),
+        (size + 10)..(size + 20),
+        #[allow(clippy::reversed_empty_ranges)]
+        (20..10),
+    ];
+    for range in ranges {
+        let start = (range.start as usize).min(data.len());
+        let end = (range.end as usize).clamp(start, data.len());
+        let expected = &data[start..end];
+
+        let output = read_range(Cursor::new(&compressed), &index, range.clone()).unwrap();
+        assert_eq!(output, expected);
+
+        let mut reader = range_reader(Cursor::new(&compressed), index.clone(), range).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn index_queries() {
+    use bzip3::seek::{scan_index, IndexEntry, INDEX_FILE_VERSION};
+
+    let data = generate_random_data(250 * KB);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    assert_eq!(index.version(), INDEX_FILE_VERSION);
+    assert_eq!(index.len(), 3);
+    assert!(!index.is_empty());
+    assert_eq!(index.uncompressed_size(), data.len() as u64);
+    assert_eq!(index.block_containing(0), Some(0));
+    assert_eq!(index.block_containing(100 * KB as u64), Some(1));
+    assert_eq!(index.block_containing(data.len() as u64 - 1), Some(2));
+    assert_eq!(index.block_containing(data.len() as u64), None);
+
+    let rebuilt = Bz3Index::from_parts(
+        index.entries().to_vec(),
+        index.uncompressed_size(),
+        index.compressed_size(),
+    )
+    .unwrap();
+    assert_eq!(rebuilt, index);
+    // the first block must start at the beginning of the data
+    let mut entries = index.entries().to_vec();
+    entries[0] = IndexEntry {
+        uncompressed_offset: 1,
+        ..entries[0]
+    };
+    assert!(
+        Bz3Index::from_parts(entries, index.uncompressed_size(), index.compressed_size()).is_err()
+    );
+    assert!(Bz3Index::from_parts(Vec::new(), 1, FRAME_HEADER_SIZE as u64).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn index_serde() {
+    use bzip3::seek::scan_index;
+
+    let data = generate_random_data(250 * KB);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+
+    let json = serde_json::to_value(&index).unwrap();
+    assert_eq!(json["version"], 1);
+    let deserialized: Bz3Index = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(deserialized, index);
+
+    let mut bad = json.clone();
+    bad["version"] = 2.into();
+    assert!(serde_json::from_value::<Bz3Index>(bad).is_err());
+    let mut bad = json;
+    bad["uncompressed_size"] = 0.into();
+    assert!(serde_json::from_value::<Bz3Index>(bad).is_err());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn read_ranges_parallel() {
+    use bzip3::seek::{read_ranges_parallel, scan_index};
+
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+
+    let len = data.len() as u64;
+    let ranges = [
+        0..10,
+        50 * KB as u64..450 * KB as u64,
+        // overlapping the previous one
+        440 * KB as u64..460 * KB as u64,
+        900 * KB as u64..len + 100,
+        len..len + 1,
+        700..700,
+    ];
+    let open = || Ok(Cursor::new(compressed.as_slice()));
+    let output = read_ranges_parallel(open, &index, &ranges).unwrap();
+    assert_eq!(output.len(), ranges.len());
+    for (range, output) in ranges.iter().zip(&output) {
+        let end = range.end.min(len) as usize;
+        let start = (range.start as usize).min(end);
+        assert_eq!(output.as_slice(), &data[start..end]);
+    }
+}
+
+#[test]
+fn uncompressed_len() {
+    let data = generate_random_data(250 * KB + 7);
+
+    let plain = mem::compress(&data, 100 * KB).unwrap();
+    let indexed = compress_indexed(&data, 100 * KB);
+    for compressed in [plain, indexed] {
+        assert_eq!(
+            bzip3::seek::uncompressed_len(Cursor::new(&compressed)).unwrap(),
+            data.len() as u64
+        );
+
+        // the read position is kept
+        let mut decoder = read::Bz3Decoder::new(Cursor::new(&compressed)).unwrap();
+        let mut head = vec![0_u8; 1000];
+        decoder.read_exact(&mut head).unwrap();
+        assert_eq!(decoder.uncompressed_len().unwrap(), data.len() as u64);
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest).unwrap();
+        head.extend_from_slice(&rest);
+        assert_eq!(head, data);
+    }
+}
+
+#[test]
+fn block_callback() {
+    use std::sync::{Arc, Mutex};
+
+    use bzip3::seek::{scan_index, IndexEntry};
+
+    let data = generate_random_data(1000 * KB + 3);
+
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let mut compressed = Vec::new();
+    let mut encoder = write::Bz3Encoder::new(&mut compressed, 100 * KB).unwrap();
+    let entries2 = Arc::clone(&entries);
+    encoder.set_block_callback(move |block, compressed_offset, uncompressed_offset| {
+        let mut entries = entries2.lock().unwrap();
+        assert_eq!(block, entries.len());
+        entries.push(IndexEntry {
+            compressed_offset,
+            uncompressed_offset,
+        });
+    });
+    for chunk in data.chunks(30 * KB) {
+        encoder.write_all(chunk).unwrap();
+    }
+    encoder.flush().unwrap();
+    drop(encoder);
+
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    assert_eq!(entries.lock().unwrap().as_slice(), index.entries());
+
+    #[cfg(feature = "parallel")]
+    {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let mut encoder =
+            bzip3::parallel::Bz3ParallelEncoder::with_threads(Vec::new(), 100 * KB, 3).unwrap();
+        let entries2 = Arc::clone(&entries);
+        encoder.set_block_callback(move |_, compressed_offset, uncompressed_offset| {
+            entries2.lock().unwrap().push(IndexEntry {
+                compressed_offset,
+                uncompressed_offset,
+            });
+        });
+        for chunk in data.chunks(30 * KB) {
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder.flush().unwrap();
+        assert_eq!(entries.lock().unwrap().as_slice(), index.entries());
+    }
+}
+
+#[test]
+fn remote_reader() {
+    use std::io::{Seek, SeekFrom};
+
+    use bzip3::seek::{scan_index, ReadAt, RemoteBz3Reader};
+
+    /// Counts the reads, to check that only the needed blocks are fetched.
+    struct Counting<'a>(&'a [u8], std::cell::Cell<usize>);
+
+    impl ReadAt for Counting<'_> {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+            self.1.set(self.1.get() + 1);
+            self.0.read_at(buf, offset)
+        }
+
+        fn size(&self) -> std::io::Result<u64> {
+            self.0.size()
+        }
+    }
+
+    let data = generate_random_data(1000 * KB + 3);
+    let compressed = compress_indexed(&data, 100 * KB);
+
+    let mut reader = RemoteBz3Reader::new(compressed.as_slice()).unwrap();
+    assert_eq!(reader.uncompressed_len(), data.len() as u64);
+    let mut decompressed = Vec::new();
+    reader.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+
+    let index = reader.index().clone();
+    let source = Counting(&compressed, Default::default());
+    let mut reader = RemoteBz3Reader::with_index(&source, index).unwrap();
+    let reads = source.1.get();
+    reader.seek(SeekFrom::Start(450 * KB as u64)).unwrap();
+    let mut buf = vec![0_u8; 100 * KB];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, &data[(450 * KB)..(550 * KB)]);
+    // two blocks, a read each
+    assert_eq!(source.1.get() - reads, 2);
+
+    // a file from a plain encoder
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+    let path = std::env::temp_dir().join(format!("bzip3-remote-{}.bz3", std::process::id()));
+    std::fs::write(&path, &compressed).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let mut reader = RemoteBz3Reader::new(file).unwrap();
+    assert_eq!(
+        reader.index(),
+        &scan_index(Cursor::new(&compressed)).unwrap()
+    );
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &data[(data.len() - 10)..]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_source() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    use bzip3::seek::{HttpSource, RemoteBz3Reader};
+
+    let data = generate_random_data(300 * KB);
+    let compressed = Arc::new(compress_indexed(&data, 100 * KB));
+
+    // a minimal server answering HEAD and ranged GET requests
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let served = Arc::clone(&compressed);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    let (start, end) = value.trim().split_once('-').unwrap();
+                    range =
+                        Some(start.parse::<usize>().unwrap()..(end.parse::<usize>().unwrap() + 1));
+                }
+            }
+            let response = match (request.starts_with("HEAD"), range) {
+                (true, _) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    served.len()
+                )
+                .into_bytes(),
+                (false, Some(range)) => {
+                    let mut response = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        range.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&served[range]);
+                    response
+                }
+                (false, None) => b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n".to_vec(),
+            };
+            stream.write_all(&response).unwrap();
+        }
+    });
+
+    let source = HttpSource::new(format!("http://{}/file.bz3", address)).unwrap();
+    let mut reader = RemoteBz3Reader::new(source).unwrap();
+    let mut decompressed = Vec::new();
+    reader.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn split_frames() {
+    let data = generate_random_data(1000 * KB + 3);
+    let mut encoder = Bz3IndexedEncoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let parts =
+        bzip3::frame::split(Cursor::new(&compressed), &[0..3, 3..11, 5..5, 10..11]).unwrap();
+    let decompressed = parts
+        .iter()
+        .map(|x| bzip3::mem::decompress(x).unwrap())
+        .collect::<Vec<_>>();
+    assert!(decompressed[0] == data[..(300 * KB)]);
+    assert!(decompressed[1] == data[(300 * KB)..]);
+    assert!(decompressed[2].is_empty());
+    assert!(decompressed[3] == data[(1000 * KB)..]);
+
+    assert!(bzip3::frame::split(Cursor::new(&compressed), &[0..5, 10..12]).is_err());
+}
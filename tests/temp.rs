@@ -0,0 +1,63 @@
+use std::io::{Read, Write};
+
+use bzip3::temp::Bz3TempBuffer;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[test]
+fn in_memory() {
+    let data = generate_random_data(150 * KB);
+    let mut buffer = Bz3TempBuffer::new(100 * KB, 1024 * KB).unwrap();
+    buffer.write_all(&data).unwrap();
+    assert_eq!(buffer.len(), data.len() as u64);
+    assert!(!buffer.is_spilled());
+
+    let mut decompressed = Vec::new();
+    buffer
+        .into_reader()
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert!(decompressed == data);
+
+    let buffer = Bz3TempBuffer::new(100 * KB, 1024 * KB).unwrap();
+    assert!(buffer.is_empty());
+    let mut decompressed = Vec::new();
+    buffer
+        .into_reader()
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert!(decompressed.is_empty());
+}
+
+#[test]
+fn spilled() {
+    let dir = std::env::temp_dir().join(format!("bzip3-temp-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = || std::fs::read_dir(&dir).unwrap().count();
+
+    let data = generate_random_data(500 * KB);
+    let mut buffer = Bz3TempBuffer::new_in(100 * KB, 150 * KB, &dir).unwrap();
+    buffer.write_all(&data[..(100 * KB)]).unwrap();
+    assert!(!buffer.is_spilled());
+    assert_eq!(files(), 0);
+    buffer.write_all(&data[(100 * KB)..]).unwrap();
+    assert!(buffer.is_spilled());
+    assert!(buffer.compressed_size() > 150 * KB as u64);
+    assert_eq!(files(), 1);
+
+    let mut reader = buffer.into_reader().unwrap();
+    let mut decompressed = Vec::new();
+    reader.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+    // the file goes with the reader
+    drop(reader);
+    assert_eq!(files(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -2,11 +2,14 @@ extern crate core;
 
 use bytesize::{ByteSize, MIB};
 use hex_literal::hex;
-use rand::{thread_rng, RngCore};
 use std::fmt::Write as _;
 use std::io::{self, Cursor, Read, Write};
 
-use bzip3::{read, write, Bz3State, BLOCK_SIZE_MAX, BLOCK_SIZE_MIN, MAGIC_NUMBER};
+use bzip3::{read, write, Bz3State, Level, BLOCK_SIZE_MAX, BLOCK_SIZE_MIN, MAGIC_NUMBER};
+
+mod common;
+
+use common::generate_random_data;
 
 const KB: usize = 1024;
 
@@ -256,14 +259,6 @@ fn test_read_based(data_size: usize, block_size: usize) {
     assert_eq!(uncompressed.get_ref().as_slice(), data.as_slice());
 }
 
-fn generate_random_data(size: usize) -> Vec<u8> {
-    let mut rng = thread_rng();
-
-    let mut data = vec![0_u8; size];
-    rng.fill_bytes(&mut data);
-    data
-}
-
 fn generate_deterministic_data(size: usize) -> Vec<u8> {
     let mut string = String::with_capacity(size + 20);
 
@@ -291,3 +286,1467 @@ fn block_size() {
     assert!(Bz3State::new(BLOCK_SIZE_MIN - 1).is_err());
     assert!(Bz3State::new(BLOCK_SIZE_MAX + 1).is_err());
 }
+
+#[test]
+fn levels() {
+    assert!(matches!(
+        Level::new(0),
+        Err(bzip3::Error::InvalidArgument(_))
+    ));
+    assert!(Level::new(10).is_err());
+    for level in 1..=9 {
+        let block_size = Level::new(level).unwrap().block_size();
+        assert!(Bz3State::new(block_size).is_ok());
+    }
+    assert_eq!(Level::FASTEST.block_size(), MIB as usize);
+    assert_eq!(Level::BEST.block_size(), 256 * MIB as usize);
+    assert!(Level::FASTEST < Level::default() && Level::default() < Level::BEST);
+
+    let data = generate_random_data(100 * KB);
+    let mut encoder = write::Bz3Encoder::with_level(Vec::new(), Level::FASTEST).unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let mut read_compressed = Vec::new();
+    read::Bz3Encoder::with_level(data.as_slice(), Level::FASTEST)
+        .unwrap()
+        .read_to_end(&mut read_compressed)
+        .unwrap();
+    assert_eq!(read_compressed, compressed);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+}
+
+#[test]
+fn block_size_str() {
+    use bzip3::parse_block_size;
+
+    assert_eq!(parse_block_size("16MiB").unwrap(), 16 * MIB as usize);
+    assert_eq!(parse_block_size(" 16 mib ").unwrap(), 16 * MIB as usize);
+    assert_eq!(parse_block_size("512k").unwrap(), 512_000);
+    assert_eq!(parse_block_size("100KiB").unwrap(), 100 * KB);
+    assert_eq!(parse_block_size("1048576").unwrap(), MIB as usize);
+    assert!(matches!(
+        parse_block_size("16 parsecs"),
+        Err(bzip3::Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        parse_block_size(""),
+        Err(bzip3::Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        parse_block_size("64KiB"),
+        Err(bzip3::Error::BlockSize)
+    ));
+    assert!(matches!(
+        parse_block_size("1GiB"),
+        Err(bzip3::Error::BlockSize)
+    ));
+    assert!(matches!(
+        parse_block_size("1PiB"),
+        Err(bzip3::Error::BlockSize)
+    ));
+
+    let data = generate_random_data(100 * KB);
+    let mut encoder = write::Bz3Encoder::with_block_size_str(Vec::new(), "1MiB").unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let mut read_compressed = Vec::new();
+    read::Bz3Encoder::with_block_size_str(data.as_slice(), "1 MiB")
+        .unwrap()
+        .read_to_end(&mut read_compressed)
+        .unwrap();
+    assert_eq!(read_compressed, compressed);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    assert!(write::Bz3Encoder::with_block_size_str(Vec::new(), "1TB").is_err());
+}
+
+#[test]
+fn defaults() {
+    use bzip3::frame::FrameHeader;
+
+    assert_eq!(bzip3::DEFAULT_BLOCK_SIZE, Level::default().block_size());
+    let data = generate_random_data(100 * KB);
+    let mut encoder = write::Bz3Encoder::with_defaults(Vec::new()).unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let header = FrameHeader::read_from(&mut compressed.as_slice()).unwrap();
+    assert_eq!(header.block_size, bzip3::DEFAULT_BLOCK_SIZE);
+
+    let mut read_compressed = Vec::new();
+    read::Bz3Encoder::with_defaults(data.as_slice())
+        .read_to_end(&mut read_compressed)
+        .unwrap();
+    assert_eq!(read_compressed, compressed);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+}
+
+#[test]
+fn debug() {
+    let data = generate_random_data(250 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.write_all(&data).unwrap();
+    let debug = format!("{encoder:?}");
+    assert!(debug.starts_with("Bz3Encoder { block_size: 102400, blocks: 2, pending_input: 51200,"));
+    assert!(debug.contains("failed: false"));
+    let compressed = encoder.finish().unwrap();
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    let mut buf = vec![0_u8; 150 * KB];
+    decoder.read_exact(&mut buf).unwrap();
+    let debug = format!("{decoder:?}");
+    assert!(debug.contains("blocks: 2"));
+    assert!(debug.contains("pending_output: 51200"));
+    assert!(debug.contains("eof: false"));
+    assert!(debug.ends_with(", .. }"));
+
+    let mut encoder = read::Bz3Encoder::new(data.as_slice(), 100 * KB).unwrap();
+    encoder.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(
+        format!("{encoder:?}"),
+        "Bz3Encoder { block_size: 102400, blocks: 3, pending_output: 0, eof: true, failed: false, .. }"
+    );
+
+    let mut decoder = read::Bz3Decoder::new(&compressed[..1000]).unwrap();
+    assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    assert!(format!("{decoder:?}").contains("failed: true"));
+}
+
+#[test]
+fn to_vec() {
+    let data = generate_random_data(250 * KB);
+    let compressed = read::Bz3Encoder::new(data.as_slice(), 100 * KB)
+        .unwrap()
+        .encode_to_vec()
+        .unwrap();
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    let decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    assert_eq!(decoder.decode_to_vec().unwrap(), data);
+
+    let decoder = read::Bz3Decoder::new(&compressed[..1000]).unwrap();
+    assert!(matches!(
+        decoder.decode_to_vec(),
+        Err(bzip3::Error::TruncatedBlock { block_index: 0, .. })
+    ));
+}
+
+#[test]
+fn codec_state() {
+    use byteorder::{ByteOrder, LE};
+
+    let data = generate_random_data(150 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    assert!(encoder.state().is_none());
+    encoder.write_all(&data).unwrap();
+    let state = encoder.state().unwrap();
+    assert_eq!(state.block_size(), 100 * KB);
+    assert_eq!(state.last_error(), 0);
+    let first_crc = state.last_crc().unwrap();
+    let compressed = encoder.finish().unwrap();
+    // the CRC leads the data of the first block
+    assert_eq!(first_crc, LE::read_u32(&compressed[(9 + 8)..]));
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    assert_eq!(decoder.state().unwrap().last_crc(), None);
+    let mut buf = vec![0_u8; 10];
+    decoder.read_exact(&mut buf).unwrap();
+    assert_eq!(decoder.state().unwrap().last_crc(), Some(first_crc));
+    assert!(!decoder.state_mut().unwrap().error().is_empty());
+
+    // the data of the first block corrupted
+    let mut corrupted = compressed.clone();
+    corrupted[9 + 8 + 100] ^= 0xff;
+    let mut decoder = read::Bz3Decoder::new(corrupted.as_slice()).unwrap();
+    assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    assert_ne!(decoder.state().unwrap().last_error(), 0);
+}
+
+#[test]
+fn replace_inner() {
+    use std::sync::{Arc, Mutex};
+
+    let data = generate_random_data(250 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_checksum(true);
+    let blocks = Arc::new(Mutex::new(Vec::new()));
+    let blocks2 = Arc::clone(&blocks);
+    encoder.set_block_callback(move |block, compressed_offset, uncompressed_offset| {
+        blocks2
+            .lock()
+            .unwrap()
+            .push((block, compressed_offset, uncompressed_offset));
+    });
+
+    encoder.write_all(&data[..(150 * KB)]).unwrap();
+    let first = encoder.replace_inner(Vec::new()).unwrap();
+    encoder.write_all(&data[(150 * KB)..]).unwrap();
+    let second = encoder.finish().unwrap();
+
+    assert_eq!(bzip3::mem::decompress(&first).unwrap(), &data[..(150 * KB)]);
+    assert_eq!(
+        bzip3::mem::decompress(&second).unwrap(),
+        &data[(150 * KB)..]
+    );
+    // each file starts over
+    let blocks = blocks.lock().unwrap();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0], (0, 9, 0));
+    assert_eq!(blocks[2], (0, 9, 0));
+}
+
+#[test]
+fn block_count() {
+    let data = generate_random_data(250 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.write_all(&data).unwrap();
+    assert_eq!(encoder.block_count(), 2);
+    encoder.flush().unwrap();
+    assert_eq!(encoder.block_count(), 3);
+    let compressed = encoder.finish().unwrap();
+
+    let mut encoder = read::Bz3Encoder::new(data.as_slice(), 100 * KB).unwrap();
+    assert_eq!(encoder.block_count(), 0);
+    encoder.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(encoder.block_count(), 3);
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    assert_eq!(decoder.block_count(), 0);
+    let mut buf = vec![0_u8; 150 * KB];
+    decoder.read_exact(&mut buf).unwrap();
+    assert_eq!(decoder.block_count(), 2);
+
+    let mut decoder = write::Bz3Decoder::new(Vec::new());
+    decoder.write_all(&compressed).unwrap();
+    assert_eq!(decoder.block_count(), 3);
+
+    let mut decoder = read::MaybeBz3Decoder::new(data.as_slice()).unwrap();
+    decoder.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(decoder.block_count(), 0);
+}
+
+#[test]
+fn mem_compress_decompress() {
+    for size in [0, 1, 8192, 300 * KB] {
+        let data = generate_random_data(size);
+        let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+        let decompressed = bzip3::mem::decompress(&compressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(decompressed.capacity(), size);
+    }
+}
+
+#[test]
+fn slice_decoder() {
+    let data = generate_random_data(350 * KB);
+    for stored in [false, true] {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(true);
+        encoder.set_stored_blocks(stored);
+        encoder.write_all(&data[..(200 * KB)]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[(200 * KB)..]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let decoder = bzip3::mem::SliceDecoder::new(&compressed).unwrap();
+        assert_eq!(decoder.block_size(), 100 * KB);
+        let decompressed = decoder.decode_all().unwrap();
+        assert!(decompressed == data);
+        assert_eq!(decompressed.capacity(), data.len());
+
+        // straight into the buffer, and through the decoder for the partial reads
+        for buffer_size in [1, 60 * KB, 200 * KB] {
+            let mut decoder = bzip3::mem::SliceDecoder::new(&compressed).unwrap();
+            let mut buffer = vec![0_u8; buffer_size];
+            let mut output = Vec::new();
+            loop {
+                let size = decoder.read(&mut buffer).unwrap();
+                if size == 0 {
+                    break;
+                }
+                output.extend_from_slice(&buffer[..size]);
+            }
+            assert!(output == data);
+        }
+
+        // the rest after a partial read
+        let mut decoder = bzip3::mem::SliceDecoder::new(&compressed).unwrap();
+        let mut output = vec![0_u8; 1000];
+        decoder.read_exact(&mut output).unwrap();
+        output.extend(decoder.decode_all().unwrap());
+        assert!(output == data);
+
+        let truncated = &compressed[..(compressed.len() / 2)];
+        let decoder = bzip3::mem::SliceDecoder::new(truncated).unwrap();
+        let bzip3::Error::Io(error) = decoder.decode_all().unwrap_err() else {
+            panic!();
+        };
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    assert!(matches!(
+        bzip3::mem::SliceDecoder::new(b"BZ3"),
+        Err(bzip3::Error::InvalidSignature)
+    ));
+}
+
+#[test]
+fn mem_scratch() {
+    // reused across calls, then replaced for another block size
+    for block_size in [100 * KB, 100 * KB, 200 * KB, 100 * KB] {
+        for size in [0, 1, 250 * KB] {
+            let data = generate_random_data(size);
+            let compressed = bzip3::mem::compress(&data, block_size).unwrap();
+            assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+        }
+    }
+    bzip3::mem::purge_scratch();
+
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    // a corrupt stream doesn't spoil the scratch
+    let mut corrupt = compressed.clone();
+    corrupt[100] ^= 0xff;
+    let _ = bzip3::mem::decompress(&corrupt);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+}
+
+#[test]
+fn checksum_trailer() {
+    use bzip3::frame::{BLOCK_HEADER_SIZE, CHECKSUM_EXTENSION_SIZE, CHECKSUM_TAG};
+
+    let data = generate_random_data(250 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_checksum(true);
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let trailer = &compressed[(compressed.len() - BLOCK_HEADER_SIZE - CHECKSUM_EXTENSION_SIZE)..];
+    assert_eq!(&trailer[BLOCK_HEADER_SIZE..][..4], CHECKSUM_TAG);
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    let mut decompressed = Vec::new();
+    let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+    decoder.write_all(&compressed).unwrap();
+    drop(decoder);
+    assert_eq!(decompressed, data);
+
+    // a reordered stream has the same blocks, and only fails the checksum
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_checksum(true);
+    encoder.write_all(&data[(100 * KB)..(200 * KB)]).unwrap();
+    encoder.write_all(&data[..(100 * KB)]).unwrap();
+    encoder.write_all(&data[(200 * KB)..]).unwrap();
+    let reordered_checksum = encoder.finish().unwrap();
+    let mut corrupt = compressed.clone();
+    let trailer_start = compressed.len() - trailer.len();
+    corrupt[..trailer_start].copy_from_slice(&reordered_checksum[..trailer_start]);
+    let error = bzip3::mem::decompress(&corrupt).unwrap_err();
+    let bzip3::Error::Io(error) = error else {
+        panic!("{error:?}");
+    };
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .as_deref(),
+        Ok(bzip3::Error::ChecksumMismatch { .. })
+    ));
+    #[cfg(feature = "parallel")]
+    assert!(matches!(
+        bzip3::mem::decompress_parallel(&corrupt),
+        Err(bzip3::Error::ChecksumMismatch { .. })
+    ));
+
+    // without the trailer, nothing is checked
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.write_all(&data).unwrap();
+    let plain = encoder.finish().unwrap();
+    assert_eq!(plain, &compressed[..trailer_start]);
+    assert_eq!(bzip3::mem::decompress(&plain).unwrap(), data);
+}
+
+#[test]
+fn crc_modes() {
+    use bzip3::seek::scan_index;
+    use bzip3::CrcMode;
+
+    let data = generate_random_data(250 * KB);
+    let mut compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    // the stored CRC is at the start of the block data
+    compressed[index.entries()[1].compressed_offset as usize + 8] ^= 1;
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .as_deref(),
+        Ok(bzip3::Error::BadCrc { block_index: 1 })
+    ));
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    decoder.set_crc_mode(CrcMode::Permissive);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decoder.crc_failures(), [1]);
+    assert_eq!(decompressed.len(), data.len());
+    assert_eq!(decompressed[..(100 * KB)], data[..(100 * KB)]);
+    assert_eq!(decompressed[(200 * KB)..], data[(200 * KB)..]);
+
+    let mut decompressed = Vec::new();
+    let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+    decoder.set_crc_mode(CrcMode::Permissive);
+    decoder.write_all(&compressed).unwrap();
+    assert_eq!(decoder.crc_failures(), [1]);
+
+    let mut decoder = bzip3::seek::Bz3SeekableDecoder::new(Cursor::new(&compressed)).unwrap();
+    assert!(decoder.decode_block_at(0).is_ok());
+    assert!(matches!(
+        decoder.decode_block_at(1),
+        Err(bzip3::Error::BadCrc { block_index: 1 })
+    ));
+    #[cfg(feature = "parallel")]
+    assert!(matches!(
+        bzip3::mem::decompress_parallel(&compressed),
+        Err(bzip3::Error::BadCrc { block_index: 1 })
+    ));
+}
+
+#[test]
+fn paranoid_mode() {
+    use bzip3::CrcMode;
+
+    let data = generate_random_data(250 * KB);
+    let mut compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    decoder.set_paranoid(true);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+
+    let mut decompressed = Vec::new();
+    let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+    decoder.set_paranoid(true);
+    decoder.write_all(&compressed).unwrap();
+    drop(decoder);
+    assert!(decompressed == data);
+
+    // a block already known to be damaged isn't reported again
+    compressed[9 + 8] ^= 1;
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    decoder.set_paranoid(true);
+    decoder.set_crc_mode(CrcMode::Permissive);
+    decoder.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(decoder.crc_failures(), [0]);
+}
+
+#[test]
+fn trailing_data() {
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+
+    for garbage in [&b"garbage!"[..], b"abc", b"BZ3v1\x00\x00\x01\x00"] {
+        let mut input = compressed.clone();
+        input.extend_from_slice(garbage);
+
+        let mut decoder = read::Bz3Decoder::new(input.as_slice()).unwrap();
+        let mut decompressed = Vec::new();
+        let error = decoder.read_to_end(&mut decompressed).unwrap_err();
+        let offset = compressed.len() as u64;
+        match error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .as_deref()
+        {
+            Ok(&bzip3::Error::TrailingData { offset: x }) => assert_eq!(x, offset),
+            Ok(&bzip3::Error::MisplacedFrameHeader { offset: x }) => {
+                assert!(garbage.starts_with(bzip3::MAGIC_NUMBER));
+                assert_eq!(x, offset);
+            }
+            Ok(bzip3::Error::TruncatedBlock { .. }) => assert!(garbage.len() < 8),
+            x => panic!("unexpected error: {x:?}"),
+        }
+
+        let mut decoder = read::Bz3Decoder::new(input.as_slice()).unwrap();
+        decoder.set_ignore_trailing_data(true);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+
+        let mut decompressed = Vec::new();
+        let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+        decoder.set_ignore_trailing_data(true);
+        decoder.write_all(&input).unwrap();
+        drop(decoder);
+        assert!(decompressed == data);
+        let mut decoder = write::Bz3Decoder::new(Vec::new());
+        assert_eq!(decoder.write_all(&input).is_err(), garbage.len() >= 8);
+    }
+}
+
+#[test]
+fn truncated_block() {
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let read_i32 = |offset: usize| {
+        i32::from_le_bytes(compressed[offset..(offset + 4)].try_into().unwrap()) as usize
+    };
+    let second_block = 9 + 8 + read_i32(9);
+    let second_block_size = 8 + read_i32(second_block);
+
+    // inside the header, and inside the data of the second block
+    for (have, need) in [(3, 8), (20, second_block_size)] {
+        let input = &compressed[..(second_block + have)];
+        let expected = bzip3::Error::TruncatedBlock {
+            block_index: 1,
+            have,
+            need,
+        };
+
+        let mut decoder = read::Bz3Decoder::new(input).unwrap();
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .unwrap();
+        assert_eq!(error.to_string(), expected.to_string());
+    }
+}
+
+#[test]
+fn integrity_report() {
+    use bzip3::integrity::{verify, BlockReport, BlockStatus};
+
+    let data = generate_random_data(250 * KB);
+    let mut compressed = Vec::new();
+    let mut encoder = write::Bz3Encoder::new(&mut compressed, 100 * KB).unwrap();
+    encoder.set_checksum(true);
+    encoder.write_all(&data).unwrap();
+    encoder.finish().unwrap();
+
+    let report = verify(compressed.as_slice()).unwrap();
+    assert!(report.is_intact());
+    assert_eq!(report.block_size, 100 * KB);
+    assert_eq!(report.checksum_valid, Some(true));
+    let offsets = report
+        .blocks
+        .iter()
+        .map(|x| (x.uncompressed_offset, x.uncompressed_size))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        offsets,
+        [
+            (0, 100 * KB),
+            (100 * KB as u64, 100 * KB),
+            (200 * KB as u64, 50 * KB)
+        ]
+    );
+
+    // the stored CRC is at the start of the block data
+    let second_block = report.blocks[1];
+    compressed[second_block.compressed_offset as usize + 8] ^= 1;
+    compressed.truncate(compressed.len() - 3);
+    let report = verify(compressed.as_slice()).unwrap();
+    assert!(!report.is_intact());
+    assert_eq!(report.blocks.len(), 3);
+    assert_eq!(
+        report.blocks[1],
+        BlockReport {
+            status: BlockStatus::BadCrc,
+            ..second_block
+        }
+    );
+    assert_eq!(report.damaged_blocks().count(), 1);
+    assert_eq!(report.checksum_valid, None);
+    assert!(matches!(
+        report.error,
+        Some(bzip3::Error::TruncatedBlock {
+            block_index: 3,
+            have: 17,
+            need: 20
+        })
+    ));
+}
+
+#[test]
+fn max_block_size() {
+    let compressed = bzip3::mem::compress(b"hello, world", 200 * KB).unwrap();
+
+    assert!(matches!(
+        read::Bz3Decoder::with_max_block_size(compressed.as_slice(), 100 * KB),
+        Err(bzip3::Error::BlockSizeLimit { block_size, limit })
+            if block_size == 200 * KB && limit == 100 * KB
+    ));
+    let mut decoder = write::Bz3Decoder::new(Vec::new());
+    decoder.set_max_block_size(100 * KB);
+    assert!(decoder.write_all(&compressed).is_err());
+
+    let mut decoder =
+        read::Bz3Decoder::with_max_block_size(compressed.as_slice(), 200 * KB).unwrap();
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, b"hello, world");
+}
+
+#[test]
+fn misplaced_frame_header() {
+    let first = bzip3::mem::compress(&generate_random_data(250 * KB), 100 * KB).unwrap();
+    let second = bzip3::mem::compress(&generate_random_data(50 * KB), 100 * KB).unwrap();
+
+    // the first file is cut inside its last block, which takes the start of the second one
+    let cut = first.len() - 1000;
+    let mut spliced = first[..cut].to_vec();
+    spliced.extend_from_slice(&second);
+
+    let mut decoder = read::Bz3Decoder::new(spliced.as_slice()).unwrap();
+    let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        error.into_inner().unwrap().downcast::<bzip3::Error>().as_deref(),
+        Ok(&bzip3::Error::MisplacedFrameHeader { offset }) if offset == cut as u64
+    ));
+
+    // and concatenated whole
+    let mut concatenated = first.clone();
+    concatenated.extend_from_slice(&second);
+    let report = bzip3::integrity::verify(concatenated.as_slice()).unwrap();
+    assert!(matches!(
+        report.error,
+        Some(bzip3::Error::MisplacedFrameHeader { offset }) if offset == first.len() as u64
+    ));
+}
+
+#[test]
+fn merge_frames() {
+    let chunks = [
+        generate_random_data(250 * KB),
+        generate_random_data(10 * KB),
+        generate_random_data(100 * KB),
+    ];
+    let compressed = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut output = Vec::new();
+            let mut encoder = write::Bz3Encoder::new(&mut output, 100 * KB).unwrap();
+            // dropped from the merged file
+            encoder.set_checksum(i == 0);
+            encoder.write_all(chunk).unwrap();
+            encoder.finish().unwrap();
+            output
+        })
+        .collect::<Vec<_>>();
+
+    let mut merged = Vec::new();
+    bzip3::frame::merge(compressed.iter().map(Vec::as_slice), &mut merged).unwrap();
+    assert!(bzip3::mem::decompress(&merged).unwrap() == chunks.concat());
+    let report = bzip3::integrity::verify(merged.as_slice()).unwrap();
+    assert!(report.is_intact());
+    assert_eq!(report.blocks.len(), 5);
+    assert_eq!(report.checksum_valid, None);
+
+    let other = bzip3::mem::compress(b"hello", 200 * KB).unwrap();
+    let inputs = [compressed[0].as_slice(), other.as_slice()];
+    assert!(bzip3::frame::merge(inputs, io::sink()).is_err());
+    assert!(bzip3::frame::merge(Vec::<&[u8]>::new(), io::sink()).is_err());
+}
+
+#[test]
+fn metadata() {
+    use bzip3::metadata::Metadata;
+
+    let metadata = Metadata {
+        filename: Some("data.bin".into()),
+        mtime: Some(1_700_000_000),
+        comment: Some("random data".into()),
+        extra: vec![
+            ("key".into(), "value".into()),
+            ("empty".into(), String::new()),
+        ],
+    };
+    let data = generate_random_data(250 * KB);
+    let mut encoder = write::Bz3Encoder::with_metadata(Vec::new(), 100 * KB, &metadata).unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    // skipped when not asked for
+    assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    assert_eq!(decoder.metadata(), None);
+    assert_eq!(decoder.read_metadata().unwrap(), Some(&metadata));
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+
+    let mut decoder = write::Bz3Decoder::new(Vec::new());
+    decoder.write_all(&compressed).unwrap();
+    assert_eq!(decoder.metadata(), Some(&metadata));
+
+    let plain = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let mut decoder = read::Bz3Decoder::new(plain.as_slice()).unwrap();
+    assert_eq!(decoder.read_metadata().unwrap(), None);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+
+    let empty = bzip3::mem::compress(b"", 100 * KB).unwrap();
+    let mut decoder = read::Bz3Decoder::new(empty.as_slice()).unwrap();
+    assert_eq!(decoder.read_metadata().unwrap(), None);
+
+    let too_large = Metadata {
+        comment: Some("x".repeat(bzip3::metadata::METADATA_MAX_SIZE)),
+        ..Default::default()
+    };
+    assert!(write::Bz3Encoder::with_metadata(Vec::new(), 100 * KB, &too_large).is_err());
+}
+
+#[test]
+fn skippable_frames() {
+    use bzip3::frame::SkippableFrame;
+
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let frame = |tag, size| SkippableFrame {
+        tag,
+        payload: generate_random_data(size),
+    };
+
+    let mut input = Vec::new();
+    for frame in [frame(0, 10), frame(1, 0), frame(2, 200 * KB)] {
+        frame.write_to(&mut input).unwrap();
+    }
+    input.extend_from_slice(&compressed);
+    let trailing = frame(3, 300 * KB);
+    trailing.write_to(&mut input).unwrap();
+    frame(4, 1).write_to(&mut input).unwrap();
+
+    let mut decoder = read::Bz3Decoder::new(SmallReads(&input)).unwrap();
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+
+    let mut decompressed = Vec::new();
+    let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+    decoder.write_all(&input).unwrap();
+    drop(decoder);
+    assert!(decompressed == data);
+
+    #[cfg(feature = "parallel")]
+    assert!(bzip3::mem::decompress_parallel(&input).unwrap() == data);
+
+    let mut trailing_frames = compressed.clone();
+    trailing.write_to(&mut trailing_frames).unwrap();
+    assert_eq!(
+        SkippableFrame::read_from(&mut &trailing_frames[compressed.len()..]).unwrap(),
+        trailing
+    );
+    let report = bzip3::integrity::verify(trailing_frames.as_slice()).unwrap();
+    assert!(report.is_intact());
+
+    // blocks may not follow a skippable frame, and skippable frames may not be truncated
+    for input in [
+        [&trailing_frames[..], &compressed[9..]].concat(),
+        trailing_frames[..(trailing_frames.len() - 1)].to_vec(),
+    ] {
+        let mut decoder = read::Bz3Decoder::new(input.as_slice()).unwrap();
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+        #[cfg(feature = "parallel")]
+        assert!(bzip3::mem::decompress_parallel(&input).is_err());
+        assert!(!bzip3::integrity::verify(input.as_slice())
+            .unwrap()
+            .is_intact());
+    }
+}
+
+/// Reader returning at most 1000 bytes per read.
+struct SmallReads<'a>(&'a [u8]);
+
+impl Read for SmallReads<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = buf.len().min(1000);
+        self.0.read(&mut buf[..size])
+    }
+}
+
+#[test]
+fn content_defined_chunking() {
+    use bzip3::chunking::Chunking;
+    use bzip3::integrity::BlockReport;
+
+    let compress = |data: &[u8]| {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 512 * KB).unwrap();
+        encoder.set_chunking(Some(Chunking::new(64 * KB))).unwrap();
+        // small writes, for boundaries to be found across them
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder.finish().unwrap()
+    };
+    let blocks = |compressed: &[u8]| {
+        let report = bzip3::integrity::verify(compressed).unwrap();
+        assert!(report.is_intact());
+        report.blocks
+    };
+    let block_data = |compressed: &[u8], block: &BlockReport| {
+        let start = block.compressed_offset as usize + 8;
+        compressed[start..(start + block.compressed_size)].to_vec()
+    };
+
+    let data = generate_random_data(2 * MIB as usize);
+    let compressed = compress(&data);
+    assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+    let original_blocks = blocks(&compressed);
+    let (last, others) = original_blocks.split_last().unwrap();
+    assert!(others.len() > 8);
+    assert!(last.uncompressed_size <= 256 * KB);
+    for block in others {
+        assert!((16 * KB..=256 * KB).contains(&block.uncompressed_size));
+    }
+
+    // an insertion only changes the blocks around it
+    let mut edited = data.clone();
+    edited.splice(1000 * KB..1000 * KB, *b"inserted");
+    let edited_compressed = compress(&edited);
+    assert!(bzip3::mem::decompress(&edited_compressed).unwrap() == edited);
+    let original: Vec<_> = original_blocks
+        .iter()
+        .map(|x| block_data(&compressed, x))
+        .collect();
+    let changed = blocks(&edited_compressed)
+        .iter()
+        .filter(|x| !original.contains(&block_data(&edited_compressed, x)))
+        .count();
+    assert!(changed <= 2);
+
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 512 * KB).unwrap();
+    assert!(encoder.set_chunking(Some(Chunking::new(256 * KB))).is_err());
+    assert!(encoder
+        .set_chunking(Some(Chunking {
+            min_size: 10,
+            avg_size: 5,
+            max_size: 100,
+        }))
+        .is_err());
+}
+
+#[test]
+fn format_version() {
+    use bzip3::frame::{FormatVersion, FrameHeader};
+
+    let mut compressed = bzip3::mem::compress(b"hello, world", 100 * KB).unwrap();
+    let header = FrameHeader::parse(compressed[..9].try_into().unwrap()).unwrap();
+    assert_eq!(header.version, FormatVersion::V1);
+    assert_eq!(header.to_bytes(), compressed[..9]);
+
+    compressed[4] = b'2';
+    assert!(matches!(
+        read::Bz3Decoder::new(compressed.as_slice()),
+        Err(bzip3::Error::UnsupportedVersion(2))
+    ));
+    let e = bzip3::mem::decompress(&compressed).unwrap_err();
+    assert!(matches!(e, bzip3::Error::UnsupportedVersion(2)));
+
+    let mut decoder = write::Bz3Decoder::new(Vec::new());
+    let e = decoder.write_all(&compressed).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+
+    // not a version digit
+    compressed[4] = b'x';
+    assert!(matches!(
+        bzip3::mem::decompress(&compressed),
+        Err(bzip3::Error::InvalidSignature)
+    ));
+}
+
+#[test]
+fn stored_blocks() {
+    use bzip3::frame::{BlockHeader, STORED_BLOCK};
+    use bzip3::integrity::{verify, BlockStatus};
+    use bzip3::recover::Bz3RecoveringDecoder;
+    use bzip3::seek::{scan_index, Bz3SeekableDecoder};
+
+    let mut data = generate_random_data(100 * KB);
+    data.extend(generate_deterministic_data(100 * KB));
+    data.extend(generate_random_data(50 * KB));
+
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_stored_blocks(true);
+    encoder.set_checksum(true);
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    // the random blocks don't compress
+    let index = scan_index(Cursor::new(&compressed)).unwrap();
+    let header_at = |block: usize| {
+        let offset = index.entries()[block].compressed_offset as usize;
+        BlockHeader::parse(compressed[offset..(offset + 8)].try_into().unwrap())
+    };
+    assert_eq!(header_at(0), BlockHeader::stored(100 * KB));
+    assert_eq!(header_at(2), BlockHeader::stored(50 * KB));
+    assert!(compressed.len() <= data.len() + 9 + 3 * 8 + 20);
+
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    #[cfg(feature = "parallel")]
+    assert_eq!(bzip3::mem::decompress_parallel(&compressed).unwrap(), data);
+    let mut decompressed = Vec::new();
+    read::Bz3Decoder::new(compressed.as_slice())
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+
+    let mut decoder = Bz3SeekableDecoder::new(Cursor::new(&compressed)).unwrap();
+    assert_eq!(decoder.decode_block_at(2).unwrap(), &data[(200 * KB)..]);
+    assert_eq!(
+        decoder.decode_block_at(1).unwrap(),
+        &data[(100 * KB)..(200 * KB)]
+    );
+
+    let mut decoder = Bz3RecoveringDecoder::new(compressed.as_slice()).unwrap();
+    decompressed.clear();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+    assert!(decoder.skipped().is_empty());
+
+    let report = verify(compressed.as_slice()).unwrap();
+    assert!(report.is_intact());
+    assert_eq!(report.blocks[0].status, BlockStatus::Valid);
+    assert_eq!(report.checksum_valid, Some(true));
+
+    let parts = bzip3::frame::split(Cursor::new(&compressed), &[0..1, 2..3]).unwrap();
+    assert_eq!(
+        bzip3::mem::decompress(&parts[0]).unwrap(),
+        &data[..(100 * KB)]
+    );
+    assert_eq!(
+        bzip3::mem::decompress(&parts[1]).unwrap(),
+        &data[(200 * KB)..]
+    );
+
+    // off by default, leaving files readable by any bzip3 decoder
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let header = BlockHeader::parse(compressed[9..17].try_into().unwrap());
+    assert_ne!(header.new_size, STORED_BLOCK);
+}
+
+#[test]
+fn maybe_compressed() {
+    use bzip3::frame::SkippableFrame;
+    use bzip3::read::MaybeBz3Decoder;
+
+    let decode = |input: &[u8]| {
+        let mut decoder = MaybeBz3Decoder::new(SmallReads(input)).unwrap();
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        (decoder.is_compressed(), output)
+    };
+
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    assert_eq!(decode(&compressed), (true, data.clone()));
+    assert_eq!(decode(&data), (false, data.clone()));
+    for short in [&b""[..], b"B", b"BZ3"] {
+        assert_eq!(decode(short), (false, short.to_vec()));
+    }
+
+    let mut framed = Vec::new();
+    SkippableFrame {
+        tag: 0,
+        payload: vec![1, 2, 3],
+    }
+    .write_to(&mut framed)
+    .unwrap();
+    framed.extend_from_slice(&compressed);
+    assert_eq!(decode(&framed), (true, data));
+
+    // bzip3 signature but broken
+    assert!(MaybeBz3Decoder::new(&b"BZ3v1abc"[..]).is_err());
+    let limited = MaybeBz3Decoder::with_max_block_size(compressed.as_slice(), 65 * KB);
+    assert!(matches!(limited, Err(bzip3::Error::BlockSizeLimit { .. })));
+}
+
+#[test]
+fn double_buffering() {
+    let data = generate_random_data(550 * KB);
+    let compress = |double_buffering: bool, stored: bool| {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_double_buffering(double_buffering);
+        encoder.set_checksum(true);
+        encoder.set_stored_blocks(stored);
+        for chunk in data[..(320 * KB)].chunks(7 * KB) {
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder.flush().unwrap();
+        // everything so far is out after a flush
+        let flushed = encoder.get_ref().len();
+        encoder.write_all(&data[(320 * KB)..]).unwrap();
+        (flushed, encoder.finish().unwrap())
+    };
+    for stored in [false, true] {
+        let (flushed, expected) = compress(false, stored);
+        let (double_flushed, compressed) = compress(true, stored);
+        assert_eq!(double_flushed, flushed);
+        assert!(compressed == expected);
+        assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+    }
+
+    // the output lags a block behind
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_double_buffering(true);
+    encoder.write_all(&data[..(100 * KB)]).unwrap();
+    assert_eq!(encoder.get_ref().len(), 9);
+    encoder.write_all(&data[(100 * KB)..(200 * KB)]).unwrap();
+    assert!(encoder.get_ref().len() > 9 + 8);
+
+    // dropped with a block in the background
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_double_buffering(true);
+    encoder.write_all(&data[..(100 * KB)]).unwrap();
+    drop(encoder);
+}
+
+#[test]
+fn block_size_growth() {
+    use bzip3::frame::FrameHeader;
+    use std::sync::{Arc, Mutex};
+
+    let data = generate_random_data(2560 * KB);
+    let blocks = Arc::new(Mutex::new(Vec::new()));
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 65 * KB).unwrap();
+    assert!(encoder.set_block_size_growth(Some(64 * KB)).is_err());
+    encoder.set_block_size_growth(Some(512 * KB)).unwrap();
+    encoder.set_checksum(true);
+    let blocks2 = Arc::clone(&blocks);
+    encoder.set_block_callback(move |_, compressed_offset, uncompressed_offset| {
+        blocks2
+            .lock()
+            .unwrap()
+            .push((compressed_offset, uncompressed_offset));
+    });
+    for chunk in data.chunks(10 * KB) {
+        encoder.write_all(chunk).unwrap();
+    }
+    let compressed = encoder.finish().unwrap();
+
+    // frames of 4 blocks of 65, 130 and 260 KiB, then 512 KiB blocks
+    let blocks = blocks.lock().unwrap();
+    assert_eq!(blocks.len(), 4 + 4 + 4 + 2);
+    let frame_block_size = |block: usize| {
+        let offset = blocks[block].0 as usize - 9;
+        FrameHeader::parse(compressed[offset..(offset + 9)].try_into().unwrap())
+            .unwrap()
+            .block_size
+    };
+    assert_eq!(frame_block_size(0), 65 * KB);
+    assert_eq!(frame_block_size(4), 130 * KB);
+    assert_eq!(frame_block_size(8), 260 * KB);
+    assert_eq!(frame_block_size(12), 512 * KB);
+    assert_eq!(blocks[13].1 as usize, 1820 * KB + 512 * KB);
+
+    let mut decoder = read::Bz3Decoder::new(SmallReads(&compressed)).unwrap();
+    decoder.multiple_members(true);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+
+    let mut decompressed = Vec::new();
+    let mut decoder = write::Bz3Decoder::new(&mut decompressed);
+    decoder.multiple_members(true);
+    decoder.write_all(&compressed).unwrap();
+    drop(decoder);
+    assert!(decompressed == data);
+
+    // a single frame only otherwise
+    assert!(bzip3::mem::decompress(&compressed).is_err());
+}
+
+#[test]
+fn set_block_size() {
+    use bzip3::chunking::Chunking;
+
+    let data = generate_random_data(600 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+    encoder.set_checksum(true);
+    encoder.write_all(&data[..(150 * KB)]).unwrap();
+    assert!(encoder.set_block_size(BLOCK_SIZE_MIN - 1).is_err());
+    encoder.set_block_size(100 * KB).unwrap();
+    encoder.set_block_size(300 * KB).unwrap();
+    assert_eq!(encoder.block_size(), 300 * KB);
+    encoder.write_all(&data[(150 * KB)..(400 * KB)]).unwrap();
+    encoder.set_block_size(BLOCK_SIZE_MIN).unwrap();
+    encoder.write_all(&data[(400 * KB)..]).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+    decoder.multiple_members(true);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).unwrap();
+    assert!(decompressed == data);
+    assert!(matches!(
+        bzip3::mem::decompress(&compressed),
+        Err(bzip3::Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+    ));
+
+    // rejected without finishing the frame
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 200 * KB).unwrap();
+    encoder
+        .set_chunking(Some(Chunking {
+            min_size: 25 * KB,
+            avg_size: 100 * KB,
+            max_size: 150 * KB,
+        }))
+        .unwrap();
+    encoder.write_all(&data[..(50 * KB)]).unwrap();
+    assert!(encoder.set_block_size(100 * KB).is_err());
+    encoder.write_all(&data[(50 * KB)..]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert!(bzip3::mem::decompress(&compressed).unwrap() == data);
+}
+
+#[test]
+fn empty_frames() {
+    let data = generate_random_data(150 * KB);
+    for checksum in [false, true] {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(checksum);
+        let compressed = encoder.finish().unwrap();
+        assert!(bzip3::mem::decompress(&compressed).unwrap().is_empty());
+
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(checksum);
+        encoder.set_block_size(200 * KB).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.set_block_size(100 * KB).unwrap();
+        encoder.set_block_size(300 * KB).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+        decoder.multiple_members(true);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+    }
+}
+
+#[test]
+fn large_read_buffers() {
+    let read_all = |input: &[u8], buffer_size: usize| -> io::Result<Vec<u8>> {
+        let mut decoder = read::Bz3Decoder::new(SmallReads(input)).unwrap();
+        decoder.set_paranoid(true);
+        let mut buffer = vec![0_u8; buffer_size];
+        let mut output = Vec::new();
+        loop {
+            let size = decoder.read(&mut buffer)?;
+            if size == 0 {
+                return Ok(output);
+            }
+            output.extend_from_slice(&buffer[..size]);
+        }
+    };
+
+    let data = generate_random_data(350 * KB);
+    for stored in [false, true] {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(true);
+        encoder.set_stored_blocks(stored);
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        // straight into the buffer, and through the decoder for the partial reads of 60 kiB
+        for buffer_size in [60 * KB, 200 * KB] {
+            assert!(read_all(&compressed, buffer_size).unwrap() == data);
+        }
+
+        let truncated = &compressed[..(compressed.len() / 2)];
+        let error = read_all(truncated, 200 * KB).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .unwrap();
+        assert!(matches!(*error, bzip3::Error::TruncatedBlock { .. }));
+    }
+}
+
+#[test]
+fn read_to_end() {
+    let data = generate_random_data(350 * KB);
+    for stored in [false, true] {
+        let mut encoder = write::Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.set_checksum(true);
+        encoder.set_stored_blocks(stored);
+        encoder.write_all(&data[..(200 * KB)]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[(200 * KB)..]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // appends to what's there
+        let mut output = b"prefix".to_vec();
+        let mut decoder = read::Bz3Decoder::new(SmallReads(&compressed)).unwrap();
+        decoder.set_paranoid(true);
+        assert_eq!(decoder.read_to_end(&mut output).unwrap(), data.len());
+        assert!(output[6..] == data);
+
+        // after some data went through the decoder
+        let mut decoder = read::Bz3Decoder::new(compressed.as_slice()).unwrap();
+        let mut output = vec![0_u8; 1000];
+        decoder.read_exact(&mut output).unwrap();
+        decoder.read_to_end(&mut output).unwrap();
+        assert!(output == data);
+
+        let truncated = &compressed[..(compressed.len() / 2)];
+        let mut output = Vec::new();
+        let mut decoder = read::Bz3Decoder::new(truncated).unwrap();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        // the complete blocks are kept
+        assert!(output.len() >= 100 * KB && data.starts_with(&output));
+    }
+}
+
+#[test]
+fn large_encoder_read_buffers() {
+    let data = generate_random_data(350 * KB);
+    let expected = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    for buffer_size in [1000, 100 * KB, 200 * KB] {
+        let mut encoder = read::Bz3Encoder::new(SmallReads(&data), 100 * KB).unwrap();
+        let mut buffer = vec![0_u8; buffer_size];
+        let mut compressed = Vec::new();
+        loop {
+            let size = encoder.read(&mut buffer).unwrap();
+            if size == 0 {
+                break;
+            }
+            compressed.extend_from_slice(&buffer[..size]);
+        }
+        assert!(compressed == expected);
+    }
+}
+
+#[test]
+fn auto_flush() {
+    use bzip3::seek::scan_index;
+    use bzip3::write::AutoFlush;
+    use std::time::{Duration, Instant};
+
+    let data = generate_random_data(250 * KB);
+
+    // blocks of at most `max_pending` bytes, output right away
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 1024 * KB).unwrap();
+    encoder
+        .set_auto_flush(AutoFlush {
+            idle: None,
+            max_pending: Some(64 * KB),
+        })
+        .unwrap();
+    encoder.write_all(&data[..(100 * KB)]).unwrap();
+    let index = scan_index(Cursor::new(encoder.get_ref())).unwrap();
+    assert_eq!(index.len(), 1);
+    assert_eq!(index.uncompressed_size(), 64 * KB as u64);
+    // lowering the limit compresses the partial block
+    encoder
+        .set_auto_flush(AutoFlush {
+            idle: None,
+            max_pending: Some(16 * KB),
+        })
+        .unwrap();
+    let index = scan_index(Cursor::new(encoder.get_ref())).unwrap();
+    assert_eq!(index.uncompressed_size(), 100 * KB as u64);
+    encoder.write_all(&data[(100 * KB)..]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 1024 * KB).unwrap();
+    assert!(encoder
+        .set_auto_flush(AutoFlush {
+            idle: None,
+            max_pending: Some(0),
+        })
+        .is_err());
+    encoder
+        .set_auto_flush(AutoFlush {
+            idle: Some(Duration::from_millis(50)),
+            max_pending: None,
+        })
+        .unwrap();
+    assert_eq!(encoder.idle_deadline(), None);
+    encoder.write_all(&data[..KB]).unwrap();
+    let deadline = encoder.idle_deadline().unwrap();
+    // not idle yet
+    assert!(!encoder.flush_if_idle().unwrap());
+    assert_eq!(encoder.get_ref().len(), 9);
+    std::thread::sleep(deadline - Instant::now());
+    assert!(encoder.flush_if_idle().unwrap());
+    assert_eq!(encoder.idle_deadline(), None);
+    assert_eq!(
+        bzip3::mem::decompress(encoder.get_ref()).unwrap(),
+        &data[..KB]
+    );
+    assert!(!encoder.flush_if_idle().unwrap());
+}
+
+#[test]
+fn options() {
+    use bzip3::options::Bz3Options;
+    use bzip3::pool::BufferPool;
+
+    let data = generate_random_data(250 * KB);
+    let options = Bz3Options {
+        block_size: 100 * KB,
+        checksum: true,
+        buffer_pool: Some(BufferPool::new(2)),
+        ..Default::default()
+    };
+
+    let mut encoder = options.write_encoder(Vec::new()).unwrap();
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    // the checksum after the blocks
+    assert!(compressed.len() > bzip3::mem::compress(&data, 100 * KB).unwrap().len());
+
+    let mut read_compressed = Vec::new();
+    options
+        .read_encoder(data.as_slice())
+        .unwrap()
+        .read_to_end(&mut read_compressed)
+        .unwrap();
+    let mut decompressed = Vec::new();
+    options
+        .read_decoder(read_compressed.as_slice())
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+
+    let mut decoder = options.write_decoder(Vec::new());
+    decoder.write_all(&compressed).unwrap();
+    decoder.flush().unwrap();
+    assert_eq!(decoder.get_ref(), &data);
+
+    // the limits apply to the decoders
+    let options = Bz3Options {
+        max_block_size: 65 * KB,
+        ..options
+    };
+    assert!(matches!(
+        options.read_decoder(compressed.as_slice()),
+        Err(bzip3::Error::BlockSizeLimit { .. })
+    ));
+    let options = Bz3Options {
+        block_size: 1,
+        ..options
+    };
+    assert!(matches!(
+        options.write_encoder(Vec::new()),
+        Err(bzip3::Error::BlockSize)
+    ));
+}
+
+#[test]
+fn write_decoder_full_writer() {
+    use bzip3::{TryReadExact, TryWriteAll};
+
+    /// Writer taking up to `room` bytes, and none after.
+    struct Limited {
+        data: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let size = buf.len().min(self.room);
+            self.data.extend_from_slice(&buf[..size]);
+            self.room -= size;
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut writer = Limited {
+        data: Vec::new(),
+        room: 10,
+    };
+    assert_eq!(writer.try_write_all(b"hello, world").unwrap(), 10);
+    let mut buf = [0_u8; 20];
+    assert_eq!((&writer.data[..]).try_read_exact(&mut buf).unwrap(), 10);
+
+    let data = generate_random_data(150 * KB);
+    let compressed = bzip3::mem::compress(&data, 100 * KB).unwrap();
+    let mut decoder = write::Bz3Decoder::new(Limited {
+        data: Vec::new(),
+        room: 120 * KB,
+    });
+    let mut pos = 0;
+    let result = loop {
+        match decoder.write(&compressed[pos..]) {
+            Ok(size) if pos + size < compressed.len() => pos += size,
+            Ok(size) => {
+                pos += size;
+                break decoder.flush();
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
+    // with room again, the decoder goes on with the data not written yet
+    decoder.get_mut().room = usize::MAX;
+    decoder.write_all(&compressed[pos..]).unwrap();
+    decoder.flush().unwrap();
+    assert_eq!(decoder.get_ref().data, data);
+}
+
+#[test]
+fn write_encoder_full_writer() {
+    /// Writer taking up to `room` bytes, and none after.
+    struct Limited {
+        data: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let size = buf.len().min(self.room);
+            self.data.extend_from_slice(&buf[..size]);
+            self.room -= size;
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let data = generate_random_data(300 * KB);
+    let mut encoder = write::Bz3Encoder::new(
+        Limited {
+            data: Vec::new(),
+            room: 150 * KB,
+        },
+        100 * KB,
+    )
+    .unwrap();
+    let mut pos = 0;
+    let error = loop {
+        match encoder.write(&data[pos..]) {
+            Ok(size) => pos += size,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+    // with room again, the encoder goes on with the data not written yet
+    encoder.get_mut().room = usize::MAX;
+    encoder.write_all(&data[pos..]).unwrap();
+    let compressed = encoder.finish().unwrap().data;
+    assert_eq!(compressed, bzip3::mem::compress(&data, 100 * KB).unwrap());
+}
@@ -0,0 +1,79 @@
+#![cfg(feature = "testutil")]
+
+use bzip3::testutil::{self, Malformation};
+use bzip3::{read, Error};
+
+const KB: usize = 1024;
+
+#[test]
+fn generators() {
+    assert_eq!(
+        testutil::random_data(1000, 1),
+        testutil::random_data(1000, 1)
+    );
+    assert_ne!(
+        testutil::random_data(1000, 1),
+        testutil::random_data(1000, 2)
+    );
+    assert_eq!(testutil::random_data(1001, 3).len(), 1001);
+
+    let data = testutil::compressible_data(100 * KB, 1);
+    assert_eq!(data.len(), 100 * KB);
+    assert_eq!(data, testutil::compressible_data(100 * KB, 1));
+    assert!(data
+        .split(u8::is_ascii_whitespace)
+        .all(|x| x.is_ascii() && x.len() < 20));
+}
+
+#[test]
+fn round_trip() {
+    let data = testutil::random_data(250 * KB, 1);
+    let compressed = testutil::assert_round_trip(&data, 100 * KB);
+    testutil::assert_decompresses_to(&compressed, &data);
+    testutil::assert_round_trip(b"", 100 * KB);
+}
+
+#[test]
+#[should_panic(expected = "differing from offset 3")]
+fn round_trip_mismatch() {
+    let compressed = bzip3::mem::compress(b"hello", 100 * KB).unwrap();
+    testutil::assert_decompresses_to(&compressed, b"help");
+}
+
+#[test]
+fn malformations() {
+    let data = testutil::compressible_data(250 * KB, 1);
+    let compressed = testutil::assert_round_trip(&data, 100 * KB);
+
+    for malformation in Malformation::ALL {
+        let broken = malformation.apply(&compressed);
+        let result = read::Bz3Decoder::new(broken.as_slice()).and_then(|x| x.decode_to_vec());
+        let error = result.expect_err(&format!("{malformation:?}"));
+        match malformation {
+            Malformation::BadMagic | Malformation::TruncatedHeader => {
+                assert!(matches!(error, Error::InvalidSignature))
+            }
+            Malformation::UnsupportedVersion => {
+                assert!(matches!(error, Error::UnsupportedVersion(9)))
+            }
+            Malformation::InvalidBlockSize => assert!(matches!(error, Error::BlockSize)),
+            Malformation::TruncatedBlock => {
+                assert!(matches!(
+                    error,
+                    Error::TruncatedBlock { block_index: 2, .. }
+                ))
+            }
+            Malformation::CorruptBlock => {
+                assert!(matches!(error, Error::BadCrc { block_index: 0 }))
+            }
+            Malformation::OversizedBlock => {
+                assert!(matches!(error, Error::TrailingData { offset: 9 }))
+            }
+            Malformation::TrailingGarbage => {
+                let offset = compressed.len() as u64;
+                assert!(matches!(error, Error::TrailingData { offset: x } if x == offset))
+            }
+        }
+        assert!(bzip3::mem::decompress(&broken).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use std::io;
+
+use bzip3::text;
+
+const KB: usize = 1024;
+
+#[test]
+fn lines() {
+    let lines = (0..20_000)
+        .map(|x| format!("line {x}: {}", "x".repeat(x % 50)))
+        .collect::<Vec<_>>();
+    let data = lines.join("\r\n");
+    // lines cross the block boundaries
+    let compressed = bzip3::mem::compress(data.as_bytes(), 100 * KB).unwrap();
+    assert!(compressed.len() > 200 * KB);
+
+    let read = text::lines(compressed.as_slice())
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(read, lines);
+    assert_eq!(text::read_to_string(compressed.as_slice()).unwrap(), data);
+
+    let mut lines = text::lines(&b"not bzip3"[..]);
+    assert!(lines.next().unwrap().is_err());
+    assert!(lines.next().is_none());
+
+    let compressed = bzip3::mem::compress(b"\xff\xfe", 100 * KB).unwrap();
+    assert_eq!(
+        text::read_to_string(compressed.as_slice())
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidData
+    );
+}
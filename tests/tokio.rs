@@ -0,0 +1,573 @@
+#![cfg(feature = "tokio")]
+
+use std::future::Future;
+use std::io;
+use std::pin::{pin, Pin};
+use std::task::{ready, Context, Poll, Waker};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use bzip3::mem;
+use bzip3::tokio::{read, write};
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+#[tokio::test]
+async fn read_decoder() {
+    for size in [0, 1, 100 * KB, 1000 * KB] {
+        let data = generate_random_data(size);
+        let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+        let mut decoder = read::Bz3Decoder::new(compressed.as_slice());
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).await.unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(decoder.block_size(), Some(100 * KB));
+
+        let truncated = &compressed[..(compressed.len() - 1)];
+        let mut decoder = read::Bz3Decoder::new(truncated);
+        assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn bufread_decoder() {
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::bufread;
+
+    for size in [0, 1, 100 * KB, 1000 * KB] {
+        let data = generate_random_data(size);
+        let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+        let reader = BufReader::with_capacity(7 * KB, compressed.as_slice());
+        let mut decoder = bufread::Bz3Decoder::new(reader);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).await.unwrap();
+        assert_eq!(decompressed, data);
+
+        let truncated = &compressed[..(compressed.len() - 1)];
+        let mut decoder = bufread::Bz3Decoder::new(BufReader::new(truncated));
+        assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn decoder_conversions() {
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::bufread;
+
+    let data = generate_random_data(250 * KB);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+    let mut decoder = read::Bz3Decoder::new(compressed.as_slice());
+    decoder.multiple_members(true);
+    let mut decompressed = vec![0_u8; 150 * KB];
+    decoder.read_exact(&mut decompressed).await.unwrap();
+    let decoder = bufread::Bz3Decoder::from(decoder);
+    assert_eq!(decoder.block_size(), Some(100 * KB));
+    // nothing buffered yet
+    let mut decoder = read::Bz3Decoder::try_from(decoder).unwrap();
+    decoder.read_to_end(&mut decompressed).await.unwrap();
+    assert_eq!(decompressed, data);
+
+    let reader = BufReader::with_capacity(1024 * KB, compressed.as_slice());
+    let mut decoder = bufread::Bz3Decoder::new(reader);
+    let mut decompressed = vec![0_u8; 50 * KB];
+    decoder.read_exact(&mut decompressed).await.unwrap();
+    // the next blocks are buffered
+    let mut decoder = read::Bz3Decoder::try_from(decoder).unwrap_err();
+    decoder.read_to_end(&mut decompressed).await.unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[tokio::test]
+async fn write_encoder() {
+    for size in [0, 1, 100 * KB, 1000 * KB] {
+        let data = generate_random_data(size);
+
+        let mut compressed = Vec::new();
+        let mut encoder = write::Bz3Encoder::new(&mut compressed, 100 * KB).unwrap();
+        encoder.write_all(&data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+#[test]
+fn codec() {
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use bzip3::tokio::codec::Bz3Codec;
+
+    let mut codec = Bz3Codec::new(100 * KB).unwrap();
+    let messages = [0, 1, 1000, 100 * KB].map(generate_random_data);
+
+    let mut wire = BytesMut::new();
+    for message in &messages {
+        let start = wire.len();
+        codec
+            .encode(Bytes::from(message.clone()), &mut wire)
+            .unwrap();
+        if !message.is_empty() {
+            // each message is a standalone bzip3 file
+            assert_eq!(&wire[start..], mem::compress(message, 100 * KB).unwrap());
+        }
+    }
+    assert!(codec
+        .encode(Bytes::from(vec![0_u8; 100 * KB + 1]), &mut wire)
+        .is_err());
+
+    // feed the wire in pieces
+    let mut src = BytesMut::new();
+    let mut decoded = Vec::new();
+    for chunk in wire.chunks(7 * KB) {
+        src.extend_from_slice(chunk);
+        while let Some(message) = codec.decode(&mut src).unwrap() {
+            decoded.push(message.to_vec());
+        }
+    }
+    assert!(src.is_empty());
+    assert_eq!(decoded, messages);
+
+    let mut small_codec = Bz3Codec::new(65 * KB).unwrap();
+    let mut src = BytesMut::from(&mem::compress(b"hello", 100 * KB).unwrap()[..]);
+    assert!(matches!(
+        small_codec.decode(&mut src),
+        Err(bzip3::Error::BlockSizeLimit { .. })
+    ));
+
+    // a stored block is taken as is, not as a compressed one of a huge size
+    let mut src = BytesMut::new();
+    src.extend_from_slice(&bzip3::frame::FrameHeader::new(100 * KB).unwrap().to_bytes());
+    src.extend_from_slice(&bzip3::frame::BlockHeader::stored(5).to_bytes());
+    src.extend_from_slice(b"hello");
+    assert_eq!(codec.decode(&mut src).unwrap().unwrap(), &b"hello"[..]);
+    assert!(src.is_empty());
+}
+
+#[tokio::test]
+async fn parallel_encoder() {
+    use bzip3::tokio::parallel::Bz3ParallelEncoder;
+
+    for size in [0, 1, 100 * KB, 1000 * KB] {
+        let data = generate_random_data(size);
+
+        for max_concurrency in [1, 3] {
+            let mut compressed = Vec::new();
+            let mut encoder =
+                Bz3ParallelEncoder::new(&mut compressed, 100 * KB, max_concurrency).unwrap();
+            for chunk in data.chunks(30 * KB) {
+                encoder.write_all(chunk).await.unwrap();
+            }
+            encoder.shutdown().await.unwrap();
+            assert_eq!(compressed, mem::compress(&data, 100 * KB).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn fs_helpers() {
+    use bzip3::tokio::fs::{compress_file, decompress_file, FileOptions};
+
+    let dir = std::env::temp_dir().join(format!("bzip3-tokio-fs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (input, compressed, decompressed) = (dir.join("a"), dir.join("a.bz3"), dir.join("a.out"));
+
+    for (size, sync) in [(0, false), (100 * KB, true), (1000 * KB + 3, false)] {
+        let data = generate_random_data(size);
+        std::fs::write(&input, &data).unwrap();
+        let options = FileOptions {
+            buffer_size: 30 * KB,
+            sync,
+        };
+
+        let compressed_size = compress_file(&input, &compressed, 100 * KB, &options)
+            .await
+            .unwrap();
+        let compressed_data = std::fs::read(&compressed).unwrap();
+        assert_eq!(compressed_size, compressed_data.len() as u64);
+        assert_eq!(compressed_data, mem::compress(&data, 100 * KB).unwrap());
+
+        let size = decompress_file(&compressed, &decompressed, &options)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(std::fs::read(&decompressed).unwrap(), data);
+    }
+    assert!(
+        decompress_file(dir.join("missing"), &decompressed, &FileOptions::default())
+            .await
+            .is_err()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// IO wrapper returning `Pending` on every other poll, and moving at most `chunk` bytes at a time.
+struct Stuttering<T> {
+    inner: T,
+    chunk: usize,
+    pending: bool,
+}
+
+impl<T> Stuttering<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            chunk: 1000,
+            pending: false,
+        }
+    }
+
+    fn stall(&mut self, cx: &mut Context<'_>) -> bool {
+        self.pending = !self.pending;
+        if self.pending {
+            cx.waker().wake_by_ref();
+        }
+        self.pending
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Stuttering<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        let size = buf.remaining().min(self.chunk);
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(size));
+        ready!(Pin::new(&mut self.inner).poll_read(cx, &mut limited))?;
+        let read_size = limited.filled().len();
+        buf.advance(read_size);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Stuttering<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        let size = buf.len().min(self.chunk);
+        Pin::new(&mut self.inner).poll_write(cx, &buf[..size])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stall(cx) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Polls `future` once; a pending future gets dropped, like a cancelled branch of `select!`.
+async fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => {
+            tokio::task::yield_now().await;
+            None
+        }
+    }
+}
+
+async fn read_with_cancellation<R: AsyncRead + Unpin>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buf = [0_u8; 3000];
+    loop {
+        match poll_once(reader.read(&mut buf)).await {
+            Some(Ok(0)) => return Ok(output),
+            Some(result) => output.extend_from_slice(&buf[..result?]),
+            None => {}
+        }
+    }
+}
+
+async fn write_with_cancellation<W: AsyncWrite + Unpin>(mut writer: W, data: &[u8]) {
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data.len().min(pos + 5 * KB);
+        if let Some(result) = poll_once(writer.write(&data[pos..end])).await {
+            pos += result.unwrap();
+        }
+        if pos == data.len() / 2 {
+            while poll_once(writer.flush()).await.is_none() {}
+        }
+    }
+    while poll_once(writer.shutdown()).await.is_none() {}
+}
+
+#[tokio::test]
+async fn cancellation_safety() {
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::{bufread, parallel::Bz3ParallelEncoder};
+
+    for size in [0, 1, 100 * KB, 300 * KB + 7] {
+        let data = generate_random_data(size);
+        let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+        let reader = Stuttering::new(compressed.as_slice());
+        let decompressed = read_with_cancellation(read::Bz3Decoder::new(reader)).await;
+        assert_eq!(decompressed.unwrap(), data);
+
+        let reader = BufReader::with_capacity(7 * KB, Stuttering::new(compressed.as_slice()));
+        let decompressed = read_with_cancellation(bufread::Bz3Decoder::new(reader)).await;
+        assert_eq!(decompressed.unwrap(), data);
+
+        let truncated = Stuttering::new(&compressed[..(compressed.len() - 1)]);
+        assert!(read_with_cancellation(read::Bz3Decoder::new(truncated))
+            .await
+            .is_err());
+
+        // flushing in the middle splits a block, so compare with a sync encoder doing the same
+        let mut expected = Vec::new();
+        let mut encoder = bzip3::write::Bz3Encoder::new(&mut expected, 100 * KB).unwrap();
+        for (i, chunk) in data.chunks(5 * KB).enumerate() {
+            std::io::Write::write_all(&mut encoder, chunk).unwrap();
+            if (i + 1) * 5 * KB == data.len() / 2 {
+                std::io::Write::flush(&mut encoder).unwrap();
+            }
+        }
+        drop(encoder);
+
+        let mut writer = Stuttering::new(Vec::new());
+        let encoder = write::Bz3Encoder::new(&mut writer, 100 * KB).unwrap();
+        write_with_cancellation(encoder, &data).await;
+        assert_eq!(writer.inner, expected);
+
+        let mut writer = Stuttering::new(Vec::new());
+        let encoder = Bz3ParallelEncoder::new(&mut writer, 100 * KB, 3).unwrap();
+        write_with_cancellation(encoder, &data).await;
+        assert_eq!(writer.inner, expected);
+    }
+}
+
+#[tokio::test]
+async fn bufread_encoder_and_write_decoder() {
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::bufread;
+
+    for size in [0, 1, 100 * KB, 1000 * KB] {
+        let data = generate_random_data(size);
+        let expected = mem::compress(&data, 100 * KB).unwrap();
+
+        let reader = BufReader::with_capacity(7 * KB, data.as_slice());
+        let mut encoder = bufread::Bz3Encoder::new(reader, 100 * KB).unwrap();
+        let mut compressed = Vec::new();
+        encoder.read_to_end(&mut compressed).await.unwrap();
+        assert_eq!(compressed, expected);
+        assert!(encoder.into_inner().buffer().is_empty());
+
+        let mut decoder = write::Bz3Decoder::new(Vec::new());
+        for chunk in compressed.chunks(3 * KB) {
+            decoder.write_all(chunk).await.unwrap();
+        }
+        decoder.shutdown().await.unwrap();
+        assert_eq!(decoder.block_size(), Some(100 * KB));
+        assert_eq!(decoder.into_inner(), data);
+
+        let mut decoder = write::Bz3Decoder::new(Vec::new());
+        decoder
+            .write_all(&compressed[..(compressed.len() - 1)])
+            .await
+            .unwrap();
+        assert!(decoder.shutdown().await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn multiple_members() {
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::bufread;
+
+    let members = [
+        (generate_random_data(100 * KB + 1), 100 * KB),
+        (Vec::new(), 100 * KB),
+        (generate_random_data(10), 65 * KB),
+        (generate_random_data(200 * KB), 100 * KB),
+    ];
+    let mut concatenated = Vec::new();
+    let mut expected = Vec::new();
+    for (data, block_size) in &members {
+        concatenated.extend(mem::compress(data, *block_size).unwrap());
+        expected.extend_from_slice(data);
+    }
+
+    let mut decoder = read::Bz3Decoder::new(concatenated.as_slice());
+    decoder.multiple_members(true);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).await.unwrap();
+    assert_eq!(decompressed, expected);
+
+    let reader = BufReader::with_capacity(5, concatenated.as_slice());
+    let mut decoder = bufread::Bz3Decoder::new(reader);
+    decoder.multiple_members(true);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).await.unwrap();
+    assert_eq!(decompressed, expected);
+
+    let mut decoder = write::Bz3Decoder::new(Vec::new());
+    decoder.multiple_members(true);
+    decoder.write_all(&concatenated).await.unwrap();
+    decoder.shutdown().await.unwrap();
+    assert_eq!(decoder.into_inner(), expected);
+
+    let mut decoder = read::Bz3Decoder::new(concatenated.as_slice());
+    assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+
+    // the last member cut off within its file header
+    let last_size = mem::compress(&members[3].0, 100 * KB).unwrap().len();
+    let truncated = &concatenated[..(concatenated.len() - last_size + 6)];
+    let mut decoder = read::Bz3Decoder::new(truncated);
+    decoder.multiple_members(true);
+    assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn read_timeout() {
+    use std::time::Duration;
+
+    use tokio::io::BufReader;
+
+    use bzip3::tokio::bufread;
+
+    let data = generate_random_data(300 * KB);
+    let compressed = mem::compress(&data, 100 * KB).unwrap();
+
+    // a peer sending one and a half blocks, and then stalling
+    let stalled_peer = || async {
+        let (mut sender, receiver) = tokio::io::duplex(1024 * KB);
+        sender.write_all(&compressed[..(150 * KB)]).await.unwrap();
+        (sender, receiver)
+    };
+    let check = |result: io::Result<usize>, decompressed: &[u8]| {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<bzip3::Error>()
+            .unwrap();
+        assert!(matches!(*error, bzip3::Error::Timeout(_)));
+        assert_eq!(decompressed, &data[..decompressed.len()]);
+    };
+
+    let (_sender, receiver) = stalled_peer().await;
+    let mut decoder = read::Bz3Decoder::new(receiver);
+    decoder.set_read_timeout(Some(Duration::from_millis(50)));
+    let mut decompressed = Vec::new();
+    let result = decoder.read_to_end(&mut decompressed).await;
+    check(result, &decompressed);
+
+    let (_sender, receiver) = stalled_peer().await;
+    let mut decoder = bufread::Bz3Decoder::new(BufReader::new(receiver));
+    decoder.set_read_timeout(Some(Duration::from_millis(50)));
+    let mut decompressed = Vec::new();
+    let result = decoder.read_to_end(&mut decompressed).await;
+    check(result, &decompressed);
+
+    // progress keeps resetting the timer
+    let (mut sender, receiver) = tokio::io::duplex(1024 * KB);
+    let mut decoder = read::Bz3Decoder::new(receiver);
+    decoder.set_read_timeout(Some(Duration::from_millis(200)));
+    let sending = async {
+        for chunk in compressed.chunks(50 * KB) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.write_all(chunk).await.unwrap();
+        }
+        drop(sender);
+    };
+    let mut decompressed = Vec::new();
+    let (_, result) = tokio::join!(sending, decoder.read_to_end(&mut decompressed));
+    result.unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[tokio::test]
+async fn idle_flush() {
+    use std::time::Duration;
+
+    use bzip3::write::AutoFlush;
+
+    let data = generate_random_data(100 * KB);
+    let mut encoder = write::Bz3Encoder::new(Vec::new(), 1024 * KB).unwrap();
+    encoder
+        .set_auto_flush(AutoFlush {
+            idle: Some(Duration::from_millis(20)),
+            max_pending: Some(64 * KB),
+        })
+        .unwrap();
+    encoder.write_all(&data[..(64 * KB)]).await.unwrap();
+    // the block left by the size limit goes out right away
+    poll_once(encoder.idle_flush()).await.unwrap().unwrap();
+    assert_eq!(
+        mem::decompress(encoder.get_ref()).unwrap(),
+        &data[..(64 * KB)]
+    );
+
+    encoder.write_all(&data[(64 * KB)..]).await.unwrap();
+    // not idle yet
+    assert!(poll_once(encoder.idle_flush()).await.is_none());
+    encoder.idle_flush().await.unwrap();
+    assert_eq!(mem::decompress(encoder.get_ref()).unwrap(), data);
+    // nothing to flush
+    assert!(poll_once(encoder.idle_flush()).await.is_none());
+    encoder.shutdown().await.unwrap();
+    assert_eq!(mem::decompress(encoder.get_ref()).unwrap(), data);
+}
+
+#[tokio::test]
+async fn options() {
+    use bzip3::options::Bz3Options;
+
+    let data = generate_random_data(250 * KB);
+    let options = Bz3Options {
+        block_size: 100 * KB,
+        checksum: true,
+        ..Default::default()
+    };
+    let mut encoder = options.tokio_encoder(Vec::new()).unwrap();
+    encoder.write_all(&data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    let compressed = encoder.into_inner();
+    // the checksum after the blocks
+    assert!(compressed.len() > mem::compress(&data, 100 * KB).unwrap().len());
+
+    let mut decoder = options.tokio_decoder(compressed.as_slice());
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).await.unwrap();
+    assert_eq!(decompressed, data);
+
+    let options = Bz3Options {
+        max_block_size: 65 * KB,
+        ..options
+    };
+    let mut decoder = options.tokio_decoder(compressed.as_slice());
+    assert!(decoder.read_to_end(&mut Vec::new()).await.is_err());
+}
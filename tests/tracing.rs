@@ -0,0 +1,164 @@
+#![cfg(feature = "tracing")]
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use bzip3::read::Bz3Decoder;
+use bzip3::write::Bz3Encoder;
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+/// An event, with the name of its span.
+#[derive(Debug)]
+struct TestEvent {
+    span: &'static str,
+    fields: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    /// The spans by id, with the name of their parent.
+    spans: Vec<(&'static str, Option<&'static str>)>,
+    /// Ids of the spans entered.
+    stack: Vec<u64>,
+    events: Vec<TestEvent>,
+}
+
+impl Recorded {
+    fn name(&self, id: u64) -> &'static str {
+        self.spans[id as usize - 1].0
+    }
+
+    fn events(&self, message: &str) -> Vec<&TestEvent> {
+        self.events
+            .iter()
+            .filter(|x| x.fields["message"] == message)
+            .collect()
+    }
+}
+
+/// Subscriber keeping the spans and events, on a single thread.
+#[derive(Clone, Default)]
+struct TestSubscriber {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for TestSubscriber {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut recorded = self.recorded.lock().unwrap();
+        let parent = match span.parent() {
+            Some(id) => Some(id.into_u64()),
+            None if span.is_contextual() => recorded.stack.last().copied(),
+            None => None,
+        };
+        let parent = parent.map(|x| recorded.name(x));
+        recorded.spans.push((span.metadata().name(), parent));
+        Id::from_u64(recorded.spans.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let span = match event.parent() {
+            Some(id) => id.into_u64(),
+            None => *recorded.stack.last().unwrap(),
+        };
+        let mut fields = HashMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let span = recorded.name(span);
+        recorded.events.push(TestEvent { span, fields });
+    }
+
+    fn enter(&self, span: &Id) {
+        self.recorded.lock().unwrap().stack.push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut recorded = self.recorded.lock().unwrap();
+        assert_eq!(recorded.stack.pop(), Some(span.into_u64()));
+    }
+}
+
+#[test]
+fn tracing() {
+    let data = generate_random_data(250 * KB);
+
+    let subscriber = TestSubscriber::default();
+    let compressed = tracing::subscriber::with_default(subscriber.clone(), || {
+        let mut encoder = Bz3Encoder::new(Vec::new(), 100 * KB).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap()
+    });
+    let recorded = subscriber.recorded.lock().unwrap();
+    let blocks = recorded.events("block processed");
+    assert_eq!(blocks.len(), 3);
+    for block in &blocks {
+        assert_eq!(block.span, "bzip3_block");
+        assert_eq!(block.fields["operation"], "compress");
+    }
+    let input_size = blocks
+        .iter()
+        .map(|x| x.fields["input_size"].parse::<usize>().unwrap())
+        .sum::<usize>();
+    assert_eq!(input_size, data.len());
+    assert!(recorded
+        .spans
+        .iter()
+        .filter(|x| x.0 == "bzip3_block")
+        .all(|x| x.1 == Some("bzip3_stream")));
+    let streams = recorded.events("stream done");
+    assert_eq!(streams.len(), 1);
+    assert_eq!(streams[0].span, "bzip3_stream");
+    assert_eq!(streams[0].fields["blocks"], "3");
+    assert_eq!(streams[0].fields["input_size"], data.len().to_string());
+    // the block headers, but not the file header
+    assert_eq!(
+        streams[0].fields["output_size"],
+        (compressed.len() - 9).to_string()
+    );
+    drop(recorded);
+
+    let subscriber = TestSubscriber::default();
+    tracing::subscriber::with_default(subscriber.clone(), || {
+        let mut decoder = Bz3Decoder::new(compressed.as_slice()).unwrap();
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    });
+    let recorded = subscriber.recorded.lock().unwrap();
+    assert_eq!(recorded.events("block processed").len(), 3);
+    let streams = recorded.events("stream done");
+    assert_eq!(streams.len(), 1);
+    assert_eq!(streams[0].fields["operation"], "decompress");
+    assert_eq!(streams[0].fields["output_size"], data.len().to_string());
+}
@@ -0,0 +1,74 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use bzip3::transcode::recompress_from;
+
+mod common;
+
+use common::generate_compressible_data;
+
+/// Reader returning one byte at a time, with an `Interrupted` error before each read.
+struct ChoppyReader<R> {
+    reader: R,
+    interrupt: bool,
+}
+
+impl<R: Read> Read for ChoppyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        let size = buf.len().min(1);
+        self.reader.read(&mut buf[..size])
+    }
+}
+
+#[test]
+fn recompress_gzip() {
+    let data = generate_compressible_data(2000 * 1024);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&data).unwrap();
+    let gz = encoder.finish().unwrap();
+
+    // two concatenated gzip members
+    let gz = [&gz[..], &gz[..]].concat();
+    let bz3 = recompress_from(MultiGzDecoder::new(gz.as_slice()), Vec::new(), 100 * 1024).unwrap();
+    assert!(bzip3::mem::decompress(&bz3).unwrap() == [&data[..], &data[..]].concat());
+
+    let reader = ChoppyReader {
+        reader: &data[..300_000],
+        interrupt: false,
+    };
+    let bz3 = recompress_from(reader, Vec::new(), 100 * 1024).unwrap();
+    // blocks are whole despite the short reads
+    let report = bzip3::integrity::verify(bz3.as_slice()).unwrap();
+    assert_eq!(report.blocks.len(), 3);
+    assert!(bzip3::mem::decompress(&bz3).unwrap() == data[..300_000]);
+
+    let truncated = &gz[..(gz.len() / 4)];
+    assert!(recompress_from(MultiGzDecoder::new(truncated), Vec::new(), 100 * 1024).is_err());
+}
+
+#[cfg(feature = "bzip2")]
+#[test]
+fn bzip2_round_trip() {
+    use bzip3::transcode::{bz2_to_bz3, bz3_to_bz2};
+
+    let data = generate_compressible_data(2000 * 1024);
+    let bz3 = bzip3::mem::compress(&data, 100 * 1024).unwrap();
+
+    let bz2 = bz3_to_bz2(bz3.as_slice(), Vec::new(), 9).unwrap();
+    assert!(bz2.starts_with(b"BZh9"));
+
+    // two concatenated bzip2 streams, as pbzip2 writes
+    let bz2 = [&bz2[..], &bz2[..]].concat();
+    let bz3 = bz2_to_bz3(bz2.as_slice(), Vec::new(), 200 * 1024).unwrap();
+    assert!(bzip3::mem::decompress(&bz3).unwrap() == [&data[..], &data[..]].concat());
+
+    assert!(bz3_to_bz2(bz3.as_slice(), Vec::new(), 0).is_err());
+    assert!(bz2_to_bz3(&b"not bzip2"[..], Vec::new(), 200 * 1024).is_err());
+}
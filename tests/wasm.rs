@@ -0,0 +1,16 @@
+#![cfg(feature = "wasm-bindgen")]
+
+mod common;
+
+use common::generate_random_data;
+
+const KB: usize = 1024;
+
+// The entry points are plain Rust functions off wasm; only throwing needs a JavaScript host.
+#[test]
+fn round_trip() {
+    let data = generate_random_data(250 * KB);
+    let compressed = bzip3::wasm::compress(&data, 100 * KB).unwrap();
+    assert_eq!(bzip3::mem::decompress(&compressed).unwrap(), data);
+    assert_eq!(bzip3::wasm::decompress(&compressed).unwrap(), data);
+}